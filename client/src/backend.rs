@@ -0,0 +1,664 @@
+use crate::permissions::{PermissionPrompt, PROMPT_TOOL, TOKEN_ENV};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+
+/// Claude's own permission modes, see `claude --help`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ClaudePermissionMode {
+    Default,
+    AcceptEdits,
+    Plan,
+    BypassPermissions,
+}
+
+impl ClaudePermissionMode {
+    fn as_arg(self) -> &'static str {
+        match self {
+            ClaudePermissionMode::Default => "default",
+            ClaudePermissionMode::AcceptEdits => "acceptEdits",
+            ClaudePermissionMode::Plan => "plan",
+            ClaudePermissionMode::BypassPermissions => "bypassPermissions",
+        }
+    }
+}
+
+/// Agent settings of one session, stored in its metadata
+/// Only `extra_args` applies to --backend command
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// Used when a message doesn't pick a model
+    pub model: Option<String>,
+    pub permission_mode: Option<ClaudePermissionMode>,
+    pub allowed_tools: Vec<String>,
+    pub disallowed_tools: Vec<String>,
+    pub append_system_prompt: Option<String>,
+    pub max_turns: Option<u32>,
+    pub extra_args: Vec<String>,
+}
+
+/// Flags a session's extra args may not carry: lychee sets them itself, or
+/// they would get around the client's allowed models and approval policy
+const RESERVED_ARGS: &[&str] = &[
+    "-p",
+    "--print",
+    "-c",
+    "--continue",
+    "-r",
+    "--resume",
+    "--session-id",
+    "--fork-session",
+    "--input-format",
+    "--output-format",
+    "--model",
+    "--fallback-model",
+    "--permission-mode",
+    "--permission-prompt-tool",
+    "--dangerously-skip-permissions",
+    "--allow-dangerously-skip-permissions",
+    "--mcp-config",
+    "--strict-mcp-config",
+    "--settings",
+    "--setting-sources",
+    "--add-dir",
+    "--plugin-dir",
+];
+
+impl SessionSettings {
+    /**
+     * Check settings sent by a browser
+     * With tool approvals on, a session may not bypass them
+     */
+    pub fn check(&self, approvals: bool) -> Result<(), String> {
+        if approvals && self.permission_mode == Some(ClaudePermissionMode::BypassPermissions) {
+            return Err("This client requires tool approvals, sessions can't bypass them".to_string());
+        }
+        for arg in &self.extra_args {
+            let flag = arg.split('=').next().unwrap_or_default();
+            if RESERVED_ARGS.contains(&flag) {
+                return Err(format!("{} can't be set through a session's extra args", flag));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything an agent needs to run one turn
+pub struct TurnRequest<'a> {
+    pub working_dir: &'a Path,
+    pub prompt: &'a str,
+    pub model: &'a str,
+    pub settings: &'a SessionSettings,
+    /// Set in --permission-mode prompt: tool uses must be approved through it
+    pub permissions: Option<&'a PermissionPrompt>,
+}
+
+/// Everything an agent needs to start a long-lived session process
+pub struct SessionRequest<'a> {
+    pub working_dir: &'a Path,
+    pub model: &'a str,
+    pub settings: &'a SessionSettings,
+    pub permissions: Option<&'a PermissionPrompt>,
+}
+
+/// A running turn or session process, kept in `AppState::active_processes`
+pub enum AgentHandle {
+    Process(Child),
+    Task(JoinHandle<()>),
+}
+
+/// A started turn: its handle and the output stream whose lines trigger transcript reads
+pub struct RunningTurn {
+    pub handle: AgentHandle,
+    pub output: Box<dyn AsyncRead + Unpin + Send>,
+}
+
+/// A started session process: user messages go to `input`, one per turn
+pub struct RunningSession {
+    pub handle: AgentHandle,
+    pub input: Box<dyn AsyncWrite + Unpin + Send>,
+    pub output: Box<dyn AsyncRead + Unpin + Send>,
+}
+
+/**
+ * An agent that lychee can drive
+ *
+ * The client treats the agent's transcript file as the source of truth: every
+ * output line is only a trigger to read new transcript lines, which are turned
+ * into browser entries by `parse_entry`.
+ */
+pub trait AgentBackend: Send + Sync {
+    fn name(&self) -> &str;
+
+    /**
+     * Start a new conversation
+     */
+    fn start_turn(&self, turn: &TurnRequest) -> Result<RunningTurn, String>;
+
+    /**
+     * Continue an existing conversation
+     */
+    fn resume_turn(&self, turn: &TurnRequest, session_id: &str) -> Result<RunningTurn, String>;
+
+    /**
+     * Start a process that stays alive between turns and reads user messages
+     * from its input, including ones sent while it is working
+     * Backends that can only run single turns return None
+     */
+    fn start_session(&self, _request: &SessionRequest, _session_id: Option<&str>) -> Option<Result<RunningSession, String>> {
+        None
+    }
+
+    /**
+     * Encode a user message for a session's input, newline included
+     */
+    fn session_input(&self, prompt: &str) -> String {
+        let message = json!({
+            "type": "user",
+            "message": { "role": "user", "content": prompt },
+        });
+        format!("{}\n", message)
+    }
+
+    /**
+     * Whether this output line ends a turn of a session process
+     */
+    fn is_turn_end(&self, line: &str) -> bool {
+        serde_json::from_str::<Value>(line)
+            .is_ok_and(|data| data.get("type").and_then(Value::as_str) == Some("result"))
+    }
+
+    /**
+     * The agent's session id, if this output line announces it
+     */
+    fn session_id_from_output(&self, line: &str) -> Option<String> {
+        let data: Value = serde_json::from_str(line).ok()?;
+        data.get("session_id")?.as_str().map(str::to_string)
+    }
+
+    /**
+     * The streaming event (message_start, content_block_delta, ...) carried by
+     * this output line, if any
+     * Subagent events are skipped, their output only shows once persisted
+     */
+    fn stream_event(&self, line: &str) -> Option<Value> {
+        let data: Value = serde_json::from_str(line).ok()?;
+        if data.get("type")?.as_str()? != "stream_event" || !data["parent_tool_use_id"].is_null() {
+            return None;
+        }
+        data.get("event").cloned()
+    }
+
+    /**
+     * Path of the transcript for a session, once it exists
+     */
+    fn locate_transcript(&self, working_dir: &Path, session_id: &str) -> Option<PathBuf>;
+
+    /**
+     * Turn one transcript line into a browser entry, or None to skip it
+     */
+    fn parse_entry(&self, line: &str) -> Option<Value> {
+        parse_claude_entry(line)
+    }
+
+    /**
+     * A short title for a session, from its transcript
+     */
+    fn transcript_title(&self, transcript: &Path) -> Option<String> {
+        claude_transcript_title(transcript)
+    }
+
+    /**
+     * Stop a running turn; its output stream must end soon after
+     */
+    fn cancel(&self, handle: &mut AgentHandle) {
+        kill_agent(handle);
+    }
+}
+
+/**
+ * Kill an agent along with any tool subprocesses it started
+ * Agents run in their own process group, so the whole group is signalled
+ */
+pub fn kill_agent(handle: &mut AgentHandle) {
+    let child = match handle {
+        AgentHandle::Process(child) => child,
+        // Aborting the task drops its output pipe, which ends the turn like a killed process
+        AgentHandle::Task(task) => return task.abort(),
+    };
+    kill_process_group(child);
+}
+
+/**
+ * Kill a child started with `process_group(0)` along with everything in its group
+ * Elsewhere only the child itself is killed
+ */
+pub fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: killpg only sends a signal. id() is None once the child has
+        // been reaped, so the pid is still ours and can't have been reused
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+        return;
+    }
+
+    let _ = child.start_kill();
+}
+
+/**
+ * Spawn an agent process with piped stdout
+ */
+fn spawn_process(mut cmd: Command, name: &str) -> Result<RunningTurn, String> {
+    cmd.stdout(std::process::Stdio::piped());
+    cmd.stderr(std::process::Stdio::null());
+    cmd.kill_on_drop(true);
+
+    // Own process group so Ctrl+C in the terminal doesn't kill the agent
+    // before we get a chance to drain it
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    let mut child = cmd.spawn().map_err(|e| {
+        tracing::error!(agent = name, error = %e, "failed to spawn agent");
+        format!("Failed to spawn {}: {}", name, e)
+    })?;
+
+    let stdout = child.stdout.take().ok_or_else(|| "Failed to capture stdout".to_string())?;
+    Ok(RunningTurn {
+        handle: AgentHandle::Process(child),
+        output: Box::new(stdout),
+    })
+}
+
+/**
+ * Spawn an agent process that also takes input on stdin
+ */
+fn spawn_session_process(mut cmd: Command, name: &str) -> Result<RunningSession, String> {
+    cmd.stdin(std::process::Stdio::piped());
+    let RunningTurn { mut handle, output } = spawn_process(cmd, name)?;
+
+    let AgentHandle::Process(ref mut child) = handle else {
+        unreachable!("spawn_process always returns a process");
+    };
+    let stdin = child.stdin.take().ok_or_else(|| "Failed to capture stdin".to_string())?;
+    Ok(RunningSession {
+        handle,
+        input: Box::new(stdin),
+        output,
+    })
+}
+
+/**
+ * Route Claude's tool approvals to lychee, or skip them entirely
+ * A session's own permission mode replaces skipping
+ */
+fn permission_args(cmd: &mut Command, permissions: Option<&PermissionPrompt>, settings: &SessionSettings) {
+    if let Some(prompt) = permissions {
+        cmd.arg("--mcp-config").arg(prompt.mcp_config());
+        cmd.env(TOKEN_ENV, &prompt.token);
+        cmd.arg("--permission-prompt-tool").arg(PROMPT_TOOL);
+    }
+
+    match settings.permission_mode {
+        Some(mode) => {
+            cmd.arg("--permission-mode").arg(mode.as_arg());
+        }
+        None if permissions.is_none() => {
+            cmd.arg("--dangerously-skip-permissions");
+        }
+        None => {}
+    }
+}
+
+/**
+ * Apply a session's settings to a Claude command
+ */
+fn settings_args(cmd: &mut Command, settings: &SessionSettings) {
+    if !settings.allowed_tools.is_empty() {
+        cmd.arg("--allowedTools").arg(settings.allowed_tools.join(","));
+    }
+    if !settings.disallowed_tools.is_empty() {
+        cmd.arg("--disallowedTools").arg(settings.disallowed_tools.join(","));
+    }
+    if let Some(ref prompt) = settings.append_system_prompt {
+        cmd.arg("--append-system-prompt").arg(prompt);
+    }
+    if let Some(max_turns) = settings.max_turns {
+        cmd.arg("--max-turns").arg(max_turns.to_string());
+    }
+    cmd.args(&settings.extra_args);
+}
+
+/// The Claude Code CLI, with transcripts in ~/.claude/projects
+pub struct ClaudeBackend {
+    projects_dir: PathBuf,
+    binary: String,
+    /// From `claude.extra_args` in the config, ahead of each session's own
+    extra_args: Vec<String>,
+}
+
+impl ClaudeBackend {
+    pub fn new(binary: String, extra_args: Vec<String>) -> ClaudeBackend {
+        let home = std::env::var("HOME").unwrap_or_default();
+        ClaudeBackend {
+            projects_dir: PathBuf::from(home).join(".claude").join("projects"),
+            binary,
+            extra_args,
+        }
+    }
+
+    fn command(&self, turn: &TurnRequest, session_id: Option<&str>) -> Command {
+        let mut cmd = Command::new(&self.binary);
+        cmd.current_dir(turn.working_dir);
+
+        if let Some(claude_id) = session_id {
+            cmd.arg("--resume").arg(claude_id);
+        }
+
+        cmd.arg("-p").arg(turn.prompt);
+        cmd.arg("--model").arg(turn.model);
+        cmd.arg("--output-format").arg("stream-json");
+        cmd.arg("--include-partial-messages");
+        permission_args(&mut cmd, turn.permissions, turn.settings);
+        cmd.args(&self.extra_args);
+        settings_args(&mut cmd, turn.settings);
+
+        tracing::info!(model = %turn.model, resume = session_id, "spawning Claude");
+        cmd
+    }
+
+    fn session_command(&self, request: &SessionRequest, session_id: Option<&str>) -> Command {
+        let mut cmd = Command::new(&self.binary);
+        cmd.current_dir(request.working_dir);
+
+        if let Some(claude_id) = session_id {
+            cmd.arg("--resume").arg(claude_id);
+        }
+
+        // No prompt argument: user messages arrive on stdin as stream-json
+        cmd.arg("-p");
+        cmd.arg("--input-format").arg("stream-json");
+        cmd.arg("--output-format").arg("stream-json");
+        cmd.arg("--verbose");
+        cmd.arg("--include-partial-messages");
+        cmd.arg("--model").arg(request.model);
+        permission_args(&mut cmd, request.permissions, request.settings);
+        cmd.args(&self.extra_args);
+        settings_args(&mut cmd, request.settings);
+
+        tracing::info!(model = %request.model, resume = session_id, "spawning Claude session");
+        cmd
+    }
+}
+
+impl AgentBackend for ClaudeBackend {
+    fn name(&self) -> &str {
+        "Claude"
+    }
+
+    fn start_turn(&self, turn: &TurnRequest) -> Result<RunningTurn, String> {
+        spawn_process(self.command(turn, None), self.name())
+    }
+
+    fn resume_turn(&self, turn: &TurnRequest, session_id: &str) -> Result<RunningTurn, String> {
+        spawn_process(self.command(turn, Some(session_id)), self.name())
+    }
+
+    fn start_session(&self, request: &SessionRequest, session_id: Option<&str>) -> Option<Result<RunningSession, String>> {
+        Some(spawn_session_process(self.session_command(request, session_id), self.name()))
+    }
+
+    fn locate_transcript(&self, working_dir: &Path, session_id: &str) -> Option<PathBuf> {
+        find_claude_session_file(&self.projects_dir, working_dir, session_id)
+    }
+}
+
+/// Argument templates for `--backend command`
+#[derive(Debug, Clone)]
+pub struct CommandBackendConfig {
+    pub program: String,
+    /// Arguments for a new conversation; {prompt}, {model} and {cwd} are substituted
+    pub args: Vec<String>,
+    /// Arguments to continue a conversation, which may also use {session_id}
+    pub resume_args: Vec<String>,
+    /// Transcript path; {home}, {cwd}, {project} (Claude-style sanitized cwd) and {session_id} are substituted
+    pub transcript: String,
+}
+
+/**
+ * Any CLI agent that prints a `session_id` in its JSON output and writes a
+ * Claude-compatible JSONL transcript
+ */
+pub struct CommandBackend {
+    config: CommandBackendConfig,
+}
+
+impl CommandBackend {
+    pub fn new(config: CommandBackendConfig) -> CommandBackend {
+        CommandBackend { config }
+    }
+
+    fn command(&self, args: &[String], turn: &TurnRequest, session_id: Option<&str>) -> Command {
+        let cwd = turn.working_dir.display().to_string();
+        let substitute = |arg: &str| {
+            arg.replace("{prompt}", turn.prompt)
+                .replace("{model}", turn.model)
+                .replace("{cwd}", &cwd)
+                .replace("{session_id}", session_id.unwrap_or_default())
+        };
+
+        let mut cmd = Command::new(&self.config.program);
+        cmd.current_dir(turn.working_dir);
+        cmd.args(args.iter().map(|a| substitute(a)));
+        cmd.args(&turn.settings.extra_args);
+
+        tracing::info!(program = %self.config.program, model = %turn.model, resume = session_id, "spawning agent");
+        cmd
+    }
+}
+
+impl AgentBackend for CommandBackend {
+    fn name(&self) -> &str {
+        &self.config.program
+    }
+
+    fn start_turn(&self, turn: &TurnRequest) -> Result<RunningTurn, String> {
+        spawn_process(self.command(&self.config.args, turn, None), self.name())
+    }
+
+    fn resume_turn(&self, turn: &TurnRequest, session_id: &str) -> Result<RunningTurn, String> {
+        spawn_process(self.command(&self.config.resume_args, turn, Some(session_id)), self.name())
+    }
+
+    fn locate_transcript(&self, working_dir: &Path, session_id: &str) -> Option<PathBuf> {
+        let path = self.config.transcript
+            .replace("{home}", &std::env::var("HOME").unwrap_or_default())
+            .replace("{cwd}", &working_dir.display().to_string())
+            .replace("{project}", &project_dir_name(working_dir))
+            .replace("{session_id}", session_id);
+        let path = PathBuf::from(path);
+        path.exists().then_some(path)
+    }
+}
+
+/**
+ * Turns the streaming events of one turn into `claude_stream` payloads
+ *
+ * Each payload is one content block delta: `{message_id, index, block_type, text}`.
+ * Block starts are sent with empty text so the browser can keep blocks in
+ * transcript order; only text and thinking deltas carry content.
+ */
+#[derive(Default)]
+pub struct PartialMessages {
+    message_id: Option<String>,
+    block_types: HashMap<u64, String>,
+}
+
+impl PartialMessages {
+    pub fn apply(&mut self, event: &Value) -> Option<Value> {
+        match event.get("type")?.as_str()? {
+            "message_start" => {
+                self.message_id = event["message"]["id"].as_str().map(str::to_string);
+                self.block_types.clear();
+                None
+            }
+            "content_block_start" => {
+                let index = event.get("index")?.as_u64()?;
+                let block_type = event["content_block"]["type"].as_str()?.to_string();
+                self.block_types.insert(index, block_type);
+                self.payload(index, "")
+            }
+            "content_block_delta" => {
+                let index = event.get("index")?.as_u64()?;
+                let delta = event.get("delta")?;
+                let text = match delta.get("type")?.as_str()? {
+                    "text_delta" => delta.get("text")?.as_str()?,
+                    "thinking_delta" => delta.get("thinking")?.as_str()?,
+                    // Tool input is shown once the tool_use entry is persisted
+                    _ => return None,
+                };
+                self.payload(index, text)
+            }
+            "message_stop" => {
+                self.message_id = None;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn payload(&self, index: u64, text: &str) -> Option<Value> {
+        Some(json!({
+            "message_id": self.message_id.as_ref()?,
+            "index": index,
+            "block_type": self.block_types.get(&index)?,
+            "text": text,
+        }))
+    }
+}
+
+/**
+ * Parse a single JSONL line into a message entry
+ * Preserves isSidechain flag for frontend filtering
+ */
+pub fn parse_claude_entry(line: &str) -> Option<Value> {
+    let entry: Value = serde_json::from_str(line).ok()?;
+
+    // Only include user and assistant messages
+    let msg_type = entry.get("type")?.as_str()?;
+    if msg_type != "user" && msg_type != "assistant" {
+        return None;
+    }
+
+    // Extract message object
+    let message = entry.get("message")?;
+    let mut enriched = message.clone();
+
+    // Preserve isSidechain flag from entry
+    if let Some(is_sidechain) = entry.get("isSidechain")
+        && let Some(obj) = enriched.as_object_mut()
+    {
+        obj.insert("isSidechain".to_string(), is_sidechain.clone());
+    }
+
+    Some(enriched)
+}
+
+/// Longest title derived from a transcript, in characters
+const MAX_TITLE_CHARS: usize = 60;
+
+/**
+ * Title a Claude transcript
+ * The latest summary entry wins, otherwise the first line of the first user message
+ */
+pub fn claude_transcript_title(transcript: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(transcript).ok()?;
+    let entries: Vec<Value> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+
+    let summary = entries
+        .iter()
+        .rev()
+        .filter(|entry| entry["type"] == "summary")
+        .find_map(|entry| entry["summary"].as_str());
+    let first_prompt = || {
+        entries
+            .iter()
+            .filter(|entry| entry["type"] == "user" && entry["isSidechain"] != true && entry["isMeta"] != true)
+            .find_map(|entry| match &entry["message"]["content"] {
+                Value::String(text) => Some(text.as_str()),
+                // Tool results are user entries too, only text blocks are prompts
+                Value::Array(blocks) => blocks
+                    .iter()
+                    .find(|block| block["type"] == "text")
+                    .and_then(|block| block["text"].as_str()),
+                _ => None,
+            })
+    };
+
+    let line = summary.or_else(first_prompt)?.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= MAX_TITLE_CHARS {
+        return Some(line);
+    }
+    let cut: String = line.chars().take(MAX_TITLE_CHARS - 1).collect();
+    Some(format!("{}…", cut.trim_end()))
+}
+
+/**
+ * Claude's project directory name for a working directory
+ * e.g. /home/me/my.repo -> -home-me-my-repo
+ */
+pub fn project_dir_name(working_dir: &Path) -> String {
+    let path_str = working_dir.display().to_string();
+    let sanitized = path_str
+        .trim_start_matches('/')
+        .replace(['/', '.'], "-");
+    format!("-{}", sanitized)
+}
+
+/**
+ * Find Claude's JSONL file for a session
+ * Searches in the project directories under `projects_dir` (normally ~/.claude/projects/)
+ */
+pub fn find_claude_session_file(projects_dir: &Path, working_dir: &Path, claude_session_id: &str) -> Option<PathBuf> {
+    let session_filename = format!("{}.jsonl", claude_session_id);
+
+    // Try the expected sanitized path first
+    let expected_file = projects_dir.join(project_dir_name(working_dir)).join(&session_filename);
+    if expected_file.exists() {
+        return Some(expected_file);
+    }
+
+    tracing::debug!(expected = %expected_file.display(), "session file not at expected path, searching all project directories");
+
+    // If not found, search through all project directories for a match
+    // This handles cases where Claude's path sanitization differs from ours
+    if let Ok(entries) = std::fs::read_dir(projects_dir) {
+        for entry in entries.filter_map(Result::ok) {
+            let dir_path = entry.path();
+            if !dir_path.is_dir() {
+                continue;
+            }
+
+            let possible_file = dir_path.join(&session_filename);
+            if possible_file.exists() {
+                tracing::debug!(path = %possible_file.display(), "found session file via fallback search");
+                return Some(possible_file);
+            }
+        }
+    }
+
+    tracing::debug!(%claude_session_id, "session file not found after exhaustive search");
+    None
+}
@@ -0,0 +1,262 @@
+use crate::backend::kill_process_group;
+use crate::config::CommandsConfig;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Instant;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::oneshot;
+
+/// Largest output event; a chatty command sends several
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Output sent per run; past it the rest is dropped and the run marked truncated
+const MAX_OUTPUT_BYTES: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finished {
+    /// None when a signal ended it
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub duration_ms: u64,
+    pub cancelled: bool,
+    /// More than MAX_OUTPUT_BYTES were written
+    pub truncated: bool,
+}
+
+/**
+ * The program and arguments a run_command asks for
+ *
+ * Either a target named in `[commands.targets]`, run as configured, or a
+ * program listed in `commands.allowed`, run with whatever arguments the
+ * browser sent. Nothing goes through a shell.
+ */
+pub fn resolve(config: &CommandsConfig, name: Option<&str>, argv: Option<Vec<String>>) -> Result<Vec<String>, String> {
+    match (name, argv) {
+        (Some(name), None) => match config.targets.get(name) {
+            Some(argv) if !argv.is_empty() => Ok(argv.clone()),
+            Some(_) => Err(format!("Command {} has nothing to run", name)),
+            None => Err(format!("Unknown command {}", name)),
+        },
+        (None, Some(argv)) => {
+            let program = argv.first().ok_or("Empty command")?;
+            if !config.allowed.iter().any(|allowed| allowed == program) {
+                return Err(format!("{} is not allowed on this client", program));
+            }
+            Ok(argv)
+        }
+        _ => Err("Give either a command name or an argv".to_string()),
+    }
+}
+
+/**
+ * Run `argv` in `cwd`, passing its output to `on_output` as it comes
+ *
+ * The command gets its own process group, so cancelling (or dropping the
+ * sender of `cancel`) kills whatever it started too.
+ */
+pub async fn run(
+    argv: &[String],
+    cwd: &Path,
+    on_output: impl Fn(OutputStream, String),
+    mut cancel: oneshot::Receiver<()>,
+) -> Result<Finished, String> {
+    let started = Instant::now();
+    let mut cmd = Command::new(&argv[0]);
+    cmd.args(&argv[1..])
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    let mut child = cmd.spawn().map_err(|e| format!("Failed to start {}: {}", argv[0], e))?;
+    let mut stdout = child.stdout.take().ok_or("Failed to capture stdout")?;
+    let mut stderr = child.stderr.take().ok_or("Failed to capture stderr")?;
+
+    let mut out_buf = vec![0; READ_CHUNK];
+    let mut err_buf = vec![0; READ_CHUNK];
+    let mut pending_out = Vec::new();
+    let mut pending_err = Vec::new();
+    let (mut stdout_open, mut stderr_open) = (true, true);
+    let mut cancelled = false;
+    let mut sent = 0;
+
+    let mut emit = |stream: OutputStream, pending: &mut Vec<u8>, bytes: &[u8]| {
+        if sent >= MAX_OUTPUT_BYTES {
+            return;
+        }
+        let bytes = &bytes[..bytes.len().min(MAX_OUTPUT_BYTES - sent)];
+        sent += bytes.len();
+        pending.extend_from_slice(bytes);
+        let text = take_utf8(pending);
+        if !text.is_empty() {
+            on_output(stream, text);
+        }
+    };
+
+    while stdout_open || stderr_open {
+        tokio::select! {
+            read = stdout.read(&mut out_buf), if stdout_open => match read {
+                Ok(n) if n > 0 => emit(OutputStream::Stdout, &mut pending_out, &out_buf[..n]),
+                _ => stdout_open = false,
+            },
+            read = stderr.read(&mut err_buf), if stderr_open => match read {
+                Ok(n) if n > 0 => emit(OutputStream::Stderr, &mut pending_err, &err_buf[..n]),
+                _ => stderr_open = false,
+            },
+            _ = &mut cancel, if !cancelled => {
+                cancelled = true;
+                kill_process_group(&mut child);
+            }
+        }
+    }
+    // Whatever is left is not valid UTF-8 and never will be
+    for (stream, pending) in [(OutputStream::Stdout, pending_out), (OutputStream::Stderr, pending_err)] {
+        if !pending.is_empty() {
+            on_output(stream, String::from_utf8_lossy(&pending).into_owned());
+        }
+    }
+
+    // It may have closed its output and kept running
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status.map_err(|e| format!("Failed to wait for {}: {}", argv[0], e))?,
+            _ = &mut cancel, if !cancelled => {
+                cancelled = true;
+                kill_process_group(&mut child);
+            }
+        }
+    };
+
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+    #[cfg(not(unix))]
+    let signal = None;
+
+    Ok(Finished {
+        exit_code: status.code(),
+        signal,
+        duration_ms: started.elapsed().as_millis() as u64,
+        cancelled,
+        truncated: sent >= MAX_OUTPUT_BYTES,
+    })
+}
+
+/**
+ * Take the complete UTF-8 text off the front of `pending`
+ * A character cut off at the end stays for the next read
+ */
+fn take_utf8(pending: &mut Vec<u8>) -> String {
+    let complete = match std::str::from_utf8(pending) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => pending.len(),
+    };
+    let text = String::from_utf8_lossy(&pending[..complete]).into_owned();
+    pending.drain(..complete);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn config() -> CommandsConfig {
+        CommandsConfig {
+            targets: [("test".to_string(), argv(&["cargo", "test"])), ("empty".to_string(), Vec::new())].into(),
+            allowed: argv(&["ls"]),
+        }
+    }
+
+    #[test]
+    fn resolve_runs_targets_as_configured() {
+        assert_eq!(resolve(&config(), Some("test"), None).unwrap(), ["cargo", "test"]);
+        assert!(resolve(&config(), Some("empty"), None).is_err());
+        assert!(resolve(&config(), Some("build"), None).is_err());
+    }
+
+    #[test]
+    fn resolve_only_runs_allowed_programs() {
+        assert_eq!(resolve(&config(), None, Some(argv(&["ls", "-la"]))).unwrap(), ["ls", "-la"]);
+        assert!(resolve(&config(), None, Some(argv(&["rm", "-rf", "/"]))).is_err());
+        assert!(resolve(&config(), None, Some(Vec::new())).is_err());
+        // Allowing a program doesn't allow a path to one of the same name
+        assert!(resolve(&config(), None, Some(argv(&["./ls"]))).is_err());
+    }
+
+    #[test]
+    fn resolve_wants_a_name_or_an_argv() {
+        assert!(resolve(&config(), None, None).is_err());
+        assert!(resolve(&config(), Some("test"), Some(argv(&["ls"]))).is_err());
+    }
+
+    #[test]
+    fn take_utf8_keeps_a_cut_off_character() {
+        let mut pending = "héllo ✓".as_bytes().to_vec();
+        let tail = pending.split_off(pending.len() - 2);
+        assert_eq!(take_utf8(&mut pending), "héllo ");
+        assert_eq!(pending.len(), 1);
+
+        pending.extend_from_slice(&tail);
+        assert_eq!(take_utf8(&mut pending), "✓");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn take_utf8_replaces_invalid_bytes() {
+        let mut pending = b"a\xffb".to_vec();
+        assert_eq!(take_utf8(&mut pending), "a\u{fffd}b");
+        assert!(pending.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_streams_output_and_reports_the_exit_code() {
+        let output = Mutex::new(Vec::new());
+        let (_cancel, cancelled) = oneshot::channel();
+        let finished = run(
+            &argv(&["sh", "-c", "echo out; echo err >&2; exit 3"]),
+            Path::new("."),
+            |stream, text| output.lock().unwrap().push((stream, text)),
+            cancelled,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(finished.exit_code, Some(3));
+        assert!(!finished.cancelled && !finished.truncated);
+        let output = output.into_inner().unwrap();
+        assert!(output.iter().any(|(stream, text)| matches!(stream, OutputStream::Stdout) && text == "out\n"));
+        assert!(output.iter().any(|(stream, text)| matches!(stream, OutputStream::Stderr) && text == "err\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_kills_cancelled_commands() {
+        let (cancel, cancelled) = oneshot::channel();
+        let sleep = argv(&["sh", "-c", "sleep 30"]);
+        let run = run(&sleep, Path::new("."), |_, _| {}, cancelled);
+        let (finished, _) = tokio::join!(run, async move { cancel.send(()) });
+
+        let finished = finished.unwrap();
+        assert!(finished.cancelled);
+        assert_eq!(finished.exit_code, None);
+        assert!(finished.duration_ms < 10_000);
+    }
+}
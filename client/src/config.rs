@@ -0,0 +1,456 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Relay to connect to when neither RELAY_URL nor a config file says otherwise
+const DEFAULT_RELAY_URL: &str = "ws://localhost:3001/ws";
+
+/// Keys the repo file may set, with everything under them. The rest decide
+/// where the client connects and what browsers may do, so a cloned repo
+/// doesn't get to change them
+const REPO_KEYS: &[&str] = &["models.default", "worktree", "commands.targets", "tui"];
+
+/**
+ * Effective configuration of `lychee up`
+ *
+ * Built from the defaults below, overlaid by the user file
+ * (`~/.config/lychee/config.toml`) and then the repo file
+ * (`<repo>/.lychee/config.toml`). Tables merge key by key, anything else is
+ * replaced by the later layer. The repo file is limited to `REPO_KEYS`.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub relay: RelayConfig,
+    pub claude: ClaudeConfig,
+    pub models: ModelsConfig,
+    pub agents: AgentsConfig,
+    pub worktree: WorktreeConfig,
+    pub tui: TuiConfig,
+    pub terminal: TerminalConfig,
+    pub commands: CommandsConfig,
+    pub preview: PreviewConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// RELAY_URL still wins, so one-off runs don't need a file edit
+    pub url: String,
+    /// Sent as `Authorization: Bearer`, checked against the relay's RELAY_CLIENT_TOKEN
+    pub token: Option<String>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> RelayConfig {
+        RelayConfig {
+            url: DEFAULT_RELAY_URL.to_string(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClaudeConfig {
+    pub binary: String,
+    /// Appended to every Claude command, before a session's own extra args
+    pub extra_args: Vec<String>,
+}
+
+impl Default for ClaudeConfig {
+    fn default() -> ClaudeConfig {
+        ClaudeConfig {
+            binary: "claude".to_string(),
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelsConfig {
+    /// For messages and sessions that don't pick a model
+    pub default: String,
+    /// Models browsers may ask for; empty allows any
+    pub allowed: Vec<String>,
+}
+
+impl Default for ModelsConfig {
+    fn default() -> ModelsConfig {
+        ModelsConfig {
+            default: "sonnet".to_string(),
+            allowed: Vec::new(),
+        }
+    }
+}
+
+impl ModelsConfig {
+    pub fn is_allowed(&self, model: &str) -> bool {
+        self.allowed.is_empty() || self.allowed.iter().any(|m| m == model)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentsConfig {
+    /// Turns and commands that may run at once across all sessions; unset for no limit
+    pub max_concurrent: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorktreeConfig {
+    /// Start point of new worktree sessions; unset for the repo's current HEAD
+    pub base_branch: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TuiConfig {
+    /// Off shows plain logs instead of the status screen, like --no-tui
+    pub enabled: bool,
+    pub refresh_ms: u64,
+    pub show_cat: bool,
+}
+
+impl Default for TuiConfig {
+    fn default() -> TuiConfig {
+        TuiConfig {
+            enabled: true,
+            refresh_ms: 500,
+            show_cat: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerminalConfig {
+    /// Whether browsers may open terminals at all
+    pub enabled: bool,
+    /// Unset for $SHELL
+    pub shell: Option<String>,
+    /// Terminals that may be open at once across all sessions
+    pub max_open: usize,
+}
+
+impl Default for TerminalConfig {
+    fn default() -> TerminalConfig {
+        TerminalConfig {
+            enabled: true,
+            shell: None,
+            max_open: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandsConfig {
+    /// Commands browsers can run by name, e.g. `test = ["cargo", "test"]`
+    pub targets: BTreeMap<String, Vec<String>>,
+    /// Programs browsers may run with arguments of their own
+    pub allowed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewConfig {
+    /// Whether browsers may expose local ports through the relay at all
+    pub enabled: bool,
+    /// Ports that may be exposed; empty for any port from 1024 up
+    pub allowed_ports: Vec<u16>,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> PreviewConfig {
+        PreviewConfig {
+            enabled: true,
+            allowed_ports: Vec::new(),
+        }
+    }
+}
+
+impl PreviewConfig {
+    pub fn is_allowed(&self, port: u16) -> bool {
+        if self.allowed_ports.is_empty() {
+            port >= 1024
+        } else {
+            self.allowed_ports.contains(&port)
+        }
+    }
+}
+
+/// Which file `lychee config set` writes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    User,
+    Repo,
+}
+
+/**
+ * `~/.config/lychee/config.toml`, or under $XDG_CONFIG_HOME when set
+ */
+pub fn user_path() -> PathBuf {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".config"));
+    base.join("lychee").join("config.toml")
+}
+
+pub fn repo_path(repo: &Path) -> PathBuf {
+    repo.join(".lychee").join("config.toml")
+}
+
+fn layer_path(layer: Layer, repo: &Path) -> PathBuf {
+    match layer {
+        Layer::User => user_path(),
+        Layer::Repo => repo_path(repo),
+    }
+}
+
+/**
+ * Read one layer; a missing file is an empty layer
+ */
+fn read_layer(path: &Path) -> Result<Table, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => text
+            .parse::<Table>()
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Table::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/**
+ * Refuse a repo layer that sets keys outside `REPO_KEYS`
+ */
+fn check_repo_layer(layer: &Table, path: &Path) -> Result<(), String> {
+    fn leaves(table: &Table, prefix: &str, out: &mut Vec<String>) {
+        for (key, value) in table {
+            let key = format!("{}{}", prefix, key);
+            match value {
+                Value::Table(table) => leaves(table, &format!("{}.", key), out),
+                _ => out.push(key),
+            }
+        }
+    }
+
+    let mut keys = Vec::new();
+    leaves(layer, "", &mut keys);
+    for key in keys {
+        let allowed = REPO_KEYS
+            .iter()
+            .any(|repo_key| key == *repo_key || key.starts_with(&format!("{}.", repo_key)));
+        if !allowed {
+            return Err(format!("{}: {} can only be set in {}", path.display(), key, user_path().display()));
+        }
+    }
+    Ok(())
+}
+
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(table)) => merge(base_table, table),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/**
+ * Defaults plus the user file at `user` and the repo's file, as a TOML table
+ */
+fn merged(user: &Path, repo: &Path) -> Result<Table, String> {
+    let mut table = Table::try_from(Config::default()).map_err(|e| e.to_string())?;
+    merge(&mut table, read_layer(user)?);
+    let repo_layer = read_layer(&repo_path(repo))?;
+    check_repo_layer(&repo_layer, &repo_path(repo))?;
+    merge(&mut table, repo_layer);
+    Ok(table)
+}
+
+impl Config {
+    /**
+     * Effective configuration for a repo
+     */
+    pub fn load(repo: &Path) -> Result<Config, String> {
+        let mut config: Config = merged(&user_path(), repo)?
+            .try_into()
+            .map_err(|e: toml::de::Error| format!("Invalid configuration: {}", e.to_string().trim_end()))?;
+
+        if let Ok(url) = std::env::var("RELAY_URL") {
+            config.relay.url = url;
+        }
+        Ok(config)
+    }
+}
+
+/**
+ * Look up a dotted key such as `relay.url` in the effective configuration
+ */
+pub fn get(repo: &Path, key: &str) -> Result<Option<Value>, String> {
+    let table = Table::try_from(Config::load(repo)?).map_err(|e| e.to_string())?;
+    let mut value = Value::Table(table);
+    for part in key.split('.') {
+        match value.get(part) {
+            Some(next) => value = next.clone(),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/**
+ * Set a dotted key in one layer's file
+ *
+ * The value is parsed as a TOML value (`3`, `true`, `["a", "b"]`) and taken
+ * as a plain string otherwise. The result must still be a valid configuration,
+ * so typos in key names are refused instead of silently ignored.
+ */
+pub fn set(repo: &Path, layer: Layer, key: &str, raw: &str) -> Result<PathBuf, String> {
+    let value = format!("value = {}", raw)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()));
+
+    let path = layer_path(layer, repo);
+    let mut table = read_layer(&path)?;
+
+    let parts: Vec<&str> = key.split('.').collect();
+    let (last, parents) = parts.split_last().ok_or("Empty key")?;
+    let mut current = &mut table;
+    for part in parents {
+        current = match current
+            .entry(part.to_string())
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(next) => next,
+            _ => return Err(format!("{} is not a table", part)),
+        };
+    }
+    current.insert(last.to_string(), value);
+
+    // Validate the layer on top of the defaults before writing it
+    if layer == Layer::Repo {
+        check_repo_layer(&table, &path)?;
+    }
+    let mut check = Table::try_from(Config::default()).map_err(|e| e.to_string())?;
+    merge(&mut check, table.clone());
+    check
+        .try_into::<Config>()
+        .map_err(|e| format!("Invalid configuration: {}", e.to_string().trim_end()))?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let text = toml::to_string_pretty(&table).map_err(|e| e.to_string())?;
+    std::fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/**
+ * The effective configuration as TOML, headed by the files it came from
+ */
+pub fn show(repo: &Path) -> Result<String, String> {
+    let mut config = Config::load(repo)?;
+    if config.relay.token.is_some() {
+        // `lychee config get relay.token` prints the real one
+        config.relay.token = Some("<hidden>".to_string());
+    }
+
+    let mut out = String::new();
+    for path in [user_path(), repo_path(repo)] {
+        let state = if path.exists() { "" } else { " (not found)" };
+        out.push_str(&format!("# {}{}\n", path.display(), state));
+    }
+    if std::env::var("RELAY_URL").is_ok() {
+        out.push_str("# relay.url from RELAY_URL\n");
+    }
+    out.push('\n');
+    out.push_str(&toml::to_string_pretty(&config).map_err(|e| e.to_string())?);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(text: &str) -> Table {
+        text.parse().unwrap()
+    }
+
+    /// A repo with its config file, and a user file next to it
+    fn layers(user: &str, repo: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let user_file = dir.path().join("user.toml");
+        std::fs::write(&user_file, user).unwrap();
+        std::fs::create_dir_all(dir.path().join(".lychee")).unwrap();
+        std::fs::write(repo_path(dir.path()), repo).unwrap();
+        (dir, user_file)
+    }
+
+    fn load(user: &Path, repo: &Path) -> Result<Config, String> {
+        merged(user, repo)?.try_into().map_err(|e: toml::de::Error| e.to_string())
+    }
+
+    #[test]
+    fn tables_merge_key_by_key() {
+        let mut base = table("[tui]\nenabled = true\nrefresh_ms = 500\n[models]\nallowed = [\"a\", \"b\"]\n");
+        merge(&mut base, table("[tui]\nrefresh_ms = 100\n[models]\nallowed = [\"c\"]\n"));
+        assert_eq!(base, table("[tui]\nenabled = true\nrefresh_ms = 100\n[models]\nallowed = [\"c\"]\n"));
+    }
+
+    #[test]
+    fn repo_layer_applies_over_the_user_layer() {
+        let (dir, user) = layers(
+            "[models]\ndefault = \"opus\"\n[tui]\nrefresh_ms = 100\n",
+            "[tui]\nrefresh_ms = 200\n",
+        );
+        let config = load(&user, dir.path()).unwrap();
+        assert_eq!(config.models.default, "opus");
+        assert_eq!(config.tui.refresh_ms, 200);
+        assert!(config.tui.show_cat);
+        assert_eq!(config.relay.url, DEFAULT_RELAY_URL);
+    }
+
+    #[test]
+    fn missing_files_are_empty_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = load(&dir.path().join("none.toml"), dir.path()).unwrap();
+        assert_eq!(config.models.default, ModelsConfig::default().default);
+    }
+
+    #[test]
+    fn repo_layer_is_limited_to_repo_keys() {
+        let path = Path::new("config.toml");
+        assert!(check_repo_layer(&table("[tui]\nenabled = false\n[worktree]\nbase_branch = \"main\"\n"), path).is_ok());
+        assert!(check_repo_layer(&table("[commands.targets]\ntest = [\"cargo\", \"test\"]\n"), path).is_ok());
+        assert!(check_repo_layer(&table("[commands]\nallowed = [\"sh\"]\n"), path).is_err());
+        assert!(check_repo_layer(&table("[relay]\nurl = \"ws://evil\"\n"), path).is_err());
+
+        let (dir, user) = layers("", "[relay]\ntoken = \"stolen\"\n");
+        assert!(load(&user, dir.path()).unwrap_err().contains("relay.token"));
+    }
+
+    #[test]
+    fn set_writes_valid_keys_and_refuses_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = set(dir.path(), Layer::Repo, "tui.refresh_ms", "250").unwrap();
+        assert_eq!(read_layer(&path).unwrap(), table("[tui]\nrefresh_ms = 250\n"));
+
+        set(dir.path(), Layer::Repo, "worktree.base_branch", "main").unwrap();
+        assert_eq!(read_layer(&path).unwrap()["worktree"]["base_branch"].as_str(), Some("main"));
+
+        assert!(set(dir.path(), Layer::Repo, "tui.refresh", "1").is_err());
+        assert!(set(dir.path(), Layer::Repo, "tui.refresh_ms", "soon").is_err());
+        assert!(set(dir.path(), Layer::Repo, "relay.url", "ws://evil").is_err());
+        assert_eq!(read_layer(&path).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,602 @@
+use crate::worktree::git;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Diff lines sent for one request; files past it are listed without hunks
+const MAX_DIFF_LINES: usize = 20_000;
+
+/// Untracked files larger than this are listed without hunks
+const MAX_UNTRACKED_BYTES: u64 = 1024 * 1024;
+
+/// Which changes of a session `get_diff` covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffScope {
+    /// Everything since the base: commits, staged and unstaged changes, untracked files
+    #[default]
+    All,
+    /// Commits since the base
+    Committed,
+    /// Index against HEAD
+    Staged,
+    /// Working tree against the index, untracked files included
+    Unstaged,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    Copied,
+    TypeChanged,
+    Untracked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineKind {
+    Context,
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    /// Without the leading marker or the newline
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hunk {
+    /// The `@@ -a,b +c,d @@ context` line
+    pub header: String,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub path: String,
+    /// Source of a rename or copy
+    pub old_path: Option<String>,
+    pub status: FileStatus,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<Hunk>,
+    /// Hunks were left out to keep the response small
+    pub truncated: bool,
+}
+
+/**
+ * Structured diff of a working directory
+ *
+ * `base` is the commit `All` and `Committed` compare against. The file list
+ * comes from `--raw -z` so paths need no unquoting; the patch supplies hunks.
+ * Its sections are matched to files by the path in their headers, since not
+ * every listed file gets one in the same order.
+ */
+pub async fn diff(dir: &Path, base: &str, scope: DiffScope) -> Result<Vec<FileDiff>, String> {
+    let range: Vec<&str> = match scope {
+        DiffScope::All => vec![base],
+        DiffScope::Committed => vec![base, "HEAD"],
+        DiffScope::Staged => vec!["--cached"],
+        DiffScope::Unstaged => vec![],
+    };
+    // Keeps non-ASCII names as they are in patch headers
+    let common = ["-c", "core.quotePath=false", "diff", "--no-color", "--no-ext-diff", "--find-renames"];
+
+    let mut raw_args = common.to_vec();
+    raw_args.extend(["--raw", "-z"]);
+    raw_args.extend(&range);
+    raw_args.push("--");
+    let raw = git(dir, &raw_args).await?;
+
+    let mut patch_args = common.to_vec();
+    patch_args.extend(["--patch", "--unified=3"]);
+    patch_args.extend(&range);
+    patch_args.push("--");
+    let patch = git(dir, &patch_args).await?;
+
+    let mut files = parse_raw(&raw);
+    let mut sections: HashMap<String, Vec<&str>> = split_sections(&patch)
+        .into_iter()
+        .filter_map(|section| Some((section_path(&section)?, section)))
+        .collect();
+    let mut budget = MAX_DIFF_LINES;
+    for file in files.iter_mut() {
+        if let Some(section) = sections.remove(&file.path) {
+            apply_section(file, section, &mut budget);
+        }
+    }
+
+    if matches!(scope, DiffScope::All | DiffScope::Unstaged) {
+        let untracked = git(dir, &["ls-files", "--others", "--exclude-standard", "-z"]).await?;
+        for path in untracked.split('\0').filter(|p| !p.is_empty()) {
+            files.push(untracked_file(dir, path, &mut budget));
+        }
+    }
+
+    Ok(files)
+}
+
+/**
+ * Files of `git diff --raw -z`: `:modes shas status\0path\0[new path\0]`
+ */
+fn parse_raw(raw: &str) -> Vec<FileDiff> {
+    let mut files = Vec::new();
+    let mut fields = raw.split('\0');
+    while let Some(meta) = fields.next() {
+        let Some(status) = meta.split_whitespace().last() else {
+            continue;
+        };
+        let Some(first) = fields.next() else { break };
+
+        let (status, old_path, path) = match status.chars().next() {
+            Some('R') | Some('C') => {
+                let Some(second) = fields.next() else { break };
+                let kind = if status.starts_with('R') { FileStatus::Renamed } else { FileStatus::Copied };
+                (kind, Some(first.to_string()), second.to_string())
+            }
+            Some('A') => (FileStatus::Added, None, first.to_string()),
+            Some('D') => (FileStatus::Deleted, None, first.to_string()),
+            Some('T') => (FileStatus::TypeChanged, None, first.to_string()),
+            _ => (FileStatus::Modified, None, first.to_string()),
+        };
+        files.push(FileDiff {
+            path,
+            old_path,
+            status,
+            binary: false,
+            additions: 0,
+            deletions: 0,
+            hunks: Vec::new(),
+            truncated: false,
+        });
+    }
+    files
+}
+
+/**
+ * Split a patch into its per-file sections
+ */
+fn split_sections(patch: &str) -> Vec<Vec<&str>> {
+    let mut sections: Vec<Vec<&str>> = Vec::new();
+    for line in patch.lines() {
+        if line.starts_with("diff --git ") {
+            sections.push(Vec::new());
+        }
+        if let Some(section) = sections.last_mut() {
+            section.push(line);
+        }
+    }
+    sections
+}
+
+/**
+ * The path a patch section is about, its new one for renames and copies
+ *
+ * Taken from `rename to`/`copy to`, the `+++`/`---` lines, or else the
+ * `diff --git a/<path> b/<path>` line, which names the same path twice when
+ * nothing else does.
+ */
+fn section_path(section: &[&str]) -> Option<String> {
+    let header = section
+        .iter()
+        .skip(1)
+        .take_while(|line| !line.starts_with("@@ ") && !line.starts_with("Binary files ") && **line != "GIT binary patch");
+
+    let mut old_path = None;
+    for line in header {
+        if let Some(path) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+            return Some(unquote(path));
+        }
+        // git ends these with a tab when the name has a space
+        if let Some(path) = line.strip_prefix("+++ ").map(|p| p.trim_end_matches('\t'))
+            && path != "/dev/null"
+        {
+            return unquote(path).strip_prefix("b/").map(str::to_string);
+        }
+        if let Some(path) = line.strip_prefix("--- ").map(|p| p.trim_end_matches('\t'))
+            && path != "/dev/null"
+        {
+            old_path = unquote(path).strip_prefix("a/").map(str::to_string);
+        }
+    }
+    if old_path.is_some() {
+        return old_path;
+    }
+
+    let names = section.first()?.strip_prefix("diff --git ")?;
+    if names.starts_with('"') {
+        let (_, new) = split_quoted(names)?;
+        return unquote(new.strip_prefix(' ')?).strip_prefix("b/").map(str::to_string);
+    }
+    // "a/<path> b/<path>"
+    let len = names.len().checked_sub(5)? / 2;
+    let (old, new) = (names.get(2..2 + len)?, names.get(5 + len..)?);
+    names.get(2 + len..5 + len).filter(|sep| *sep == " b/" && old == new)?;
+    Some(new.to_string())
+}
+
+/**
+ * Split a line after its leading quoted name
+ */
+fn split_quoted(line: &str) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (i, c) in line.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(line.split_at(i + 1)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/**
+ * Undo git's C-style quoting of a name; unquoted names are returned as they are
+ */
+fn unquote(name: &str) -> String {
+    let Some(inner) = name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) else {
+        return name.to_string();
+    };
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('a') => bytes.push(0x07),
+            Some('b') => bytes.push(0x08),
+            Some('t') => bytes.push(b'\t'),
+            Some('n') => bytes.push(b'\n'),
+            Some('v') => bytes.push(0x0b),
+            Some('f') => bytes.push(0x0c),
+            Some('r') => bytes.push(b'\r'),
+            // Octal escapes are single bytes of a UTF-8 sequence
+            Some(digit @ '0'..='7') => {
+                let octal: String = std::iter::once(digit).chain(chars.by_ref().take(2)).collect();
+                bytes.push(u8::from_str_radix(&octal, 8).unwrap_or(b'?'));
+            }
+            Some(other) => bytes.push(other as u8),
+            None => {}
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/**
+ * Fill a file's hunks from its patch section, while the line budget lasts
+ */
+fn apply_section(file: &mut FileDiff, section: Vec<&str>, budget: &mut usize) {
+    let mut lines = section.into_iter().skip(1);
+    let mut hunks: Vec<Hunk> = Vec::new();
+
+    for line in lines.by_ref() {
+        if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            file.binary = true;
+            return;
+        }
+        if let Some(hunk) = parse_hunk_header(line) {
+            hunks.push(hunk);
+            break;
+        }
+    }
+
+    for line in lines {
+        if let Some(hunk) = parse_hunk_header(line) {
+            hunks.push(hunk);
+            continue;
+        }
+        let Some(hunk) = hunks.last_mut() else { continue };
+        let (kind, content) = match line.split_at_checked(1) {
+            Some(("+", rest)) => (LineKind::Add, rest),
+            Some(("-", rest)) => (LineKind::Remove, rest),
+            Some((" ", rest)) => (LineKind::Context, rest),
+            // "\ No newline at end of file", or an empty context line
+            Some(("\\", _)) => continue,
+            _ => (LineKind::Context, ""),
+        };
+        match kind {
+            LineKind::Add => file.additions += 1,
+            LineKind::Remove => file.deletions += 1,
+            LineKind::Context => {}
+        }
+        hunk.lines.push(DiffLine { kind, content: content.to_string() });
+    }
+
+    let size: usize = hunks.iter().map(|hunk| hunk.lines.len()).sum();
+    if size > *budget {
+        file.truncated = true;
+        return;
+    }
+    *budget -= size;
+    file.hunks = hunks;
+}
+
+/**
+ * Parse `@@ -a,b +c,d @@ context`; a missing count means 1
+ */
+fn parse_hunk_header(line: &str) -> Option<Hunk> {
+    let rest = line.strip_prefix("@@ -")?;
+    let (ranges, _) = rest.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |range: &str| -> Option<(u32, u32)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_lines) = range(old)?;
+    let (new_start, new_lines) = range(new)?;
+    Some(Hunk {
+        header: line.to_string(),
+        old_start,
+        old_lines,
+        new_start,
+        new_lines,
+        lines: Vec::new(),
+    })
+}
+
+/**
+ * An untracked file as one all-added hunk
+ */
+fn untracked_file(dir: &Path, path: &str, budget: &mut usize) -> FileDiff {
+    let mut file = FileDiff {
+        path: path.to_string(),
+        old_path: None,
+        status: FileStatus::Untracked,
+        binary: false,
+        additions: 0,
+        deletions: 0,
+        hunks: Vec::new(),
+        truncated: false,
+    };
+
+    let full_path = dir.join(path);
+    let Ok(metadata) = std::fs::symlink_metadata(&full_path) else {
+        file.truncated = true;
+        return file;
+    };
+    // Like git, a symlink is the text of its target, never the file it points to
+    let read = if metadata.file_type().is_symlink() {
+        std::fs::read_link(&full_path).map(|target| target.into_os_string().into_encoded_bytes())
+    } else if metadata.is_file() && metadata.len() <= MAX_UNTRACKED_BYTES {
+        std::fs::read(&full_path)
+    } else {
+        file.truncated = true;
+        return file;
+    };
+    let Ok(bytes) = read else {
+        file.truncated = true;
+        return file;
+    };
+    // Same test as git: a NUL in the first 8000 bytes
+    if bytes.iter().take(8000).any(|&b| b == 0) {
+        file.binary = true;
+        return file;
+    }
+
+    let text = String::from_utf8_lossy(&bytes);
+    let lines: Vec<DiffLine> = text
+        .lines()
+        .map(|line| DiffLine { kind: LineKind::Add, content: line.to_string() })
+        .collect();
+    file.additions = lines.len();
+    if lines.is_empty() {
+        return file;
+    }
+    if lines.len() > *budget {
+        file.truncated = true;
+        return file;
+    }
+    *budget -= lines.len();
+    file.hunks.push(Hunk {
+        header: format!("@@ -0,0 +1,{} @@", lines.len()),
+        old_start: 0,
+        old_lines: 0,
+        new_start: 1,
+        new_lines: lines.len() as u32,
+        lines,
+    });
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn section(patch: &str) -> Vec<&str> {
+        split_sections(patch).remove(0)
+    }
+
+    fn file(path: &str) -> FileDiff {
+        parse_raw(&format!(":100644 100644 abc def M\0{}\0", path)).remove(0)
+    }
+
+    #[test]
+    fn parse_raw_statuses_and_renames() {
+        let raw = ":100644 100644 a b M\0src/main.rs\0\
+                   :000000 100644 0 b A\0new file.txt\0\
+                   :100644 000000 a 0 D\0gone.rs\0\
+                   :100644 100644 a b R087\0old.rs\0new.rs\0\
+                   :100644 100644 a b C100\0orig.rs\0copy.rs\0\
+                   :100644 120000 a b T\0link\0";
+        let files = parse_raw(raw);
+        let summary: Vec<(&str, Option<&str>, FileStatus)> = files
+            .iter()
+            .map(|f| (f.path.as_str(), f.old_path.as_deref(), f.status))
+            .collect();
+        assert_eq!(summary, vec![
+            ("src/main.rs", None, FileStatus::Modified),
+            ("new file.txt", None, FileStatus::Added),
+            ("gone.rs", None, FileStatus::Deleted),
+            ("new.rs", Some("old.rs"), FileStatus::Renamed),
+            ("copy.rs", Some("orig.rs"), FileStatus::Copied),
+            ("link", None, FileStatus::TypeChanged),
+        ]);
+        assert!(parse_raw("").is_empty());
+    }
+
+    #[test]
+    fn split_sections_by_diff_header() {
+        let patch = "diff --git a/a.rs b/a.rs\n@@ -1 +1 @@\n-a\n+b\ndiff --git a/b.rs b/b.rs\nold mode 100644\nnew mode 100755\n";
+        let sections = split_sections(patch);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].len(), 4);
+        assert_eq!(sections[1], vec!["diff --git a/b.rs b/b.rs", "old mode 100644", "new mode 100755"]);
+    }
+
+    #[test]
+    fn section_paths() {
+        let modified = "diff --git a/src/a.rs b/src/a.rs\nindex 1..2 100644\n--- a/src/a.rs\n+++ b/src/a.rs\n@@ -1 +1 @@\n";
+        assert_eq!(section_path(&section(modified)).as_deref(), Some("src/a.rs"));
+
+        let renamed = "diff --git a/old.rs b/new.rs\nsimilarity index 90%\nrename from old.rs\nrename to new.rs\n";
+        assert_eq!(section_path(&section(renamed)).as_deref(), Some("new.rs"));
+
+        let deleted = "diff --git a/gone.rs b/gone.rs\ndeleted file mode 100644\n--- a/gone.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n";
+        assert_eq!(section_path(&section(deleted)).as_deref(), Some("gone.rs"));
+
+        let mode_only = "diff --git a/run b/run\nold mode 100644\nnew mode 100755\n";
+        assert_eq!(section_path(&section(mode_only)).as_deref(), Some("run"));
+
+        let empty = "diff --git a/a b/a b/a b/a\nnew file mode 100644\nindex 0..e69de29\n";
+        assert_eq!(section_path(&section(empty)).as_deref(), Some("a b/a"));
+
+        let binary = "diff --git a/logo.png b/logo.png\nindex 1..2 100644\nBinary files a/logo.png and b/logo.png differ\n";
+        assert_eq!(section_path(&section(binary)).as_deref(), Some("logo.png"));
+
+        let spaced = "diff --git a/my file.txt b/my file.txt\n--- a/my file.txt\t\n+++ b/my file.txt\t\n@@ -1 +1 @@\n";
+        assert_eq!(section_path(&section(spaced)).as_deref(), Some("my file.txt"));
+
+        let quoted = "diff --git \"a/tab\\there\" \"b/tab\\there\"\nnew file mode 100644\n";
+        assert_eq!(section_path(&section(quoted)).as_deref(), Some("tab\there"));
+    }
+
+    #[test]
+    fn unquote_c_style_names() {
+        assert_eq!(unquote("plain.txt"), "plain.txt");
+        assert_eq!(unquote("\"a \\\"quoted\\\" \\\\ name\""), "a \"quoted\" \\ name");
+        assert_eq!(unquote("\"caf\\303\\251\""), "café");
+        assert_eq!(unquote("\"line\\nbreak\""), "line\nbreak");
+    }
+
+    #[test]
+    fn parse_hunk_headers() {
+        let hunk = parse_hunk_header("@@ -10,7 +12,8 @@ fn main() {").unwrap();
+        assert_eq!((hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines), (10, 7, 12, 8));
+        assert_eq!(hunk.header, "@@ -10,7 +12,8 @@ fn main() {");
+
+        // A missing count means one line
+        let hunk = parse_hunk_header("@@ -1 +1 @@").unwrap();
+        assert_eq!((hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines), (1, 1, 1, 1));
+        let hunk = parse_hunk_header("@@ -0,0 +1 @@").unwrap();
+        assert_eq!((hunk.old_lines, hunk.new_lines), (0, 1));
+
+        assert!(parse_hunk_header("@@ -x +1 @@").is_none());
+        assert!(parse_hunk_header("+@@ -1 +1 @@").is_none());
+    }
+
+    #[test]
+    fn apply_section_lines_and_no_newline_markers() {
+        let patch = "diff --git a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n keep\n-old\n\\ No newline at end of file\n+new\n\\ No newline at end of file\n@@ -9 +9,2 @@\n--- not a header\n++++ also content\n";
+        let mut diff = file("a.txt");
+        let mut budget = 100;
+        apply_section(&mut diff, section(patch), &mut budget);
+
+        assert_eq!(diff.hunks.len(), 2);
+        let kinds: Vec<(LineKind, &str)> = diff.hunks[0].lines.iter().map(|l| (l.kind, l.content.as_str())).collect();
+        assert_eq!(kinds, vec![(LineKind::Context, "keep"), (LineKind::Remove, "old"), (LineKind::Add, "new")]);
+        let kinds: Vec<(LineKind, &str)> = diff.hunks[1].lines.iter().map(|l| (l.kind, l.content.as_str())).collect();
+        assert_eq!(kinds, vec![(LineKind::Remove, "-- not a header"), (LineKind::Add, "+++ also content")]);
+        assert_eq!((diff.additions, diff.deletions), (2, 2));
+        assert_eq!(budget, 95);
+    }
+
+    #[test]
+    fn apply_section_binary() {
+        let patch = "diff --git a/logo.png b/logo.png\nindex 1..2 100644\nBinary files a/logo.png and b/logo.png differ\n";
+        let mut diff = file("logo.png");
+        apply_section(&mut diff, section(patch), &mut 100);
+        assert!(diff.binary);
+        assert!(diff.hunks.is_empty());
+    }
+
+    #[test]
+    fn apply_section_truncates_past_the_budget() {
+        let patch = "diff --git a/a.txt b/a.txt\n@@ -1,3 +1,3 @@\n-a\n-b\n-c\n+d\n+e\n+f\n";
+        let mut budget = 5;
+        let mut diff = file("a.txt");
+        apply_section(&mut diff, section(patch), &mut budget);
+        assert!(diff.truncated);
+        assert!(diff.hunks.is_empty());
+        // Counts stay right and the budget is left for smaller files
+        assert_eq!((diff.additions, diff.deletions), (3, 3));
+        assert_eq!(budget, 5);
+    }
+
+    fn git_in(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(status.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&status.stderr));
+    }
+
+    #[tokio::test]
+    async fn diff_matches_hunks_to_their_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git_in(root, &["init", "-q"]);
+        std::fs::write(root.join("run.sh"), "echo hi\n").unwrap();
+        std::fs::write(root.join("old name.txt"), "one\ntwo\nthree\nfour\nfive\n").unwrap();
+        std::fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+        git_in(root, &["add", "."]);
+        git_in(root, &["commit", "-q", "-m", "base"]);
+
+        // A mode-only change and an empty file come first and have no hunks
+        std::fs::set_permissions(root.join("run.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(root.join("empty"), "").unwrap();
+        git_in(root, &["mv", "old name.txt", "new name.txt"]);
+        std::fs::write(root.join("new name.txt"), "one\ntwo\nthree\nfour\nsix\n").unwrap();
+        std::fs::write(root.join("main.rs"), "fn main() { println!(\"hi\"); }\n").unwrap();
+        git_in(root, &["add", "-A"]);
+
+        let files = diff(root, "HEAD", DiffScope::All).await.unwrap();
+        let by_path = |path: &str| files.iter().find(|f| f.path == path).unwrap();
+
+        assert!(by_path("empty").hunks.is_empty());
+        assert_eq!(by_path("empty").status, FileStatus::Added);
+        assert!(by_path("run.sh").hunks.is_empty());
+
+        let renamed = by_path("new name.txt");
+        assert_eq!(renamed.status, FileStatus::Renamed);
+        assert_eq!(renamed.old_path.as_deref(), Some("old name.txt"));
+        assert_eq!((renamed.additions, renamed.deletions), (1, 1));
+
+        let main = by_path("main.rs");
+        assert_eq!(main.hunks.len(), 1);
+        assert_eq!(main.hunks[0].lines[1].content, "fn main() { println!(\"hi\"); }");
+    }
+}
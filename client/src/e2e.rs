@@ -0,0 +1,334 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Mixed into every key derivation so keys can't be confused across protocol versions
+const KDF_INFO: &[u8] = b"lychee-e2e-v1";
+
+/// Unambiguous base32 alphabet for the pairing code (no 0/O, 1/I)
+const PAIRING_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// 20 symbols of 5 bits = 100 bits, enough that the relay can't brute-force it offline
+const PAIRING_CODE_LEN: usize = 20;
+
+/// Browser nonces are a random sender id followed by its 8-byte big-endian message counter
+const SENDER_LEN: usize = 4;
+
+/// How far out of order a sender's messages may arrive before they count as replayed
+const REPLAY_WINDOW: u64 = 64;
+
+/**
+ * End-to-end encryption state for one `lychee up --e2e` run
+ *
+ * Pairing: the browser sends its X25519 public key (`e2e_hello`). The client
+ * answers with its own ephemeral public key and the group key wrapped under
+ * HKDF(DH shared secret, salt = pairing code). Only a browser that was given
+ * the pairing code out of band can unwrap the group key, so a relay that swaps
+ * public keys learns nothing.
+ *
+ * Payloads: every session message is then sealed with AES-256-GCM under the
+ * group key and sent as `nonce || ciphertext`, base64 encoded. Browsers build
+ * their nonces from a per-page sender id and a counter, so replays are caught
+ * with a small window per sender instead of remembering every nonce.
+ */
+pub struct E2eSession {
+    pairing_code: String,
+    group_key: [u8; 32],
+    cipher: Aes256Gcm,
+    // Counters of browser messages already opened, so the relay can't replay them
+    replay_windows: Mutex<HashMap<[u8; SENDER_LEN], ReplayWindow>>,
+}
+
+/// The counters one sender has used, near the highest one seen
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit i is set once counter `highest - i` was seen
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /**
+     * Record a counter, false if it was seen before or is too old to tell
+     */
+    fn accept(&mut self, counter: u64) -> bool {
+        match self.highest {
+            Some(highest) if counter <= highest => {
+                let age = highest - counter;
+                if age >= REPLAY_WINDOW || self.seen & (1 << age) != 0 {
+                    return false;
+                }
+                self.seen |= 1 << age;
+            }
+            Some(highest) => {
+                let shift = counter - highest;
+                self.seen = if shift >= REPLAY_WINDOW { 1 } else { (self.seen << shift) | 1 };
+                self.highest = Some(counter);
+            }
+            None => {
+                self.seen = 1;
+                self.highest = Some(counter);
+            }
+        }
+        true
+    }
+}
+
+impl E2eSession {
+    pub fn new() -> E2eSession {
+        let mut group_key = [0u8; 32];
+        OsRng.fill_bytes(&mut group_key);
+
+        let mut code = String::with_capacity(PAIRING_CODE_LEN + PAIRING_CODE_LEN / 5);
+        let mut random = [0u8; PAIRING_CODE_LEN];
+        OsRng.fill_bytes(&mut random);
+        for (i, byte) in random.iter().enumerate() {
+            if i > 0 && i % 5 == 0 {
+                code.push('-');
+            }
+            code.push(PAIRING_ALPHABET[(*byte & 0x1f) as usize] as char);
+        }
+
+        E2eSession {
+            pairing_code: code,
+            cipher: Aes256Gcm::new(&group_key.into()),
+            group_key,
+            replay_windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /**
+     * Code the user types into the browser to pair it with this client
+     */
+    pub fn pairing_code(&self) -> &str {
+        &self.pairing_code
+    }
+
+    /**
+     * Answer a browser's key exchange
+     * Returns (client public key, wrapped group key), both base64
+     */
+    pub fn welcome(&self, browser_key: &str) -> Result<(String, String), String> {
+        let browser_bytes: [u8; 32] = BASE64
+            .decode(browser_key)
+            .map_err(|_| "browser key is not valid base64".to_string())?
+            .try_into()
+            .map_err(|_| "browser key must be 32 bytes".to_string())?;
+        let browser_public = PublicKey::from(browser_bytes);
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let client_public = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&browser_public);
+
+        // Both public keys go into the info so a swapped key yields a different wrap key
+        let mut info = KDF_INFO.to_vec();
+        info.extend_from_slice(client_public.as_bytes());
+        info.extend_from_slice(browser_public.as_bytes());
+
+        let salt = normalize_pairing_code(&self.pairing_code);
+        let hkdf = Hkdf::<Sha256>::new(Some(salt.as_bytes()), shared.as_bytes());
+        let mut wrap_key = [0u8; 32];
+        hkdf.expand(&info, &mut wrap_key)
+            .map_err(|_| "key derivation failed".to_string())?;
+
+        let wrapped = seal_with(&Aes256Gcm::new(&wrap_key.into()), &self.group_key)?;
+        Ok((BASE64.encode(client_public.as_bytes()), wrapped))
+    }
+
+    /**
+     * Encrypt a serialized message for the browsers
+     */
+    pub fn seal(&self, plaintext: &str) -> Result<String, String> {
+        seal_with(&self.cipher, plaintext.as_bytes())
+    }
+
+    /**
+     * Decrypt a browser message, rejecting tampered or replayed payloads
+     */
+    pub fn open(&self, payload: &str) -> Result<String, String> {
+        let data = BASE64
+            .decode(payload)
+            .map_err(|_| "payload is not valid base64".to_string())?;
+        if data.len() < 12 {
+            return Err("payload too short".to_string());
+        }
+
+        let (nonce, ciphertext) = data.split_at(12);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "payload failed authentication".to_string())?;
+
+        let (sender, counter) = nonce.split_at(SENDER_LEN);
+        let sender: [u8; SENDER_LEN] = sender.try_into().unwrap();
+        let counter = u64::from_be_bytes(counter.try_into().unwrap());
+        if !self.replay_windows.lock().unwrap().entry(sender).or_default().accept(counter) {
+            return Err("replayed payload".to_string());
+        }
+
+        String::from_utf8(plaintext).map_err(|_| "payload is not UTF-8".to_string())
+    }
+}
+
+/**
+ * Whether a decrypted browser message is what its `encrypted` envelope says
+ *
+ * The relay gates, audits and routes on the envelope, so the payload must not
+ * be relabeled as something else.
+ */
+pub fn matches_envelope(inner: &Value, repo_path: &str, lychee_id: Option<&str>, message_type: &str) -> bool {
+    message_type != "encrypted"
+        && inner.get("type").and_then(Value::as_str) == Some(message_type)
+        && inner.get("repo_path").and_then(Value::as_str) == Some(repo_path)
+        && inner.get("lychee_id").and_then(Value::as_str) == lychee_id
+}
+
+fn seal_with(cipher: &Aes256Gcm, plaintext: &[u8]) -> Result<String, String> {
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| "encryption failed".to_string())?;
+
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(BASE64.encode(out))
+}
+
+/**
+ * Pairing codes are compared without dashes or case
+ */
+fn normalize_pairing_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Seal like a browser does, with a sender id and counter as the nonce
+    fn seal_as(session: &E2eSession, sender: [u8; SENDER_LEN], counter: u64, plaintext: &str) -> String {
+        let mut nonce = sender.to_vec();
+        nonce.extend_from_slice(&counter.to_be_bytes());
+        let ciphertext = session.cipher.encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes()).unwrap();
+        nonce.extend_from_slice(&ciphertext);
+        BASE64.encode(nonce)
+    }
+
+    #[test]
+    fn open_browser_messages() {
+        let session = E2eSession::new();
+        let payload = seal_as(&session, [1; SENDER_LEN], 0, "hello");
+        assert_eq!(session.open(&payload).unwrap(), "hello");
+
+        let mut tampered = BASE64.decode(seal_as(&session, [1; SENDER_LEN], 1, "hello")).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(session.open(&BASE64.encode(tampered)).is_err());
+        assert!(session.open("AAAA").is_err());
+        assert!(session.open("not base64!").is_err());
+
+        // Only the browsers' key opens it
+        let other = E2eSession::new();
+        assert!(other.open(&seal_as(&session, [1; SENDER_LEN], 2, "hello")).is_err());
+    }
+
+    #[test]
+    fn open_refuses_replays() {
+        let session = E2eSession::new();
+        let first = seal_as(&session, [1; SENDER_LEN], 0, "first");
+        assert!(session.open(&first).is_ok());
+        assert_eq!(session.open(&first).unwrap_err(), "replayed payload");
+
+        // Counters are per sender
+        assert!(session.open(&seal_as(&session, [2; SENDER_LEN], 0, "other")).is_ok());
+    }
+
+    #[test]
+    fn replay_window_takes_reordered_counters() {
+        let mut window = ReplayWindow::default();
+        assert!(window.accept(5));
+        assert!(window.accept(3));
+        assert!(window.accept(4));
+        assert!(!window.accept(3));
+        assert!(window.accept(5 + REPLAY_WINDOW));
+        assert!(!window.accept(5));
+        assert!(!window.accept(5 + REPLAY_WINDOW));
+        assert!(window.accept(6 + REPLAY_WINDOW));
+        assert!(window.accept(7));
+        assert!(!window.accept(6));
+    }
+
+    #[test]
+    fn seal_round_trips() {
+        let session = E2eSession::new();
+        let sealed = BASE64.decode(session.seal("hello").unwrap()).unwrap();
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let plaintext = session.cipher.decrypt(Nonce::from_slice(nonce), ciphertext).unwrap();
+        assert_eq!(plaintext, b"hello");
+    }
+
+    #[test]
+    fn welcome_wraps_the_group_key_under_the_pairing_code() {
+        let session = E2eSession::new();
+        let browser = EphemeralSecret::random_from_rng(OsRng);
+        let browser_public = PublicKey::from(&browser);
+        let (client_key, wrapped) = session.welcome(&BASE64.encode(browser_public.as_bytes())).unwrap();
+
+        let client_public: [u8; 32] = BASE64.decode(client_key).unwrap().try_into().unwrap();
+        let shared = browser.diffie_hellman(&PublicKey::from(client_public));
+        let mut info = KDF_INFO.to_vec();
+        info.extend_from_slice(&client_public);
+        info.extend_from_slice(browser_public.as_bytes());
+        let unwrap = |code: &str| {
+            let hkdf = Hkdf::<Sha256>::new(Some(normalize_pairing_code(code).as_bytes()), shared.as_bytes());
+            let mut wrap_key = [0u8; 32];
+            hkdf.expand(&info, &mut wrap_key).unwrap();
+            let wrapped = BASE64.decode(&wrapped).unwrap();
+            let (nonce, ciphertext) = wrapped.split_at(12);
+            Aes256Gcm::new(&wrap_key.into()).decrypt(Nonce::from_slice(nonce), ciphertext)
+        };
+
+        let typed = session.pairing_code().to_lowercase().replace('-', " ");
+        assert_eq!(unwrap(&typed).unwrap(), session.group_key);
+        assert!(unwrap("AAAAA-AAAAA-AAAAA-AAAAA").is_err());
+        assert!(session.welcome("short").is_err());
+    }
+
+    #[test]
+    fn pairing_codes_are_grouped_and_unambiguous() {
+        let code = E2eSession::new().pairing_code().to_string();
+        assert_eq!(code.len(), PAIRING_CODE_LEN + 3);
+        assert!(code.split('-').all(|group| group.len() == 5));
+        assert!(code.chars().all(|c| c == '-' || PAIRING_ALPHABET.contains(&(c as u8))));
+    }
+
+    #[test]
+    fn envelopes_must_match_their_payload() {
+        let inner = json!({"type": "send_message", "repo_path": "/repo", "lychee_id": "a", "content": "hi"});
+        assert!(matches_envelope(&inner, "/repo", Some("a"), "send_message"));
+        assert!(!matches_envelope(&inner, "/repo", Some("a"), "list_sessions"));
+        assert!(!matches_envelope(&inner, "/other", Some("a"), "send_message"));
+        assert!(!matches_envelope(&inner, "/repo", Some("b"), "send_message"));
+        assert!(!matches_envelope(&inner, "/repo", None, "send_message"));
+
+        let new_session = json!({"type": "send_message", "repo_path": "/repo", "lychee_id": null});
+        assert!(matches_envelope(&new_session, "/repo", None, "send_message"));
+        let list = json!({"type": "list_sessions", "repo_path": "/repo"});
+        assert!(matches_envelope(&list, "/repo", None, "list_sessions"));
+
+        let nested = json!({"type": "encrypted", "repo_path": "/repo"});
+        assert!(!matches_envelope(&nested, "/repo", None, "encrypted"));
+    }
+}
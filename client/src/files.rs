@@ -0,0 +1,498 @@
+use crate::worktree::git;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Entries returned for one directory
+const MAX_DIR_ENTRIES: usize = 5000;
+
+/// Bytes returned by one read; larger files are read in ranges
+pub const MAX_READ_BYTES: u64 = 1024 * 1024;
+
+/// Largest file write_file accepts
+const MAX_WRITE_BYTES: usize = 1024 * 1024;
+
+/// Bytes looked at to tell binary from text, like git does
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+/// Git's own files and lychee's session metadata and worktrees
+const PRIVATE_DIRS: &[&str] = &[".git", ".lychee"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    /// Files only
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirListing {
+    pub entries: Vec<DirEntry>,
+    /// More entries than MAX_DIR_ENTRIES
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileContent {
+    /// Size of the whole file
+    pub size: u64,
+    pub offset: u64,
+    pub binary: bool,
+    /// Text as UTF-8, binary files as base64
+    pub encoding: ContentEncoding,
+    pub content: String,
+    /// The file goes on past this range
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileStat {
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified: Option<String>,
+    /// Files only
+    pub binary: Option<bool>,
+    /// Hex SHA-256 of a file's content, what write_file expects back; files
+    /// over MAX_READ_BYTES aren't hashed and are checked by `modified` instead
+    pub sha256: Option<String>,
+}
+
+/// What a writer believes the file is; all None means it must not exist yet
+#[derive(Debug, Clone, Default)]
+pub struct Expected {
+    pub sha256: Option<String>,
+    /// RFC 3339, as FileStat gives it
+    pub modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    /// The file is not what the writer expected
+    Conflict(String),
+    Failed(String),
+}
+
+impl From<String> for WriteError {
+    fn from(message: String) -> Self {
+        WriteError::Failed(message)
+    }
+}
+
+/**
+ * Resolve a browser supplied path inside `root`
+ *
+ * Relative paths are taken from `root`; absolute ones, as tool calls show
+ * them, must point inside it. The result is canonical, so neither `..` nor
+ * symlinks lead out, and never inside `.git` or `.lychee`. Returns the
+ * canonical path and its `/`-separated path relative to root.
+ */
+pub fn resolve(root: &Path, path: &str) -> Result<(PathBuf, String), String> {
+    let root = root
+        .canonicalize()
+        .map_err(|e| format!("Session directory unavailable: {}", e))?;
+    let requested = Path::new(path);
+    let joined = if requested.is_absolute() { requested.to_path_buf() } else { root.join(requested) };
+
+    let resolved = joined.canonicalize().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => format!("No such file: {}", path),
+        _ => format!("{}: {}", path, e),
+    })?;
+    let Ok(relative) = resolved.strip_prefix(&root) else {
+        return Err(format!("{} is outside the session directory", path));
+    };
+    if let Some(dir) = relative.components().find_map(|c| match c {
+        Component::Normal(name) if PRIVATE_DIRS.iter().any(|dir| name == *dir) => Some(name.to_string_lossy()),
+        _ => None,
+    }) {
+        return Err(format!("{} is inside {}", path, dir));
+    }
+
+    let relative = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    Ok((resolved, relative))
+}
+
+/**
+ * `resolve` for a path that may not exist yet
+ *
+ * A missing file is resolved through its parent directory, which must exist.
+ */
+pub fn resolve_for_write(root: &Path, path: &str) -> Result<(PathBuf, String), String> {
+    let requested = Path::new(path);
+    let joined = if requested.is_absolute() { requested.to_path_buf() } else { root.join(requested) };
+    if joined.symlink_metadata().is_ok() {
+        return resolve(root, path);
+    }
+
+    let name = match joined.components().next_back() {
+        Some(Component::Normal(name)) if !PRIVATE_DIRS.iter().any(|dir| name == *dir) => name.to_os_string(),
+        _ => return Err(format!("{} is not a file path", path)),
+    };
+    let parent = requested.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    let (parent, relative) = resolve(root, &parent)?;
+    let name = name.to_string_lossy();
+    let relative = if relative.is_empty() { name.to_string() } else { format!("{}/{}", relative, name) };
+    Ok((parent.join(name.as_ref()), relative))
+}
+
+/**
+ * Whether git ignores a path; outside a git repository nothing is
+ */
+pub async fn is_ignored(root: &Path, relative: &str) -> bool {
+    !relative.is_empty() && git(root, &["check-ignore", "--quiet", "--", relative]).await.is_ok()
+}
+
+/**
+ * `resolve`, refusing paths git ignores
+ */
+pub async fn resolve_visible(root: &Path, path: &str) -> Result<(PathBuf, String), String> {
+    let (resolved, relative) = resolve(root, path)?;
+    if is_ignored(root, &relative).await {
+        return Err(format!("{} is ignored", path));
+    }
+    Ok((resolved, relative))
+}
+
+/**
+ * `resolve_for_write`, refusing paths git ignores
+ */
+pub async fn resolve_visible_for_write(root: &Path, path: &str) -> Result<(PathBuf, String), String> {
+    let (resolved, relative) = resolve_for_write(root, path)?;
+    if is_ignored(root, &relative).await {
+        return Err(format!("{} is ignored", path));
+    }
+    Ok((resolved, relative))
+}
+
+/**
+ * Entries of a directory, minus what .gitignore hides, directories first
+ */
+pub fn list_dir(dir: &Path) -> Result<DirListing, String> {
+    let walker = ignore::WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .hidden(false)
+        .parents(true)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    let mut entries = Vec::new();
+    let mut truncated = false;
+    // The first entry is the directory itself
+    for entry in walker.skip(1) {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entries.len() == MAX_DIR_ENTRIES {
+            truncated = true;
+            break;
+        }
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        let kind = entry_kind(&metadata);
+        entries.push(DirEntry {
+            name: entry.file_name().to_string_lossy().to_string(),
+            kind,
+            size: (kind == EntryKind::File).then_some(metadata.len()),
+        });
+    }
+
+    entries.sort_by(|a, b| (a.kind != EntryKind::Dir, &a.name).cmp(&(b.kind != EntryKind::Dir, &b.name)));
+    Ok(DirListing { entries, truncated })
+}
+
+/**
+ * Read up to `length` bytes from `offset`, capped at MAX_READ_BYTES
+ * Text ranges end on a character boundary, so the next read starts at
+ * `offset` plus the byte length of the returned content
+ */
+pub fn read_file(path: &Path, offset: u64, length: Option<u64>) -> Result<FileContent, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let metadata = file.metadata().map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err("Not a regular file".to_string());
+    }
+    let size = metadata.len();
+    let binary = sniff_binary(&mut file)?;
+
+    let offset = offset.min(size);
+    let length = length.unwrap_or(MAX_READ_BYTES).min(MAX_READ_BYTES).min(size - offset);
+    let mut bytes = Vec::with_capacity(length as usize);
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    file.take(length).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+
+    let (encoding, content, read) = if binary {
+        let content = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        (ContentEncoding::Base64, content, bytes.len())
+    } else {
+        // Leave a character cut by the range end for the next read
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(_) => bytes.len(),
+            Err(e) if e.error_len().is_none() && e.valid_up_to() > 0 => e.valid_up_to(),
+            Err(_) => bytes.len(),
+        };
+        let content = String::from_utf8_lossy(&bytes[..valid]).to_string();
+        (ContentEncoding::Utf8, content, valid)
+    };
+
+    Ok(FileContent {
+        size,
+        offset,
+        binary,
+        encoding,
+        content,
+        truncated: offset + (read as u64) < size,
+    })
+}
+
+pub fn stat(path: &Path) -> Result<FileStat, String> {
+    let metadata = path.metadata().map_err(|e| e.to_string())?;
+    let kind = entry_kind(&metadata);
+    let (binary, sha256) = if kind == EntryKind::File {
+        let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let binary = sniff_binary(&mut file)?;
+        let sha256 = if metadata.len() <= MAX_READ_BYTES {
+            file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut file, &mut hasher).map_err(|e| e.to_string())?;
+            Some(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+        } else {
+            None
+        };
+        (Some(binary), sha256)
+    } else {
+        (None, None)
+    };
+
+    Ok(FileStat {
+        kind,
+        size: metadata.len(),
+        modified: metadata.modified().ok().map(format_time),
+        binary,
+        sha256,
+    })
+}
+
+/**
+ * Replace a file's content, if it is still what the writer expected
+ *
+ * The content goes to a temporary file next to the target, which is renamed
+ * over it, so readers see the old file or the new one and never a mix. An
+ * existing file keeps its permissions.
+ */
+pub fn write_file(path: &Path, content: &[u8], expected: &Expected) -> Result<FileStat, WriteError> {
+    if content.len() > MAX_WRITE_BYTES {
+        return Err(WriteError::Failed(format!("Files over {} bytes can't be written", MAX_WRITE_BYTES)));
+    }
+
+    let current = match path.metadata() {
+        Ok(_) => Some(stat(path)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(WriteError::Failed(e.to_string())),
+    };
+    check_expected(current.as_ref(), expected)?;
+
+    let dir = path.parent().ok_or_else(|| "No parent directory".to_string())?;
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let temp_path = dir.join(format!(".{}.lychee-{}.tmp", name, uuid::Uuid::new_v4()));
+    let written = write_temp(&temp_path, content, path).and_then(|()| {
+        std::fs::rename(&temp_path, path).map_err(|e| e.to_string())
+    });
+    if let Err(message) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(WriteError::Failed(message));
+    }
+
+    Ok(stat(path)?)
+}
+
+/**
+ * Bytes of write_file content sent with `encoding`
+ */
+pub fn decode_content(content: String, encoding: ContentEncoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        ContentEncoding::Utf8 => Ok(content.into_bytes()),
+        ContentEncoding::Base64 => base64::engine::general_purpose::STANDARD
+            .decode(content)
+            .map_err(|e| format!("Invalid base64: {}", e)),
+    }
+}
+
+fn check_expected(current: Option<&FileStat>, expected: &Expected) -> Result<(), WriteError> {
+    let Some(current) = current else {
+        if expected.sha256.is_some() || expected.modified.is_some() {
+            return Err(WriteError::Conflict("The file was deleted".to_string()));
+        }
+        return Ok(());
+    };
+    if current.kind != EntryKind::File {
+        return Err(WriteError::Failed("Not a regular file".to_string()));
+    }
+
+    if let Some(sha256) = &expected.sha256 {
+        if current.sha256.as_deref() != Some(sha256.to_lowercase().as_str()) {
+            return Err(WriteError::Conflict("The file changed since it was read".to_string()));
+        }
+    } else if let Some(modified) = &expected.modified {
+        let expected = chrono::DateTime::parse_from_rfc3339(modified)
+            .map_err(|e| WriteError::Failed(format!("Bad expected_modified: {}", e)))?;
+        let actual = current.modified.as_deref().and_then(|m| chrono::DateTime::parse_from_rfc3339(m).ok());
+        if actual != Some(expected) {
+            return Err(WriteError::Conflict("The file changed since it was read".to_string()));
+        }
+    } else {
+        return Err(WriteError::Conflict("The file already exists".to_string()));
+    }
+    Ok(())
+}
+
+fn write_temp(temp_path: &Path, content: &[u8], target: &Path) -> Result<(), String> {
+    let mut file = std::fs::File::create_new(temp_path).map_err(|e| e.to_string())?;
+    file.write_all(content).map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())?;
+    if let Ok(metadata) = target.metadata() {
+        std::fs::set_permissions(temp_path, metadata.permissions()).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn format_time(time: std::time::SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339()
+}
+
+fn entry_kind(metadata: &std::fs::Metadata) -> EntryKind {
+    if metadata.is_symlink() {
+        EntryKind::Symlink
+    } else if metadata.is_dir() {
+        EntryKind::Dir
+    } else {
+        EntryKind::File
+    }
+}
+
+/**
+ * A NUL byte near the start means binary
+ */
+fn sniff_binary(file: &mut std::fs::File) -> Result<bool, String> {
+    let mut head = Vec::with_capacity(BINARY_SNIFF_BYTES);
+    file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    Read::by_ref(file)
+        .take(BINARY_SNIFF_BYTES as u64)
+        .read_to_end(&mut head)
+        .map_err(|e| e.to_string())?;
+    Ok(head.contains(&0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A session root with `src/main.rs`, next to a file outside it
+    fn session() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join(".lychee")).unwrap();
+        std::fs::write(root.join("src").join("main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join(".lychee").join(".session-info.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("secret"), "outside").unwrap();
+        (dir, root)
+    }
+
+    #[test]
+    fn resolve_relative_and_absolute_paths_inside_root() {
+        let (_dir, root) = session();
+        let (path, relative) = resolve(&root, "src/main.rs").unwrap();
+        assert_eq!(relative, "src/main.rs");
+        assert_eq!(path, root.canonicalize().unwrap().join("src").join("main.rs"));
+
+        let absolute = root.canonicalize().unwrap().join("src/main.rs");
+        assert_eq!(resolve(&root, &absolute.to_string_lossy()).unwrap().1, "src/main.rs");
+        assert_eq!(resolve(&root, "src/../src/main.rs").unwrap().1, "src/main.rs");
+        assert_eq!(resolve(&root, "").unwrap().1, "");
+    }
+
+    #[test]
+    fn resolve_refuses_paths_out_of_root() {
+        let (dir, root) = session();
+        assert!(resolve(&root, "../secret").is_err());
+        assert!(resolve(&root, "src/../../secret").is_err());
+        assert!(resolve(&root, &dir.path().join("secret").to_string_lossy()).is_err());
+        assert!(resolve(&root, "/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn resolve_refuses_private_dirs() {
+        let (_dir, root) = session();
+        assert!(resolve(&root, ".git").is_err());
+        assert!(resolve(&root, ".lychee/.session-info.json").is_err());
+        assert!(resolve(&root, "src/../.lychee").is_err());
+        assert!(resolve_for_write(&root, ".lychee/.session-info.json").is_err());
+        assert!(resolve_for_write(&root, ".lychee/new.json").is_err());
+        assert!(resolve_for_write(&root, "src/.git").is_err());
+    }
+
+    #[test]
+    fn resolve_follows_symlinks_only_inside_root() {
+        let (dir, root) = session();
+        symlink(root.join("src").join("main.rs"), root.join("inside")).unwrap();
+        symlink(dir.path().join("secret"), root.join("outside")).unwrap();
+        symlink(dir.path(), root.join("parent")).unwrap();
+        symlink(root.join(".lychee"), root.join("metadata")).unwrap();
+
+        assert_eq!(resolve(&root, "inside").unwrap().1, "src/main.rs");
+        assert!(resolve(&root, "outside").is_err());
+        assert!(resolve(&root, "parent/secret").is_err());
+        assert!(resolve(&root, "metadata/.session-info.json").is_err());
+        assert!(resolve_for_write(&root, "outside").is_err());
+        assert!(resolve_for_write(&root, "parent/new.txt").is_err());
+    }
+
+    #[test]
+    fn resolve_dangling_symlinks() {
+        let (dir, root) = session();
+        symlink(root.join("missing"), root.join("dangling-inside")).unwrap();
+        symlink(dir.path().join("missing"), root.join("dangling-outside")).unwrap();
+
+        assert!(resolve(&root, "dangling-inside").is_err());
+        // Writing through a dangling link would create its target, wherever that is
+        assert!(resolve_for_write(&root, "dangling-inside").is_err());
+        assert!(resolve_for_write(&root, "dangling-outside").is_err());
+    }
+
+    #[test]
+    fn resolve_for_write_new_files() {
+        let (_dir, root) = session();
+        let (path, relative) = resolve_for_write(&root, "src/new.rs").unwrap();
+        assert_eq!(relative, "src/new.rs");
+        assert_eq!(path, root.canonicalize().unwrap().join("src").join("new.rs"));
+        assert_eq!(resolve_for_write(&root, "new.txt").unwrap().1, "new.txt");
+        assert_eq!(resolve_for_write(&root, "src/main.rs").unwrap().1, "src/main.rs");
+
+        assert!(resolve_for_write(&root, "missing/new.rs").is_err());
+        assert!(resolve_for_write(&root, "../new.txt").is_err());
+        assert!(resolve_for_write(&root, "/tmp/new.txt").is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use clap::ValueEnum;
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+/**
+ * Set up tracing for `lychee up`
+ *
+ * With the TUI running nothing may be written to the terminal, so logs only
+ * go to `log_dir` (if set); without it they go to stderr. --debug only lowers
+ * the default level. The returned guard must be kept alive for file logs to be
+ * flushed.
+ */
+pub fn init(
+    tui: bool,
+    debug: bool,
+    level: Option<&str>,
+    format: LogFormat,
+    log_dir: Option<PathBuf>,
+) -> Option<WorkerGuard> {
+    let default_filter = if debug { "lychee=debug,info" } else { "info" };
+    let filter = level
+        .and_then(|l| EnvFilter::try_new(l).ok())
+        .unwrap_or_else(|| EnvFilter::new(default_filter));
+    let json = format == LogFormat::Json;
+
+    let stderr_layer = (!tui).then(|| {
+        if json {
+            fmt::layer().json().with_writer(std::io::stderr).boxed()
+        } else {
+            fmt::layer().with_writer(std::io::stderr).boxed()
+        }
+    });
+
+    let (file_layer, guard) = match log_dir {
+        Some(dir) => {
+            let appender = tracing_appender::rolling::daily(dir, "lychee.log");
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = if json {
+                fmt::layer().json().with_writer(writer).boxed()
+            } else {
+                fmt::layer().with_ansi(false).with_writer(writer).boxed()
+            };
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr_layer)
+        .with(file_layer)
+        .init();
+
+    guard
+}
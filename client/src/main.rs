@@ -1,22 +1,50 @@
-use clap::{Parser, Subcommand};
+mod backend;
+mod commands;
+mod config;
+mod diff;
+mod files;
+mod e2e;
+mod logging;
+mod mock;
+mod permissions;
+mod pty;
+mod replay;
+mod tunnel;
+mod watch;
+mod worktree;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use backend::{
+    AgentBackend, AgentHandle, ClaudeBackend, CommandBackend, CommandBackendConfig, PartialMessages, RunningSession, SessionRequest,
+    SessionSettings, TurnRequest,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::Config;
 use crossterm::{
     cursor,
     style::{Color, Print, ResetColor, SetForegroundColor},
     terminal::{self, ClearType},
     ExecutableCommand,
 };
+use e2e::E2eSession;
+use permissions::{Decision, Fallback, PermissionBroker, PermissionMode, Scope};
+use diff::{DiffScope, FileDiff};
+use files::{ContentEncoding, WriteError};
+use worktree::{FinishOutcome, FinishStage, FinishStatus, FinishStrategy};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{stdout, Write as IoWrite, BufRead};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::{mpsc, RwLock};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::AUTHORIZATION;
 use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+use tracing::Instrument;
 use uuid::Uuid;
 
 #[derive(Parser)]
@@ -31,25 +59,142 @@ struct Cli {
 enum Commands {
     /// Start the client and connect to relay
     Up {
-        #[arg(short, long, help = "Enable debug output")]
+        #[arg(short, long, help = "Log at debug level, like --log-level lychee=debug,info")]
         debug: bool,
+        #[arg(long, help = "Show logs on the terminal instead of the TUI")]
+        no_tui: bool,
+        #[arg(long, default_value_t = 30, help = "Seconds to wait for running agents on shutdown before cancelling them")]
+        shutdown_timeout: u64,
+        #[arg(long, default_value_t = 600, help = "Seconds before an idle agent session process is stopped (the next message restarts it)")]
+        idle_timeout: u64,
+        #[arg(long, help = "Log filter, e.g. \"info\" or \"lychee=trace\" (default: info, or debug with --debug)")]
+        log_level: Option<String>,
+        #[arg(long, value_enum, default_value = "pretty", help = "Log output format")]
+        log_format: logging::LogFormat,
+        #[arg(long, help = "Also write logs to daily-rotated files in this directory")]
+        log_dir: Option<PathBuf>,
+        #[arg(long, help = "Encrypt session traffic end-to-end so the relay only sees routing info")]
+        e2e: bool,
+        #[arg(long, value_enum, default_value = "claude", help = "Agent that runs turns (mock needs no claude binary or credits)")]
+        backend: Backend,
+        #[arg(long, required_if_eq("backend", "command"), help = "Program to run with --backend command")]
+        agent_command: Option<String>,
+        #[arg(long = "agent-arg", allow_hyphen_values = true, help = "Argument for a new conversation, repeatable; {prompt}, {model} and {cwd} are substituted")]
+        agent_args: Vec<String>,
+        #[arg(long = "agent-resume-arg", allow_hyphen_values = true, help = "Argument to continue a conversation, repeatable; also substitutes {session_id} (default: --agent-arg)")]
+        agent_resume_args: Vec<String>,
+        #[arg(long, default_value = "{home}/.claude/projects/{project}/{session_id}.jsonl", help = "Transcript path template for --backend command")]
+        agent_transcript: String,
+        #[arg(long, value_enum, default_value = "skip", help = "Whether tool uses need approval from a browser")]
+        permission_mode: PermissionMode,
+        #[arg(long, default_value_t = 120, help = "Seconds a tool approval waits while no browser has its session open")]
+        permission_timeout: u64,
+        #[arg(long, value_enum, default_value = "deny", help = "Answer to tool approvals nobody answered in time")]
+        permission_fallback: Fallback,
+    },
+    /// Impersonate a client by replaying a relay recording (RELAY_RECORD_DIR)
+    Replay {
+        #[arg(help = "Client recording file written by the relay")]
+        recording: PathBuf,
+        #[arg(long, default_value_t = 1.0, help = "Playback speed multiplier, e.g. 10 for ten times faster")]
+        speed: f64,
+        #[arg(long, help = "Register under this repo path instead of the recorded one")]
+        repo_path: Option<String>,
+        #[arg(long, help = "Start replaying once a browser sends its first request")]
+        wait_for_browser: bool,
+    },
+    /// Show or change the configuration (~/.config/lychee/config.toml, .lychee/config.toml)
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Tool approval server that Claude starts in --permission-mode prompt
+    #[command(hide = true)]
+    PermissionMcp {
+        #[arg(long)]
+        addr: std::net::SocketAddr,
+        #[arg(long)]
+        session: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective configuration for this repo
+    Show,
+    /// Print one effective value, e.g. `relay.url`
+    Get { key: String },
+    /// Set a value in the user config, or the repo config with --repo
+    Set {
+        key: String,
+        #[arg(help = "TOML value such as 3, true or [\"a\", \"b\"]; anything else is a string")]
+        value: String,
+        #[arg(long, help = "Write .lychee/config.toml instead of the user config; only models.default, worktree, commands.targets and tui")]
+        repo: bool,
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    /// The `claude` CLI
+    Claude,
+    /// In-process fake that writes canned transcripts (see mock.rs)
+    Mock,
+    /// Any Claude-compatible CLI agent, see --agent-command
+    Command,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
 enum Message {
     // Registration
     #[serde(rename = "register_client")]
-    RegisterClient { repo_path: String, repo_name: String },
+    RegisterClient {
+        repo_path: String,
+        repo_name: String,
+        #[serde(default)]
+        e2e: bool,
+    },
+
+    // End-to-end encryption (see e2e.rs)
+    #[serde(rename = "e2e_hello")]
+    E2eHello { repo_path: String, browser_key: String },
+    #[serde(rename = "e2e_welcome")]
+    E2eWelcome {
+        repo_path: String,
+        browser_key: String,
+        client_key: String,
+        wrapped_key: String,
+    },
+    #[serde(rename = "encrypted")]
+    Encrypted {
+        repo_path: String,
+        lychee_id: Option<String>,
+        message_type: String,
+        payload: String,
+    },
 
     // Browser -> Client requests
     #[serde(rename = "list_sessions")]
-    ListSessions { repo_path: String },
+    ListSessions {
+        repo_path: String,
+        /// Also send archived sessions, in `archived_sessions`
+        #[serde(default)]
+        include_archived: bool,
+    },
     #[serde(rename = "create_session")]
     CreateSession { repo_path: String },
     #[serde(rename = "create_worktree_session")]
-    CreateWorktreeSession { repo_path: String },
+    CreateWorktreeSession {
+        repo_path: String,
+        /// Branch, tag or commit to start from; the configured base branch or HEAD when unset
+        #[serde(default)]
+        base_ref: Option<String>,
+        /// Defaults to `lychee/<lychee_id>`
+        #[serde(default)]
+        branch: Option<String>,
+    },
     #[serde(rename = "load_session")]
     LoadSession { repo_path: String, lychee_id: String },
     #[serde(rename = "send_message")]
@@ -57,7 +202,38 @@ enum Message {
         repo_path: String,
         lychee_id: String,
         content: String,
-        model: String,
+        /// Falls back to the session's default model
+        #[serde(default)]
+        model: Option<String>,
+    },
+    #[serde(rename = "update_session_settings")]
+    UpdateSessionSettings {
+        repo_path: String,
+        lychee_id: String,
+        settings: SessionSettings,
+    },
+    #[serde(rename = "rename_session")]
+    RenameSession {
+        repo_path: String,
+        lychee_id: String,
+        /// Empty clears it, so the next turn derives one again
+        title: String,
+    },
+    #[serde(rename = "archive_session")]
+    ArchiveSession {
+        repo_path: String,
+        lychee_id: String,
+        /// False restores an archived session
+        #[serde(default = "default_true")]
+        archived: bool,
+    },
+    #[serde(rename = "delete_session")]
+    DeleteSession {
+        repo_path: String,
+        lychee_id: String,
+        /// Discard uncommitted changes and unmerged commits, stop a running turn
+        #[serde(default)]
+        force: bool,
     },
 
     // Client -> Browser responses
@@ -66,6 +242,169 @@ enum Message {
         repo_path: String,
         sessions: Vec<SessionInfo>,
         active_session_ids: Option<Vec<String>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        archived_sessions: Option<Vec<SessionInfo>>,
+    },
+    #[serde(rename = "session_deleted")]
+    SessionDeleted {
+        repo_path: String,
+        lychee_id: String,
+        /// Set when cleanup was partial, e.g. an unmerged branch was kept
+        warning: Option<String>,
+    },
+    #[serde(rename = "finish_worktree_session")]
+    FinishWorktreeSession {
+        repo_path: String,
+        lychee_id: String,
+        strategy: FinishStrategy,
+        /// Commit outstanding changes first instead of refusing
+        #[serde(default)]
+        auto_commit: bool,
+        /// For the auto, squash or merge commit; derived from the session title when unset
+        #[serde(default)]
+        commit_message: Option<String>,
+    },
+    #[serde(rename = "get_diff")]
+    GetDiff {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default)]
+        scope: DiffScope,
+    },
+    #[serde(rename = "session_diff")]
+    SessionDiff {
+        repo_path: String,
+        lychee_id: String,
+        scope: DiffScope,
+        /// Commit `all` and `committed` compare against
+        base: String,
+        files: Vec<FileDiff>,
+    },
+    #[serde(rename = "list_dir")]
+    ListDir {
+        repo_path: String,
+        lychee_id: String,
+        /// Relative to the session's working directory, or absolute inside it
+        #[serde(default)]
+        path: String,
+    },
+    #[serde(rename = "read_file")]
+    ReadFile {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(default)]
+        offset: u64,
+        /// Capped at files::MAX_READ_BYTES
+        #[serde(default)]
+        length: Option<u64>,
+    },
+    #[serde(rename = "stat")]
+    Stat {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+    },
+    #[serde(rename = "dir_listing")]
+    DirListing {
+        repo_path: String,
+        lychee_id: String,
+        /// Relative to the session's working directory
+        path: String,
+        #[serde(flatten)]
+        listing: files::DirListing,
+    },
+    #[serde(rename = "file_content")]
+    FileContent {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        content: files::FileContent,
+    },
+    #[serde(rename = "file_stat")]
+    FileStat {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        stat: files::FileStat,
+    },
+    /// A list_dir, read_file, stat or write_file that failed; `path` is as requested
+    #[serde(rename = "file_error")]
+    FileError {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        message: String,
+    },
+    /// Replace a file's content, or create it
+    #[serde(rename = "write_file")]
+    WriteFile {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        content: String,
+        #[serde(default)]
+        encoding: ContentEncoding,
+        /// The file's sha256 from file_stat; with expected_modified, both None means create
+        #[serde(default)]
+        expected_sha256: Option<String>,
+        /// The file's modified time from file_stat, used when no hash is given
+        #[serde(default)]
+        expected_modified: Option<String>,
+        /// Write even while an agent turn is running in the session
+        #[serde(default)]
+        force: bool,
+    },
+    #[serde(rename = "file_written")]
+    FileWritten {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        stat: files::FileStat,
+        /// The file was written, but the edit didn't make it into the session's history
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        warning: Option<String>,
+    },
+    /// A write_file refused; it can be retried with `current`, or forced
+    #[serde(rename = "file_conflict")]
+    FileConflict {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        reason: ConflictReason,
+        message: String,
+        /// The file as it is now, None if it doesn't exist
+        current: Option<files::FileStat>,
+    },
+    #[serde(rename = "finish_progress")]
+    FinishProgress {
+        repo_path: String,
+        lychee_id: String,
+        stage: FinishStage,
+    },
+    #[serde(rename = "finish_result")]
+    FinishResult {
+        repo_path: String,
+        lychee_id: String,
+        strategy: FinishStrategy,
+        status: FinishStatus,
+        base_branch: Option<String>,
+        /// New tip of the base branch
+        commit: Option<String>,
+        /// Conflicting or uncommitted files, depending on status
+        files: Vec<String>,
+        message: Option<String>,
+    },
+    #[serde(rename = "session_delete_blocked")]
+    SessionDeleteBlocked {
+        repo_path: String,
+        lychee_id: String,
+        reason: String,
+        /// Files that would lose uncommitted changes
+        uncommitted_files: Vec<String>,
     },
     #[serde(rename = "session_created")]
     SessionCreated {
@@ -109,6 +448,261 @@ enum Message {
     ClientCount {
         count: usize,
     },
+    /// Sessions browsers are watching, from the relay; replaces the previous list
+    #[serde(rename = "watched_sessions")]
+    WatchedSessions {
+        lychee_ids: Vec<String>,
+    },
+    /// Debounced changes in a watched session's working directory
+    #[serde(rename = "files_changed")]
+    FilesChanged {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(flatten)]
+        batch: watch::ChangeBatch,
+    },
+
+    // Terminals (see pty.rs)
+    #[serde(rename = "open_terminal")]
+    OpenTerminal {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(flatten)]
+        size: pty::TerminalSize,
+    },
+    #[serde(rename = "terminal_opened")]
+    TerminalOpened {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+        shell: String,
+    },
+    /// Keystrokes or pasted text
+    #[serde(rename = "terminal_input")]
+    TerminalInput {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+        data: String,
+        #[serde(default)]
+        encoding: ContentEncoding,
+    },
+    #[serde(rename = "terminal_resize")]
+    TerminalResize {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+        #[serde(flatten)]
+        size: pty::TerminalSize,
+    },
+    #[serde(rename = "close_terminal")]
+    CloseTerminal {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+    },
+    #[serde(rename = "list_terminals")]
+    ListTerminals { repo_path: String, lychee_id: String },
+    #[serde(rename = "terminals_list")]
+    TerminalsList {
+        repo_path: String,
+        lychee_id: String,
+        terminals: Vec<TerminalInfo>,
+    },
+    /// Raw terminal output, base64 encoded; may split UTF-8 sequences
+    #[serde(rename = "terminal_output")]
+    TerminalOutput {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+        data: String,
+    },
+    /// The shell exited or the terminal was closed
+    #[serde(rename = "terminal_exited")]
+    TerminalExited {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+        #[serde(flatten)]
+        exit: pty::Exit,
+    },
+    /// A terminal request that failed; terminal_id is None when opening failed
+    #[serde(rename = "terminal_error")]
+    TerminalError {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: Option<String>,
+        message: String,
+    },
+
+    // Commands (see commands.rs)
+    /// Run a configured target by name, or an allowed program with its arguments
+    #[serde(rename = "run_command")]
+    RunCommand {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        argv: Option<Vec<String>>,
+    },
+    #[serde(rename = "cancel_command")]
+    CancelCommand {
+        repo_path: String,
+        lychee_id: String,
+        command_id: String,
+    },
+    /// What can be run, and what is running in the session
+    #[serde(rename = "list_commands")]
+    ListCommands { repo_path: String, lychee_id: String },
+    #[serde(rename = "commands_list")]
+    CommandsList {
+        repo_path: String,
+        lychee_id: String,
+        targets: std::collections::BTreeMap<String, Vec<String>>,
+        allowed: Vec<String>,
+        running: Vec<CommandInfo>,
+    },
+    #[serde(rename = "command_started")]
+    CommandStarted {
+        repo_path: String,
+        lychee_id: String,
+        command_id: String,
+        name: Option<String>,
+        argv: Vec<String>,
+    },
+    #[serde(rename = "command_output")]
+    CommandOutput {
+        repo_path: String,
+        lychee_id: String,
+        command_id: String,
+        stream: commands::OutputStream,
+        data: String,
+    },
+    #[serde(rename = "command_finished")]
+    CommandFinished {
+        repo_path: String,
+        lychee_id: String,
+        command_id: String,
+        #[serde(flatten)]
+        finished: commands::Finished,
+    },
+    /// A command request that failed; command_id is None when it never started
+    #[serde(rename = "command_error")]
+    CommandError {
+        repo_path: String,
+        lychee_id: String,
+        command_id: Option<String>,
+        message: String,
+    },
+
+    // Previews: local ports served through the relay at /preview/{client}/{preview_id}/
+    #[serde(rename = "expose_port")]
+    ExposePort {
+        repo_path: String,
+        lychee_id: String,
+        /// None stops exposing
+        port: Option<u16>,
+    },
+    #[serde(rename = "get_preview")]
+    GetPreview {
+        repo_path: String,
+        lychee_id: String,
+    },
+    #[serde(rename = "preview_updated")]
+    PreviewUpdated {
+        repo_path: String,
+        lychee_id: String,
+        port: Option<u16>,
+        /// Names this exposure in preview URLs; None while nothing is exposed
+        preview_id: Option<String>,
+    },
+    #[serde(rename = "preview_error")]
+    PreviewError {
+        repo_path: String,
+        lychee_id: String,
+        message: String,
+    },
+
+    // Relay <-> Client: preview traffic, which the relay has to read and so never travels encrypted
+    #[serde(rename = "tunnel_request")]
+    TunnelRequest {
+        request_id: String,
+        preview_id: String,
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+        /// base64
+        body: String,
+        /// A WebSocket upgrade
+        #[serde(default)]
+        websocket: bool,
+    },
+    #[serde(rename = "tunnel_response")]
+    TunnelResponse {
+        request_id: String,
+        status: u16,
+        headers: Vec<(String, String)>,
+    },
+    #[serde(rename = "tunnel_data")]
+    TunnelData {
+        request_id: String,
+        /// base64
+        data: String,
+    },
+    #[serde(rename = "tunnel_frame")]
+    TunnelFrame {
+        request_id: String,
+        #[serde(flatten)]
+        frame: tunnel::Frame,
+    },
+    #[serde(rename = "tunnel_close")]
+    TunnelClose {
+        request_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    // Tool approvals (see permissions.rs)
+    #[serde(rename = "permission_request")]
+    PermissionRequest {
+        repo_path: String,
+        lychee_id: String,
+        request_id: String,
+        tool_name: String,
+        input: Value,
+    },
+    #[serde(rename = "permission_response")]
+    PermissionResponse {
+        repo_path: String,
+        lychee_id: String,
+        request_id: String,
+        decision: Decision,
+        #[serde(default)]
+        scope: Scope,
+    },
+    #[serde(rename = "permission_resolved")]
+    PermissionResolved {
+        repo_path: String,
+        lychee_id: String,
+        request_id: String,
+        decision: Decision,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CommandInfo {
+    command_id: String,
+    name: Option<String>,
+    argv: Vec<String>,
+    started_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TerminalInfo {
+    terminal_id: String,
+    shell: String,
+    opened_at: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -118,6 +712,47 @@ struct SessionInfo {
     created_at: String,
     last_active: String,
     is_worktree: bool,
+    settings: SessionSettings,
+    title: Option<String>,
+    branch: Option<String>,
+    base_ref: Option<String>,
+    base_commit: Option<String>,
+}
+
+/// Why a write_file was refused
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ConflictReason {
+    /// The file is not what the browser read
+    Changed,
+    /// An agent turn is running in the session
+    Streaming,
+}
+
+/// A file a person wrote through write_file, shown in the session's transcript
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManualEdit {
+    path: String,
+    created: bool,
+    sha256: Option<String>,
+    at: String,
+    /// Non-blank transcript lines before the edit, where it is shown
+    position: usize,
+}
+
+impl ManualEdit {
+    /**
+     * The edit as a transcript entry
+     */
+    fn entry(&self) -> Value {
+        let verb = if self.created { "Created" } else { "Edited" };
+        serde_json::json!({
+            "role": "system",
+            "content": format!("{} {} by hand", verb, self.path),
+            "uuid": format!("edit-{}", self.at),
+            "timestamp": self.at,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -133,15 +768,131 @@ struct SessionMetadata {
     last_active: String,
     #[serde(default)]
     is_worktree: bool,
+    #[serde(default)]
+    settings: SessionSettings,
+    /// Hidden from the sessions list until restored
+    #[serde(default)]
+    archived: bool,
+    /// Branch lychee created for a worktree session, deleted along with it
+    #[serde(default)]
+    branch: Option<String>,
+    /// Set by rename_session, or derived from the transcript after the first turn
+    #[serde(default)]
+    title: Option<String>,
+    /// Branch or tag a worktree session started from, None for a detached HEAD or a bare commit
+    #[serde(default)]
+    base_ref: Option<String>,
+    /// Commit the worktree session's branch was created at
+    #[serde(default)]
+    base_commit: Option<String>,
+    /// Files written through write_file, oldest first
+    #[serde(default)]
+    edits: Vec<ManualEdit>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// The agent process of a session, keyed by lychee_id
+struct AgentProcess {
+    handle: AgentHandle,
+    /// User messages for a long-lived session process; None for a single turn
+    input: Option<mpsc::UnboundedSender<String>>,
+    model: String,
+    settings: SessionSettings,
+    /// Whether a turn is in progress
+    busy: bool,
+    /// Tells a replaced process apart from its successor
+    run_id: Uuid,
+}
+
+/// A session's exposed port, keyed by lychee_id
+#[derive(Clone)]
+struct Preview {
+    port: u16,
+    /// Random, so a preview URL can't be guessed and dies with its exposure
+    id: String,
+}
+
+/// A preview request or WebSocket in flight, keyed by request_id
+struct OpenTunnel {
+    lychee_id: String,
+    /// Browser frames for a WebSocket
+    frames: Option<mpsc::Sender<tunnel::Frame>>,
+    /// Dropping it ends the request or closes the socket
+    _cancel: tokio::sync::oneshot::Sender<()>,
+}
+
+/// A run_command in progress, keyed by command_id
+struct RunningCommand {
+    lychee_id: String,
+    name: Option<String>,
+    argv: Vec<String>,
+    started_at: chrono::DateTime<chrono::Utc>,
+    /// Sending or dropping it kills the command
+    cancel: tokio::sync::oneshot::Sender<()>,
 }
 
 #[derive(Clone)]
 struct AppState {
-    active_processes: Arc<RwLock<HashMap<String, Child>>>,
+    active_processes: Arc<RwLock<HashMap<String, AgentProcess>>>,
     start_time: Instant,
     animation_frame: Arc<RwLock<u8>>,
     client_count: Arc<RwLock<usize>>,
-    debug: bool,
+    shutting_down: Arc<RwLock<bool>>,
+    e2e: Option<Arc<E2eSession>>,
+    agent: Arc<dyn AgentBackend>,
+    idle_timeout: Duration,
+    permissions: Option<Arc<PermissionBroker>>,
+    config: Arc<Config>,
+    /// Working directory watchers of the sessions browsers are watching, keyed by lychee_id
+    watchers: Arc<RwLock<HashMap<String, watch::DirWatcher>>>,
+    /// Open terminals of all sessions, keyed by terminal_id
+    terminals: Arc<RwLock<HashMap<String, pty::Terminal>>>,
+    commands: Arc<RwLock<HashMap<String, RunningCommand>>>,
+    /// Exposed ports, keyed by lychee_id
+    previews: Arc<RwLock<HashMap<String, Preview>>>,
+    tunnels: Arc<RwLock<HashMap<String, OpenTunnel>>>,
+    /// Held for every read-modify-write of .session-info.json
+    metadata_lock: Arc<Mutex<()>>,
+    /// Worktree sessions being finished; they take no messages, writes or commands meanwhile
+    finishing: Arc<RwLock<HashSet<String>>>,
+    /// Slow git and filesystem jobs waiting their turn, keyed by lychee_id or WATCHERS_QUEUE
+    queues: Arc<std::sync::Mutex<HashMap<String, mpsc::UnboundedSender<Job>>>>,
+}
+
+type Job = std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>;
+
+/// Watcher updates replace each other, so they go through one queue in the order they came
+const WATCHERS_QUEUE: &str = "watched-sessions";
+
+/**
+ * Run `job` off the socket loop, after the jobs queued before it under `key`
+ *
+ * Each queue has one task working through it, so a session's file requests,
+ * diffs, finish and delete happen in the order the browser asked while other
+ * messages go on.
+ */
+fn enqueue(state: &AppState, key: String, job: impl std::future::Future<Output = ()> + Send + 'static) {
+    let mut queues = state.queues.lock().unwrap();
+    let job: Job = Box::pin(job);
+    let job = match queues.get(&key) {
+        Some(queue) => match queue.send(job) {
+            Ok(()) => return,
+            Err(mpsc::error::SendError(job)) => job,
+        },
+        None => job,
+    };
+
+    let (queue, mut jobs) = mpsc::unbounded_channel::<Job>();
+    let _ = queue.send(job);
+    queues.insert(key, queue);
+    tokio::spawn(async move {
+        while let Some(job) = jobs.recv().await {
+            job.await;
+        }
+    });
 }
 
 // Cat animation frames
@@ -234,14 +985,132 @@ async fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Up { debug } => {
-            run_client(debug).await;
+        Commands::Up {
+            debug,
+            no_tui,
+            shutdown_timeout,
+            idle_timeout,
+            log_level,
+            log_format,
+            log_dir,
+            e2e,
+            backend,
+            agent_command,
+            agent_args,
+            agent_resume_args,
+            agent_transcript,
+            permission_mode,
+            permission_timeout,
+            permission_fallback,
+        } => {
+            let repo_path = std::env::current_dir().unwrap();
+            let config = match Config::load(&repo_path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("❌ {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let tui = config.tui.enabled && !no_tui;
+
+            // Keep the guard alive so buffered file logs are flushed on exit
+            let _log_guard = logging::init(tui, debug, log_level.as_deref(), log_format, log_dir);
+
+            let agent: Arc<dyn AgentBackend> = match backend {
+                Backend::Claude => Arc::new(ClaudeBackend::new(config.claude.binary.clone(), config.claude.extra_args.clone())),
+                Backend::Mock => Arc::new(mock::MockBackend::new(repo_path.join(".lychee").join("mock-projects"))),
+                Backend::Command => Arc::new(CommandBackend::new(CommandBackendConfig {
+                    program: agent_command.unwrap_or_default(),
+                    resume_args: if agent_resume_args.is_empty() { agent_args.clone() } else { agent_resume_args },
+                    args: agent_args,
+                    transcript: agent_transcript,
+                })),
+            };
+
+            let timeouts = Timeouts {
+                shutdown: Duration::from_secs(shutdown_timeout),
+                idle: Duration::from_secs(idle_timeout),
+            };
+            let permissions = (permission_mode == PermissionMode::Prompt).then_some(PermissionSettings {
+                timeout: Duration::from_secs(permission_timeout),
+                fallback: permission_fallback,
+            });
+            run_client(tui, timeouts, e2e, agent, permissions, config).await;
+        }
+        Commands::Replay { recording, speed, repo_path, wait_for_browser } => {
+            if speed <= 0.0 || !speed.is_finite() {
+                eprintln!("❌ --speed must be a positive number");
+                std::process::exit(1);
+            }
+
+            let config = match Config::load(&std::env::current_dir().unwrap()) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("❌ {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let options = replay::ReplayOptions { speed, repo_path, wait_for_browser };
+            if let Err(e) = replay::run(&config.relay, &recording, options).await {
+                eprintln!("❌ {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Config { action } => {
+            let repo_path = std::env::current_dir().unwrap();
+            let result = match action {
+                ConfigAction::Show => config::show(&repo_path).map(|text| print!("{}", text)),
+                ConfigAction::Get { key } => config::get(&repo_path, &key).and_then(|value| match value {
+                    // Strings print bare so the output can be used in scripts
+                    Some(toml::Value::String(s)) => {
+                        println!("{}", s);
+                        Ok(())
+                    }
+                    Some(value) => {
+                        println!("{}", value);
+                        Ok(())
+                    }
+                    None => Err(format!("{} is not set", key)),
+                }),
+                ConfigAction::Set { key, value, repo } => {
+                    let layer = if repo { config::Layer::Repo } else { config::Layer::User };
+                    config::set(&repo_path, layer, &key, &value).map(|path| println!("✅ Set {} in {}", key, path.display()))
+                }
+            };
+            if let Err(e) = result {
+                eprintln!("❌ {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::PermissionMcp { addr, session } => {
+            // stdout is the MCP channel, so errors can only go to stderr
+            if let Err(e) = permissions::run_mcp_server(addr, session).await {
+                eprintln!("lychee permission-mcp: {}", e);
+                std::process::exit(1);
+            }
         }
     }
 }
 
-async fn run_client(debug: bool) {
-    let relay_url = std::env::var("RELAY_URL").unwrap_or_else(|_| "ws://localhost:3001/ws".to_string());
+struct Timeouts {
+    shutdown: Duration,
+    idle: Duration,
+}
+
+/// Set with --permission-mode prompt
+struct PermissionSettings {
+    timeout: Duration,
+    fallback: Fallback,
+}
+
+async fn run_client(
+    tui: bool,
+    timeouts: Timeouts,
+    e2e: bool,
+    agent: Arc<dyn AgentBackend>,
+    permission_settings: Option<PermissionSettings>,
+    config: Config,
+) {
     let repo_path = std::env::current_dir().unwrap().display().to_string();
     let repo_name = std::env::current_dir()
         .unwrap()
@@ -250,16 +1119,51 @@ async fn run_client(debug: bool) {
         .to_string_lossy()
         .to_string();
 
+    let (permissions, permission_listener) = match permission_settings {
+        Some(settings) => match PermissionBroker::bind(&repo_path, settings.timeout, settings.fallback).await {
+            Ok((broker, listener)) => (Some(broker), Some(listener)),
+            Err(e) => {
+                tracing::error!(error = %e, "failed to start the tool approval listener");
+                eprintln!("❌ Failed to start the tool approval listener: {}", e);
+                return;
+            }
+        },
+        None => (None, None),
+    };
+
     let state = Arc::new(AppState {
         active_processes: Arc::new(RwLock::new(HashMap::new())),
         start_time: Instant::now(),
         animation_frame: Arc::new(RwLock::new(0)),
         client_count: Arc::new(RwLock::new(1)),
-        debug,
+        shutting_down: Arc::new(RwLock::new(false)),
+        e2e: e2e.then(|| Arc::new(E2eSession::new())),
+        agent,
+        idle_timeout: timeouts.idle,
+        permissions,
+        config: Arc::new(config),
+        watchers: Arc::new(RwLock::new(HashMap::new())),
+        terminals: Arc::new(RwLock::new(HashMap::new())),
+        commands: Arc::new(RwLock::new(HashMap::new())),
+        previews: Arc::new(RwLock::new(HashMap::new())),
+        tunnels: Arc::new(RwLock::new(HashMap::new())),
+        metadata_lock: Arc::new(Mutex::new(())),
+        finishing: Arc::new(RwLock::new(HashSet::new())),
+        queues: Arc::new(std::sync::Mutex::new(HashMap::new())),
     });
 
+    tracing::info!(agent = state.agent.name(), "agent backend");
+
+    if let Some(ref e2e) = state.e2e {
+        tracing::info!("end-to-end encryption enabled");
+        // The code is the shared secret, so it never goes through the logs; the TUI shows it otherwise
+        if !tui {
+            eprintln!("E2E pairing code: {}", e2e.pairing_code());
+        }
+    }
+
     // Clear screen and hide cursor for TUI
-    if !debug {
+    if tui {
         let mut stdout = stdout();
         stdout.execute(terminal::Clear(ClearType::All)).ok();
         stdout.execute(cursor::Hide).ok();
@@ -267,17 +1171,18 @@ async fn run_client(debug: bool) {
     }
 
     // Connect to relay
-    let (ws_stream, _) = match connect_async(&relay_url).await {
+    let relay_url = state.config.relay.url.clone();
+    let ws_stream = match connect_relay(&state.config.relay).await {
         Ok(conn) => conn,
         Err(e) => {
+            restore_terminal(tui);
+            tracing::error!(%relay_url, error = %e, "failed to connect to relay");
             eprintln!("❌ Failed to connect to relay: {}", e);
             return;
         }
     };
 
-    if debug {
-        println!("✅ Connected to relay at {}", relay_url);
-    }
+    tracing::info!(%relay_url, repo = %repo_path, "connected to relay");
 
     let (mut write, mut read) = ws_stream.split();
 
@@ -285,6 +1190,7 @@ async fn run_client(debug: bool) {
     let register_msg = Message::RegisterClient {
         repo_path: repo_path.clone(),
         repo_name: repo_name.clone(),
+        e2e: state.e2e.is_some(),
     };
     write
         .send(WsMessage::Text(serde_json::to_string(&register_msg).unwrap()))
@@ -296,44 +1202,293 @@ async fn run_client(debug: bool) {
 
     // Spawn TUI animation task
     let state_clone = state.clone();
-    let tui_task = if !debug {
+    let tui_task = if tui {
         Some(tokio::spawn(async move {
             loop {
                 render_tui(&state_clone).await;
-                tokio::time::sleep(Duration::from_millis(500)).await;
+                tokio::time::sleep(Duration::from_millis(state_clone.config.tui.refresh_ms)).await;
             }
         }))
     } else {
         None
     };
 
-    // Spawn task to send messages
+    // Serve tool approvals; aborted on shutdown so its sender doesn't hold the socket open
+    let permission_task = match (state.permissions.clone(), permission_listener) {
+        (Some(broker), Some(listener)) => {
+            tracing::info!("tool uses need approval from a browser");
+            Some(tokio::spawn(broker.serve(listener, tx.clone())))
+        }
+        _ => None,
+    };
+
+    // Spawn task to send messages
+    // Runs until every sender is dropped, then closes the socket cleanly
     let mut write_clone = write;
-    let _send_task = tokio::spawn(async move {
+    let e2e = state.e2e.clone();
+    let send_repo_path = repo_path.clone();
+    let send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
+            let msg = match e2e {
+                Some(ref e2e) => match seal_outgoing(e2e, &send_repo_path, msg) {
+                    Some(sealed) => sealed,
+                    None => continue,
+                },
+                None => msg,
+            };
             let _ = write_clone.send(WsMessage::Text(msg)).await;
         }
+        let _ = write_clone.close().await;
     });
 
-    // Handle incoming messages
-    while let Some(Ok(WsMessage::Text(text))) = read.next().await {
-        if let Ok(msg) = serde_json::from_str::<Message>(&text) {
-            handle_message(msg, tx.clone(), &repo_path, &state).await;
+    // Handle incoming messages until the relay goes away or we get a signal
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let signalled = loop {
+        tokio::select! {
+            incoming = read.next() => {
+                match incoming {
+                    Some(Ok(WsMessage::Text(text))) => {
+                        tracing::trace!(%text, "received message from relay");
+                        if let Ok(msg) = serde_json::from_str::<Message>(&text)
+                            && let Some(msg) = unseal_incoming(msg, &state)
+                        {
+                            handle_message(msg, tx.clone(), &repo_path, &state).await;
+                        }
+                    }
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break false,
+                    Some(Ok(_)) => {}
+                }
+            }
+            _ = &mut shutdown => break true,
         }
-    }
+    };
+
+    // Stop accepting new turns, then give running ones a chance to finish
+    *state.shutting_down.write().await = true;
+    drain_agents(&state, timeouts.shutdown).await;
 
     // Cleanup
     if let Some(tui) = tui_task {
         tui.abort();
     }
+    if let Some(permission_task) = permission_task {
+        permission_task.abort();
+    }
+
+    // Watchers, terminals, commands and tunnels hold senders too; dropping a
+    // terminal hangs up on its shell, dropping a command kills it
+    state.watchers.write().await.clear();
+    state.terminals.write().await.clear();
+    state.commands.write().await.clear();
+    state.tunnels.write().await.clear();
+
+    // Flush anything the finished turns queued (stream_end, sessions_list) and close the socket
+    drop(tx);
+    let _ = tokio::time::timeout(Duration::from_secs(2), send_task).await;
+
+    restore_terminal(tui);
+
+    if signalled {
+        tracing::info!("client stopped");
+        println!("👋 Lychee client stopped");
+    } else {
+        tracing::warn!("disconnected from relay");
+        println!("❌ Disconnected from relay");
+    }
+}
+
+/**
+ * Apply the E2E policy to a message from the relay
+ * With E2E on, browser requests are only accepted encrypted; plaintext ones
+ * could have been injected by the relay and are dropped
+ */
+fn unseal_incoming(msg: Message, state: &AppState) -> Option<Message> {
+    let Some(ref e2e) = state.e2e else {
+        return Some(msg);
+    };
+
+    match msg {
+        Message::Encrypted { repo_path, lychee_id, message_type, payload } => {
+            let inner = e2e
+                .open(&payload)
+                .and_then(|text| serde_json::from_str::<Value>(&text).map_err(|e| e.to_string()))
+                .and_then(|inner| {
+                    match e2e::matches_envelope(&inner, &repo_path, lychee_id.as_deref(), &message_type) {
+                        true => serde_json::from_value::<Message>(inner).map_err(|e| e.to_string()),
+                        false => Err("payload doesn't match its envelope".to_string()),
+                    }
+                });
+            match inner {
+                Ok(inner) => Some(inner),
+                Err(e) => {
+                    tracing::warn!(%message_type, error = %e, "dropping undecryptable message");
+                    None
+                }
+            }
+        }
+        // Key exchange and relay bookkeeping travel in the clear
+        Message::E2eHello { .. }
+        | Message::ClientCount { .. }
+        | Message::WatchedSessions { .. }
+        | Message::TunnelRequest { .. }
+        | Message::TunnelFrame { .. }
+        | Message::TunnelClose { .. } => Some(msg),
+        _ => {
+            tracing::warn!("dropping plaintext request in E2E mode");
+            None
+        }
+    }
+}
+
+/**
+ * Wrap an outgoing message in an `encrypted` envelope
+ * Only the type, repo_path and lychee_id stay visible to the relay for routing
+ */
+fn seal_outgoing(e2e: &E2eSession, repo_path: &str, text: String) -> Option<String> {
+    let value = serde_json::from_str::<Value>(&text).ok()?;
+    let message_type = value.get("type").and_then(|t| t.as_str()).unwrap_or_default().to_string();
+
+    // The browser can't read anything sealed until it has unwrapped the group key,
+    // and preview traffic is for the relay itself
+    if message_type == "e2e_welcome" || message_type.starts_with("tunnel_") {
+        return Some(text);
+    }
+
+    match e2e.seal(&text) {
+        Ok(payload) => {
+            let envelope = Message::Encrypted {
+                repo_path: repo_path.to_string(),
+                lychee_id: value.get("lychee_id").and_then(|id| id.as_str()).map(str::to_string),
+                message_type,
+                payload,
+            };
+            Some(serde_json::to_string(&envelope).unwrap())
+        }
+        Err(e) => {
+            tracing::error!(%message_type, error = %e, "failed to encrypt outgoing message");
+            None
+        }
+    }
+}
+
+/**
+ * Resolve on SIGINT (Ctrl+C) or SIGTERM
+ */
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/**
+ * Wait for running agent turns and worktree finishes, cancelling turns after `timeout`
+ * A second signal while waiting cancels immediately
+ * Idle session processes are stopped either way
+ * Each cancelled turn still runs its own cleanup (metadata, stream_end)
+ */
+async fn drain_agents(state: &AppState, timeout: Duration) {
+    // A finish cut off halfway would leave its scratch worktree behind
+    let running = busy_sessions(state).await.len() + state.finishing.read().await.len();
+    if running > 0 {
+        tracing::info!(running, timeout_secs = timeout.as_secs(), "waiting for running agents");
+
+        let wait_for_idle = async {
+            while !busy_sessions(state).await.is_empty() || !state.finishing.read().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::timeout(timeout, wait_for_idle) => {},
+            _ = shutdown_signal() => {},
+        }
+    }
+
+    // Cancel whatever is still running, and stop idle sessions
+    {
+        let mut processes = state.active_processes.write().await;
+        for (lychee_id, process) in processes.iter_mut() {
+            if process.busy {
+                tracing::warn!(%lychee_id, "cancelling agent");
+            }
+            state.agent.cancel(&mut process.handle);
+        }
+    }
+
+    // Give the spawn tasks a moment to observe EOF and persist metadata
+    let _ = tokio::time::timeout(Duration::from_secs(5), async {
+        while !state.active_processes.read().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+}
+
+type RelaySocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/**
+ * Open the relay socket, with the client token if one is configured
+ */
+async fn connect_relay(relay: &config::RelayConfig) -> Result<RelaySocket, String> {
+    let mut request = relay.url.as_str().into_client_request().map_err(|e| e.to_string())?;
+    if let Some(ref token) = relay.token {
+        let value = format!("Bearer {}", token)
+            .parse()
+            .map_err(|_| "relay.token is not a valid header value".to_string())?;
+        request.headers_mut().insert(AUTHORIZATION, value);
+    }
+    let (socket, _) = connect_async(request).await.map_err(|e| e.to_string())?;
+    Ok(socket)
+}
+
+/**
+ * Sessions with a turn in progress
+ */
+async fn busy_sessions(state: &AppState) -> Vec<String> {
+    let processes = state.active_processes.read().await;
+    processes
+        .iter()
+        .filter(|(_, process)| process.busy)
+        .map(|(lychee_id, _)| lychee_id.clone())
+        .collect()
+}
 
-    if !debug {
+/**
+ * Agent turns plus commands running, what agents.max_concurrent limits
+ */
+async fn running_jobs(state: &AppState) -> usize {
+    busy_sessions(state).await.len() + state.commands.read().await.len()
+}
+
+/**
+ * Undo the TUI's terminal changes (hidden cursor, drawn screen)
+ */
+fn restore_terminal(tui: bool) {
+    if tui {
         let mut stdout = stdout();
         stdout.execute(cursor::Show).ok();
         stdout.execute(terminal::Clear(ClearType::All)).ok();
+        stdout.execute(cursor::MoveTo(0, 0)).ok();
     }
-
-    println!("❌ Disconnected from relay");
 }
 
 async fn handle_message(
@@ -343,16 +1498,18 @@ async fn handle_message(
     state: &AppState,
 ) {
     match msg {
-        Message::ListSessions { .. } => {
+        Message::ListSessions { include_archived, .. } => {
             // Get list of currently streaming sessions
-            let active_session_ids = {
-                let processes = state.active_processes.read().await;
-                processes.keys().cloned().collect::<Vec<_>>()
-            };
+            let active_session_ids = busy_sessions(state).await;
 
             // Send sessions list with active sessions included in same message
             // This avoids race conditions with separate stream_start messages
-            let sessions = list_sessions(repo_path).await;
+            let sessions = list_sessions(repo_path, false).await;
+            let archived_sessions = if include_archived {
+                Some(list_sessions(repo_path, true).await)
+            } else {
+                None
+            };
             let response = Message::SessionsList {
                 repo_path: repo_path.to_string(),
                 sessions,
@@ -361,12 +1518,13 @@ async fn handle_message(
                 } else {
                     Some(active_session_ids)
                 },
+                archived_sessions,
             };
             let _ = tx.send(serde_json::to_string(&response).unwrap());
         }
 
         Message::CreateSession { .. } => {
-            if let Some(lychee_id) = create_session(repo_path, state.debug).await {
+            if let Some(lychee_id) = create_session(state, repo_path).await {
                 let response = Message::SessionCreated {
                     repo_path: repo_path.to_string(),
                     lychee_id,
@@ -375,18 +1533,32 @@ async fn handle_message(
             }
         }
 
-        Message::CreateWorktreeSession { .. } => {
-            if let Some(lychee_id) = create_worktree_session(repo_path, state.debug).await {
-                let response = Message::SessionCreated {
-                    repo_path: repo_path.to_string(),
-                    lychee_id,
-                };
-                let _ = tx.send(serde_json::to_string(&response).unwrap());
+        Message::CreateWorktreeSession { base_ref, branch, .. } => {
+            // Blank form fields mean "use the default"
+            let base_ref = base_ref.filter(|r| !r.trim().is_empty());
+            let base = base_ref.as_deref().or(state.config.worktree.base_branch.as_deref());
+            let branch = branch.as_deref().map(str::trim).filter(|b| !b.is_empty());
+            match create_worktree_session(state, repo_path, base, branch).await {
+                Ok(lychee_id) => {
+                    let response = Message::SessionCreated {
+                        repo_path: repo_path.to_string(),
+                        lychee_id,
+                    };
+                    let _ = tx.send(serde_json::to_string(&response).unwrap());
+                }
+                Err(message) => {
+                    tracing::error!(%message, "failed to create worktree session");
+                    let error = Message::Error {
+                        repo_path: Some(repo_path.to_string()),
+                        message,
+                    };
+                    let _ = tx.send(serde_json::to_string(&error).unwrap());
+                }
             }
         }
 
         Message::LoadSession { lychee_id, .. } => {
-            let messages = load_session_history(repo_path, &lychee_id, state.debug).await;
+            let messages = load_session_history(repo_path, &lychee_id, state.agent.as_ref()).await;
             let response = Message::SessionHistory {
                 repo_path: repo_path.to_string(),
                 lychee_id: lychee_id.clone(),
@@ -395,27 +1567,50 @@ async fn handle_message(
             let _ = tx.send(serde_json::to_string(&response).unwrap());
 
             // If this session is currently streaming, send stream_start to restore state
-            let is_active = {
-                let processes = state.active_processes.read().await;
-                processes.contains_key(&lychee_id)
-            };
+            let is_active = busy_sessions(state).await.contains(&lychee_id);
 
             if is_active {
                 let start_msg = Message::StreamStart {
                     repo_path: repo_path.to_string(),
-                    lychee_id,
+                    lychee_id: lychee_id.clone(),
                 };
                 let _ = tx.send(serde_json::to_string(&start_msg).unwrap());
             }
+
+            // Approvals asked before this browser was looking
+            if let Some(ref broker) = state.permissions {
+                for request in broker.pending_requests(&lychee_id) {
+                    let _ = tx.send(serde_json::to_string(&request).unwrap());
+                }
+            }
         }
 
         Message::SendMessage {
             lychee_id, content, model, ..
         } => {
-            // Check if already running
+            // Refuse new turns while draining for shutdown
+            if *state.shutting_down.read().await {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: "Client is shutting down".to_string(),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
+
+            if state.finishing.read().await.contains(&lychee_id) {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("Session {} is being finished", lychee_id),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
+
+            // Single turns can't take more input while running
             {
                 let processes = state.active_processes.read().await;
-                if processes.contains_key(&lychee_id) {
+                if processes.get(&lychee_id).is_some_and(|process| process.input.is_none()) {
                     let error = Message::Error {
                         repo_path: Some(repo_path.to_string()),
                         message: format!("Claude already running for session {}", lychee_id),
@@ -425,32 +1620,95 @@ async fn handle_message(
                 }
             }
 
-            // Update last_active immediately when message is sent
-            let lychee_dir = PathBuf::from(repo_path).join(".lychee");
-            let session_info_path = lychee_dir.join(".session-info.json");
-            if let Some(mut info) = std::fs::read_to_string(&session_info_path)
-                .ok()
-                .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
+            let context = session_context(repo_path, &lychee_id);
+            let model = model
+                .or_else(|| context.settings.model.clone())
+                .unwrap_or_else(|| state.config.models.default.clone());
+
+            if !state.config.models.is_allowed(&model) {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("Model {} is not allowed on this client", model),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
+
+            // Steering a running turn doesn't take another slot
+            let running = state.active_processes.read().await.get(&lychee_id).is_some_and(|process| process.busy);
+            if let Some(max) = state.config.agents.max_concurrent
+                && !running
+                && running_jobs(state).await >= max
             {
-                if let Some(metadata) = info.sessions.get_mut(&lychee_id) {
-                    metadata.last_active = chrono::Utc::now().to_rfc3339();
-                    let _ = std::fs::write(
-                        &session_info_path,
-                        serde_json::to_string_pretty(&info).unwrap(),
-                    );
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("{} agents or commands are already running on this client, try again later", max),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
 
-                    // Send updated sessions list to frontend immediately
-                    let sessions = list_sessions(repo_path).await;
-                    let update_msg = Message::SessionsList {
-                        repo_path: repo_path.to_string(),
-                        sessions,
-                        active_session_ids: None,
+            // Update last_active immediately when message is sent
+            let now = chrono::Utc::now().to_rfc3339();
+            if update_session_metadata(state, repo_path, &lychee_id, |metadata| metadata.last_active = now).await {
+                // Send updated sessions list to frontend immediately
+                let sessions = list_sessions(repo_path, false).await;
+                let update_msg = Message::SessionsList {
+                    repo_path: repo_path.to_string(),
+                    sessions,
+                    active_session_ids: None,
+                    archived_sessions: None,
+                };
+                let _ = tx.send(serde_json::to_string(&update_msg).unwrap());
+            }
+
+            if send_to_session(state, &lychee_id, &content, &model, &context.settings).await {
+                return;
+            }
+
+            let permission_prompt = state.permissions.as_ref().map(|broker| broker.prompt(&lychee_id));
+            let request = SessionRequest {
+                working_dir: &context.working_dir,
+                model: &model,
+                settings: &context.settings,
+                permissions: permission_prompt.as_ref(),
+            };
+            match state.agent.start_session(&request, context.claude_session_id.as_deref()) {
+                Some(Ok(RunningSession { handle, input, output })) => {
+                    // Registered before returning, so the next message finds it
+                    let (input_tx, input_rx) = mpsc::unbounded_channel();
+                    let _ = input_tx.send(content);
+                    let run_id = Uuid::new_v4();
+                    state.active_processes.write().await.insert(lychee_id.clone(), AgentProcess {
+                        handle,
+                        input: Some(input_tx),
+                        model,
+                        settings: context.settings.clone(),
+                        busy: false,
+                        run_id,
+                    });
+
+                    let span = tracing::info_span!("session", repo = %repo_path, %lychee_id);
+                    let io = SessionIo { input, output, input_rx };
+                    tokio::spawn(
+                        run_session(tx, repo_path.to_string(), lychee_id, run_id, context, io, state.clone()).instrument(span),
+                    );
+                    return;
+                }
+                Some(Err(message)) => {
+                    let error = Message::Error {
+                        repo_path: Some(repo_path.to_string()),
+                        message,
                     };
-                    let _ = tx.send(serde_json::to_string(&update_msg).unwrap());
+                    let _ = tx.send(serde_json::to_string(&error).unwrap());
+                    return;
                 }
+                // Backend only runs single turns
+                None => {}
             }
 
             // Spawn Claude in background task
+            let span = tracing::info_span!("turn", repo = %repo_path, %lychee_id);
             let tx_clone = tx.clone();
             let repo_path_clone = repo_path.to_string();
             let lychee_id_clone = lychee_id.clone();
@@ -468,137 +1726,1367 @@ async fn handle_message(
                     &state_clone,
                 )
                 .await;
-            });
-        }
-
-        Message::ClientCount { count } => {
-            let mut client_count = state.client_count.write().await;
-            *client_count = count;
+            }.instrument(span));
         }
 
-        _ => {}
-    }
-}
+        Message::UpdateSessionSettings { lychee_id, mut settings, .. } => {
+            // An empty model means "no default"
+            settings.model = settings.model.filter(|model| !model.trim().is_empty());
+            if let Some(ref model) = settings.model
+                && !state.config.models.is_allowed(model)
+            {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("Model {} is not allowed on this client", model),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
+            if let Err(message) = settings.check(state.permissions.is_some()) {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message,
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
 
-async fn list_sessions(repo_path: &str) -> Vec<SessionInfo> {
-    let mut sessions = Vec::new();
-    let lychee_dir = PathBuf::from(repo_path).join(".lychee");
-    let session_info_path = lychee_dir.join(".session-info.json");
+            if !update_session_metadata(state, repo_path, &lychee_id, |metadata| metadata.settings = settings).await {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("Failed to update settings of session {}", lychee_id),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
 
-    // Load session info file - this is the source of truth
-    let session_metadata = if session_info_path.exists() {
-        match std::fs::read_to_string(&session_info_path) {
-            Ok(content) => serde_json::from_str::<SessionInfoFile>(&content).unwrap_or_default(),
-            Err(_) => SessionInfoFile { sessions: HashMap::new() },
+            // A running process keeps its settings until the next message restarts it
+            tracing::info!(%lychee_id, "updated session settings");
+            let sessions = list_sessions(repo_path, false).await;
+            let update_msg = Message::SessionsList {
+                repo_path: repo_path.to_string(),
+                sessions,
+                active_session_ids: None,
+                archived_sessions: None,
+            };
+            let _ = tx.send(serde_json::to_string(&update_msg).unwrap());
         }
-    } else {
-        SessionInfoFile { sessions: HashMap::new() }
-    };
 
-    // Build session list from metadata
-    for (lychee_id, metadata) in session_metadata.sessions.iter() {
-        sessions.push(SessionInfo {
-            lychee_id: lychee_id.clone(),
-            claude_session_id: metadata.claude_session_id.clone(),
-            created_at: metadata.created_at.clone(),
-            last_active: metadata.last_active.clone(),
-            is_worktree: metadata.is_worktree,
-        });
-    }
+        Message::RenameSession { lychee_id, title, .. } => {
+            let title = Some(title.trim().to_string()).filter(|title| !title.is_empty());
+            if !update_session_metadata(state, repo_path, &lychee_id, |metadata| metadata.title = title).await {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("Failed to rename session {}", lychee_id),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
 
-    // Sort by last_active descending
-    sessions.sort_by(|a, b| b.last_active.cmp(&a.last_active));
-    sessions
-}
+            tracing::info!(%lychee_id, "renamed session");
+            let sessions = list_sessions(repo_path, false).await;
+            let update_msg = Message::SessionsList {
+                repo_path: repo_path.to_string(),
+                sessions,
+                active_session_ids: None,
+                archived_sessions: None,
+            };
+            let _ = tx.send(serde_json::to_string(&update_msg).unwrap());
+        }
 
-async fn create_session(repo_path: &str, debug: bool) -> Option<String> {
-    let lychee_id = format!("session-{}", Uuid::new_v4().to_string().split('-').next().unwrap());
-    let lychee_dir = PathBuf::from(repo_path).join(".lychee");
+        Message::ArchiveSession { lychee_id, archived, .. } => {
+            if !update_session_metadata(state, repo_path, &lychee_id, |metadata| metadata.archived = archived).await {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("Failed to archive session {}", lychee_id),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
 
-    // Create .lychee directory if it doesn't exist
-    if !lychee_dir.exists() {
-        std::fs::create_dir(&lychee_dir).ok()?;
+            tracing::info!(%lychee_id, archived, "archived session");
+            send_sessions_with_archived(&tx, repo_path).await;
+        }
 
-        // Add .lychee to git exclude
-        let git_exclude_path = PathBuf::from(repo_path).join(".git").join("info").join("exclude");
-        if let Ok(mut exclude_content) = std::fs::read_to_string(&git_exclude_path) {
-            if !exclude_content.contains("/.lychee") {
-                exclude_content.push_str("\n/.lychee\n");
-                let _ = std::fs::write(&git_exclude_path, exclude_content);
-            }
+        Message::GetDiff { lychee_id, scope, .. } => {
+            let repo_path = repo_path.to_string();
+            enqueue(state, lychee_id.clone(), async move {
+                // Worktree sessions compare against where they started, others against HEAD
+                let context = session_context(&repo_path, &lychee_id);
+                let base = context.base_commit.unwrap_or_else(|| "HEAD".to_string());
+                match diff::diff(&context.working_dir, &base, scope).await {
+                    Ok(files) => {
+                        let response = Message::SessionDiff {
+                            repo_path: repo_path.clone(),
+                            lychee_id,
+                            scope,
+                            base,
+                            files,
+                        };
+                        let _ = tx.send(serde_json::to_string(&response).unwrap());
+                    }
+                    Err(e) => {
+                        let error = Message::Error {
+                            repo_path: Some(repo_path.clone()),
+                            message: format!("Failed to diff session {}: {}", lychee_id, e),
+                        };
+                        let _ = tx.send(serde_json::to_string(&error).unwrap());
+                    }
+                }
+            });
         }
-    }
 
-    // Update session info file (no worktree creation for regular sessions)
-    let session_info_path = lychee_dir.join(".session-info.json");
-    let mut session_info = if session_info_path.exists() {
-        std::fs::read_to_string(&session_info_path)
-            .ok()
-            .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
-            .unwrap_or_default()
-    } else {
-        SessionInfoFile { sessions: HashMap::new() }
-    };
+        Message::ListDir { lychee_id, path, .. } => {
+            let repo_path = repo_path.to_string();
+            enqueue(state, lychee_id.clone(), async move {
+                let response = async {
+                    let (dir, relative) = resolve_session_path(&repo_path, &lychee_id, &path).await?;
+                    Ok(Message::DirListing {
+                        repo_path: repo_path.clone(),
+                        lychee_id: lychee_id.clone(),
+                        path: relative,
+                        listing: blocking(move || files::list_dir(&dir)).await?,
+                    })
+                }.await;
+                send_file_response(&tx, &repo_path, &lychee_id, &path, response);
+            });
+        }
 
-    session_info.sessions.insert(
-        lychee_id.clone(),
-        SessionMetadata {
-            claude_session_id: None,
-            created_at: chrono::Utc::now().to_rfc3339(),
-            last_active: chrono::Utc::now().to_rfc3339(),
-            is_worktree: false,
-        },
-    );
+        Message::ReadFile { lychee_id, path, offset, length, .. } => {
+            let repo_path = repo_path.to_string();
+            enqueue(state, lychee_id.clone(), async move {
+                let response = async {
+                    let (file, relative) = resolve_session_path(&repo_path, &lychee_id, &path).await?;
+                    Ok(Message::FileContent {
+                        repo_path: repo_path.clone(),
+                        lychee_id: lychee_id.clone(),
+                        path: relative,
+                        content: blocking(move || files::read_file(&file, offset, length)).await?,
+                    })
+                }.await;
+                send_file_response(&tx, &repo_path, &lychee_id, &path, response);
+            });
+        }
 
-    std::fs::write(
-        session_info_path,
-        serde_json::to_string_pretty(&session_info).unwrap(),
-    ).ok()?;
+        Message::Stat { lychee_id, path, .. } => {
+            let repo_path = repo_path.to_string();
+            enqueue(state, lychee_id.clone(), async move {
+                let response = async {
+                    let (file, relative) = resolve_session_path(&repo_path, &lychee_id, &path).await?;
+                    Ok(Message::FileStat {
+                        repo_path: repo_path.clone(),
+                        lychee_id: lychee_id.clone(),
+                        path: relative,
+                        stat: blocking(move || files::stat(&file)).await?,
+                    })
+                }.await;
+                send_file_response(&tx, &repo_path, &lychee_id, &path, response);
+            });
+        }
 
-    if debug {
-        println!("✅ Created regular session: {}", lychee_id);
-    }
+        Message::WriteFile { lychee_id, path, content, encoding, expected_sha256, expected_modified, force, .. } => {
+            let span = tracing::info_span!("write_file", %lychee_id, %path);
+            let expected = files::Expected { sha256: expected_sha256, modified: expected_modified };
+            let request = WriteRequest { path, content, encoding, expected, force };
+            let repo_path = repo_path.to_string();
+            let job_state = state.clone();
+            enqueue(state, lychee_id.clone(), async move {
+                write_session_file(&tx, &repo_path, &lychee_id, request, &job_state).await;
+            }.instrument(span));
+        }
 
-    Some(lychee_id)
-}
+        Message::OpenTerminal { lychee_id, size, .. } => {
+            let span = tracing::info_span!("terminal", %lychee_id);
+            open_terminal(&tx, repo_path, &lychee_id, size, state).instrument(span).await;
+        }
 
-async fn create_worktree_session(repo_path: &str, debug: bool) -> Option<String> {
-    let lychee_id = format!("session-{}", Uuid::new_v4().to_string().split('-').next().unwrap());
-    let lychee_dir = PathBuf::from(repo_path).join(".lychee");
-    let session_dir = lychee_dir.join(&lychee_id);
+        Message::TerminalInput { lychee_id, terminal_id, data, encoding, .. } => {
+            let result = files::decode_content(data, encoding);
+            let terminals = state.terminals.read().await;
+            let result = match session_terminal(&terminals, &lychee_id, &terminal_id) {
+                Ok(terminal) => result.and_then(|bytes| terminal.write(bytes)),
+                Err(message) => Err(message),
+            };
+            if let Err(message) = result {
+                send_terminal_error(&tx, repo_path, &lychee_id, Some(&terminal_id), message);
+            }
+        }
 
-    // Create .lychee directory if it doesn't exist
-    if !lychee_dir.exists() {
+        Message::TerminalResize { lychee_id, terminal_id, size, .. } => {
+            let terminals = state.terminals.read().await;
+            let result = session_terminal(&terminals, &lychee_id, &terminal_id).and_then(|terminal| terminal.resize(size));
+            if let Err(message) = result {
+                send_terminal_error(&tx, repo_path, &lychee_id, Some(&terminal_id), message);
+            }
+        }
+
+        Message::CloseTerminal { lychee_id, terminal_id, .. } => {
+            let mut terminals = state.terminals.write().await;
+            match session_terminal(&terminals, &lychee_id, &terminal_id) {
+                // Dropping it hangs up; terminal_exited follows once the shell is gone
+                Ok(_) => {
+                    terminals.remove(&terminal_id);
+                    tracing::info!(%lychee_id, %terminal_id, "closing terminal");
+                }
+                Err(message) => send_terminal_error(&tx, repo_path, &lychee_id, Some(&terminal_id), message),
+            }
+        }
+
+        Message::ListTerminals { lychee_id, .. } => {
+            let terminals = state.terminals.read().await;
+            let mut list: Vec<TerminalInfo> = terminals
+                .iter()
+                .filter(|(_, terminal)| terminal.lychee_id == lychee_id)
+                .map(|(terminal_id, terminal)| TerminalInfo {
+                    terminal_id: terminal_id.clone(),
+                    shell: terminal.shell.clone(),
+                    opened_at: terminal.opened_at.to_rfc3339(),
+                })
+                .collect();
+            list.sort_by(|a, b| a.opened_at.cmp(&b.opened_at));
+            let response = Message::TerminalsList {
+                repo_path: repo_path.to_string(),
+                lychee_id,
+                terminals: list,
+            };
+            let _ = tx.send(serde_json::to_string(&response).unwrap());
+        }
+
+        Message::RunCommand { lychee_id, name, argv, .. } => {
+            let span = tracing::info_span!("command", %lychee_id);
+            run_command(&tx, repo_path, &lychee_id, name, argv, state).instrument(span).await;
+        }
+
+        Message::CancelCommand { lychee_id, command_id, .. } => {
+            let mut commands = state.commands.write().await;
+            match commands.get(&command_id) {
+                // command_finished follows once it is gone
+                Some(command) if command.lychee_id == lychee_id => {
+                    if let Some(command) = commands.remove(&command_id) {
+                        let _ = command.cancel.send(());
+                    }
+                    tracing::info!(%lychee_id, %command_id, "cancelling command");
+                }
+                _ => send_command_error(&tx, repo_path, &lychee_id, Some(&command_id), format!("Unknown command {}", command_id)),
+            }
+        }
+
+        Message::ListCommands { lychee_id, .. } => {
+            let commands = state.commands.read().await;
+            let mut running: Vec<CommandInfo> = commands
+                .iter()
+                .filter(|(_, command)| command.lychee_id == lychee_id)
+                .map(|(command_id, command)| CommandInfo {
+                    command_id: command_id.clone(),
+                    name: command.name.clone(),
+                    argv: command.argv.clone(),
+                    started_at: command.started_at.to_rfc3339(),
+                })
+                .collect();
+            running.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+            let response = Message::CommandsList {
+                repo_path: repo_path.to_string(),
+                lychee_id,
+                targets: state.config.commands.targets.clone(),
+                allowed: state.config.commands.allowed.clone(),
+                running,
+            };
+            let _ = tx.send(serde_json::to_string(&response).unwrap());
+        }
+
+        Message::ExposePort { lychee_id, port, .. } => {
+            expose_port(&tx, repo_path, &lychee_id, port, state).await;
+        }
+
+        Message::GetPreview { lychee_id, .. } => {
+            let preview = state.previews.read().await.get(&lychee_id).cloned();
+            let response = Message::PreviewUpdated {
+                repo_path: repo_path.to_string(),
+                lychee_id,
+                port: preview.as_ref().map(|preview| preview.port),
+                preview_id: preview.map(|preview| preview.id),
+            };
+            let _ = tx.send(serde_json::to_string(&response).unwrap());
+        }
+
+        Message::TunnelRequest { request_id, preview_id, method, path, headers, body, websocket } => {
+            let Ok(body) = BASE64.decode(body) else {
+                let close = Message::TunnelClose {
+                    request_id,
+                    error: Some("Invalid request body".to_string()),
+                };
+                let _ = tx.send(serde_json::to_string(&close).unwrap());
+                return;
+            };
+            let request = tunnel::Request { method, path, headers, body };
+            open_tunnel(&tx, request_id, &preview_id, request, websocket, state).await;
+        }
+
+        Message::TunnelFrame { request_id, frame } => {
+            let mut tunnels = state.tunnels.write().await;
+            let Some(frames) = tunnels.get(&request_id).and_then(|tunnel| tunnel.frames.as_ref()) else {
+                return;
+            };
+            // Waiting here would hold up every other message, so a server that falls behind loses the socket
+            if let Err(mpsc::error::TrySendError::Full(_)) = frames.try_send(frame) {
+                tracing::warn!(%request_id, "preview server isn't keeping up, closing the WebSocket");
+                tunnels.remove(&request_id);
+                let close = Message::TunnelClose {
+                    request_id,
+                    error: Some("The preview server isn't keeping up".to_string()),
+                };
+                let _ = tx.send(serde_json::to_string(&close).unwrap());
+            }
+        }
+
+        Message::TunnelClose { request_id, .. } => {
+            // The browser went away; dropping the tunnel ends the request
+            state.tunnels.write().await.remove(&request_id);
+        }
+
+        Message::FinishWorktreeSession { lychee_id, strategy, auto_commit, commit_message, .. } => {
+            // Held until the finish is over, so no turn, write or command starts meanwhile
+            if !state.finishing.write().await.insert(lychee_id.clone()) {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: format!("Session {} is already being finished", lychee_id),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            }
+
+            let span = tracing::info_span!("finish", %lychee_id, ?strategy);
+            let options = FinishOptions { strategy, auto_commit, commit_message };
+            let repo_path = repo_path.to_string();
+            let job_state = state.clone();
+            enqueue(state, lychee_id.clone(), async move {
+                finish_worktree_session(&tx, &repo_path, &lychee_id, options, &job_state).await;
+                job_state.finishing.write().await.remove(&lychee_id);
+            }.instrument(span));
+        }
+
+        Message::DeleteSession { lychee_id, force, .. } => {
+            let span = tracing::info_span!("delete", %lychee_id);
+            let repo_path = repo_path.to_string();
+            let job_state = state.clone();
+            enqueue(state, lychee_id.clone(), async move {
+                delete_session(&tx, &repo_path, &lychee_id, force, &job_state).await;
+            }.instrument(span));
+        }
+
+        Message::ClientCount { count } => {
+            let mut client_count = state.client_count.write().await;
+            *client_count = count;
+        }
+
+        Message::WatchedSessions { lychee_ids } => {
+            if let Some(ref broker) = state.permissions {
+                broker.set_watched_sessions(&lychee_ids);
+            }
+            // Starting a watcher walks the working directory
+            let repo_path = repo_path.to_string();
+            let job_state = state.clone();
+            enqueue(state, WATCHERS_QUEUE.to_string(), async move {
+                update_watchers(&tx, &repo_path, lychee_ids, &job_state).await;
+            });
+        }
+
+        Message::PermissionResponse { lychee_id, request_id, decision, scope, .. } => {
+            let settled = state.permissions.as_ref().is_some_and(|broker| broker.respond(&lychee_id, &request_id, decision, scope));
+            if !settled {
+                tracing::debug!(%lychee_id, %request_id, "ignoring answer to a settled or foreign tool approval");
+            }
+        }
+
+        Message::E2eHello { browser_key, .. } => {
+            let Some(ref e2e) = state.e2e else {
+                let error = Message::Error {
+                    repo_path: Some(repo_path.to_string()),
+                    message: "End-to-end encryption is not enabled on this client".to_string(),
+                };
+                let _ = tx.send(serde_json::to_string(&error).unwrap());
+                return;
+            };
+
+            match e2e.welcome(&browser_key) {
+                Ok((client_key, wrapped_key)) => {
+                    let welcome = Message::E2eWelcome {
+                        repo_path: repo_path.to_string(),
+                        browser_key,
+                        client_key,
+                        wrapped_key,
+                    };
+                    let _ = tx.send(serde_json::to_string(&welcome).unwrap());
+                }
+                Err(e) => tracing::warn!(error = %e, "rejected E2E key exchange"),
+            }
+        }
+
+        _ => {}
+    }
+}
+
+/**
+ * Sessions of a repo, most recently active first
+ * Archived sessions are listed separately
+ */
+async fn list_sessions(repo_path: &str, archived: bool) -> Vec<SessionInfo> {
+    let mut sessions = Vec::new();
+    let lychee_dir = PathBuf::from(repo_path).join(".lychee");
+    let session_info_path = lychee_dir.join(".session-info.json");
+
+    // Load session info file - this is the source of truth
+    let session_metadata = if session_info_path.exists() {
+        match std::fs::read_to_string(&session_info_path) {
+            Ok(content) => serde_json::from_str::<SessionInfoFile>(&content).unwrap_or_default(),
+            Err(_) => SessionInfoFile { sessions: HashMap::new() },
+        }
+    } else {
+        SessionInfoFile { sessions: HashMap::new() }
+    };
+
+    // Build session list from metadata
+    for (lychee_id, metadata) in session_metadata.sessions.iter().filter(|(_, m)| m.archived == archived) {
+        sessions.push(SessionInfo {
+            lychee_id: lychee_id.clone(),
+            claude_session_id: metadata.claude_session_id.clone(),
+            created_at: metadata.created_at.clone(),
+            last_active: metadata.last_active.clone(),
+            is_worktree: metadata.is_worktree,
+            settings: metadata.settings.clone(),
+            title: metadata.title.clone(),
+            branch: metadata.branch.clone(),
+            base_ref: metadata.base_ref.clone(),
+            base_commit: metadata.base_commit.clone(),
+        });
+    }
+
+    // Sort by last_active descending
+    sessions.sort_by(|a, b| b.last_active.cmp(&a.last_active));
+    sessions
+}
+
+/**
+ * Change one session's entry in .session-info.json
+ * Returns false if the session doesn't exist or the file can't be written
+ */
+async fn update_session_metadata(
+    state: &AppState,
+    repo_path: &str,
+    lychee_id: &str,
+    update: impl FnOnce(&mut SessionMetadata),
+) -> bool {
+    let _guard = state.metadata_lock.lock().await;
+    let session_info_path = PathBuf::from(repo_path).join(".lychee").join(".session-info.json");
+    std::fs::read_to_string(&session_info_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
+        .and_then(|mut info| {
+            update(info.sessions.get_mut(lychee_id)?);
+            std::fs::write(&session_info_path, serde_json::to_string_pretty(&info).unwrap()).ok()
+        })
+        .is_some()
+}
+
+/**
+ * Send the sessions list including archived sessions, after archiving or deleting
+ */
+async fn send_sessions_with_archived(tx: &mpsc::UnboundedSender<String>, repo_path: &str) {
+    let update_msg = Message::SessionsList {
+        repo_path: repo_path.to_string(),
+        sessions: list_sessions(repo_path, false).await,
+        active_session_ids: None,
+        archived_sessions: Some(list_sessions(repo_path, true).await),
+    };
+    let _ = tx.send(serde_json::to_string(&update_msg).unwrap());
+}
+
+/**
+ * Delete a session: its worktree, the branch lychee made for it, and its metadata
+ *
+ * Refused with `session_delete_blocked` while a turn is running or the
+ * worktree has uncommitted changes, unless forced. The agent's own transcript
+ * is left alone.
+ */
+async fn delete_session(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, force: bool, state: &AppState) {
+    let send_error = |message: String| {
+        let error = Message::Error {
+            repo_path: Some(repo_path.to_string()),
+            message,
+        };
+        let _ = tx.send(serde_json::to_string(&error).unwrap());
+    };
+    let send_blocked = |reason: String, uncommitted_files: Vec<String>| {
+        let blocked = Message::SessionDeleteBlocked {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            reason,
+            uncommitted_files,
+        };
+        let _ = tx.send(serde_json::to_string(&blocked).unwrap());
+    };
+
+    let lychee_dir = PathBuf::from(repo_path).join(".lychee");
+    let Some(metadata) = read_session_metadata(repo_path, lychee_id) else {
+        return send_error(format!("Unknown session {}", lychee_id));
+    };
+    let worktree_dir = lychee_dir.join(lychee_id);
+
+    // Every refusal comes before anything is torn down
+    if state.finishing.read().await.contains(lychee_id) {
+        return send_blocked("The session is being finished".to_string(), Vec::new());
+    }
+    if metadata.is_worktree && worktree_dir.exists() && !force {
+        match worktree::uncommitted_changes(&worktree_dir).await {
+            Ok(files) if !files.is_empty() => {
+                return send_blocked(format!("{} file(s) have uncommitted changes", files.len()), files);
+            }
+            Ok(_) => {}
+            Err(e) => return send_error(format!("Failed to check the worktree of {}: {}", lychee_id, e)),
+        }
+    }
+
+    // Stop the session's agent; a turn in progress needs force
+    {
+        let mut processes = state.active_processes.write().await;
+        if processes.get(lychee_id).is_some_and(|process| process.busy) && !force {
+            drop(processes);
+            return send_blocked("Claude is still working in this session".to_string(), Vec::new());
+        }
+        if let Some(mut process) = processes.remove(lychee_id) {
+            state.agent.cancel(&mut process.handle);
+        }
+    }
+    state.watchers.write().await.remove(lychee_id);
+    state.terminals.write().await.retain(|_, terminal| terminal.lychee_id != lychee_id);
+    state.commands.write().await.retain(|_, command| command.lychee_id != lychee_id);
+    state.previews.write().await.remove(lychee_id);
+    state.tunnels.write().await.retain(|_, tunnel| tunnel.lychee_id != lychee_id);
+
+    let mut warning = None;
+    if metadata.is_worktree {
+        if let Err(e) = worktree::remove(Path::new(repo_path), &worktree_dir, force).await {
+            return send_error(format!("Failed to remove the worktree of {}: {}", lychee_id, e));
+        }
+
+        if let Some(ref branch) = metadata.branch
+            && let Err(e) = worktree::delete_branch(Path::new(repo_path), branch, force).await
+        {
+            tracing::warn!(%branch, error = %e, "kept session branch");
+            warning = Some(format!("Kept branch {}: {}", branch, e));
+        }
+    }
+
+    // Re-read, other sessions may have changed while the worktree was removed
+    {
+        let _guard = state.metadata_lock.lock().await;
+        let session_info_path = lychee_dir.join(".session-info.json");
+        let Some(mut info) = std::fs::read_to_string(&session_info_path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
+        else {
+            return send_error(format!("Failed to read {}", session_info_path.display()));
+        };
+        info.sessions.remove(lychee_id);
+        if let Err(e) = std::fs::write(&session_info_path, serde_json::to_string_pretty(&info).unwrap()) {
+            return send_error(format!("Failed to update session info: {}", e));
+        }
+    }
+
+    tracing::info!(worktree = metadata.is_worktree, "deleted session");
+    let deleted = Message::SessionDeleted {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        warning,
+    };
+    let _ = tx.send(serde_json::to_string(&deleted).unwrap());
+    send_sessions_with_archived(tx, repo_path).await;
+}
+
+/**
+ * Working directory of a session that browsers may use files in
+ * Unknown and archived sessions have none, rather than the repo root
+ */
+fn file_root(repo_path: &str, lychee_id: &str) -> Result<PathBuf, String> {
+    match read_session_metadata(repo_path, lychee_id) {
+        None => Err(format!("Unknown session {}", lychee_id)),
+        Some(metadata) if metadata.archived => Err(format!("Session {} is archived", lychee_id)),
+        Some(_) => Ok(session_context(repo_path, lychee_id).working_dir),
+    }
+}
+
+/**
+ * Resolve a path a browser sent for reading, inside the session's file_root
+ */
+async fn resolve_session_path(repo_path: &str, lychee_id: &str, path: &str) -> Result<(PathBuf, String), String> {
+    let root = file_root(repo_path, lychee_id)?;
+    files::resolve_visible(&root, path).await
+}
+
+/**
+ * Run synchronous filesystem work on the blocking pool, off the async threads
+ */
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    tokio::task::spawn_blocking(work).await.map_err(|e| e.to_string())?
+}
+
+/**
+ * Send the answer to a file request, or a file_error naming the requested path
+ */
+fn send_file_response(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, path: &str, response: Result<Message, String>) {
+    let message = response.unwrap_or_else(|message| {
+        tracing::debug!(%lychee_id, %path, %message, "file request failed");
+        Message::FileError {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            path: path.to_string(),
+            message,
+        }
+    });
+    let _ = tx.send(serde_json::to_string(&message).unwrap());
+}
+
+/**
+ * Watch the working directories of exactly the sessions browsers are watching
+ */
+async fn update_watchers(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_ids: Vec<String>, state: &AppState) {
+    let mut watchers = state.watchers.write().await;
+    watchers.retain(|lychee_id, _| {
+        let keep = lychee_ids.contains(lychee_id);
+        if !keep {
+            tracing::info!(%lychee_id, "stopped watching session directory");
+        }
+        keep
+    });
+
+    for lychee_id in lychee_ids {
+        if watchers.contains_key(&lychee_id) || read_session_metadata(repo_path, &lychee_id).is_none() {
+            continue;
+        }
+        let root = session_context(repo_path, &lychee_id).working_dir;
+        let tx = tx.clone();
+        let (repo, id) = (repo_path.to_string(), lychee_id.clone());
+        let watcher = watch::watch(&root, move |batch| {
+            tracing::debug!(lychee_id = %id, changes = batch.changes.len(), "files changed");
+            let changed = Message::FilesChanged {
+                repo_path: repo.clone(),
+                lychee_id: id.clone(),
+                batch,
+            };
+            let _ = tx.send(serde_json::to_string(&changed).unwrap());
+        });
+        match watcher {
+            Ok(watcher) => {
+                tracing::info!(%lychee_id, "watching session directory");
+                watchers.insert(lychee_id, watcher);
+            }
+            Err(message) => tracing::warn!(%lychee_id, %message, "failed to watch session directory"),
+        }
+    }
+}
+
+/**
+ * Run a command in a session's working directory
+ * Output is streamed as `command_output` until `command_finished`
+ */
+async fn run_command(
+    tx: &mpsc::UnboundedSender<String>,
+    repo_path: &str,
+    lychee_id: &str,
+    name: Option<String>,
+    argv: Option<Vec<String>>,
+    state: &AppState,
+) {
+    let argv = match commands::resolve(&state.config.commands, name.as_deref(), argv) {
+        Ok(argv) => argv,
+        Err(message) => return send_command_error(tx, repo_path, lychee_id, None, message),
+    };
+    if *state.shutting_down.read().await {
+        return send_command_error(tx, repo_path, lychee_id, None, "The client is shutting down".to_string());
+    }
+    if read_session_metadata(repo_path, lychee_id).is_none() {
+        return send_command_error(tx, repo_path, lychee_id, None, format!("Unknown session {}", lychee_id));
+    }
+    if state.finishing.read().await.contains(lychee_id) {
+        return send_command_error(tx, repo_path, lychee_id, None, "The session is being finished".to_string());
+    }
+    let cwd = session_context(repo_path, lychee_id).working_dir;
+    if !cwd.is_dir() {
+        return send_command_error(tx, repo_path, lychee_id, None, "The session's working directory is gone".to_string());
+    }
+    if let Some(max) = state.config.agents.max_concurrent
+        && running_jobs(state).await >= max
+    {
+        let message = format!("{} agents or commands are already running on this client, try again later", max);
+        return send_command_error(tx, repo_path, lychee_id, None, message);
+    }
+
+    let command_id = format!("cmd-{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    let (cancel, cancelled) = tokio::sync::oneshot::channel();
+    state.commands.write().await.insert(
+        command_id.clone(),
+        RunningCommand {
+            lychee_id: lychee_id.to_string(),
+            name: name.clone(),
+            argv: argv.clone(),
+            started_at: chrono::Utc::now(),
+            cancel,
+        },
+    );
+    tracing::info!(%command_id, ?argv, cwd = %cwd.display(), "running command");
+
+    let started = Message::CommandStarted {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        command_id: command_id.clone(),
+        name,
+        argv: argv.clone(),
+    };
+    let _ = tx.send(serde_json::to_string(&started).unwrap());
+
+    let tx = tx.clone();
+    let (repo_path, lychee_id) = (repo_path.to_string(), lychee_id.to_string());
+    let commands = state.commands.clone();
+    tokio::spawn(
+        async move {
+            let on_output = |stream, data| {
+                let output = Message::CommandOutput {
+                    repo_path: repo_path.clone(),
+                    lychee_id: lychee_id.clone(),
+                    command_id: command_id.clone(),
+                    stream,
+                    data,
+                };
+                let _ = tx.send(serde_json::to_string(&output).unwrap());
+            };
+            let result = commands::run(&argv, &cwd, on_output, cancelled).await;
+            commands.write().await.remove(&command_id);
+
+            let message = match result {
+                Ok(finished) => {
+                    tracing::info!(%command_id, exit_code = ?finished.exit_code, duration_ms = finished.duration_ms, cancelled = finished.cancelled, "command finished");
+                    Message::CommandFinished {
+                        repo_path,
+                        lychee_id,
+                        command_id,
+                        finished,
+                    }
+                }
+                Err(message) => {
+                    tracing::warn!(%command_id, %message, "command failed");
+                    Message::CommandError {
+                        repo_path,
+                        lychee_id,
+                        command_id: Some(command_id),
+                        message,
+                    }
+                }
+            };
+            let _ = tx.send(serde_json::to_string(&message).unwrap());
+        }
+        .in_current_span(),
+    );
+}
+
+fn send_command_error(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, command_id: Option<&str>, message: String) {
+    let error = Message::CommandError {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        command_id: command_id.map(str::to_string),
+        message,
+    };
+    let _ = tx.send(serde_json::to_string(&error).unwrap());
+}
+
+/**
+ * Serve `port` at the session's preview URL on the relay, or stop when None
+ * Only what listens on localhost is reachable, and only while exposed
+ */
+async fn expose_port(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, port: Option<u16>, state: &AppState) {
+    let Some(port) = port else {
+        state.previews.write().await.remove(lychee_id);
+        state.tunnels.write().await.retain(|_, tunnel| tunnel.lychee_id != lychee_id);
+        tracing::info!(%lychee_id, "stopped exposing port");
+        let response = Message::PreviewUpdated {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            port: None,
+            preview_id: None,
+        };
+        let _ = tx.send(serde_json::to_string(&response).unwrap());
+        return;
+    };
+
+    let settings = &state.config.preview;
+    let refusal = if !settings.enabled {
+        Some("Previews are disabled on this client".to_string())
+    } else if state.e2e.is_some() {
+        // The relay has to read preview traffic to serve it
+        Some("Previews are not available with end-to-end encryption".to_string())
+    } else if read_session_metadata(repo_path, lychee_id).is_none() {
+        Some(format!("Unknown session {}", lychee_id))
+    } else if !settings.is_allowed(port) {
+        Some(format!("Port {} may not be exposed on this client", port))
+    } else {
+        None
+    };
+    if let Some(message) = refusal {
+        let error = Message::PreviewError {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            message,
+        };
+        let _ = tx.send(serde_json::to_string(&error).unwrap());
+        return;
+    }
+
+    tracing::info!(%lychee_id, port, "exposing port");
+    // A new id each time, so URLs handed out for an earlier exposure stop working
+    let preview = Preview { port, id: Uuid::new_v4().simple().to_string() };
+    state.previews.write().await.insert(lychee_id.to_string(), preview.clone());
+    state.tunnels.write().await.retain(|_, tunnel| tunnel.lychee_id != lychee_id);
+    let response = Message::PreviewUpdated {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        port: Some(preview.port),
+        preview_id: Some(preview.id),
+    };
+    let _ = tx.send(serde_json::to_string(&response).unwrap());
+}
+
+/**
+ * Pass a preview request from the relay on to the exposed port `preview_id` names
+ * The answer goes back as tunnel_response and tunnel_data or tunnel_frame, then tunnel_close
+ */
+async fn open_tunnel(
+    tx: &mpsc::UnboundedSender<String>,
+    request_id: String,
+    preview_id: &str,
+    request: tunnel::Request,
+    websocket: bool,
+    state: &AppState,
+) {
+    let found = state
+        .previews
+        .read()
+        .await
+        .iter()
+        .find(|(_, preview)| preview.id == preview_id)
+        .map(|(lychee_id, preview)| (lychee_id.clone(), preview.port));
+    let Some((lychee_id, port)) = found else {
+        let not_found = [
+            Message::TunnelResponse {
+                request_id: request_id.clone(),
+                status: 404,
+                headers: vec![("content-type".to_string(), "text/plain; charset=utf-8".to_string())],
+            },
+            Message::TunnelData {
+                request_id: request_id.clone(),
+                data: BASE64.encode("Nothing is exposed at this address"),
+            },
+            Message::TunnelClose { request_id, error: None },
+        ];
+        for message in not_found {
+            let _ = tx.send(serde_json::to_string(&message).unwrap());
+        }
+        return;
+    };
+
+    let (cancel, cancelled) = tokio::sync::oneshot::channel();
+    let (frames, frames_rx) = match websocket {
+        true => {
+            let (frames, frames_rx) = mpsc::channel(tunnel::FRAME_BUFFER);
+            (Some(frames), Some(frames_rx))
+        }
+        false => (None, None),
+    };
+    state.tunnels.write().await.insert(
+        request_id.clone(),
+        OpenTunnel {
+            lychee_id: lychee_id.to_string(),
+            frames,
+            _cancel: cancel,
+        },
+    );
+    tracing::debug!(%request_id, %lychee_id, port, method = %request.method, path = %request.path, websocket, "preview request");
+
+    let tx = tx.clone();
+    let tunnels = state.tunnels.clone();
+    tokio::spawn(
+        async move {
+            let on_event = |event| {
+                let message = match event {
+                    tunnel::Event::Response { status, headers } => Message::TunnelResponse {
+                        request_id: request_id.clone(),
+                        status,
+                        headers,
+                    },
+                    tunnel::Event::Data(bytes) => Message::TunnelData {
+                        request_id: request_id.clone(),
+                        data: BASE64.encode(bytes),
+                    },
+                    tunnel::Event::Frame(frame) => Message::TunnelFrame {
+                        request_id: request_id.clone(),
+                        frame,
+                    },
+                };
+                let _ = tx.send(serde_json::to_string(&message).unwrap());
+            };
+            let result = match frames_rx {
+                Some(frames) => tunnel::websocket(port, request, on_event, frames, cancelled).await,
+                None => tunnel::http(port, request, on_event, cancelled).await,
+            };
+
+            // Already gone when the relay closed it first
+            if tunnels.write().await.remove(&request_id).is_some() {
+                if let Err(ref e) = result {
+                    tracing::debug!(%request_id, error = %e, "preview request failed");
+                }
+                let close = Message::TunnelClose {
+                    request_id,
+                    error: result.err(),
+                };
+                let _ = tx.send(serde_json::to_string(&close).unwrap());
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/**
+ * Open a terminal in a session's working directory
+ * Output is streamed as `terminal_output` until `terminal_exited`
+ */
+async fn open_terminal(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, size: pty::TerminalSize, state: &AppState) {
+    let settings = &state.config.terminal;
+    if !settings.enabled {
+        return send_terminal_error(tx, repo_path, lychee_id, None, "Terminals are disabled on this client".to_string());
+    }
+    if *state.shutting_down.read().await {
+        return send_terminal_error(tx, repo_path, lychee_id, None, "The client is shutting down".to_string());
+    }
+    if read_session_metadata(repo_path, lychee_id).is_none() {
+        return send_terminal_error(tx, repo_path, lychee_id, None, format!("Unknown session {}", lychee_id));
+    }
+    let cwd = session_context(repo_path, lychee_id).working_dir;
+    if !cwd.is_dir() {
+        return send_terminal_error(tx, repo_path, lychee_id, None, "The session's working directory is gone".to_string());
+    }
+
+    let mut terminals = state.terminals.write().await;
+    if terminals.len() >= settings.max_open {
+        drop(terminals);
+        let message = format!("{} terminals are already open on this client, close one first", settings.max_open);
+        return send_terminal_error(tx, repo_path, lychee_id, None, message);
+    }
+
+    let terminal_id = format!("term-{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    let shell = pty::default_shell(settings.shell.as_deref());
+    let output_tx = tx.clone();
+    let (repo, id, term) = (repo_path.to_string(), lychee_id.to_string(), terminal_id.clone());
+    let opened = pty::open(lychee_id, &shell, &cwd, size, move |bytes| {
+        let output = Message::TerminalOutput {
+            repo_path: repo.clone(),
+            lychee_id: id.clone(),
+            terminal_id: term.clone(),
+            data: BASE64.encode(bytes),
+        };
+        let _ = output_tx.send(serde_json::to_string(&output).unwrap());
+    });
+    let (terminal, exited) = match opened {
+        Ok(opened) => opened,
+        Err(message) => {
+            drop(terminals);
+            tracing::warn!(%message, "failed to open terminal");
+            return send_terminal_error(tx, repo_path, lychee_id, None, message);
+        }
+    };
+    terminals.insert(terminal_id.clone(), terminal);
+    drop(terminals);
+    tracing::info!(%terminal_id, %shell, cwd = %cwd.display(), "opened terminal");
+
+    let opened = Message::TerminalOpened {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        terminal_id: terminal_id.clone(),
+        shell,
+    };
+    let _ = tx.send(serde_json::to_string(&opened).unwrap());
+
+    let tx = tx.clone();
+    let (repo_path, lychee_id) = (repo_path.to_string(), lychee_id.to_string());
+    let terminals = state.terminals.clone();
+    tokio::spawn(async move {
+        let Ok(exit) = exited.await else { return };
+        terminals.write().await.remove(&terminal_id);
+        tracing::info!(%lychee_id, %terminal_id, exit_code = exit.exit_code, "terminal exited");
+        let exited = Message::TerminalExited {
+            repo_path,
+            lychee_id,
+            terminal_id,
+            exit,
+        };
+        let _ = tx.send(serde_json::to_string(&exited).unwrap());
+    });
+}
+
+/**
+ * A terminal by id, as long as it belongs to the session asking
+ */
+fn session_terminal<'a>(terminals: &'a HashMap<String, pty::Terminal>, lychee_id: &str, terminal_id: &str) -> Result<&'a pty::Terminal, String> {
+    terminals
+        .get(terminal_id)
+        .filter(|terminal| terminal.lychee_id == lychee_id)
+        .ok_or_else(|| format!("Unknown terminal {}", terminal_id))
+}
+
+fn send_terminal_error(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, terminal_id: Option<&str>, message: String) {
+    let error = Message::TerminalError {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        terminal_id: terminal_id.map(str::to_string),
+        message,
+    };
+    let _ = tx.send(serde_json::to_string(&error).unwrap());
+}
+
+/// What a browser asked of write_file
+struct WriteRequest {
+    path: String,
+    content: String,
+    encoding: ContentEncoding,
+    expected: files::Expected,
+    force: bool,
+}
+
+/**
+ * Write a file in a session's working directory on a person's behalf
+ *
+ * Refused with `file_conflict` when the file is no longer what the browser
+ * read, or while an agent turn is running in the session unless forced.
+ * A write is recorded in the session metadata and added to the transcript.
+ */
+async fn write_session_file(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, request: WriteRequest, state: &AppState) {
+    let root = match file_root(repo_path, lychee_id) {
+        Ok(root) => root,
+        Err(message) => return send_file_response(tx, repo_path, lychee_id, &request.path, Err(message)),
+    };
+    let (file, relative) = match files::resolve_visible_for_write(&root, &request.path).await {
+        Ok(resolved) => resolved,
+        Err(message) => return send_file_response(tx, repo_path, lychee_id, &request.path, Err(message)),
+    };
+    let send_conflict = |reason: ConflictReason, message: String| {
+        tracing::info!(?reason, %message, "write refused");
+        let conflict = Message::FileConflict {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            path: relative.clone(),
+            reason,
+            message,
+            current: files::stat(&file).ok(),
+        };
+        let _ = tx.send(serde_json::to_string(&conflict).unwrap());
+    };
+
+    if !request.force && busy_sessions(state).await.iter().any(|id| id == lychee_id) {
+        return send_conflict(ConflictReason::Streaming, "An agent turn is running in this session".to_string());
+    }
+    if state.finishing.read().await.contains(lychee_id) {
+        let message = "The session is being finished".to_string();
+        return send_file_response(tx, repo_path, lychee_id, &request.path, Err(message));
+    }
+
+    let content = match files::decode_content(request.content, request.encoding) {
+        Ok(bytes) => bytes,
+        Err(message) => return send_file_response(tx, repo_path, lychee_id, &request.path, Err(message)),
+    };
+    let created = !file.exists();
+    let target = file.clone();
+    let written = tokio::task::spawn_blocking(move || files::write_file(&target, &content, &request.expected)).await;
+    let stat = match written.unwrap_or_else(|e| Err(WriteError::Failed(e.to_string()))) {
+        Ok(stat) => stat,
+        Err(WriteError::Conflict(message)) => return send_conflict(ConflictReason::Changed, message),
+        Err(WriteError::Failed(message)) => return send_file_response(tx, repo_path, lychee_id, &request.path, Err(message)),
+    };
+    tracing::info!(path = %relative, bytes = stat.size, created, "file written");
+
+    let (session_repo, session_id, agent) = (repo_path.to_string(), lychee_id.to_string(), state.agent.clone());
+    let position = tokio::task::spawn_blocking(move || transcript_length(&session_repo, &session_id, agent.as_ref()))
+        .await
+        .unwrap_or(0);
+    let edit = ManualEdit {
+        path: relative.clone(),
+        created,
+        sha256: stat.sha256.clone(),
+        at: chrono::Utc::now().to_rfc3339(),
+        position,
+    };
+    let recorded = update_session_metadata(state, repo_path, lychee_id, |m| m.edits.push(edit.clone())).await;
+    if !recorded {
+        tracing::warn!(path = %edit.path, "failed to record the edit in session metadata");
+    }
+
+    let written = Message::FileWritten {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        path: relative,
+        stat,
+        warning: (!recorded).then(|| format!("{} was saved, but the edit couldn't be added to the session history", edit.path)),
+    };
+    let _ = tx.send(serde_json::to_string(&written).unwrap());
+    if !recorded {
+        return;
+    }
+    let update = Message::SessionUpdate {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+        new_entries: serde_json::json!([edit.entry()]),
+    };
+    let _ = tx.send(serde_json::to_string(&update).unwrap());
+}
+
+/// What a browser asked of finish_worktree_session
+struct FinishOptions {
+    strategy: FinishStrategy,
+    auto_commit: bool,
+    commit_message: Option<String>,
+}
+
+/**
+ * Land a worktree session's branch on its base branch
+ *
+ * The base is the branch the session started from, or the branch checked out
+ * in the repo when it started from a tag or commit. Every step is reported
+ * with `finish_progress`, and the outcome with one `finish_result`.
+ */
+async fn finish_worktree_session(tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, options: FinishOptions, state: &AppState) {
+    let FinishOptions { strategy, auto_commit, commit_message } = options;
+    let send_result = |status: FinishStatus, base_branch: Option<String>, commit: Option<String>, files: Vec<String>, message: Option<String>| {
+        let result = Message::FinishResult {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            strategy,
+            status,
+            base_branch,
+            commit,
+            files,
+            message,
+        };
+        let _ = tx.send(serde_json::to_string(&result).unwrap());
+    };
+    let send_progress = |stage: FinishStage| {
+        let progress = Message::FinishProgress {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            stage,
+        };
+        let _ = tx.send(serde_json::to_string(&progress).unwrap());
+    };
+    let failed = |base_branch: Option<String>, message: String| {
+        tracing::warn!(%message, "finish failed");
+        send_result(FinishStatus::Failed, base_branch, None, Vec::new(), Some(message));
+    };
+
+    let repo = Path::new(repo_path);
+    let context = session_context(repo_path, lychee_id);
+    let metadata = std::fs::read_to_string(&context.session_info_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
+        .and_then(|info| info.sessions.get(lychee_id).cloned());
+    let Some(metadata) = metadata.filter(|m| m.is_worktree) else {
+        return failed(None, format!("{} is not a worktree session", lychee_id));
+    };
+    let Some(ref branch) = metadata.branch else {
+        return failed(None, "Session has no branch of its own".to_string());
+    };
+
+    let started_on_branch = match metadata.base_ref {
+        Some(ref base) => worktree::git(repo, &["show-ref", "--verify", "--quiet", &format!("refs/heads/{}", base)]).await.is_ok(),
+        None => false,
+    };
+    let base_branch = match metadata.base_ref {
+        Some(ref base) if started_on_branch => Some(base.clone()),
+        _ => worktree::current_branch(repo).await,
+    };
+    let Some(base) = base_branch.clone() else {
+        return failed(None, "No base branch to finish into, the repo's HEAD is detached".to_string());
+    };
+    if base == *branch {
+        return failed(base_branch, "The session's branch is its own base".to_string());
+    }
+
+    if busy_sessions(state).await.iter().any(|id| id == lychee_id) {
+        return failed(base_branch, "Claude is still working in this session".to_string());
+    }
+
+    let summary = metadata.title.clone().unwrap_or_else(|| format!("lychee session {}", lychee_id));
+    let commit_message = commit_message.filter(|m| !m.trim().is_empty());
+
+    let uncommitted = match worktree::uncommitted_changes(&context.working_dir).await {
+        Ok(files) => files,
+        Err(e) => return failed(base_branch, e),
+    };
+    if !uncommitted.is_empty() {
+        if !auto_commit {
+            let message = format!("{} file(s) have uncommitted changes", uncommitted.len());
+            return send_result(FinishStatus::Uncommitted, base_branch, None, uncommitted, Some(message));
+        }
+        send_progress(FinishStage::Committing);
+        let message = commit_message.clone().unwrap_or_else(|| summary.clone());
+        if let Err(e) = worktree::commit_all(&context.working_dir, &message).await {
+            return failed(base_branch, format!("Failed to commit outstanding changes: {}", e));
+        }
+    }
+
+    let message = match strategy {
+        FinishStrategy::Merge => commit_message.unwrap_or_else(|| format!("Merge branch '{}': {}", branch, summary)),
+        _ => commit_message.unwrap_or(summary),
+    };
+    let request = worktree::FinishRequest {
+        repo,
+        worktree_dir: &context.working_dir,
+        branch,
+        base: &base,
+        strategy,
+        message: &message,
+    };
+    match worktree::finish(&request, send_progress).await {
+        Ok(FinishOutcome::Finished(commit)) => {
+            // Diffs of the session now start from where it was merged
+            let new_base = match strategy {
+                // The squashed commits aren't in the base: the branch carries on from their squash instead
+                FinishStrategy::Squash => {
+                    if let Err(e) = worktree::git(&context.working_dir, &["reset", "--keep", "--quiet", &commit]).await {
+                        tracing::warn!(error = %e, "failed to move the session branch to the squashed commit");
+                    }
+                    Some(commit.clone())
+                }
+                _ => worktree::git(repo, &["merge-base", &base, branch]).await.ok().map(|merge_base| merge_base.trim().to_string()),
+            };
+            if let Some(new_base) = new_base {
+                update_session_metadata(state, repo_path, lychee_id, |metadata| metadata.base_commit = Some(new_base)).await;
+            }
+            tracing::info!(%base, %commit, "finished worktree session");
+            send_result(FinishStatus::Finished, base_branch, Some(commit), Vec::new(), None);
+        }
+        Ok(FinishOutcome::UpToDate) => {
+            send_result(FinishStatus::UpToDate, base_branch, None, Vec::new(), None);
+        }
+        Ok(FinishOutcome::Conflict(files)) => {
+            let message = format!("{} file(s) conflict with {}, nothing was changed", files.len(), base);
+            send_result(FinishStatus::Conflict, base_branch, None, files, Some(message));
+        }
+        Err(e) => failed(base_branch, e),
+    }
+}
+
+async fn create_session(state: &AppState, repo_path: &str) -> Option<String> {
+    let lychee_id = format!("session-{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    let lychee_dir = PathBuf::from(repo_path).join(".lychee");
+
+    // Create .lychee directory if it doesn't exist
+    if !lychee_dir.exists() {
         std::fs::create_dir(&lychee_dir).ok()?;
 
         // Add .lychee to git exclude
         let git_exclude_path = PathBuf::from(repo_path).join(".git").join("info").join("exclude");
-        if let Ok(mut exclude_content) = std::fs::read_to_string(&git_exclude_path) {
-            if !exclude_content.contains("/.lychee") {
-                exclude_content.push_str("\n/.lychee\n");
-                let _ = std::fs::write(&git_exclude_path, exclude_content);
-            }
+        if let Ok(mut exclude_content) = std::fs::read_to_string(&git_exclude_path)
+            && !exclude_content.contains("/.lychee")
+        {
+            exclude_content.push_str("\n/.lychee\n");
+            let _ = std::fs::write(&git_exclude_path, exclude_content);
         }
     }
 
-    // Create git worktree
-    let output = Command::new("git")
-        .arg("worktree")
-        .arg("add")
-        .arg(&session_dir)
-        .current_dir(repo_path)
-        .output()
-        .await
-        .ok()?;
+    // Update session info file (no worktree creation for regular sessions)
+    let _guard = state.metadata_lock.lock().await;
+    let session_info_path = lychee_dir.join(".session-info.json");
+    let mut session_info = if session_info_path.exists() {
+        std::fs::read_to_string(&session_info_path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
+            .unwrap_or_default()
+    } else {
+        SessionInfoFile { sessions: HashMap::new() }
+    };
+
+    session_info.sessions.insert(
+        lychee_id.clone(),
+        SessionMetadata {
+            claude_session_id: None,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            last_active: chrono::Utc::now().to_rfc3339(),
+            is_worktree: false,
+            settings: SessionSettings::default(),
+            archived: false,
+            branch: None,
+            title: None,
+            base_ref: None,
+            base_commit: None,
+            edits: Vec::new(),
+        },
+    );
+
+    std::fs::write(
+        session_info_path,
+        serde_json::to_string_pretty(&session_info).unwrap(),
+    ).ok()?;
+
+    tracing::info!(%lychee_id, "created regular session");
+
+    Some(lychee_id)
+}
+
+/**
+ * Create a session with its own worktree, on a new branch
+ *
+ * The branch defaults to `lychee/<lychee_id>` and starts at `base` (any
+ * branch, tag or commit), or at the repo's HEAD. Both are checked before
+ * anything is created; the resolved base commit is recorded so diffs and
+ * merges know where the session started.
+ */
+async fn create_worktree_session(
+    state: &AppState,
+    repo_path: &str,
+    base: Option<&str>,
+    branch: Option<&str>,
+) -> Result<String, String> {
+    let lychee_id = format!("session-{}", Uuid::new_v4().to_string().split('-').next().unwrap());
+    let repo = Path::new(repo_path);
+    let lychee_dir = repo.join(".lychee");
+    let session_dir = lychee_dir.join(&lychee_id);
+
+    let (base_ref, base_commit) = match base {
+        Some(base) => {
+            let commit = worktree::resolve_commit(repo, base).await?;
+            // A commit hash is its own record, only names are worth keeping
+            let is_name = worktree::git(repo, &["show-ref", "--quiet", "--", base]).await.is_ok();
+            (is_name.then(|| base.to_string()), commit)
+        }
+        None => (worktree::current_branch(repo).await, worktree::resolve_commit(repo, "HEAD").await?),
+    };
+
+    let branch = branch.map(str::to_string).unwrap_or_else(|| format!("lychee/{}", lychee_id));
+    worktree::validate_branch_name(repo, &branch).await?;
 
-    if !output.status.success() {
-        if debug {
-            eprintln!("❌ Failed to create worktree: {}", String::from_utf8_lossy(&output.stderr));
+    // Create .lychee directory if it doesn't exist
+    if !lychee_dir.exists() {
+        std::fs::create_dir(&lychee_dir).map_err(|e| format!("Failed to create {}: {}", lychee_dir.display(), e))?;
+
+        // Add .lychee to git exclude
+        let git_exclude_path = PathBuf::from(repo_path).join(".git").join("info").join("exclude");
+        if let Ok(mut exclude_content) = std::fs::read_to_string(&git_exclude_path)
+            && !exclude_content.contains("/.lychee")
+        {
+            exclude_content.push_str("\n/.lychee\n");
+            let _ = std::fs::write(&git_exclude_path, exclude_content);
         }
-        return None;
     }
 
+    worktree::add(repo, &session_dir, &branch, &base_commit).await?;
+
     // Update session info file
+    let _guard = state.metadata_lock.lock().await;
     let session_info_path = lychee_dir.join(".session-info.json");
     let mut session_info = if session_info_path.exists() {
         std::fs::read_to_string(&session_info_path)
@@ -616,26 +3104,125 @@ async fn create_worktree_session(repo_path: &str, debug: bool) -> Option<String>
             created_at: chrono::Utc::now().to_rfc3339(),
             last_active: chrono::Utc::now().to_rfc3339(),
             is_worktree: true,
+            settings: SessionSettings::default(),
+            archived: false,
+            branch: Some(branch.clone()),
+            title: None,
+            base_ref,
+            base_commit: Some(base_commit),
+            edits: Vec::new(),
         },
     );
 
-    std::fs::write(
-        session_info_path,
-        serde_json::to_string_pretty(&session_info).unwrap(),
-    ).ok()?;
+    std::fs::write(
+        &session_info_path,
+        serde_json::to_string_pretty(&session_info).unwrap(),
+    ).map_err(|e| format!("Failed to write {}: {}", session_info_path.display(), e))?;
+
+    tracing::info!(%lychee_id, %branch, "created worktree session");
+
+    Ok(lychee_id)
+}
+
+/**
+ * A session's transcript, with the edits made through write_file where they happened
+ */
+async fn load_session_history(repo_path: &str, lychee_id: &str, agent: &dyn AgentBackend) -> Value {
+    let entries = transcript_entries(repo_path, lychee_id, agent);
+    let edits = read_session_metadata(repo_path, lychee_id).map(|m| m.edits).unwrap_or_default();
+    let mut edits = edits.iter().peekable();
+    let mut messages = Vec::with_capacity(entries.len() + edits.len());
+    for (line, entry) in entries {
+        while let Some(edit) = edits.next_if(|edit| edit.position <= line) {
+            messages.push(edit.entry());
+        }
+        messages.push(entry);
+    }
+    messages.extend(edits.map(ManualEdit::entry));
+    serde_json::json!(messages)
+}
+
+fn read_session_metadata(repo_path: &str, lychee_id: &str) -> Option<SessionMetadata> {
+    let session_info_path = PathBuf::from(repo_path).join(".lychee").join(".session-info.json");
+    std::fs::read_to_string(&session_info_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
+        .and_then(|mut info| info.sessions.remove(lychee_id))
+}
+
+/**
+ * The agent's transcript file of a session, if it has one yet
+ */
+fn transcript_file(repo_path: &str, lychee_id: &str, agent: &dyn AgentBackend) -> Option<PathBuf> {
+    let meta = read_session_metadata(repo_path, lychee_id)?;
+    let claude_id = meta.claude_session_id?;
+
+    // Determine working directory based on session type
+    let working_dir = if meta.is_worktree {
+        PathBuf::from(repo_path).join(".lychee").join(lychee_id)
+    } else {
+        PathBuf::from(repo_path)
+    };
+    agent.locate_transcript(&working_dir, &claude_id)
+}
+
+/**
+ * Entries of the agent's transcript of a session, each with the number of
+ * non-blank lines before it
+ */
+fn transcript_entries(repo_path: &str, lychee_id: &str, agent: &dyn AgentBackend) -> Vec<(usize, Value)> {
+    let Some(file_path) = transcript_file(repo_path, lychee_id, agent) else {
+        // Empty if no history
+        return Vec::new();
+    };
+    tracing::debug!(%lychee_id, path = %file_path.display(), "loading Claude history");
 
-    if debug {
-        println!("✅ Created session: {}", lychee_id);
-    }
+    // Read JSONL file - each line is a JSON object
+    let Ok(content) = std::fs::read_to_string(&file_path) else {
+        tracing::warn!(%lychee_id, "no Claude session file found");
+        return Vec::new();
+    };
+    let messages: Vec<(usize, Value)> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .filter_map(|(line_number, line)| Some((line_number, agent.parse_entry(line)?)))
+        .collect();
 
-    Some(lychee_id)
+    tracing::debug!(%lychee_id, count = messages.len(), "loaded session history");
+    tracing::trace!(%lychee_id, ?messages, "session history");
+    messages
+}
+
+/**
+ * Non-blank lines in a session's transcript so far, without parsing them
+ */
+fn transcript_length(repo_path: &str, lychee_id: &str, agent: &dyn AgentBackend) -> usize {
+    let Some(file) = transcript_file(repo_path, lychee_id, agent).and_then(|path| std::fs::File::open(path).ok()) else {
+        return 0;
+    };
+    std::io::BufReader::new(file)
+        .split(b'\n')
+        .map_while(Result::ok)
+        .filter(|line| !line.trim_ascii().is_empty())
+        .count()
+}
+
+/// Where a session's agent runs and which agent conversation it continues
+struct SessionContext {
+    session_info_path: PathBuf,
+    working_dir: PathBuf,
+    claude_session_id: Option<String>,
+    settings: SessionSettings,
+    /// Where a worktree session's branch started
+    base_commit: Option<String>,
 }
 
-async fn load_session_history(repo_path: &str, lychee_id: &str, debug: bool) -> Value {
+fn session_context(repo_path: &str, lychee_id: &str) -> SessionContext {
     let lychee_dir = PathBuf::from(repo_path).join(".lychee");
     let session_info_path = lychee_dir.join(".session-info.json");
 
-    // Get session metadata
+    // Get session metadata to determine working directory
     let metadata = if session_info_path.exists() {
         std::fs::read_to_string(&session_info_path)
             .ok()
@@ -645,151 +3232,358 @@ async fn load_session_history(repo_path: &str, lychee_id: &str, debug: bool) ->
         None
     };
 
-    if let Some(ref meta) = metadata {
-        if let Some(ref claude_id) = meta.claude_session_id {
-            // Determine working directory based on session type
-            let is_worktree = meta.is_worktree;
-            let working_dir = if is_worktree {
-                lychee_dir.join(lychee_id)
-            } else {
-                PathBuf::from(repo_path)
-            };
+    let is_worktree = metadata.as_ref().map(|m| m.is_worktree).unwrap_or(false);
+    let working_dir = if is_worktree {
+        lychee_dir.join(lychee_id)
+    } else {
+        PathBuf::from(repo_path)
+    };
+
+    let (claude_session_id, settings, base_commit) = match metadata {
+        Some(m) => (m.claude_session_id, m.settings, m.base_commit),
+        None => (None, SessionSettings::default(), None),
+    };
+    SessionContext {
+        session_info_path,
+        working_dir,
+        claude_session_id,
+        settings,
+        base_commit,
+    }
+}
 
-            // Find the Claude session file
-            let session_file = find_claude_session_file(&working_dir, claude_id);
+/**
+ * Follows an agent's output for one session
+ *
+ * Strategy: Use the agent's stdout events as triggers to check the JSONL file
+ * The file is the source of truth - stdout is only parsed for the session id
+ * and for partial message deltas, which are forwarded as they come
+ */
+struct OutputFollower {
+    repo_path: String,
+    lychee_id: String,
+    context: SessionContext,
+    is_resuming_session: bool,
+    jsonl_file_path: Option<PathBuf>,
+    last_line_count: usize,
+    partials: PartialMessages,
+}
 
-            if let Some(file_path) = session_file {
-            if debug {
-                println!("Looking for Claude history at: {:?}", file_path);
-            }
+impl OutputFollower {
+    fn new(repo_path: &str, lychee_id: &str, context: SessionContext) -> OutputFollower {
+        OutputFollower {
+            repo_path: repo_path.to_string(),
+            lychee_id: lychee_id.to_string(),
+            is_resuming_session: context.claude_session_id.is_some(),
+            context,
+            jsonl_file_path: None,
+            last_line_count: 0,
+            partials: PartialMessages::default(),
+        }
+    }
 
-            // Read JSONL file - each line is a JSON object
-            if let Ok(content) = std::fs::read_to_string(&file_path) {
-                let mut messages = Vec::new();
-
-                // Parse each line as a separate JSON message
-                for line in content.lines() {
-                    if !line.trim().is_empty() {
-                        if let Ok(entry) = serde_json::from_str::<Value>(line) {
-                            // Check if this is a user or assistant message
-                            if let Some(msg_type) = entry.get("type").and_then(|t| t.as_str()) {
-                                if msg_type == "user" || msg_type == "assistant" {
-                                    // Extract the nested message object
-                                    if let Some(message) = entry.get("message") {
-                                        let mut enriched = message.clone();
-
-                                        // Preserve isSidechain flag from the entry
-                                        if let Some(is_sidechain) = entry.get("isSidechain") {
-                                            if let Some(obj) = enriched.as_object_mut() {
-                                                obj.insert("isSidechain".to_string(), is_sidechain.clone());
-                                            }
-                                        }
-
-                                        messages.push(enriched);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    async fn handle_line(&mut self, line: &str, tx: &mpsc::UnboundedSender<String>, state: &AppState) {
+        let agent = state.agent.as_ref();
 
-                if debug {
-                    println!("📖 Loaded {} messages for session {}", messages.len(), lychee_id);
-                    println!("   Messages: {:?}", messages);
-                }
+        // New sessions need to extract the session ID from the agent's first message
+        if self.context.claude_session_id.is_none()
+            && let Some(session_id) = agent.session_id_from_output(line)
+        {
+            self.context.claude_session_id = Some(session_id.clone());
 
-                return serde_json::json!(messages);
-            } else if debug {
-                println!("⚠️  No Claude session file found for session {}", lychee_id);
-            }
+            // Save session ID to metadata
+            update_session_metadata(state, &self.repo_path, &self.lychee_id, |metadata| {
+                metadata.claude_session_id = Some(session_id.clone());
+            })
+            .await;
+
+            tracing::debug!(claude_session_id = %session_id, "got Claude session ID");
+        }
+
+        // Deltas never touch the file, so there is nothing to check for them
+        if let Some(event) = agent.stream_event(line) {
+            if let Some(data) = self.partials.apply(&event) {
+                let stream_msg = Message::ClaudeStream {
+                    repo_path: self.repo_path.clone(),
+                    lychee_id: self.lychee_id.clone(),
+                    data,
+                };
+                let _ = tx.send(serde_json::to_string(&stream_msg).unwrap());
             }
+            return;
         }
+
+        self.check_transcript(tx, agent);
     }
 
-    // Return empty array if no history
-    serde_json::json!([])
+    /**
+     * Send whatever was added to the JSONL file since the last check
+     */
+    fn check_transcript(&mut self, tx: &mpsc::UnboundedSender<String>, agent: &dyn AgentBackend) {
+        // Locate the JSONL file once we have a session ID
+        if self.jsonl_file_path.is_none()
+            && let Some(ref claude_id) = self.context.claude_session_id
+            && let Some(file) = agent.locate_transcript(&self.context.working_dir, claude_id)
+        {
+            // Set baseline: where to start reading from
+            // Resuming: skip old messages (start from current file size)
+            // New session: send everything (start from line 0)
+            if self.is_resuming_session {
+                if let Ok(count) = count_file_lines(&file) {
+                    self.last_line_count = count;
+                }
+            } else {
+                self.last_line_count = 0;
+            }
+
+            self.jsonl_file_path = Some(file);
+
+            tracing::debug!(baseline = self.last_line_count, resuming = self.is_resuming_session, "found JSONL file");
+        }
+
+        if let Some(ref file_path) = self.jsonl_file_path {
+            send_incremental_update(
+                file_path,
+                &mut self.last_line_count,
+                tx,
+                &self.repo_path,
+                &self.lychee_id,
+                agent,
+            );
+        }
+    }
 }
 
 /**
- * Spawn Claude and watch the JSONL file for updates
- *
- * Strategy: Use Claude's stdout events as triggers to check the JSONL file
- * The file is the source of truth - we only read from disk, never parse stdout content
- * This eliminates streaming/loading collisions
+ * Wrap up a turn: bump last_active, title an untitled session, refresh the
+ * sessions list and end the stream
  */
-async fn spawn_claude(
-    tx: mpsc::UnboundedSender<String>,
+async fn finish_turn(
+    tx: &mpsc::UnboundedSender<String>,
     repo_path: &str,
     lychee_id: &str,
-    content: &str,
-    model: &str,
+    transcript: Option<&Path>,
     state: &AppState,
 ) {
-    let lychee_dir = PathBuf::from(repo_path).join(".lychee");
-    let session_info_path = lychee_dir.join(".session-info.json");
+    // Update metadata
+    update_session_metadata(state, repo_path, lychee_id, |metadata| {
+        metadata.last_active = chrono::Utc::now().to_rfc3339();
+        if metadata.title.is_none()
+            && let Some(title) = transcript.and_then(|file| state.agent.transcript_title(file))
+        {
+            tracing::debug!(%lychee_id, %title, "titled session");
+            metadata.title = Some(title);
+        }
+    })
+    .await;
 
-    // Get session metadata to determine working directory
-    let metadata = if session_info_path.exists() {
-        std::fs::read_to_string(&session_info_path)
-            .ok()
-            .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
-            .and_then(|info| info.sessions.get(lychee_id).cloned())
-    } else {
-        None
+    // Send updated sessions list
+    let sessions = list_sessions(repo_path, false).await;
+    let update_msg = Message::SessionsList {
+        repo_path: repo_path.to_string(),
+        sessions,
+        active_session_ids: None,
+        archived_sessions: None,
     };
+    let _ = tx.send(serde_json::to_string(&update_msg).unwrap());
 
-    let is_worktree = metadata.as_ref().map(|m| m.is_worktree).unwrap_or(false);
-    let working_dir = if is_worktree {
-        lychee_dir.join(lychee_id)
-    } else {
-        PathBuf::from(repo_path)
+    // Notify frontend that streaming has ended
+    let end_msg = Message::StreamEnd {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
+    };
+    let _ = tx.send(serde_json::to_string(&end_msg).unwrap());
+}
+
+/**
+ * Mark whether a session process is in a turn, if it is still the registered one
+ */
+async fn set_busy(state: &AppState, lychee_id: &str, run_id: Uuid, busy: bool) {
+    let mut processes = state.active_processes.write().await;
+    if let Some(process) = processes.get_mut(lychee_id)
+        && process.run_id == run_id
+    {
+        process.busy = busy;
+    }
+}
+
+/**
+ * Mark a session process busy and tell browsers a turn started
+ */
+async fn begin_turn(state: &AppState, tx: &mpsc::UnboundedSender<String>, repo_path: &str, lychee_id: &str, run_id: Uuid) {
+    set_busy(state, lychee_id, run_id, true).await;
+    let start_msg = Message::StreamStart {
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
     };
+    let _ = tx.send(serde_json::to_string(&start_msg).unwrap());
+}
+
+/// The input and output of a session process, owned by its driver task
+struct SessionIo {
+    input: Box<dyn AsyncWrite + Unpin + Send>,
+    output: Box<dyn AsyncRead + Unpin + Send>,
+    /// Queued user messages
+    input_rx: mpsc::UnboundedReceiver<String>,
+}
 
-    let is_resuming_session = metadata.as_ref().and_then(|m| m.claude_session_id.as_ref()).is_some();
-    let mut claude_session_id = metadata.as_ref().and_then(|m| m.claude_session_id.clone());
+/**
+ * Hand a message to the session's running process
+ * Returns false when there is none, or it must be restarted for another model or settings
+ */
+async fn send_to_session(state: &AppState, lychee_id: &str, content: &str, model: &str, settings: &SessionSettings) -> bool {
+    let mut processes = state.active_processes.write().await;
+    let Some(process) = processes.get(lychee_id) else {
+        return false;
+    };
+    let Some(ref input) = process.input else {
+        return false;
+    };
 
-    // Build Claude command
-    let mut cmd = Command::new("claude");
-    cmd.current_dir(&working_dir);
-    cmd.stdout(std::process::Stdio::piped());
-    cmd.stderr(std::process::Stdio::null());
+    // A different model or settings need a new process; mid-turn messages steer the current one
+    if (process.model != model || process.settings != *settings) && !process.busy {
+        tracing::info!(%lychee_id, model, "restarting agent session for new model or settings");
+    } else if input.send(content.to_string()).is_ok() {
+        return true;
+    }
 
-    if let Some(ref claude_id) = claude_session_id {
-        cmd.arg("--resume").arg(claude_id);
+    if let Some(mut process) = processes.remove(lychee_id) {
+        state.agent.cancel(&mut process.handle);
     }
+    false
+}
+
+/**
+ * Drive a long-lived agent session process
+ *
+ * Writes queued user messages to the agent's input, including ones sent while
+ * it is working, and follows its output across turns. The process is stopped
+ * after `idle_timeout` without a turn; the next message starts a new one that
+ * resumes the conversation.
+ */
+async fn run_session(
+    tx: mpsc::UnboundedSender<String>,
+    repo_path: String,
+    lychee_id: String,
+    run_id: Uuid,
+    context: SessionContext,
+    io: SessionIo,
+    state: AppState,
+) {
+    let SessionIo { mut input, output, mut input_rx } = io;
+    let mut reader = BufReader::new(output).lines();
+    let mut follower = OutputFollower::new(&repo_path, &lychee_id, context);
+    let mut busy = false;
+
+    // A resumed conversation already has a transcript: take the baseline
+    // before our first message is written to it
+    follower.check_transcript(&tx, state.agent.as_ref());
+
+    loop {
+        tokio::select! {
+            prompt = input_rx.recv() => {
+                // The sender is dropped when the process is replaced or shut down
+                let Some(prompt) = prompt else { break };
+
+                let line = state.agent.session_input(&prompt);
+                if let Err(e) = input.write_all(line.as_bytes()).await {
+                    tracing::warn!(error = %e, "failed to write to agent session");
+                    break;
+                }
+                let _ = input.flush().await;
+
+                if !busy {
+                    busy = true;
+                    begin_turn(&state, &tx, &repo_path, &lychee_id, run_id).await;
+                } else {
+                    tracing::debug!("queued message for running turn");
+                }
+            }
+            line = reader.next_line() => {
+                let Ok(Some(line)) = line else { break };
+                if line.trim().is_empty() {
+                    continue;
+                }
 
-    cmd.arg("-p").arg(content);
-    cmd.arg("--model").arg(model);
-    cmd.arg("--output-format").arg("stream-json");
-    cmd.arg("--dangerously-skip-permissions");
+                let is_turn_end = state.agent.is_turn_end(&line);
 
-    if state.debug {
-        println!("🚀 Spawning Claude for session {}", lychee_id);
-        println!("   Model: {}", model);
-        if let Some(ref id) = claude_session_id {
-            println!("   Resuming Claude session: {}", id);
+                // Output between turns means a queued message started a new one
+                if !busy && !is_turn_end {
+                    busy = true;
+                    begin_turn(&state, &tx, &repo_path, &lychee_id, run_id).await;
+                }
+
+                follower.handle_line(&line, &tx, &state).await;
+
+                if is_turn_end && busy {
+                    busy = false;
+                    set_busy(&state, &lychee_id, run_id, false).await;
+                    let transcript = follower.jsonl_file_path.as_deref();
+                    finish_turn(&tx, &repo_path, &lychee_id, transcript, &state).await;
+                    tracing::info!("turn finished");
+                }
+            }
+            _ = tokio::time::sleep(state.idle_timeout), if !busy => {
+                tracing::info!("stopping idle agent session");
+                break;
+            }
         }
     }
 
-    // Spawn Claude
-    let mut child = match cmd.spawn() {
-        Ok(child) => child,
-        Err(e) => {
-            let error = Message::Error {
-                repo_path: Some(repo_path.to_string()),
-                message: format!("Failed to spawn Claude: {}", e),
-            };
-            let _ = tx.send(serde_json::to_string(&error).unwrap());
-            return;
+    // Unregister, unless the process was already replaced
+    let process = {
+        let mut processes = state.active_processes.write().await;
+        match processes.get(&lychee_id) {
+            Some(process) if process.run_id == run_id => processes.remove(&lychee_id),
+            _ => None,
         }
     };
+    if let Some(mut process) = process {
+        state.agent.cancel(&mut process.handle);
+    }
+
+    // The process died mid-turn: send what it managed to write
+    if busy {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        follower.check_transcript(&tx, state.agent.as_ref());
+        let transcript = follower.jsonl_file_path.as_deref();
+        finish_turn(&tx, &repo_path, &lychee_id, transcript, &state).await;
+    }
 
-    let stdout = match child.stdout.take() {
-        Some(stdout) => stdout,
-        None => {
+    tracing::debug!("agent session ended");
+}
+
+/**
+ * Run a single turn with a backend that has no session processes
+ */
+async fn spawn_claude(
+    tx: mpsc::UnboundedSender<String>,
+    repo_path: &str,
+    lychee_id: &str,
+    content: &str,
+    model: &str,
+    state: &AppState,
+) {
+    let context = session_context(repo_path, lychee_id);
+
+    let permission_prompt = state.permissions.as_ref().map(|broker| broker.prompt(lychee_id));
+    let turn = TurnRequest {
+        working_dir: &context.working_dir,
+        prompt: content,
+        model,
+        settings: &context.settings,
+        permissions: permission_prompt.as_ref(),
+    };
+    let started = match context.claude_session_id {
+        Some(ref claude_id) => state.agent.resume_turn(&turn, claude_id),
+        None => state.agent.start_turn(&turn),
+    };
+    let (agent, stdout) = match started {
+        Ok(running) => (running.handle, running.output),
+        Err(message) => {
             let error = Message::Error {
                 repo_path: Some(repo_path.to_string()),
-                message: "Failed to capture stdout".to_string(),
+                message,
             };
             let _ = tx.send(serde_json::to_string(&error).unwrap());
             return;
@@ -800,145 +3594,47 @@ async fn spawn_claude(
     // Store process in active list
     {
         let mut processes = state.active_processes.write().await;
-        processes.insert(lychee_id.to_string(), child);
+        processes.insert(lychee_id.to_string(), AgentProcess {
+            handle: agent,
+            input: None,
+            model: model.to_string(),
+            settings: context.settings.clone(),
+            busy: true,
+            run_id: Uuid::new_v4(),
+        });
     }
 
-    let lychee_id_str = lychee_id.to_string();
-    let repo_path_str = repo_path.to_string();
-
     // Notify frontend that streaming has started
     let start_msg = Message::StreamStart {
-        repo_path: repo_path_str.clone(),
-        lychee_id: lychee_id_str.clone(),
+        repo_path: repo_path.to_string(),
+        lychee_id: lychee_id.to_string(),
     };
     let _ = tx.send(serde_json::to_string(&start_msg).unwrap());
 
-    // File watching setup: Use stdout events as triggers to check the JSONL file
-    // We don't parse stdout content - just use it to know when to check the file
-    let mut jsonl_file_path: Option<PathBuf> = None;
-    let mut last_line_count: usize = 0;
+    let mut follower = OutputFollower::new(repo_path, lychee_id, context);
 
     // Watch stdout for events - each event triggers a file check
     while let Ok(Some(line)) = reader.next_line().await {
         if line.trim().is_empty() {
             continue;
         }
-
-        // New sessions need to extract the session ID from Claude's first message
-        if claude_session_id.is_none() {
-            if let Ok(data) = serde_json::from_str::<Value>(&line) {
-                if let Some(session_id) = data.get("session_id").and_then(|v| v.as_str()) {
-                    claude_session_id = Some(session_id.to_string());
-
-                    // Save session ID to metadata
-                    if let Some(mut info) = std::fs::read_to_string(&session_info_path)
-                        .ok()
-                        .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
-                    {
-                        if let Some(metadata) = info.sessions.get_mut(&lychee_id_str) {
-                            metadata.claude_session_id = Some(session_id.to_string());
-                            let _ = std::fs::write(
-                                &session_info_path,
-                                serde_json::to_string_pretty(&info).unwrap(),
-                            );
-                        }
-                    }
-
-                    if state.debug {
-                        println!("📝 Got Claude session ID: {}", session_id);
-                    }
-                }
-            }
-        }
-
-        // Locate the JSONL file once we have a session ID
-        if claude_session_id.is_some() && jsonl_file_path.is_none() {
-            if let Some(file) = find_claude_session_file(&working_dir, claude_session_id.as_ref().unwrap()) {
-                jsonl_file_path = Some(file);
-
-                // Set baseline: where to start reading from
-                // Resuming: skip old messages (start from current file size)
-                // New session: send everything (start from line 0)
-                if is_resuming_session {
-                    if let Ok(count) = count_file_lines(&jsonl_file_path.as_ref().unwrap()) {
-                        last_line_count = count;
-                    }
-                } else {
-                    last_line_count = 0;
-                }
-
-                if state.debug {
-                    println!("📁 Found JSONL file, baseline: {} lines (resuming: {})", last_line_count, is_resuming_session);
-                }
-            }
-        }
-
-        // Stdout event triggered - check if file has new content
-        if let Some(ref file_path) = jsonl_file_path {
-            send_incremental_update(
-                file_path,
-                &mut last_line_count,
-                &tx,
-                &repo_path_str,
-                &lychee_id_str,
-                state.debug
-            );
-        }
+        follower.handle_line(&line, &tx, state).await;
     }
 
     // Final check after Claude exits (file might have buffered writes)
     tokio::time::sleep(Duration::from_millis(200)).await;
+    follower.check_transcript(&tx, state.agent.as_ref());
 
-    if let Some(ref file_path) = jsonl_file_path {
-        send_incremental_update(
-            file_path,
-            &mut last_line_count,
-            &tx,
-            &repo_path_str,
-            &lychee_id_str,
-            state.debug
-        );
-    }
-
-    // Update metadata
-    if let Some(mut info) = std::fs::read_to_string(&session_info_path)
-        .ok()
-        .and_then(|s| serde_json::from_str::<SessionInfoFile>(&s).ok())
-    {
-        if let Some(metadata) = info.sessions.get_mut(&lychee_id_str) {
-            metadata.last_active = chrono::Utc::now().to_rfc3339();
-            let _ = std::fs::write(
-                &session_info_path,
-                serde_json::to_string_pretty(&info).unwrap(),
-            );
-        }
-    }
-
-    // Send updated sessions list
-    let sessions = list_sessions(&repo_path_str).await;
-    let update_msg = Message::SessionsList {
-        repo_path: repo_path_str.clone(),
-        sessions,
-        active_session_ids: None,
-    };
-    let _ = tx.send(serde_json::to_string(&update_msg).unwrap());
-
-    // Notify frontend that streaming has ended
-    let end_msg = Message::StreamEnd {
-        repo_path: repo_path_str.clone(),
-        lychee_id: lychee_id_str.clone(),
-    };
-    let _ = tx.send(serde_json::to_string(&end_msg).unwrap());
+    let transcript = follower.jsonl_file_path.as_deref();
+    finish_turn(&tx, repo_path, lychee_id, transcript, state).await;
 
     // Remove from active processes
     {
         let mut processes = state.active_processes.write().await;
-        processes.remove(&lychee_id_str);
+        processes.remove(lychee_id);
     }
 
-    if state.debug {
-        println!("✅ Claude finished for session {}", lychee_id_str);
-    }
+    tracing::info!("Claude finished");
 }
 
 /**
@@ -959,7 +3655,7 @@ fn send_incremental_update(
     tx: &mpsc::UnboundedSender<String>,
     repo_path: &str,
     lychee_id: &str,
-    debug: bool,
+    agent: &dyn AgentBackend,
 ) {
     // Read all lines from file
     let file = match std::fs::File::open(file_path) {
@@ -969,7 +3665,7 @@ fn send_incremental_update(
 
     let all_lines: Vec<String> = std::io::BufReader::new(file)
         .lines()
-        .filter_map(Result::ok)
+        .map_while(Result::ok)
         .collect();
 
     let current_count = all_lines.len();
@@ -979,14 +3675,12 @@ fn send_incremental_update(
         return;
     }
 
-    if debug {
-        println!("📥 Reading {} new lines (total: {})", current_count - *last_line_count, current_count);
-    }
+    tracing::debug!(new = current_count - *last_line_count, total = current_count, "reading new JSONL lines");
 
     // Parse new entries
     let new_entries: Vec<Value> = all_lines[*last_line_count..]
         .iter()
-        .filter_map(|line| parse_jsonl_entry(line))
+        .filter_map(|line| agent.parse_entry(line))
         .collect();
 
     if !new_entries.is_empty() {
@@ -1001,87 +3695,17 @@ fn send_incremental_update(
     *last_line_count = current_count;
 }
 
-/**
- * Parse a single JSONL line into a message entry
- * Preserves isSidechain flag for frontend filtering
- */
-fn parse_jsonl_entry(line: &str) -> Option<Value> {
-    let entry: Value = serde_json::from_str(line).ok()?;
-
-    // Only include user and assistant messages
-    let msg_type = entry.get("type")?.as_str()?;
-    if msg_type != "user" && msg_type != "assistant" {
-        return None;
-    }
-
-    // Extract message object
-    let message = entry.get("message")?;
-    let mut enriched = message.clone();
-
-    // Preserve isSidechain flag from entry
-    if let Some(is_sidechain) = entry.get("isSidechain") {
-        if let Some(obj) = enriched.as_object_mut() {
-            obj.insert("isSidechain".to_string(), is_sidechain.clone());
-        }
-    }
-
-    Some(enriched)
-}
-
-/**
- * Find Claude's JSONL file for a session
- * Searches in ~/.claude/projects/ directories
- */
-fn find_claude_session_file(working_dir: &PathBuf, claude_session_id: &str) -> Option<PathBuf> {
-    let home_dir = std::env::var("HOME").ok()?;
-    let projects_dir = PathBuf::from(&home_dir).join(".claude").join("projects");
-    let session_filename = format!("{}.jsonl", claude_session_id);
-
-    // Sanitize the working directory path to match Claude's project directory naming
-    let path_str = working_dir.display().to_string();
-    let sanitized = path_str
-        .trim_start_matches('/')
-        .replace('/', "-")
-        .replace('.', "-");
-    let sanitized_path = format!("-{}", sanitized);
-
-    // Try the expected sanitized path first
-    let expected_file = projects_dir.join(&sanitized_path).join(&session_filename);
-    if expected_file.exists() {
-        return Some(expected_file);
-    }
-
-    eprintln!("⚠️  Expected path not found: {:?}", expected_file);
-    eprintln!("🔍 Searching all project directories for session file...");
-
-    // If not found, search through all project directories for a match
-    // This handles cases where Claude's path sanitization differs from ours
-    if let Ok(entries) = std::fs::read_dir(&projects_dir) {
-        for entry in entries.filter_map(Result::ok) {
-            let dir_path = entry.path();
-            if !dir_path.is_dir() {
-                continue;
-            }
-
-            let possible_file = dir_path.join(&session_filename);
-            if possible_file.exists() {
-                eprintln!("✅ Found session file via fallback search: {:?}", possible_file);
-                return Some(possible_file);
-            }
-        }
-    }
-
-    eprintln!("❌ Session file not found after exhaustive search");
-    None
-}
-
 async fn render_tui(state: &Arc<AppState>) {
     let mut stdout = stdout();
     stdout.execute(cursor::MoveTo(0, 0)).ok();
     stdout.execute(terminal::Clear(ClearType::All)).ok();
 
-    let processes = state.active_processes.read().await;
-    let is_active = !processes.is_empty();
+    let (busy, idle) = {
+        let processes = state.active_processes.read().await;
+        let busy = processes.values().filter(|process| process.busy).count();
+        (busy, processes.len() - busy)
+    };
+    let is_active = busy > 0;
 
     // Update and get animation frame
     let frame = {
@@ -1116,13 +3740,15 @@ async fn render_tui(state: &Arc<AppState>) {
         .to_string();
 
     // Display the cat
-    stdout.execute(SetForegroundColor(Color::Cyan)).ok();
-    for line in cat.lines() {
-        stdout.execute(Print(format!("{}\n", line))).ok();
-    }
-    stdout.execute(ResetColor).ok();
+    if state.config.tui.show_cat {
+        stdout.execute(SetForegroundColor(Color::Cyan)).ok();
+        for line in cat.lines() {
+            stdout.execute(Print(format!("{}\n", line))).ok();
+        }
+        stdout.execute(ResetColor).ok();
 
-    stdout.execute(Print("\n")).ok();
+        stdout.execute(Print("\n")).ok();
+    }
 
     // Title
     stdout.execute(SetForegroundColor(Color::Magenta)).ok();
@@ -1137,12 +3763,12 @@ async fn render_tui(state: &Arc<AppState>) {
 
     // Repository info
     stdout.execute(SetForegroundColor(Color::Blue)).ok();
-    stdout.execute(Print(format!("  Repository: "))).ok();
+    stdout.execute(Print("  Repository: ")).ok();
     stdout.execute(ResetColor).ok();
     stdout.execute(Print(format!("{}\n", repo_name))).ok();
 
     stdout.execute(SetForegroundColor(Color::Blue)).ok();
-    stdout.execute(Print(format!("  Path:       "))).ok();
+    stdout.execute(Print("  Path:       ")).ok();
     stdout.execute(ResetColor).ok();
     stdout.execute(SetForegroundColor(Color::DarkGrey)).ok();
     stdout.execute(Print(format!("{}\n", repo_path))).ok();
@@ -1158,16 +3784,66 @@ async fn render_tui(state: &Arc<AppState>) {
     if is_active {
         stdout.execute(SetForegroundColor(Color::Green)).ok();
         stdout.execute(Print(format!("● Active ({} session{})\n",
-            processes.len(),
-            if processes.len() == 1 { "" } else { "s" }))).ok();
+            busy,
+            if busy == 1 { "" } else { "s" }))).ok();
     } else {
         stdout.execute(SetForegroundColor(Color::Yellow)).ok();
         stdout.execute(Print("● Waiting for messages\n")).ok();
     }
+    if idle > 0 {
+        stdout.execute(SetForegroundColor(Color::DarkGrey)).ok();
+        stdout.execute(Print(format!("              {} idle agent{} kept warm\n",
+            idle,
+            if idle == 1 { "" } else { "s" }))).ok();
+    }
+    let commands = state.commands.read().await.len();
+    if commands > 0 {
+        stdout.execute(SetForegroundColor(Color::DarkGrey)).ok();
+        stdout.execute(Print(format!("              {} command{} running\n",
+            commands,
+            if commands == 1 { "" } else { "s" }))).ok();
+    }
+    let previews = state.previews.read().await.len();
+    if previews > 0 {
+        stdout.execute(SetForegroundColor(Color::DarkGrey)).ok();
+        stdout.execute(Print(format!("              {} port{} exposed for previews\n",
+            previews,
+            if previews == 1 { "" } else { "s" }))).ok();
+    }
     stdout.execute(ResetColor).ok();
 
     stdout.execute(Print("\n")).ok();
 
+    // Open terminals, oldest first
+    let mut terminals: Vec<(String, String, String, i64)> = state
+        .terminals
+        .read()
+        .await
+        .iter()
+        .map(|(terminal_id, terminal)| {
+            let age = (chrono::Utc::now() - terminal.opened_at).num_seconds();
+            (terminal_id.clone(), terminal.lychee_id.clone(), terminal.shell.clone(), age)
+        })
+        .collect();
+    if !terminals.is_empty() {
+        terminals.sort_by_key(|terminal| std::cmp::Reverse(terminal.3));
+        stdout.execute(SetForegroundColor(Color::Blue)).ok();
+        stdout.execute(Print(format!("  Terminals:  {} open\n", terminals.len()))).ok();
+        stdout.execute(ResetColor).ok();
+        stdout.execute(SetForegroundColor(Color::DarkGrey)).ok();
+        for (terminal_id, lychee_id, shell, age) in terminals {
+            stdout.execute(Print(format!("              {} {} {} ({}:{:02})\n",
+                terminal_id,
+                lychee_id,
+                shell,
+                age / 60,
+                age % 60))).ok();
+        }
+        stdout.execute(ResetColor).ok();
+
+        stdout.execute(Print("\n")).ok();
+    }
+
     // Uptime
     stdout.execute(SetForegroundColor(Color::Blue)).ok();
     stdout.execute(Print("  Uptime:     ")).ok();
@@ -1176,6 +3852,18 @@ async fn render_tui(state: &Arc<AppState>) {
 
     stdout.execute(Print("\n")).ok();
 
+    // Pairing code for browsers in E2E mode
+    if let Some(ref e2e) = state.e2e {
+        stdout.execute(SetForegroundColor(Color::Blue)).ok();
+        stdout.execute(Print("  E2E code:   ")).ok();
+        stdout.execute(ResetColor).ok();
+        stdout.execute(SetForegroundColor(Color::Green)).ok();
+        stdout.execute(Print(format!("{}\n", e2e.pairing_code()))).ok();
+        stdout.execute(ResetColor).ok();
+
+        stdout.execute(Print("\n")).ok();
+    }
+
     // Client count
     let client_count = state.client_count.read().await;
     stdout.execute(SetForegroundColor(Color::Blue)).ok();
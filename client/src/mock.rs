@@ -0,0 +1,335 @@
+use crate::backend::{
+    find_claude_session_file, project_dir_name, AgentBackend, AgentHandle, RunningSession, RunningTurn, SessionRequest, TurnRequest,
+};
+use crate::permissions::{self, PermissionPrompt};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use uuid::Uuid;
+
+/// Pause between the steps of a mock turn, so streaming is visible in the UI
+const STEP_DELAY: Duration = Duration::from_millis(400);
+
+/// Pause between the text deltas of a streamed block
+const DELTA_DELAY: Duration = Duration::from_millis(40);
+
+/**
+ * In-process fake agent for developing the UI without the claude binary
+ *
+ * Each turn writes JSONL entries the way Claude does (text, tool_use,
+ * tool_result and a sidechain from a Task call) into a fake projects
+ * directory, printing a stream-json line after each write. Assistant
+ * messages are streamed word by word first, like --include-partial-messages.
+ * Sessions read stream-json user messages and run one turn per message.
+ * In --permission-mode prompt the Bash call asks for approval first.
+ */
+pub struct MockBackend {
+    projects_dir: PathBuf,
+}
+
+impl MockBackend {
+    pub fn new(projects_dir: PathBuf) -> MockBackend {
+        MockBackend { projects_dir }
+    }
+
+    fn start(&self, turn: &TurnRequest, session_id: Option<&str>) -> RunningTurn {
+        let (stdout, reader) = tokio::io::duplex(64 * 1024);
+        let session_id = session_id.map(str::to_string).unwrap_or_else(|| Uuid::new_v4().to_string());
+        let project_dir = self.projects_dir.join(project_dir_name(turn.working_dir));
+        let working_dir = turn.working_dir.to_path_buf();
+        let prompt = turn.prompt.to_string();
+        let model = turn.model.to_string();
+        let permissions = turn.permissions.cloned();
+
+        tracing::info!(%model, %session_id, "starting mock agent");
+
+        let task = tokio::spawn(async move {
+            let mut turn = MockTurn {
+                stdout,
+                transcript: project_dir.join(format!("{}.jsonl", session_id)),
+                session_id,
+                working_dir,
+                model,
+                permissions,
+                parent_uuid: None,
+            };
+            if let Err(e) = turn.run(&prompt).await {
+                tracing::warn!(error = %e, "mock turn failed");
+            }
+        });
+
+        RunningTurn {
+            handle: AgentHandle::Task(task),
+            output: Box::new(reader),
+        }
+    }
+
+    fn open_session(&self, request: &SessionRequest, session_id: Option<&str>) -> RunningSession {
+        let (stdout, reader) = tokio::io::duplex(64 * 1024);
+        let (writer, stdin) = tokio::io::duplex(64 * 1024);
+        let session_id = session_id.map(str::to_string).unwrap_or_else(|| Uuid::new_v4().to_string());
+        let project_dir = self.projects_dir.join(project_dir_name(request.working_dir));
+        let working_dir = request.working_dir.to_path_buf();
+        let model = request.model.to_string();
+        let permissions = request.permissions.cloned();
+
+        tracing::info!(%model, %session_id, "starting mock agent session");
+
+        let task = tokio::spawn(async move {
+            let mut turn = MockTurn {
+                stdout,
+                transcript: project_dir.join(format!("{}.jsonl", session_id)),
+                session_id,
+                working_dir,
+                model,
+                permissions,
+                parent_uuid: None,
+            };
+
+            // Messages sent mid-turn simply wait for the next turn
+            let mut lines = BufReader::new(stdin).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Some(prompt) = serde_json::from_str::<Value>(&line)
+                    .ok()
+                    .and_then(|input| input["message"]["content"].as_str().map(str::to_string))
+                else {
+                    tracing::warn!(%line, "mock session ignoring unknown input");
+                    continue;
+                };
+                if let Err(e) = turn.run(&prompt).await {
+                    tracing::warn!(error = %e, "mock turn failed");
+                    break;
+                }
+            }
+        });
+
+        RunningSession {
+            handle: AgentHandle::Task(task),
+            input: Box::new(writer),
+            output: Box::new(reader),
+        }
+    }
+}
+
+impl AgentBackend for MockBackend {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn start_turn(&self, turn: &TurnRequest) -> Result<RunningTurn, String> {
+        Ok(self.start(turn, None))
+    }
+
+    fn resume_turn(&self, turn: &TurnRequest, session_id: &str) -> Result<RunningTurn, String> {
+        Ok(self.start(turn, Some(session_id)))
+    }
+
+    fn start_session(&self, request: &SessionRequest, session_id: Option<&str>) -> Option<Result<RunningSession, String>> {
+        Some(Ok(self.open_session(request, session_id)))
+    }
+
+    fn locate_transcript(&self, working_dir: &Path, session_id: &str) -> Option<PathBuf> {
+        find_claude_session_file(&self.projects_dir, working_dir, session_id)
+    }
+}
+
+struct MockTurn {
+    stdout: DuplexStream,
+    transcript: PathBuf,
+    session_id: String,
+    working_dir: PathBuf,
+    model: String,
+    permissions: Option<PermissionPrompt>,
+    parent_uuid: Option<String>,
+}
+
+impl MockTurn {
+    async fn run(&mut self, prompt: &str) -> std::io::Result<()> {
+        if let Some(dir) = self.transcript.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        self.emit(json!({
+            "type": "system",
+            "subtype": "init",
+            "session_id": self.session_id,
+            "model": self.model,
+            "cwd": self.working_dir.display().to_string(),
+            "tools": ["Bash", "Read", "Task"],
+        })).await?;
+
+        self.user(json!(prompt), false).await?;
+
+        self.assistant(json!([{ "type": "text", "text": "Let me take a look at the repository first." }]), false).await?;
+
+        let ls_id = tool_id();
+        let ls_input = json!({ "command": "ls", "description": "List files in the working directory" });
+        self.assistant(json!([{
+            "type": "tool_use",
+            "id": ls_id,
+            "name": "Bash",
+            "input": ls_input,
+        }]), false).await?;
+        let verdict = match self.permissions {
+            Some(ref prompt) => permissions::ask(prompt, "Bash", &ls_input).await,
+            None => permissions::Verdict { allow: true, message: None },
+        };
+        let output = if verdict.allow {
+            list_dir(&self.working_dir)
+        } else {
+            verdict.message.unwrap_or_default()
+        };
+        self.tool_result(&ls_id, &output, false).await?;
+
+        // A Task call runs a subagent, whose messages are marked as sidechain
+        let task_id = tool_id();
+        self.assistant(json!([{
+            "type": "tool_use",
+            "id": task_id,
+            "name": "Task",
+            "input": { "description": "Summarize the project", "prompt": "Read the README and summarize the project" },
+        }]), false).await?;
+        self.user(json!("Read the README and summarize the project"), true).await?;
+        self.assistant(json!([{ "type": "text", "text": "This looks like a small project. (mock subagent)" }]), true).await?;
+        self.tool_result(&task_id, "This looks like a small project. (mock subagent)", false).await?;
+
+        let reply = format!("This is a mock reply from {}. You said:\n\n> {}", self.model, prompt);
+        self.assistant(json!([{ "type": "text", "text": reply }]), false).await?;
+
+        self.emit(json!({
+            "type": "result",
+            "subtype": "success",
+            "is_error": false,
+            "session_id": self.session_id,
+            "result": reply,
+            "num_turns": 3,
+            "total_cost_usd": 0.0,
+        })).await
+    }
+
+    async fn user(&mut self, content: Value, sidechain: bool) -> std::io::Result<()> {
+        let message = json!({ "role": "user", "content": content });
+        self.record("user", message, sidechain).await
+    }
+
+    async fn assistant(&mut self, content: Value, sidechain: bool) -> std::io::Result<()> {
+        let id = format!("msg_mock_{}", Uuid::new_v4().simple());
+        if !sidechain {
+            self.stream(&id, &content).await?;
+        }
+
+        let message = json!({
+            "id": id,
+            "type": "message",
+            "role": "assistant",
+            "model": self.model,
+            "content": content,
+            "stop_reason": null,
+        });
+        self.record("assistant", message, sidechain).await
+    }
+
+    async fn tool_result(&mut self, tool_use_id: &str, output: &str, sidechain: bool) -> std::io::Result<()> {
+        let content = json!([{ "type": "tool_result", "tool_use_id": tool_use_id, "content": output }]);
+        self.user(content, sidechain).await
+    }
+
+    /**
+     * Emit the partial message events Claude prints before persisting a message
+     */
+    async fn stream(&mut self, id: &str, content: &Value) -> std::io::Result<()> {
+        self.stream_event(json!({
+            "type": "message_start",
+            "message": { "id": id, "type": "message", "role": "assistant", "model": self.model, "content": [] },
+        })).await?;
+
+        for (index, block) in content.as_array().into_iter().flatten().enumerate() {
+            let block_type = block["type"].as_str().unwrap_or("text");
+            let start = match block_type {
+                "text" => json!({ "type": "text", "text": "" }),
+                _ => json!({ "type": block_type, "id": block["id"], "name": block["name"], "input": {} }),
+            };
+            self.stream_event(json!({ "type": "content_block_start", "index": index, "content_block": start })).await?;
+
+            if let Some(text) = block["text"].as_str() {
+                for word in text.split_inclusive(' ') {
+                    tokio::time::sleep(DELTA_DELAY).await;
+                    self.stream_event(json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": { "type": "text_delta", "text": word },
+                    })).await?;
+                }
+            }
+            self.stream_event(json!({ "type": "content_block_stop", "index": index })).await?;
+        }
+
+        self.stream_event(json!({ "type": "message_stop" })).await
+    }
+
+    async fn stream_event(&mut self, event: Value) -> std::io::Result<()> {
+        self.emit(json!({
+            "type": "stream_event",
+            "event": event,
+            "session_id": self.session_id,
+            "parent_tool_use_id": null,
+        })).await
+    }
+
+    /**
+     * Append an entry to the transcript, then announce it on stdout
+     */
+    async fn record(&mut self, entry_type: &str, message: Value, sidechain: bool) -> std::io::Result<()> {
+        tokio::time::sleep(STEP_DELAY).await;
+
+        let uuid = Uuid::new_v4().to_string();
+        let entry = json!({
+            "type": entry_type,
+            "uuid": uuid,
+            "parentUuid": self.parent_uuid,
+            "sessionId": self.session_id,
+            "isSidechain": sidechain,
+            "cwd": self.working_dir.display().to_string(),
+            "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "message": message,
+        });
+        self.parent_uuid = Some(uuid);
+
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.transcript)?;
+        writeln!(file, "{}", entry)?;
+
+        self.emit(json!({
+            "type": entry_type,
+            "message": entry["message"],
+            "session_id": self.session_id,
+            "parent_tool_use_id": null,
+        })).await
+    }
+
+    async fn emit(&mut self, line: Value) -> std::io::Result<()> {
+        self.stdout.write_all(format!("{}\n", line).as_bytes()).await
+    }
+}
+
+fn tool_id() -> String {
+    format!("toolu_mock_{}", Uuid::new_v4().simple())
+}
+
+/**
+ * Real directory listing so tool results look like the repo being worked on
+ */
+fn list_dir(dir: &Path) -> String {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|name| !name.starts_with('.'))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names.join("\n")
+}
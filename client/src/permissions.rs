@@ -0,0 +1,546 @@
+use crate::Message;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+/// The approval tool as Claude names it: server "lychee", tool "approve"
+pub const PROMPT_TOOL: &str = "mcp__lychee__approve";
+
+/// Carries the broker token to the agent and on to the MCP server it starts,
+/// so it stays out of `ps`
+pub const TOKEN_ENV: &str = "LYCHEE_PERMISSION_TOKEN";
+
+/// MCP protocol version we speak when the agent doesn't say
+const MCP_VERSION: &str = "2024-11-05";
+
+/// How often an unattended request checks whether a browser has opened its session
+const ATTENDANCE_TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PermissionMode {
+    /// Let the agent use any tool without asking
+    Skip,
+    /// Ask attached browsers before each tool use
+    Prompt,
+}
+
+/// What happens to a request nobody answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Fallback {
+    Allow,
+    Deny,
+}
+
+/// A browser's answer to a `permission_request`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Allow,
+    Deny,
+    AlwaysAllow,
+}
+
+/// How far an `always_allow` answer reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    #[default]
+    Session,
+    Repo,
+}
+
+/// Always-allowed tools, persisted in .lychee/permissions.json
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Rules {
+    #[serde(default)]
+    repo: HashSet<String>,
+    #[serde(default)]
+    sessions: HashMap<String, HashSet<String>>,
+}
+
+/// What an agent's approval tool needs to reach the broker
+#[derive(Debug, Clone)]
+pub struct PermissionPrompt {
+    pub addr: SocketAddr,
+    pub token: String,
+    pub lychee_id: String,
+}
+
+impl PermissionPrompt {
+    /**
+     * MCP configuration that makes Claude start `lychee permission-mcp` as
+     * its approval tool
+     *
+     * The token isn't part of it; the agent needs TOKEN_ENV in its
+     * environment, which the MCP server inherits.
+     */
+    pub fn mcp_config(&self) -> String {
+        let exe = std::env::current_exe()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| "lychee".to_string());
+
+        let config = json!({
+            "mcpServers": {
+                "lychee": {
+                    "type": "stdio",
+                    "command": exe,
+                    "args": ["permission-mcp", "--addr", self.addr.to_string(), "--session", self.lychee_id],
+                }
+            }
+        });
+        config.to_string()
+    }
+}
+
+/// One approval request on the broker's local socket
+#[derive(Debug, Serialize, Deserialize)]
+struct AskRequest {
+    token: String,
+    lychee_id: String,
+    tool_name: String,
+    input: Value,
+}
+
+/// The broker's answer on the local socket
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Verdict {
+    pub allow: bool,
+    pub message: Option<String>,
+}
+
+impl Verdict {
+    fn deny(message: impl Into<String>) -> Verdict {
+        Verdict {
+            allow: false,
+            message: Some(message.into()),
+        }
+    }
+}
+
+/**
+ * Ask the broker whether a tool may run; blocks until a browser answers or
+ * the fallback applies
+ */
+pub async fn ask(prompt: &PermissionPrompt, tool_name: &str, input: &Value) -> Verdict {
+    let request = AskRequest {
+        token: prompt.token.clone(),
+        lychee_id: prompt.lychee_id.clone(),
+        tool_name: tool_name.to_string(),
+        input: input.clone(),
+    };
+
+    let exchange = async {
+        let mut stream = TcpStream::connect(prompt.addr).await?;
+        stream.write_all(format!("{}\n", serde_json::to_string(&request)?).as_bytes()).await?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        Ok::<_, std::io::Error>(serde_json::from_str::<Verdict>(&line)?)
+    };
+
+    exchange
+        .await
+        .unwrap_or_else(|e| Verdict::deny(format!("Could not reach lychee for approval: {}", e)))
+}
+
+/// A request waiting for a browser
+struct Pending {
+    lychee_id: String,
+    request: Message,
+    answer: oneshot::Sender<(Decision, Scope)>,
+}
+
+/**
+ * Relays tool approvals between agents and browsers
+ *
+ * Agents connect to a local socket (through `lychee permission-mcp` or
+ * directly, like the mock backend) and wait while the request is shown to
+ * browsers as a `permission_request`. Requests only time out while no browser
+ * has their session open, as reported by the relay's watched sessions; then
+ * the fallback policy decides. A browser attached to another session doesn't
+ * hold them open.
+ */
+pub struct PermissionBroker {
+    repo_path: String,
+    rules_path: PathBuf,
+    addr: SocketAddr,
+    token: String,
+    timeout: Duration,
+    fallback: Fallback,
+    /// Sessions open in some browser
+    watched: Mutex<HashSet<String>>,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl PermissionBroker {
+    pub async fn bind(repo_path: &str, timeout: Duration, fallback: Fallback) -> std::io::Result<(Arc<PermissionBroker>, TcpListener)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let broker = PermissionBroker {
+            repo_path: repo_path.to_string(),
+            rules_path: PathBuf::from(repo_path).join(".lychee").join("permissions.json"),
+            addr: listener.local_addr()?,
+            token: Uuid::new_v4().simple().to_string(),
+            timeout,
+            fallback,
+            // Until the relay reports otherwise
+            watched: Mutex::new(HashSet::new()),
+            pending: Mutex::new(HashMap::new()),
+        };
+        Ok((Arc::new(broker), listener))
+    }
+
+    pub fn prompt(&self, lychee_id: &str) -> PermissionPrompt {
+        PermissionPrompt {
+            addr: self.addr,
+            token: self.token.clone(),
+            lychee_id: lychee_id.to_string(),
+        }
+    }
+
+    pub fn set_watched_sessions(&self, lychee_ids: &[String]) {
+        *self.watched.lock().unwrap() = lychee_ids.iter().cloned().collect();
+    }
+
+    /**
+     * Accept approval requests until the task is aborted
+     * Aborting also drops every request still waiting
+     */
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, tx: mpsc::UnboundedSender<String>) {
+        let mut connections = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        connections.spawn(self.clone().handle_connection(stream, tx.clone()));
+                    }
+                    Err(e) => tracing::warn!(error = %e, "failed to accept approval request"),
+                },
+                Some(_) = connections.join_next() => {}
+            }
+        }
+    }
+
+    async fn handle_connection(self: Arc<Self>, stream: TcpStream, tx: mpsc::UnboundedSender<String>) {
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+        if BufReader::new(reader).read_line(&mut line).await.is_err() {
+            return;
+        }
+
+        let verdict = match serde_json::from_str::<AskRequest>(&line) {
+            Ok(request) if request.token == self.token => self.decide(request, &tx).await,
+            Ok(_) => {
+                tracing::warn!("rejected approval request with a wrong token");
+                Verdict::deny("Invalid approval token")
+            }
+            Err(e) => Verdict::deny(format!("Invalid approval request: {}", e)),
+        };
+
+        let _ = writer.write_all(format!("{}\n", serde_json::to_string(&verdict).unwrap()).as_bytes()).await;
+    }
+
+    async fn decide(&self, request: AskRequest, tx: &mpsc::UnboundedSender<String>) -> Verdict {
+        let AskRequest { lychee_id, tool_name, input, .. } = request;
+
+        if self.is_always_allowed(&lychee_id, &tool_name) {
+            tracing::debug!(%lychee_id, tool = %tool_name, "tool always allowed");
+            return Verdict { allow: true, message: None };
+        }
+
+        let request_id = Uuid::new_v4().to_string();
+        let message = Message::PermissionRequest {
+            repo_path: self.repo_path.clone(),
+            lychee_id: lychee_id.clone(),
+            request_id: request_id.clone(),
+            tool_name: tool_name.clone(),
+            input,
+        };
+        let (answer_tx, mut answer_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(request_id.clone(), Pending {
+            lychee_id: lychee_id.clone(),
+            request: message.clone(),
+            answer: answer_tx,
+        });
+        let _ = tx.send(serde_json::to_string(&message).unwrap());
+
+        tracing::info!(%lychee_id, tool = %tool_name, "waiting for tool approval");
+
+        // Only time spent without a browser on this session counts towards the timeout
+        let mut unattended = Duration::ZERO;
+        let answer = loop {
+            tokio::select! {
+                answer = &mut answer_rx => break answer.ok(),
+                _ = tokio::time::sleep(ATTENDANCE_TICK) => {
+                    if self.watched.lock().unwrap().contains(&lychee_id) {
+                        unattended = Duration::ZERO;
+                    } else {
+                        unattended += ATTENDANCE_TICK;
+                        if unattended >= self.timeout {
+                            break None;
+                        }
+                    }
+                }
+            }
+        };
+
+        let decision = match answer {
+            Some((decision, scope)) => {
+                if decision == Decision::AlwaysAllow {
+                    self.remember(&lychee_id, &tool_name, scope);
+                }
+                decision
+            }
+            None => {
+                self.pending.lock().unwrap().remove(&request_id);
+                tracing::warn!(%lychee_id, tool = %tool_name, fallback = ?self.fallback, "no browser answered, applying fallback");
+                match self.fallback {
+                    Fallback::Allow => Decision::Allow,
+                    Fallback::Deny => Decision::Deny,
+                }
+            }
+        };
+        tracing::info!(%lychee_id, tool = %tool_name, ?decision, "tool approval decided");
+
+        // Closes the prompt in every browser, including ones that didn't answer
+        let resolved = Message::PermissionResolved {
+            repo_path: self.repo_path.clone(),
+            lychee_id,
+            request_id,
+            decision,
+        };
+        let _ = tx.send(serde_json::to_string(&resolved).unwrap());
+
+        match decision {
+            Decision::Allow | Decision::AlwaysAllow => Verdict { allow: true, message: None },
+            Decision::Deny if answer.is_none() => Verdict::deny("No one approved this tool use in time"),
+            Decision::Deny => Verdict::deny("The user denied this tool use"),
+        }
+    }
+
+    /**
+     * Settle a request with a browser's answer
+     * Returns false if it was already settled (answered elsewhere or timed out)
+     * or was raised for another session than `lychee_id`
+     */
+    pub fn respond(&self, lychee_id: &str, request_id: &str, decision: Decision, scope: Scope) -> bool {
+        let pending = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.get(request_id).is_none_or(|p| p.lychee_id != lychee_id) {
+                return false;
+            }
+            pending.remove(request_id).unwrap()
+        };
+        pending.answer.send((decision, scope)).is_ok()
+    }
+
+    /**
+     * Requests still waiting for a session, for browsers that just opened it
+     */
+    pub fn pending_requests(&self, lychee_id: &str) -> Vec<Message> {
+        self.pending
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|pending| pending.lychee_id == lychee_id)
+            .map(|pending| pending.request.clone())
+            .collect()
+    }
+
+    fn load_rules(&self) -> Rules {
+        std::fs::read_to_string(&self.rules_path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn is_always_allowed(&self, lychee_id: &str, tool_name: &str) -> bool {
+        let rules = self.load_rules();
+        rules.repo.contains(tool_name)
+            || rules.sessions.get(lychee_id).is_some_and(|tools| tools.contains(tool_name))
+    }
+
+    fn remember(&self, lychee_id: &str, tool_name: &str, scope: Scope) {
+        let mut rules = self.load_rules();
+        match scope {
+            Scope::Repo => rules.repo.insert(tool_name.to_string()),
+            Scope::Session => rules
+                .sessions
+                .entry(lychee_id.to_string())
+                .or_default()
+                .insert(tool_name.to_string()),
+        };
+
+        if let Err(e) = std::fs::write(&self.rules_path, serde_json::to_string_pretty(&rules).unwrap()) {
+            tracing::warn!(path = %self.rules_path.display(), error = %e, "failed to save permission rules");
+        }
+    }
+}
+
+/**
+ * Minimal MCP stdio server exposing the `approve` tool to Claude
+ * (`--permission-prompt-tool mcp__lychee__approve`), forwarding each call
+ * to the broker of the `lychee up` that started the agent
+ */
+pub async fn run_mcp_server(addr: SocketAddr, lychee_id: String) -> Result<(), String> {
+    let token = std::env::var(TOKEN_ENV).map_err(|_| format!("{} is not set", TOKEN_ENV))?;
+    let prompt = PermissionPrompt { addr, token, lychee_id };
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(request) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        // Notifications have no id and get no response
+        let Some(id) = request.get("id").cloned() else {
+            continue;
+        };
+        let params = &request["params"];
+
+        let result = match request["method"].as_str().unwrap_or_default() {
+            "initialize" => Ok(json!({
+                "protocolVersion": params["protocolVersion"].as_str().unwrap_or(MCP_VERSION),
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "lychee", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({
+                "tools": [{
+                    "name": "approve",
+                    "description": "Ask the lychee user whether a tool may run",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "tool_name": { "type": "string" },
+                            "input": { "type": "object" },
+                            "tool_use_id": { "type": "string" },
+                        },
+                        "required": ["tool_name", "input"],
+                    },
+                }],
+            })),
+            "tools/call" => {
+                let arguments = &params["arguments"];
+                let tool_name = arguments["tool_name"].as_str().unwrap_or_default();
+                let input = arguments["input"].clone();
+
+                let verdict = ask(&prompt, tool_name, &input).await;
+                let decision = if verdict.allow {
+                    json!({ "behavior": "allow", "updatedInput": input })
+                } else {
+                    json!({ "behavior": "deny", "message": verdict.message.unwrap_or_default() })
+                };
+                Ok(json!({ "content": [{ "type": "text", "text": decision.to_string() }] }))
+            }
+            method => Err(json!({ "code": -32601, "message": format!("Method not found: {}", method) })),
+        };
+
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        };
+        stdout
+            .write_all(format!("{}\n", response).as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        stdout.flush().await.map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn broker(timeout: Duration, fallback: Fallback) -> (tempfile::TempDir, Arc<PermissionBroker>, TcpListener) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(".lychee")).unwrap();
+        let (broker, listener) = PermissionBroker::bind(&dir.path().to_string_lossy(), timeout, fallback).await.unwrap();
+        (dir, broker, listener)
+    }
+
+    /// Put a request in the broker as if an agent were waiting on it
+    fn raise(broker: &PermissionBroker, lychee_id: &str, request_id: &str) -> oneshot::Receiver<(Decision, Scope)> {
+        let (answer, answer_rx) = oneshot::channel();
+        let request = Message::PermissionRequest {
+            repo_path: broker.repo_path.clone(),
+            lychee_id: lychee_id.to_string(),
+            request_id: request_id.to_string(),
+            tool_name: "Bash".to_string(),
+            input: json!({}),
+        };
+        broker.pending.lock().unwrap().insert(request_id.to_string(), Pending {
+            lychee_id: lychee_id.to_string(),
+            request,
+            answer,
+        });
+        answer_rx
+    }
+
+    #[tokio::test]
+    async fn mcp_config_leaves_the_token_out() {
+        let (_dir, broker, _listener) = broker(Duration::from_secs(60), Fallback::Deny).await;
+        let prompt = broker.prompt("session");
+        let config = prompt.mcp_config();
+        assert!(!config.contains(&prompt.token));
+        assert!(config.contains("permission-mcp"));
+    }
+
+    #[tokio::test]
+    async fn respond_only_settles_requests_of_that_session() {
+        let (_dir, broker, _listener) = broker(Duration::from_secs(60), Fallback::Deny).await;
+        let mut answer = raise(&broker, "mine", "request");
+
+        assert!(!broker.respond("other", "request", Decision::Allow, Scope::Session));
+        assert!(answer.try_recv().is_err());
+        assert_eq!(broker.pending_requests("mine").len(), 1);
+
+        assert!(broker.respond("mine", "request", Decision::Deny, Scope::Session));
+        assert_eq!(answer.try_recv().unwrap(), (Decision::Deny, Scope::Session));
+        assert!(!broker.respond("mine", "request", Decision::Allow, Scope::Session));
+        assert!(broker.pending_requests("mine").is_empty());
+    }
+
+    #[tokio::test]
+    async fn always_allow_by_scope() {
+        let (_dir, broker, _listener) = broker(Duration::from_secs(60), Fallback::Deny).await;
+        broker.remember("a", "Edit", Scope::Session);
+        assert!(broker.is_always_allowed("a", "Edit"));
+        assert!(!broker.is_always_allowed("b", "Edit"));
+        assert!(!broker.is_always_allowed("a", "Bash"));
+
+        broker.remember("a", "Bash", Scope::Repo);
+        assert!(broker.is_always_allowed("b", "Bash"));
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_fall_back_and_wrong_tokens_are_denied() {
+        let (_dir, broker, listener) = broker(Duration::from_secs(1), Fallback::Allow).await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let serve = tokio::spawn(broker.clone().serve(listener, tx));
+
+        let mut prompt = broker.prompt("session");
+        assert!(ask(&prompt, "Bash", &json!({})).await.allow);
+        assert!(rx.recv().await.unwrap().contains("permission_request"));
+        assert!(rx.recv().await.unwrap().contains("permission_resolved"));
+
+        prompt.token = "wrong".to_string();
+        let verdict = ask(&prompt, "Bash", &json!({})).await;
+        assert!(!verdict.allow);
+        assert_eq!(verdict.message.as_deref(), Some("Invalid approval token"));
+
+        serve.abort();
+    }
+}
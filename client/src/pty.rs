@@ -0,0 +1,253 @@
+use portable_pty::{CommandBuilder, MasterPty, PtySize};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Mutex, mpsc as std_mpsc};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Largest output frame; a busy terminal sends several
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Output still in flight when the shell exits is sent before the exit, if it comes this soon
+const OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+/// How long a closed shell gets to exit on SIGHUP before it is killed
+const CLOSE_GRACE: Duration = Duration::from_secs(2);
+
+/// How often the thread waiting on a shell looks whether it exited
+const WAIT_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TerminalSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl From<TerminalSize> for PtySize {
+    fn from(size: TerminalSize) -> PtySize {
+        PtySize {
+            cols: size.cols.max(1),
+            rows: size.rows.max(1),
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+}
+
+/// How a terminal's shell ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exit {
+    pub exit_code: u32,
+    /// Set when a signal ended it, e.g. on close
+    pub signal: Option<String>,
+}
+
+/// A shell on a pseudo terminal; closed when dropped
+pub struct Terminal {
+    pub lychee_id: String,
+    pub shell: String,
+    pub opened_at: chrono::DateTime<chrono::Utc>,
+    input: std_mpsc::Sender<Vec<u8>>,
+    master: Mutex<Box<dyn MasterPty>>,
+    /// Asks the thread that reaps the shell to hang up on it
+    hang_up: std_mpsc::Sender<()>,
+}
+
+impl Terminal {
+    /**
+     * Queue keystrokes or pasted text for the shell
+     */
+    pub fn write(&self, bytes: Vec<u8>) -> Result<(), String> {
+        self.input.send(bytes).map_err(|_| "The terminal has exited".to_string())
+    }
+
+    pub fn resize(&self, size: TerminalSize) -> Result<(), String> {
+        let master = self.master.lock().unwrap();
+        master.resize(size.into()).map_err(|e| format!("Failed to resize the terminal: {}", e))
+    }
+
+    /**
+     * Hang up on the shell, and kill it if it doesn't exit
+     */
+    pub fn close(&self) {
+        // Fails once the shell is gone, which is fine
+        let _ = self.hang_up.send(());
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/**
+ * Start `shell` in `cwd` on a new pseudo terminal
+ *
+ * Output is passed to `on_output` in chunks as it arrives, from a thread of
+ * its own. The receiver resolves once the shell has exited and its remaining
+ * output was passed on.
+ */
+pub fn open(
+    lychee_id: &str,
+    shell: &str,
+    cwd: &Path,
+    size: TerminalSize,
+    on_output: impl Fn(&[u8]) + Send + 'static,
+) -> Result<(Terminal, oneshot::Receiver<Exit>), String> {
+    let pair = portable_pty::native_pty_system()
+        .openpty(size.into())
+        .map_err(|e| format!("Failed to open a pseudo terminal: {}", e))?;
+
+    let mut command = CommandBuilder::new(shell);
+    command.cwd(cwd);
+    command.env("TERM", "xterm-256color");
+    let mut child = pair
+        .slave
+        .spawn_command(command)
+        .map_err(|e| format!("Failed to start {}: {}", shell, e))?;
+    // Only the shell holds the other end, so reads end when it and its jobs are gone
+    drop(pair.slave);
+
+    let mut killer = child.clone_killer();
+    let mut reader = pair
+        .master
+        .try_clone_reader()
+        .map_err(|e| format!("Failed to read from the terminal: {}", e))?;
+    let mut writer = pair
+        .master
+        .take_writer()
+        .map_err(|e| format!("Failed to write to the terminal: {}", e))?;
+
+    // Writes block while the shell isn't reading, so they get a thread of their own
+    let (input, input_rx) = std_mpsc::channel::<Vec<u8>>();
+    std::thread::spawn(move || {
+        for bytes in input_rx {
+            if writer.write_all(&bytes).and_then(|_| writer.flush()).is_err() {
+                return;
+            }
+        }
+    });
+
+    let (drained_tx, drained_rx) = std_mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        let mut buf = vec![0; READ_CHUNK];
+        // Linux reports the closed other end as an error rather than EOF
+        while let Ok(n) = reader.read(&mut buf)
+            && n > 0
+        {
+            on_output(&buf[..n]);
+        }
+        let _ = drained_tx.send(());
+    });
+
+    let (hang_up, hang_up_rx) = std_mpsc::channel::<()>();
+    let (exit_tx, exit_rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        // Signals only go out from here, between seeing the shell still running
+        // and reaping it, so a pid that was reaped and reused is never signalled
+        let mut hung_up_at: Option<Instant> = None;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+            match hung_up_at {
+                // A dropped terminal hangs up too
+                None => {
+                    if !matches!(hang_up_rx.recv_timeout(WAIT_POLL), Err(std_mpsc::RecvTimeoutError::Timeout)) {
+                        let _ = killer.kill();
+                        hung_up_at = Some(Instant::now());
+                    }
+                }
+                Some(at) if at.elapsed() >= CLOSE_GRACE => {
+                    tracing::debug!("shell ignored hangup, killing it");
+                    let _ = child.kill();
+                    break child.wait();
+                }
+                Some(_) => std::thread::sleep(WAIT_POLL),
+            }
+        };
+        let exit = match status {
+            Ok(status) => Exit {
+                exit_code: status.exit_code(),
+                signal: status.signal().map(str::to_string),
+            },
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to wait for terminal shell");
+                Exit { exit_code: 1, signal: None }
+            }
+        };
+        // Background jobs may keep the terminal open after the shell is gone
+        let _ = drained_rx.recv_timeout(OUTPUT_GRACE);
+        let _ = exit_tx.send(exit);
+    });
+
+    let terminal = Terminal {
+        lychee_id: lychee_id.to_string(),
+        shell: shell.to_string(),
+        opened_at: chrono::Utc::now(),
+        input,
+        master: Mutex::new(pair.master),
+        hang_up,
+    };
+    Ok((terminal, exit_rx))
+}
+
+/**
+ * The shell from the configuration, else the user's login shell
+ */
+pub fn default_shell(configured: Option<&str>) -> String {
+    match configured {
+        Some(shell) if !shell.trim().is_empty() => shell.to_string(),
+        _ => CommandBuilder::new_default_prog().get_shell(),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    const SIZE: TerminalSize = TerminalSize { cols: 80, rows: 24 };
+
+    /// A "shell" that runs `script`
+    fn shell(dir: &tempfile::TempDir, script: &str) -> String {
+        let path = dir.path().join("shell.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.display().to_string()
+    }
+
+    /// Close the terminal once `script` printed "ready"
+    async fn closed(script: &str) -> Exit {
+        let dir = tempfile::tempdir().unwrap();
+        let (output, output_rx) = std_mpsc::channel();
+        let on_output = move |bytes: &[u8]| {
+            let _ = output.send(bytes.to_vec());
+        };
+        let (terminal, exited) = open("s1", &shell(&dir, script), dir.path(), SIZE, on_output).unwrap();
+        let mut seen = Vec::new();
+        while !String::from_utf8_lossy(&seen).contains("ready") {
+            seen.extend(output_rx.recv_timeout(Duration::from_secs(10)).unwrap());
+        }
+        terminal.close();
+        tokio::time::timeout(Duration::from_secs(10), exited).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn closing_hangs_up_on_the_shell() {
+        let exit = closed("echo ready\nwhile :; do sleep 1; done").await;
+        assert_eq!(exit.signal.as_deref(), Some("Hangup"));
+    }
+
+    #[tokio::test]
+    async fn shells_that_ignore_the_hangup_are_killed() {
+        let started = Instant::now();
+        let exit = closed("trap '' HUP\necho ready\nwhile :; do sleep 1; done").await;
+        assert_eq!(exit.signal.as_deref(), Some("Killed"));
+        assert!(started.elapsed() >= CLOSE_GRACE);
+    }
+}
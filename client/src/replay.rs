@@ -0,0 +1,193 @@
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::config::RelayConfig;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// One line of a relay recording (see RELAY_RECORD_DIR in the relay)
+#[derive(Debug, Deserialize)]
+struct RecordedFrame {
+    elapsed_ms: u64,
+    direction: String,
+    frame: String,
+}
+
+pub struct ReplayOptions {
+    pub speed: f64,
+    pub repo_path: Option<String>,
+    pub wait_for_browser: bool,
+}
+
+/**
+ * Whether a frame from the relay came from a browser
+ *
+ * The relay's own frames (client_count, watched_sessions, announcements,
+ * maintenance, errors) arrive without any browser connected.
+ */
+fn is_browser_request(text: &str) -> bool {
+    match serde_json::from_str::<crate::Message>(text) {
+        Ok(crate::Message::ClientCount { .. } | crate::Message::WatchedSessions { .. } | crate::Message::Error { .. }) => false,
+        Ok(_) => true,
+        // announcement and maintenance aren't client messages
+        Err(_) => false,
+    }
+}
+
+/**
+ * Impersonate the client from a relay recording
+ *
+ * Registers with the recorded repo_path (or an override) and sends every frame
+ * the original client sent, spaced as recorded and divided by `speed`.
+ * Browser requests are ignored. Stays connected after the last frame so the
+ * UI keeps its state, until Ctrl+C.
+ */
+pub async fn run(relay: &RelayConfig, recording: &Path, options: ReplayOptions) -> Result<(), String> {
+    let contents = std::fs::read_to_string(recording)
+        .map_err(|e| format!("Failed to read {}: {}", recording.display(), e))?;
+
+    let frames: Vec<RecordedFrame> = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Not a relay recording: {}", e))?;
+
+    // Only what the client sent is replayed; the first frame is its registration
+    let mut sent = frames.into_iter().filter(|f| f.direction == "recv");
+    let registration = sent.next().ok_or("Recording is empty")?;
+    let mut register: serde_json::Value = serde_json::from_str(&registration.frame)
+        .map_err(|e| format!("Invalid registration frame: {}", e))?;
+    if register.get("type").and_then(|t| t.as_str()) != Some("register_client") {
+        return Err("Not a client recording (it doesn't start with register_client)".to_string());
+    }
+
+    if register.get("e2e").and_then(|e| e.as_bool()) == Some(true) {
+        println!("⚠️  Recording is end-to-end encrypted, browsers won't be able to read it");
+    }
+    if let Some(repo_path) = options.repo_path {
+        let repo_name = Path::new(&repo_path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| repo_path.clone());
+        register["repo_path"] = repo_path.into();
+        register["repo_name"] = repo_name.into();
+    }
+
+    let frames: Vec<RecordedFrame> = sent.collect();
+    let repo_path = register["repo_path"].as_str().unwrap_or_default().to_string();
+
+    let ws_stream = crate::connect_relay(relay)
+        .await
+        .map_err(|e| format!("Failed to connect to relay: {}", e))?;
+    let (mut write, mut read) = ws_stream.split();
+
+    write.send(WsMessage::Text(register.to_string()))
+        .await
+        .map_err(|e| format!("Failed to register: {}", e))?;
+    println!("🔌 Registered as {} ({} frames to replay)", repo_path, frames.len());
+
+    if options.wait_for_browser {
+        println!("⏳ Waiting for a browser request...");
+        while let Some(Ok(msg)) = read.next().await {
+            if let WsMessage::Text(text) = msg
+                && is_browser_request(&text)
+            {
+                break;
+            }
+        }
+    }
+
+    // Timing is relative to the first replayed frame, not the original connect
+    let offset = frames.first().map(|f| f.elapsed_ms).unwrap_or(0);
+    let start = Instant::now();
+
+    let replay = async {
+        for (i, frame) in frames.iter().enumerate() {
+            let due = Duration::from_secs_f64((frame.elapsed_ms - offset) as f64 / 1000.0 / options.speed);
+            tokio::time::sleep_until(start + due).await;
+
+            write.send(WsMessage::Text(frame.frame.clone()))
+                .await
+                .map_err(|e| format!("Relay connection lost: {}", e))?;
+            tracing::debug!(frame = i + 1, total = frames.len(), "replayed frame");
+        }
+        Ok::<_, String>(())
+    };
+
+    // Keep draining the socket so the relay doesn't see us as stuck
+    let drain = async {
+        while let Some(Ok(_)) = read.next().await {}
+    };
+
+    tokio::select! {
+        result = replay => result?,
+        _ = drain => return Err("Disconnected from relay".to_string()),
+    }
+    println!("✅ Replay finished, press Ctrl+C to disconnect");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = async { while let Some(Ok(_)) = read.next().await {} } => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn relay_frames_are_not_browser_requests() {
+        assert!(!is_browser_request(r#"{"type":"client_count","count":1}"#));
+        assert!(!is_browser_request(r#"{"type":"watched_sessions","lychee_ids":[]}"#));
+        assert!(!is_browser_request(r#"{"type":"announcement","message":"hi","level":"info"}"#));
+        assert!(!is_browser_request(r#"{"type":"maintenance","enabled":true,"message":null}"#));
+        assert!(!is_browser_request(r#"{"type":"error","repo_path":null,"message":"no"}"#));
+        assert!(!is_browser_request("not json"));
+        assert!(is_browser_request(r#"{"type":"list_sessions","repo_path":"/repo"}"#));
+    }
+
+    #[tokio::test]
+    async fn wait_for_browser_ignores_relay_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay = RelayConfig {
+            url: format!("ws://{}", listener.local_addr().unwrap()),
+            token: None,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("recording.jsonl");
+        let frames = [
+            r#"{"type":"register_client","repo_path":"/repo","repo_name":"repo"}"#,
+            r#"{"type":"sessions_list","repo_path":"/repo","sessions":[]}"#,
+        ];
+        let lines: Vec<String> = frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| serde_json::json!({"elapsed_ms": i, "direction": "recv", "frame": frame}).to_string())
+            .collect();
+        std::fs::write(&recording, lines.join("\n")).unwrap();
+
+        let options = ReplayOptions { speed: 1.0, repo_path: None, wait_for_browser: true };
+        let replay = tokio::spawn(async move { run(&relay, &recording, options).await });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let register = socket.next().await.unwrap().unwrap();
+        assert!(register.to_text().unwrap().contains("register_client"));
+
+        socket.send(WsMessage::Text(r#"{"type":"watched_sessions","lychee_ids":[]}"#.to_string())).await.unwrap();
+        socket.send(WsMessage::Text(r#"{"type":"client_count","count":1}"#.to_string())).await.unwrap();
+        let early = tokio::time::timeout(Duration::from_millis(300), socket.next()).await;
+        assert!(early.is_err(), "replay started without a browser: {:?}", early);
+
+        socket.send(WsMessage::Text(r#"{"type":"list_sessions","repo_path":"/repo"}"#.to_string())).await.unwrap();
+        let replayed = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+        assert!(replayed.to_text().unwrap().contains("sessions_list"));
+
+        replay.abort();
+    }
+}
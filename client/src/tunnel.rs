@@ -0,0 +1,204 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+
+/// Largest response body passed on; WebSocket frames don't count
+const MAX_RESPONSE_BYTES: usize = 100 * 1024 * 1024;
+
+/// Browser frames held for the local server before the socket is given up
+pub const FRAME_BUFFER: usize = 64;
+
+/// Headers the WebSocket handshake to the local server sets itself
+const HANDSHAKE_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "upgrade",
+    "origin",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-extensions",
+];
+
+/// A browser's request for a preview, as the relay passed it on
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path and query under the session's preview root
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// A WebSocket message; binary data is base64
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    pub data: String,
+    #[serde(default)]
+    pub binary: bool,
+}
+
+/// What the local server sent back
+pub enum Event {
+    Response { status: u16, headers: Vec<(String, String)> },
+    Data(Vec<u8>),
+    Frame(Frame),
+}
+
+/**
+ * Send `request` to the server on `port` and pass its response on as it comes
+ *
+ * The body is streamed, so long polls and server-sent events work. Resolves
+ * once the whole response was passed on, or right away when `cancel` fires
+ * or its sender is dropped. Bodies over MAX_RESPONSE_BYTES are cut off.
+ */
+pub async fn http(port: u16, request: Request, on_event: impl Fn(Event), mut cancel: oneshot::Receiver<()>) -> Result<(), String> {
+    let stream = TcpStream::connect(("localhost", port))
+        .await
+        .map_err(|e| format!("Nothing is listening on port {}: {}", port, e))?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+        .await
+        .map_err(|e| format!("Failed to talk to port {}: {}", port, e))?;
+
+    let mut builder = hyper::Request::builder()
+        .method(request.method.as_str())
+        .uri(request.path.as_str())
+        .header(hyper::header::HOST, format!("localhost:{}", port));
+    for (name, value) in &request.headers {
+        if !name.eq_ignore_ascii_case("host") {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+    }
+    let upstream = builder
+        .body(Full::new(Bytes::from(request.body)))
+        .map_err(|e| format!("Invalid request: {}", e))?;
+
+    let exchange = async {
+        let response = sender
+            .send_request(upstream)
+            .await
+            .map_err(|e| format!("Port {} didn't answer: {}", port, e))?;
+        on_event(Event::Response {
+            status: response.status().as_u16(),
+            headers: header_pairs(response.headers()),
+        });
+
+        let mut body = response.into_body();
+        let mut sent = 0;
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| format!("Port {} broke off the response: {}", port, e))?;
+            if let Ok(data) = frame.into_data()
+                && !data.is_empty()
+            {
+                sent += data.len();
+                if sent > MAX_RESPONSE_BYTES {
+                    return Err(format!("The response is over {} bytes", MAX_RESPONSE_BYTES));
+                }
+                on_event(Event::Data(data.to_vec()));
+            }
+        }
+        Ok(())
+    };
+    tokio::pin!(exchange, conn);
+
+    // The connection has to be driven for the exchange to make progress
+    tokio::select! {
+        biased;
+        _ = &mut cancel => Ok(()),
+        result = &mut exchange => result,
+        _ = &mut conn => tokio::select! {
+            _ = &mut cancel => Ok(()),
+            result = &mut exchange => result,
+        },
+    }
+}
+
+/**
+ * Open a WebSocket to the server on `port` and relay frames both ways
+ *
+ * A refused upgrade is passed on as an ordinary response. Resolves when
+ * either side closes, or when `cancel` fires or its sender is dropped.
+ */
+pub async fn websocket(
+    port: u16,
+    request: Request,
+    on_event: impl Fn(Event),
+    mut frames: mpsc::Receiver<Frame>,
+    mut cancel: oneshot::Receiver<()>,
+) -> Result<(), String> {
+    let mut upstream = format!("ws://localhost:{}{}", port, request.path)
+        .into_client_request()
+        .map_err(|e| format!("Invalid request: {}", e))?;
+    for (name, value) in &request.headers {
+        if HANDSHAKE_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            upstream.headers_mut().append(name, value);
+        }
+    }
+
+    let socket = match tokio_tungstenite::connect_async(upstream).await {
+        Ok((socket, response)) => {
+            on_event(Event::Response {
+                status: response.status().as_u16(),
+                headers: header_pairs(response.headers()),
+            });
+            socket
+        }
+        Err(tungstenite::Error::Http(response)) => {
+            on_event(Event::Response {
+                status: response.status().as_u16(),
+                headers: header_pairs(response.headers()),
+            });
+            if let Some(body) = response.body().clone() {
+                on_event(Event::Data(body));
+            }
+            return Ok(());
+        }
+        Err(e) => return Err(format!("Failed to open a WebSocket on port {}: {}", port, e)),
+    };
+
+    let (mut write, mut read) = socket.split();
+    loop {
+        tokio::select! {
+            _ = &mut cancel => break,
+            frame = frames.recv() => {
+                let Some(frame) = frame else { break };
+                let message = if frame.binary {
+                    match BASE64.decode(&frame.data) {
+                        Ok(bytes) => WsMessage::Binary(bytes),
+                        Err(_) => continue,
+                    }
+                } else {
+                    WsMessage::Text(frame.data)
+                };
+                if write.send(message).await.is_err() {
+                    return Ok(());
+                }
+            }
+            message = read.next() => match message {
+                Some(Ok(WsMessage::Text(data))) => on_event(Event::Frame(Frame { data, binary: false })),
+                Some(Ok(WsMessage::Binary(bytes))) => on_event(Event::Frame(Frame { data: BASE64.encode(bytes), binary: true })),
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                // tungstenite answers pings itself
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = write.close().await;
+    Ok(())
+}
+
+fn header_pairs(headers: &hyper::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
@@ -0,0 +1,324 @@
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Quiet time that ends a batch of changes
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Longest a batch is held back while changes keep coming
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(2);
+
+/// Changes listed in one batch; past it the batch is marked truncated
+const MAX_CHANGES: usize = 1000;
+
+/// Directories watched per working directory; inotify watches are a limited resource
+const MAX_WATCHED_DIRS: usize = 10_000;
+
+/// Never watched and never reported
+const SKIPPED_DIRS: [&str; 2] = [".git", ".lychee"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileChange {
+    /// Relative to the watched directory, `/`-separated
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeBatch {
+    pub changes: Vec<FileChange>,
+    /// More than MAX_CHANGES changed; anything may be stale
+    pub truncated: bool,
+}
+
+/// Watches one directory until dropped
+pub struct DirWatcher {
+    task: JoinHandle<()>,
+}
+
+impl Drop for DirWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/**
+ * Watch `root`, passing debounced batches of changes to `on_batch`
+ *
+ * Each directory gets its own non-recursive watch, so `.git`, `.lychee` and
+ * whatever git ignores (build output, dependencies) cost nothing. Directories
+ * created later are watched as they appear.
+ */
+pub fn watch(root: &Path, on_batch: impl Fn(ChangeBatch) + Send + 'static) -> Result<DirWatcher, String> {
+    let root = root.canonicalize().map_err(|e| format!("{}: {}", root.display(), e))?;
+    let (event_tx, event_rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = event_tx.send(event);
+    })
+    .map_err(|e| format!("Failed to start watching {}: {}", root.display(), e))?;
+
+    let mut tracker = Tracker {
+        watcher,
+        root: root.clone(),
+        watched: HashSet::new(),
+        pending: HashMap::new(),
+    };
+    tracker.add_watches(&root);
+    tracing::debug!(root = %root.display(), dirs = tracker.watched.len(), "watching directory");
+
+    let task = tokio::spawn(tracker.run(event_rx, on_batch));
+    Ok(DirWatcher { task })
+}
+
+struct Tracker {
+    watcher: RecommendedWatcher,
+    root: PathBuf,
+    watched: HashSet<PathBuf>,
+    /// Changes since the last batch, already merged per path
+    pending: HashMap<PathBuf, ChangeKind>,
+}
+
+impl Tracker {
+    async fn run(mut self, mut events: mpsc::UnboundedReceiver<notify::Result<Event>>, on_batch: impl Fn(ChangeBatch)) {
+        let mut first_at = Instant::now();
+        let mut last_at = Instant::now();
+        loop {
+            let event = if self.pending.is_empty() {
+                events.recv().await
+            } else {
+                let deadline = (last_at + DEBOUNCE).min(first_at + MAX_BATCH_DELAY);
+                match tokio::time::timeout_at(deadline, events.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        if let Some(batch) = self.take_batch().await {
+                            on_batch(batch);
+                        }
+                        continue;
+                    }
+                }
+            };
+            let Some(event) = event else { return };
+
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!(root = %self.root.display(), error = %e, "file watch error");
+                    continue;
+                }
+            };
+            if self.pending.is_empty() {
+                first_at = Instant::now();
+            }
+            last_at = Instant::now();
+            for (path, kind) in changes_of(event) {
+                self.record(path, kind);
+            }
+        }
+    }
+
+    fn record(&mut self, path: PathBuf, kind: ChangeKind) {
+        if relative_path(&self.root, &path).is_none() {
+            return;
+        }
+        // A removed or renamed directory's watch is gone or points at the wrong path
+        if kind == ChangeKind::Removed && self.watched.remove(&path) {
+            let _ = self.watcher.unwatch(&path);
+        }
+        let merged = match self.pending.remove(&path) {
+            Some(earlier) => merge(earlier, kind),
+            None => Some(kind),
+        };
+        if let Some(kind) = merged {
+            self.pending.insert(path, kind);
+        }
+    }
+
+    /**
+     * The pending changes, minus what git ignores; new directories get
+     * watched and their contents reported as created
+     */
+    async fn take_batch(&mut self) -> Option<ChangeBatch> {
+        let mut pending: Vec<(PathBuf, ChangeKind)> = std::mem::take(&mut self.pending).into_iter().collect();
+        let ignored = ignored_paths(&self.root, pending.iter().map(|(path, _)| path)).await;
+        pending.retain(|(path, _)| !ignored.contains(path));
+
+        let mut created = Vec::new();
+        for (path, kind) in &pending {
+            if *kind == ChangeKind::Created && path.is_dir() && !self.watched.contains(path) {
+                created.extend(self.add_watches(path));
+            }
+        }
+        let reported: HashSet<PathBuf> = pending.iter().map(|(path, _)| path.clone()).collect();
+        pending.extend(
+            created
+                .into_iter()
+                .filter(|path| !reported.contains(path))
+                .map(|path| (path, ChangeKind::Created)),
+        );
+
+        let mut changes: Vec<FileChange> = pending
+            .into_iter()
+            .filter_map(|(path, kind)| Some(FileChange { path: relative_path(&self.root, &path)?, kind }))
+            .collect();
+        if changes.is_empty() {
+            return None;
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        let truncated = changes.len() > MAX_CHANGES;
+        changes.truncate(MAX_CHANGES);
+        Some(ChangeBatch { changes, truncated })
+    }
+
+    /**
+     * Watch `dir` and the directories under it that git doesn't ignore
+     * Returns everything found below `dir`
+     */
+    fn add_watches(&mut self, dir: &Path) -> Vec<PathBuf> {
+        let walker = ignore::WalkBuilder::new(dir)
+            .hidden(false)
+            .parents(true)
+            .filter_entry(|entry| !SKIPPED_DIRS.iter().any(|name| entry.file_name() == *name))
+            .build();
+
+        let mut found = Vec::new();
+        for entry in walker.flatten() {
+            let path = entry.path().to_path_buf();
+            if entry.file_type().is_some_and(|t| t.is_dir()) {
+                if self.watched.len() >= MAX_WATCHED_DIRS {
+                    tracing::warn!(root = %self.root.display(), "too many directories, not watching the rest");
+                    return found;
+                }
+                match self.watcher.watch(&path, RecursiveMode::NonRecursive) {
+                    Ok(()) => {
+                        self.watched.insert(path.clone());
+                    }
+                    Err(e) => tracing::debug!(path = %path.display(), error = %e, "failed to watch directory"),
+                }
+            }
+            if path != dir {
+                found.push(path);
+            }
+        }
+        found
+    }
+}
+
+/**
+ * What an event means for each path it names
+ */
+fn changes_of(event: Event) -> Vec<(PathBuf, ChangeKind)> {
+    let kind = match event.kind {
+        EventKind::Create(_) => ChangeKind::Created,
+        EventKind::Remove(_) => ChangeKind::Removed,
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            let mut paths = event.paths.into_iter();
+            return match (paths.next(), paths.next()) {
+                (Some(from), Some(to)) => vec![(from, ChangeKind::Removed), (to, ChangeKind::Created)],
+                _ => Vec::new(),
+            };
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => ChangeKind::Removed,
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => ChangeKind::Created,
+        EventKind::Modify(ModifyKind::Name(_)) => {
+            return event
+                .paths
+                .into_iter()
+                .map(|path| {
+                    let kind = if path.exists() { ChangeKind::Created } else { ChangeKind::Removed };
+                    (path, kind)
+                })
+                .collect();
+        }
+        // Permission and timestamp changes don't change what a browser shows
+        EventKind::Modify(ModifyKind::Metadata(_)) => return Vec::new(),
+        EventKind::Modify(_) => ChangeKind::Modified,
+        _ => return Vec::new(),
+    };
+    event.paths.into_iter().map(|path| (path, kind)).collect()
+}
+
+/**
+ * One change standing for two in a row on the same path; None if they cancel out
+ */
+fn merge(earlier: ChangeKind, later: ChangeKind) -> Option<ChangeKind> {
+    match (earlier, later) {
+        (ChangeKind::Created, ChangeKind::Removed) => None,
+        (ChangeKind::Created, _) => Some(ChangeKind::Created),
+        (ChangeKind::Removed, ChangeKind::Created) => Some(ChangeKind::Modified),
+        (_, later) => Some(later),
+    }
+}
+
+/**
+ * `path` relative to `root`, None for root itself and anything skipped
+ */
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        let Component::Normal(part) = component else { return None };
+        if SKIPPED_DIRS.iter().any(|name| part == *name) {
+            return None;
+        }
+        parts.push(part.to_string_lossy());
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/**
+ * The paths git ignores, asked in one `git check-ignore --stdin` call
+ * Outside a git repository nothing is
+ */
+async fn ignored_paths<'a>(root: &Path, paths: impl Iterator<Item = &'a PathBuf>) -> HashSet<PathBuf> {
+    let mut input = Vec::new();
+    for path in paths {
+        input.extend_from_slice(path.to_string_lossy().as_bytes());
+        input.push(0);
+    }
+    if input.is_empty() {
+        return HashSet::new();
+    }
+
+    let child = Command::new("git")
+        .args(["check-ignore", "-z", "--stdin"])
+        .current_dir(root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else {
+        return HashSet::new();
+    };
+    // Written alongside reading the output, so neither pipe can fill up and stall git
+    if let Some(mut stdin) = child.stdin.take() {
+        tokio::spawn(async move {
+            let _ = stdin.write_all(&input).await;
+        });
+    }
+    let Ok(output) = child.wait_with_output().await else {
+        return HashSet::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(|path| root.join(path))
+        .collect()
+}
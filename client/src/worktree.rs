@@ -0,0 +1,485 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// How a finished worktree session lands on its base branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishStrategy {
+    /// One new commit on the base branch with all of the session's changes
+    Squash,
+    /// A merge commit, keeping the session's commits
+    Merge,
+    /// The session's commits replayed onto the base branch, which is fast-forwarded
+    Rebase,
+}
+
+/// Step a finish is at, reported to browsers as it goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishStage {
+    Committing,
+    Rebasing,
+    Merging,
+    UpdatingBase,
+}
+
+/// How a finish ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishStatus {
+    Finished,
+    /// The base branch already has everything
+    UpToDate,
+    /// Aborted, with nothing changed
+    Conflict,
+    /// The worktree has changes and auto-commit was off
+    Uncommitted,
+    Failed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FinishOutcome {
+    /// The base branch now points at this commit
+    Finished(String),
+    UpToDate,
+    Conflict(Vec<String>),
+}
+
+pub struct FinishRequest<'a> {
+    pub repo: &'a Path,
+    pub worktree_dir: &'a Path,
+    pub branch: &'a str,
+    pub base: &'a str,
+    pub strategy: FinishStrategy,
+    /// For the merge or squash commit
+    pub message: &'a str,
+}
+
+/**
+ * Run git in `dir`
+ * Returns stdout, or git's error message when it fails
+ */
+pub async fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/**
+ * Check that `name` can be a new branch in `repo`
+ */
+pub async fn validate_branch_name(repo: &Path, name: &str) -> Result<(), String> {
+    if git(repo, &["check-ref-format", "--branch", name]).await.is_err() {
+        return Err(format!("Invalid branch name: {}", name));
+    }
+    let refname = format!("refs/heads/{}", name);
+    if git(repo, &["rev-parse", "--verify", "--quiet", &refname]).await.is_ok() {
+        return Err(format!("Branch {} already exists", name));
+    }
+    Ok(())
+}
+
+/**
+ * Resolve a branch, tag or commit to a full commit hash
+ */
+pub async fn resolve_commit(repo: &Path, base: &str) -> Result<String, String> {
+    // Keep refs from being read as options
+    if base.starts_with('-') {
+        return Err(format!("Invalid base ref: {}", base));
+    }
+    let spec = format!("{}^{{commit}}", base);
+    git(repo, &["rev-parse", "--verify", "--quiet", &spec])
+        .await
+        .map(|out| out.trim().to_string())
+        .map_err(|_| format!("Unknown base ref: {}", base))
+}
+
+/**
+ * Name of the branch checked out in `dir`, None when HEAD is detached
+ */
+pub async fn current_branch(dir: &Path) -> Option<String> {
+    git(dir, &["symbolic-ref", "--short", "--quiet", "HEAD"])
+        .await
+        .ok()
+        .map(|out| out.trim().to_string())
+}
+
+/**
+ * Check out a new branch starting at `base_commit` into `worktree_dir`
+ */
+pub async fn add(repo: &Path, worktree_dir: &Path, branch: &str, base_commit: &str) -> Result<(), String> {
+    let dir = worktree_dir.display().to_string();
+    git(repo, &["worktree", "add", "-b", branch, &dir, base_commit]).await.map(|_| ())
+}
+
+/**
+ * Files with uncommitted changes in a working tree, untracked ones included
+ */
+pub async fn uncommitted_changes(dir: &Path) -> Result<Vec<String>, String> {
+    let status = git(dir, &["status", "--porcelain", "-z"]).await?;
+    Ok(parse_status(&status))
+}
+
+/**
+ * Paths of `git status --porcelain -z`: `XY path\0`, where renames and copies
+ * are followed by `old path\0`; the new path is the one listed
+ */
+fn parse_status(status: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut entries = status.split('\0');
+    while let Some(entry) = entries.next() {
+        let Some((code, path)) = entry.split_at_checked(3) else {
+            continue;
+        };
+        if code.contains(['R', 'C']) {
+            entries.next();
+        }
+        paths.push(path.to_string());
+    }
+    paths
+}
+
+/**
+ * Remove a session's worktree
+ * A directory that is already gone only leaves git's bookkeeping to prune
+ */
+pub async fn remove(repo: &Path, worktree_dir: &Path, force: bool) -> Result<(), String> {
+    if !worktree_dir.exists() {
+        return git(repo, &["worktree", "prune"]).await.map(|_| ());
+    }
+
+    let dir = worktree_dir.display().to_string();
+    let mut args = vec!["worktree", "remove"];
+    if force {
+        args.push("--force");
+    }
+    args.push(&dir);
+    git(repo, &args).await.map(|_| ())
+}
+
+/**
+ * Delete a branch lychee created
+ * Without force git refuses if it has commits not merged anywhere else
+ */
+pub async fn delete_branch(repo: &Path, branch: &str, force: bool) -> Result<(), String> {
+    let flag = if force { "-D" } else { "-d" };
+    git(repo, &["branch", flag, branch]).await.map(|_| ())
+}
+
+/**
+ * Files left conflicted by a merge or rebase in `dir`
+ */
+pub async fn conflicted_files(dir: &Path) -> Vec<String> {
+    git(dir, &["diff", "--name-only", "--diff-filter=U", "-z"])
+        .await
+        .map(|out| out.split('\0').filter(|path| !path.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/**
+ * Worktree that has `branch` checked out, the main one included
+ */
+pub async fn worktree_for_branch(repo: &Path, branch: &str) -> Option<PathBuf> {
+    let list = git(repo, &["worktree", "list", "--porcelain"]).await.ok()?;
+    let target = format!("branch refs/heads/{}", branch);
+    let mut current = None;
+    for line in list.lines() {
+        if let Some(path) = line.strip_prefix("worktree ") {
+            current = Some(PathBuf::from(path));
+        } else if line == target {
+            return current;
+        }
+    }
+    None
+}
+
+/**
+ * Stage and commit everything in a working tree
+ */
+pub async fn commit_all(dir: &Path, message: &str) -> Result<(), String> {
+    git(dir, &["add", "--all"]).await?;
+    git(dir, &["commit", "--quiet", "-m", message]).await.map(|_| ())
+}
+
+/**
+ * Land a session's branch on its base branch
+ *
+ * Merges and squashes run in a scratch worktree detached at the base, so a
+ * conflict never touches a checkout anyone is using; rebases run in the
+ * session's own worktree and are aborted on conflict, or undone when the base
+ * can't be moved. Only a clean result moves the base branch, and only forward
+ * from where it was when we started.
+ */
+pub async fn finish(request: &FinishRequest<'_>, mut progress: impl FnMut(FinishStage)) -> Result<FinishOutcome, String> {
+    let FinishRequest { repo, worktree_dir, branch, base, strategy, message } = *request;
+    let old_base = resolve_commit(repo, &format!("refs/heads/{}", base)).await?;
+
+    let ahead = git(repo, &["rev-list", "--count", &format!("{}..refs/heads/{}", old_base, branch)]).await?;
+    if ahead.trim() == "0" {
+        return Ok(FinishOutcome::UpToDate);
+    }
+
+    let commit = match strategy {
+        FinishStrategy::Rebase => {
+            progress(FinishStage::Rebasing);
+            let original = resolve_commit(worktree_dir, "HEAD").await?;
+            if let Err(e) = git(worktree_dir, &["rebase", &old_base]).await {
+                let files = conflicted_files(worktree_dir).await;
+                let _ = git(worktree_dir, &["rebase", "--abort"]).await;
+                return if files.is_empty() { Err(e) } else { Ok(FinishOutcome::Conflict(files)) };
+            }
+            let commit = resolve_commit(worktree_dir, "HEAD").await?;
+
+            progress(FinishStage::UpdatingBase);
+            if let Err(e) = advance_branch(repo, base, &old_base, &commit).await {
+                // The session keeps its commits as they were
+                if let Err(reset) = git(worktree_dir, &["reset", "--keep", "--quiet", &original]).await {
+                    tracing::error!(%branch, %original, error = %reset, "failed to undo the rebase");
+                }
+                return Err(e);
+            }
+            return Ok(FinishOutcome::Finished(commit));
+        }
+        FinishStrategy::Merge | FinishStrategy::Squash => {
+            progress(FinishStage::Merging);
+            let scratch = repo.join(".lychee").join(format!(".finish-{}", branch.replace('/', "-")));
+            // Left behind by a finish that didn't get to clean up
+            clear_scratch(repo, &scratch).await;
+            let scratch_dir = scratch.display().to_string();
+            git(repo, &["worktree", "add", "--detach", &scratch_dir, &old_base]).await?;
+            let merged = merge_in(&scratch, branch, strategy, message).await;
+            // Conflicts included, whatever happened in there is thrown away
+            clear_scratch(repo, &scratch).await;
+            match merged? {
+                FinishOutcome::Finished(commit) => commit,
+                outcome => return Ok(outcome),
+            }
+        }
+    };
+
+    progress(FinishStage::UpdatingBase);
+    advance_branch(repo, base, &old_base, &commit).await?;
+    Ok(FinishOutcome::Finished(commit))
+}
+
+/**
+ * Remove a scratch worktree, registered with git or not
+ */
+async fn clear_scratch(repo: &Path, scratch: &Path) {
+    if remove(repo, scratch, true).await.is_err()
+        && let Err(e) = tokio::fs::remove_dir_all(scratch).await
+    {
+        tracing::warn!(dir = %scratch.display(), error = %e, "failed to remove scratch worktree");
+    }
+    let _ = git(repo, &["worktree", "prune"]).await;
+}
+
+/**
+ * Merge or squash `branch` into the detached HEAD of `dir`
+ * Finished with the new commit, UpToDate when a squash changes nothing, or
+ * the conflicting files
+ */
+async fn merge_in(dir: &Path, branch: &str, strategy: FinishStrategy, message: &str) -> Result<FinishOutcome, String> {
+    let merged = match strategy {
+        FinishStrategy::Squash => git(dir, &["merge", "--squash", branch]).await,
+        _ => git(dir, &["merge", "--no-ff", "-m", message, branch]).await,
+    };
+    if let Err(e) = merged {
+        let files = conflicted_files(dir).await;
+        return if files.is_empty() { Err(e) } else { Ok(FinishOutcome::Conflict(files)) };
+    }
+
+    if strategy == FinishStrategy::Squash {
+        // The session's commits cancel out, or are already on the base
+        if git(dir, &["diff", "--cached", "--quiet"]).await.is_ok() {
+            return Ok(FinishOutcome::UpToDate);
+        }
+        git(dir, &["commit", "--quiet", "-m", message]).await?;
+    }
+    resolve_commit(dir, "HEAD").await.map(FinishOutcome::Finished)
+}
+
+/**
+ * Fast-forward `branch` from `old` to `new`
+ * A checked out branch is moved by its worktree, so files follow; git refuses
+ * if that would overwrite local changes there
+ */
+async fn advance_branch(repo: &Path, branch: &str, old: &str, new: &str) -> Result<(), String> {
+    match worktree_for_branch(repo, branch).await {
+        Some(dir) => git(&dir, &["merge", "--ff-only", "--quiet", new]).await.map(|_| ()),
+        None => {
+            let refname = format!("refs/heads/{}", branch);
+            git(repo, &["update-ref", &refname, new, old]).await.map(|_| ())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repository with one commit on `main`
+    async fn repo() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git(root, &["init", "--quiet", "--initial-branch=main"]).await.unwrap();
+        git(root, &["config", "user.name", "Test"]).await.unwrap();
+        git(root, &["config", "user.email", "test@example.com"]).await.unwrap();
+        std::fs::write(root.join("README.md"), "hello\n").unwrap();
+        std::fs::write(root.join("old.txt"), "old\n").unwrap();
+        git(root, &["add", "."]).await.unwrap();
+        git(root, &["commit", "--quiet", "-m", "initial"]).await.unwrap();
+        dir
+    }
+
+    #[test]
+    fn parse_status_entries() {
+        let status = " M src/main.rs\0R  new name.txt\0old name.txt\0?? café.txt\0A  \"quoted\".txt\0";
+        assert_eq!(parse_status(status), vec!["src/main.rs", "new name.txt", "café.txt", "\"quoted\".txt"]);
+        assert!(parse_status("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn uncommitted_changes_lists_plain_paths() {
+        let dir = repo().await;
+        let root = dir.path();
+        git(root, &["mv", "old.txt", "renamed file.txt"]).await.unwrap();
+        std::fs::write(root.join("README.md"), "changed\n").unwrap();
+        std::fs::write(root.join("naïve notes.md"), "new\n").unwrap();
+
+        let mut changes = uncommitted_changes(root).await.unwrap();
+        changes.sort();
+        assert_eq!(changes, vec!["README.md", "naïve notes.md", "renamed file.txt"]);
+    }
+
+    /// A session worktree on `lychee/test`, with one commit changing README.md
+    async fn session(repo: &Path) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let worktree = dir.path().join("session");
+        let base = resolve_commit(repo, "main").await.unwrap();
+        add(repo, &worktree, "lychee/test", &base).await.unwrap();
+        std::fs::write(worktree.join("README.md"), "hello from the session\n").unwrap();
+        commit_all(&worktree, "session work").await.unwrap();
+        (dir, worktree)
+    }
+
+    fn request<'a>(repo: &'a Path, worktree_dir: &'a Path, strategy: FinishStrategy) -> FinishRequest<'a> {
+        FinishRequest {
+            repo,
+            worktree_dir,
+            branch: "lychee/test",
+            base: "main",
+            strategy,
+            message: "Finish session",
+        }
+    }
+
+    async fn show(dir: &Path, spec: &str) -> String {
+        git(dir, &["show", spec]).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn finish_lands_each_strategy_on_the_base() {
+        for strategy in [FinishStrategy::Squash, FinishStrategy::Merge, FinishStrategy::Rebase] {
+            let repo = repo().await;
+            let (_dir, worktree) = session(repo.path()).await;
+
+            let outcome = finish(&request(repo.path(), &worktree, strategy), |_| {}).await.unwrap();
+            let FinishOutcome::Finished(commit) = outcome else {
+                panic!("{:?} didn't finish: {:?}", strategy, outcome);
+            };
+            assert_eq!(resolve_commit(repo.path(), "main").await.unwrap(), commit);
+            assert_eq!(show(repo.path(), "main:README.md").await, "hello from the session\n");
+            // The main checkout follows its branch
+            assert_eq!(std::fs::read_to_string(repo.path().join("README.md")).unwrap(), "hello from the session\n");
+
+            let parents = git(repo.path(), &["rev-list", "--parents", "-n", "1", "main"]).await.unwrap();
+            let expected = if strategy == FinishStrategy::Merge { 3 } else { 2 };
+            assert_eq!(parents.split_whitespace().count(), expected, "{:?}", strategy);
+
+            // Nothing left to land; a squashed branch still has its own commits
+            let again = finish(&request(repo.path(), &worktree, strategy), |_| {}).await.unwrap();
+            assert_eq!(again, FinishOutcome::UpToDate, "{:?}", strategy);
+        }
+    }
+
+    #[tokio::test]
+    async fn finish_reports_conflicts_and_leaves_the_base() {
+        let repo = repo().await;
+        let (_dir, worktree) = session(repo.path()).await;
+        std::fs::write(repo.path().join("README.md"), "hello from main\n").unwrap();
+        commit_all(repo.path(), "main work").await.unwrap();
+        let base = resolve_commit(repo.path(), "main").await.unwrap();
+        let head = resolve_commit(&worktree, "HEAD").await.unwrap();
+
+        for strategy in [FinishStrategy::Squash, FinishStrategy::Merge, FinishStrategy::Rebase] {
+            let outcome = finish(&request(repo.path(), &worktree, strategy), |_| {}).await.unwrap();
+            assert_eq!(outcome, FinishOutcome::Conflict(vec!["README.md".to_string()]), "{:?}", strategy);
+            assert_eq!(resolve_commit(repo.path(), "main").await.unwrap(), base);
+            assert_eq!(resolve_commit(&worktree, "HEAD").await.unwrap(), head);
+        }
+    }
+
+    #[tokio::test]
+    async fn finish_replaces_a_stale_scratch_worktree() {
+        let repo = repo().await;
+        let (_dir, worktree) = session(repo.path()).await;
+
+        // A registered one, as a crashed finish leaves it, and an unregistered one
+        let scratch = repo.path().join(".lychee").join(".finish-lychee-test");
+        git(repo.path(), &["worktree", "add", "--detach", &scratch.display().to_string(), "main"]).await.unwrap();
+        let outcome = finish(&request(repo.path(), &worktree, FinishStrategy::Merge), |_| {}).await.unwrap();
+        assert!(matches!(outcome, FinishOutcome::Finished(_)), "{:?}", outcome);
+        assert!(!scratch.exists());
+
+        std::fs::write(worktree.join("new.txt"), "more\n").unwrap();
+        commit_all(&worktree, "more work").await.unwrap();
+        std::fs::create_dir_all(&scratch).unwrap();
+        std::fs::write(scratch.join("junk"), "junk").unwrap();
+        let outcome = finish(&request(repo.path(), &worktree, FinishStrategy::Squash), |_| {}).await.unwrap();
+        assert!(matches!(outcome, FinishOutcome::Finished(_)), "{:?}", outcome);
+        assert!(!scratch.exists());
+    }
+
+    #[tokio::test]
+    async fn squash_without_net_changes_is_up_to_date() {
+        let repo = repo().await;
+        let (_dir, worktree) = session(repo.path()).await;
+        std::fs::write(worktree.join("README.md"), "hello\n").unwrap();
+        commit_all(&worktree, "undo session work").await.unwrap();
+        let base = resolve_commit(repo.path(), "main").await.unwrap();
+
+        let outcome = finish(&request(repo.path(), &worktree, FinishStrategy::Squash), |_| {}).await.unwrap();
+        assert_eq!(outcome, FinishOutcome::UpToDate);
+        assert_eq!(resolve_commit(repo.path(), "main").await.unwrap(), base);
+    }
+
+    #[tokio::test]
+    async fn rebase_is_undone_when_the_base_cant_move() {
+        let repo = repo().await;
+        let (_dir, worktree) = session(repo.path()).await;
+        std::fs::write(repo.path().join("other.txt"), "main work\n").unwrap();
+        commit_all(repo.path(), "main work").await.unwrap();
+        let base = resolve_commit(repo.path(), "main").await.unwrap();
+        let head = resolve_commit(&worktree, "HEAD").await.unwrap();
+
+        // Fast-forwarding the main checkout would overwrite this
+        std::fs::write(repo.path().join("README.md"), "local edit\n").unwrap();
+
+        let result = finish(&request(repo.path(), &worktree, FinishStrategy::Rebase), |_| {}).await;
+        assert!(result.is_err());
+        assert_eq!(resolve_commit(repo.path(), "main").await.unwrap(), base);
+        assert_eq!(resolve_commit(&worktree, "HEAD").await.unwrap(), head);
+        assert_eq!(current_branch(&worktree).await.as_deref(), Some("lychee/test"));
+    }
+}
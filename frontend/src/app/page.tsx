@@ -3,6 +3,7 @@
 import { useEffect, useMemo, useRef, useState } from "react";
 import { useSessionsContext } from "@/components/AppShell";
 import ChatComposer from "@/components/ChatComposer";
+import PermissionPrompt from "@/components/PermissionPrompt";
 import MarkdownRenderer from "@/components/MarkdownRenderer";
 import WorklogSection from "@/components/WorklogSection";
 import { ChatMessage, ClaudeToolUse } from "@/lib/sessions";
@@ -302,6 +303,16 @@ export default function Home() {
     [sessions.messages]
   );
 
+  // Text Claude is writing right now, replaced by the real entry once persisted
+  const streamingText = sessions.partialBlocks
+    .filter((b) => b.block_type === "text")
+    .map((b) => b.text)
+    .join("\n\n");
+
+  const permissionRequests = sessions.permissionRequests.filter(
+    (r) => r.lychee_id === sessions.currentSessionId
+  );
+
   // Handle scroll position tracking
   useEffect(() => {
     const el = messageContainerRef.current;
@@ -336,7 +347,7 @@ export default function Home() {
         });
       });
     }
-  }, [processedMessages]);
+  }, [processedMessages, streamingText]);
 
   // Continuous smooth scroll during streaming
   useEffect(() => {
@@ -438,8 +449,16 @@ export default function Home() {
                 return null;
               })}
 
+              {streamingText.trim() && (
+                <div className="flex justify-start">
+                  <div className="w-full">
+                    <MarkdownRenderer content={streamingText} className="text-sm leading-relaxed" />
+                  </div>
+                </div>
+              )}
+
               {/* Show thinking indicator at the end if streaming but no assistant response yet */}
-              {isStreaming && processedMessages.length > 0 && (
+              {isStreaming && processedMessages.length > 0 && !streamingText.trim() && (
                 <div className="flex justify-start">
                   <div className="thinking-indicator flex items-center gap-2 text-muted-foreground">
                     <span className="h-2 w-2 animate-pulse rounded-full bg-primary/70" />
@@ -454,13 +473,14 @@ export default function Home() {
 
       <div className="pointer-events-none absolute bottom-0 left-0 right-0 flex justify-center pb-1 bg-gradient-to-t from-background via-background to-transparent pt-8">
         <div className="pointer-events-auto w-full max-w-4xl px-6">
+          <PermissionPrompt requests={permissionRequests} onAnswer={sessions.answerPermission} />
           <ChatComposer
             onSend={sessions.sendChatMessage}
-            disabled={!sessions.currentSessionId || isStreaming}
+            disabled={!sessions.currentSessionId}
             placeholder={
               sessions.currentSessionId
                 ? isStreaming
-                  ? "Claude is thinking..."
+                  ? "Claude is working, send a message to steer..."
                   : "Message Claude..."
                 : "Select or create a branch first"
             }
@@ -5,6 +5,7 @@ import Sidebar from "./Sidebar";
 import RightSidebar from "./RightSidebar";
 import TopBar from "./TopBar";
 import SessionInfoPanel from "./SessionInfoPanel";
+import RelayBanner from "./RelayBanner";
 import { useSessions, ChatMessage, ClaudeToolUse } from "@/lib/sessions";
 
 interface AppShellProps {
@@ -51,6 +52,7 @@ export default function AppShell({ children }: AppShellProps) {
   } | null>(null);
 
   const activeRepo = sessions.repos.find((repo) => repo.path === sessions.activeRepoPath) || null;
+  const currentSession = activeRepo?.sessions.find((s) => s.lychee_id === sessions.currentSessionId);
 
   const prevSessionIdRef = useRef<string | null>(null);
 
@@ -141,6 +143,13 @@ export default function AppShell({ children }: AppShellProps) {
             onSelectSession={sessions.selectSession}
             onNewSession={sessions.createSession}
             onNewWorktreeSession={sessions.createWorktreeSession}
+            onPair={sessions.pairRepo}
+            onRenameSession={sessions.renameSession}
+            onArchiveSession={sessions.archiveSession}
+            onDeleteSession={sessions.deleteSession}
+            onLoadArchived={sessions.loadArchivedSessions}
+            deleteBlocked={sessions.deleteBlocked}
+            onDismissDeleteBlocked={sessions.dismissDeleteBlocked}
             creatingSessionForRepo={sessions.creatingSessionForRepo}
             isCreatingSession={sessions.isCreatingSession}
             isCollapsed={isCollapsed}
@@ -148,6 +157,12 @@ export default function AppShell({ children }: AppShellProps) {
           />
 
           <div className="flex-1 min-w-0 flex flex-col overflow-hidden">
+            <RelayBanner
+              announcement={sessions.announcement}
+              maintenance={sessions.maintenance}
+              onDismissAnnouncement={sessions.dismissAnnouncement}
+            />
+
             {/* Session info panel - pushes content down when open */}
             {activeRepo && sessions.currentSessionId && (
               <SessionInfoPanel
@@ -155,9 +170,23 @@ export default function AppShell({ children }: AppShellProps) {
                 onClose={() => setIsPanelOpen(false)}
                 repoName={activeRepo.name}
                 sessionId={sessions.currentSessionId}
-                branchOrigin="origin/main"
-                isWorktree={
-                  activeRepo.sessions.find(s => s.lychee_id === sessions.currentSessionId)?.is_worktree ?? false
+                branch={currentSession?.branch}
+                branchOrigin={currentSession?.base_ref ?? currentSession?.base_commit?.slice(0, 8)}
+                baseRef={currentSession?.base_ref}
+                isWorktree={currentSession?.is_worktree ?? false}
+                settings={currentSession?.settings}
+                onSaveSettings={(settings) =>
+                  sessions.updateSessionSettings(activeRepo.path, sessions.currentSessionId!, settings)
+                }
+                finish={sessions.finishes[sessions.currentSessionId]}
+                onFinish={(strategy, autoCommit, commitMessage) =>
+                  sessions.finishWorktreeSession(
+                    activeRepo.path,
+                    sessions.currentSessionId!,
+                    strategy,
+                    autoCommit,
+                    commitMessage
+                  )
                 }
               />
             )}
@@ -179,6 +208,85 @@ export default function AppShell({ children }: AppShellProps) {
               setSelectedToolCall(null);
               setIsRightSidebarOpen(false);
             }}
+            sessionId={sessions.currentSessionId}
+            sessionLastActive={currentSession?.last_active}
+            filesVersion={sessions.currentSessionId ? sessions.filesVersion[sessions.currentSessionId] : undefined}
+            diff={sessions.currentSessionId ? sessions.diffs[sessions.currentSessionId] : undefined}
+            onRequestDiff={
+              activeRepo && sessions.currentSessionId
+                ? (scope) => sessions.requestDiff(activeRepo.path, sessions.currentSessionId!, scope)
+                : undefined
+            }
+            files={sessions.currentSessionId ? sessions.files[sessions.currentSessionId] : undefined}
+            onListDir={
+              activeRepo && sessions.currentSessionId
+                ? (path) => sessions.listDir(activeRepo.path, sessions.currentSessionId!, path)
+                : undefined
+            }
+            onOpenFile={
+              activeRepo && sessions.currentSessionId
+                ? (path) => {
+                    sessions.openFile(activeRepo.path, sessions.currentSessionId!, path);
+                    setIsRightSidebarOpen(true);
+                  }
+                : undefined
+            }
+            onLoadMoreOfFile={
+              activeRepo && sessions.currentSessionId
+                ? () => sessions.loadMoreOfFile(activeRepo.path, sessions.currentSessionId!)
+                : undefined
+            }
+            onWriteFile={
+              activeRepo && sessions.currentSessionId
+                ? (content, overwrite) => sessions.writeFile(activeRepo.path, sessions.currentSessionId!, content, overwrite)
+                : undefined
+            }
+            onCloseFile={() => sessions.currentSessionId && sessions.closeFile(sessions.currentSessionId)}
+            terminals={sessions.currentSessionId ? sessions.terminals[sessions.currentSessionId] : undefined}
+            onOpenTerminal={
+              activeRepo && sessions.currentSessionId
+                ? (cols, rows) => sessions.openTerminal(activeRepo.path, sessions.currentSessionId!, cols, rows)
+                : undefined
+            }
+            onTerminalInput={
+              activeRepo && sessions.currentSessionId
+                ? (terminalId, data) => sessions.sendTerminalInput(activeRepo.path, sessions.currentSessionId!, terminalId, data)
+                : undefined
+            }
+            onResizeTerminal={
+              activeRepo && sessions.currentSessionId
+                ? (terminalId, cols, rows) =>
+                    sessions.resizeTerminal(activeRepo.path, sessions.currentSessionId!, terminalId, cols, rows)
+                : undefined
+            }
+            onCloseTerminal={
+              activeRepo && sessions.currentSessionId
+                ? (terminalId) => sessions.closeTerminal(activeRepo.path, sessions.currentSessionId!, terminalId)
+                : undefined
+            }
+            commands={sessions.currentSessionId ? sessions.commands[sessions.currentSessionId] : undefined}
+            onRunCommand={
+              activeRepo && sessions.currentSessionId
+                ? (command) => sessions.runCommand(activeRepo.path, sessions.currentSessionId!, command)
+                : undefined
+            }
+            onCancelCommand={
+              activeRepo && sessions.currentSessionId
+                ? (commandId) => sessions.cancelCommand(activeRepo.path, sessions.currentSessionId!, commandId)
+                : undefined
+            }
+            onDismissCommandRun={(commandId) =>
+              sessions.currentSessionId && sessions.dismissCommandRun(sessions.currentSessionId, commandId)
+            }
+            preview={sessions.currentSessionId ? sessions.previews[sessions.currentSessionId] : undefined}
+            previewUrl={
+              activeRepo && sessions.currentSessionId ? sessions.previewUrl(activeRepo.path, sessions.currentSessionId) : null
+            }
+            onExposePort={
+              activeRepo && sessions.currentSessionId
+                ? (port) => sessions.exposePort(activeRepo.path, sessions.currentSessionId!, port)
+                : undefined
+            }
           />
         </div>
       </div>
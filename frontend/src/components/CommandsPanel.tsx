@@ -0,0 +1,160 @@
+"use client";
+
+import { useEffect, useRef, useState } from "react";
+import { Play, Square, X } from "lucide-react";
+import type { CommandRun, SessionCommands } from "@/lib/sessions";
+
+interface CommandsPanelProps {
+  commands?: SessionCommands;
+  onRun: (command: { name: string } | { argv: string[] }) => void;
+  onCancel: (commandId: string) => void;
+  onDismiss: (commandId: string) => void;
+}
+
+function formatDuration(ms: number): string {
+  if (ms < 1000) return `${ms} ms`;
+  if (ms < 60_000) return `${(ms / 1000).toFixed(1)} s`;
+  return `${Math.floor(ms / 60_000)} min ${Math.round((ms % 60_000) / 1000)} s`;
+}
+
+function statusLabel(run: CommandRun): string {
+  if (run.error && !run.finished) return "failed";
+  if (!run.finished) return "running";
+  const duration = formatDuration(run.finished.duration_ms);
+  if (run.finished.cancelled) return `cancelled after ${duration}`;
+  if (run.finished.exit_code === null) return `killed by signal ${run.finished.signal} after ${duration}`;
+  return `exited with code ${run.finished.exit_code} in ${duration}`;
+}
+
+function RunOutput({ run, onCancel, onDismiss }: { run: CommandRun; onCancel: () => void; onDismiss: () => void }) {
+  const outputRef = useRef<HTMLPreElement>(null);
+  const failed = run.error !== null || (run.finished !== null && run.finished.exit_code !== 0);
+
+  // Follow the output while it comes in
+  useEffect(() => {
+    const output = outputRef.current;
+    if (output) output.scrollTop = output.scrollHeight;
+  }, [run.output]);
+
+  return (
+    <div className="border-b border-border">
+      <div className="flex items-center gap-2 px-3 py-1.5">
+        <div className="flex-1 min-w-0">
+          <p className="text-xs font-mono truncate" title={run.argv.join(" ")}>
+            {run.name ?? run.argv.join(" ")}
+          </p>
+          <p className={`text-xs ${failed ? "text-red-700" : "text-sidebar-foreground/50"}`}>{statusLabel(run)}</p>
+        </div>
+        {run.finished || run.error ? (
+          <button
+            onClick={onDismiss}
+            title="Remove"
+            className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer"
+          >
+            <X className="w-3 h-3" />
+          </button>
+        ) : (
+          <button
+            onClick={onCancel}
+            title="Cancel"
+            className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer"
+          >
+            <Square className="w-3 h-3" />
+          </button>
+        )}
+      </div>
+      {run.error && <p className="px-3 pb-1.5 text-xs text-red-700">{run.error}</p>}
+      {run.output.length > 0 && (
+        <pre
+          ref={outputRef}
+          className="max-h-64 overflow-auto bg-black text-gray-100 px-2 py-1.5 text-xs font-mono leading-4 whitespace-pre"
+        >
+          {run.output.map((chunk, index) => (
+            <span key={index} className={chunk.stream === "stderr" ? "text-red-400" : undefined}>
+              {chunk.data}
+            </span>
+          ))}
+        </pre>
+      )}
+      {run.finished?.truncated && (
+        <p className="px-3 py-1 text-xs text-sidebar-foreground/50">Output past the client&apos;s limit was dropped</p>
+      )}
+    </div>
+  );
+}
+
+/**
+ * Build, test and other commands run in the session directory, on the client's machine
+ */
+export default function CommandsPanel({ commands, onRun, onCancel, onDismiss }: CommandsPanelProps) {
+  const [commandLine, setCommandLine] = useState("");
+  const targets = Object.entries(commands?.targets ?? {});
+  const allowed = commands?.allowed ?? [];
+  const runs = commands?.runs ?? [];
+
+  const handleSubmit = (e: React.FormEvent) => {
+    e.preventDefault();
+    // Arguments are split on whitespace; nothing goes through a shell
+    const argv = commandLine.trim().split(/\s+/).filter(Boolean);
+    if (argv.length === 0) return;
+    onRun({ argv });
+    setCommandLine("");
+  };
+
+  return (
+    <div className="flex flex-col">
+      {targets.length > 0 && (
+        <div className="flex flex-wrap gap-1 px-3 py-2 border-b border-border">
+          {targets.map(([name, argv]) => (
+            <button
+              key={name}
+              onClick={() => onRun({ name })}
+              disabled={commands?.starting}
+              title={argv.join(" ")}
+              className="flex items-center gap-1 px-1.5 py-0.5 rounded-sm text-xs font-mono bg-sidebar-accent text-sidebar-foreground hover:bg-sidebar-accent/70 cursor-pointer disabled:opacity-50"
+            >
+              <Play className="w-3 h-3" />
+              {name}
+            </button>
+          ))}
+        </div>
+      )}
+
+      {allowed.length > 0 && (
+        <form onSubmit={handleSubmit} className="px-3 py-2 border-b border-border">
+          <input
+            value={commandLine}
+            onChange={(e) => setCommandLine(e.target.value)}
+            disabled={commands?.starting}
+            placeholder={`${allowed.join(", ")} ...`}
+            spellCheck={false}
+            className="w-full rounded border border-border bg-background px-2 py-1 text-xs font-mono"
+          />
+        </form>
+      )}
+
+      {commands?.error && <p className="px-3 py-2 text-xs text-red-700">{commands.error}</p>}
+
+      {runs.length > 0 ? (
+        [...runs]
+          .reverse()
+          .map((run) => (
+            <RunOutput
+              key={run.command_id}
+              run={run}
+              onCancel={() => onCancel(run.command_id)}
+              onDismiss={() => onDismiss(run.command_id)}
+            />
+          ))
+      ) : (
+        <p className="px-3 py-2 text-xs text-sidebar-foreground/50">
+          {commands?.starting
+            ? "Starting..."
+            : targets.length === 0 && allowed.length === 0
+              ? "No commands are configured for this client. Add them under [commands] in its config."
+              : "Nothing has run in this session yet."}
+        </p>
+      )}
+    </div>
+  );
+}
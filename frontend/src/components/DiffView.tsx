@@ -0,0 +1,131 @@
+"use client";
+
+import { useEffect, useState } from "react";
+import { ChevronDown, ChevronRight, RefreshCw } from "lucide-react";
+import type { DiffScope, FileDiff, FileStatus, SessionDiff } from "@/lib/sessions";
+
+interface DiffViewProps {
+  sessionId: string;
+  // Changes after every turn, so the diff is reloaded when it does
+  lastActive?: string;
+  // Bumped when files in the session directory change
+  filesVersion?: number;
+  diff?: SessionDiff;
+  onRequest: (scope: DiffScope) => void;
+}
+
+const SCOPES: { value: DiffScope; label: string }[] = [
+  { value: "all", label: "All" },
+  { value: "committed", label: "Committed" },
+  { value: "staged", label: "Staged" },
+  { value: "unstaged", label: "Unstaged" },
+];
+
+const STATUS_LETTERS: Record<FileStatus, string> = {
+  added: "A",
+  modified: "M",
+  deleted: "D",
+  renamed: "R",
+  copied: "C",
+  type_changed: "T",
+  untracked: "U",
+};
+
+const LINE_STYLES = {
+  add: "bg-green-500/10 text-green-900",
+  remove: "bg-red-500/10 text-red-900",
+  context: "text-sidebar-foreground/70",
+};
+
+const LINE_MARKERS = { add: "+", remove: "-", context: " " };
+
+function FileSection({ file }: { file: FileDiff }) {
+  const [isOpen, setIsOpen] = useState(file.hunks.length > 0 && file.hunks.length < 20);
+
+  return (
+    <div className="border-b border-border">
+      <button
+        onClick={() => setIsOpen(!isOpen)}
+        className="w-full flex items-center gap-2 px-3 py-1.5 text-left text-xs hover:bg-sidebar-accent cursor-pointer"
+      >
+        {isOpen ? (
+          <ChevronDown className="w-3 h-3 flex-shrink-0 text-sidebar-foreground/50" />
+        ) : (
+          <ChevronRight className="w-3 h-3 flex-shrink-0 text-sidebar-foreground/50" />
+        )}
+        <span className="w-3 flex-shrink-0 font-mono text-sidebar-foreground/50">{STATUS_LETTERS[file.status]}</span>
+        <span className="flex-1 truncate font-mono text-sidebar-foreground" title={file.path}>
+          {file.old_path ? `${file.old_path} → ${file.path}` : file.path}
+        </span>
+        <span className="flex-shrink-0 font-mono text-green-700">+{file.additions}</span>
+        <span className="flex-shrink-0 font-mono text-red-700">-{file.deletions}</span>
+      </button>
+
+      {isOpen && (
+        <div className="overflow-x-auto pb-1">
+          {file.binary && <p className="px-3 py-1 text-xs text-sidebar-foreground/50">Binary file</p>}
+          {file.truncated && <p className="px-3 py-1 text-xs text-sidebar-foreground/50">Diff too large to show</p>}
+          {file.hunks.map((hunk) => (
+            <div key={hunk.header} className="font-mono text-[11px] leading-4">
+              <div className="px-3 py-0.5 bg-sidebar-accent text-sidebar-foreground/50">{hunk.header}</div>
+              {hunk.lines.map((line, index) => (
+                <div key={index} className={`px-3 whitespace-pre ${LINE_STYLES[line.kind]}`}>
+                  {LINE_MARKERS[line.kind]}
+                  {line.content}
+                </div>
+              ))}
+            </div>
+          ))}
+        </div>
+      )}
+    </div>
+  );
+}
+
+/**
+ * What the session changed, for review
+ */
+export default function DiffView({ sessionId, lastActive, filesVersion, diff, onRequest }: DiffViewProps) {
+  const [scope, setScope] = useState<DiffScope>("all");
+
+  // Load when the session or scope changes, after each turn, and as files change
+  useEffect(() => {
+    onRequest(scope);
+  }, [sessionId, scope, lastActive, filesVersion]);
+
+  return (
+    <div className="flex flex-col">
+      <div className="flex items-center gap-1 px-3 py-2 border-b border-border">
+        <h3 className="text-sm font-medium text-sidebar-foreground mr-auto">Changes</h3>
+        {SCOPES.map((option) => (
+          <button
+            key={option.value}
+            onClick={() => setScope(option.value)}
+            className={`px-1.5 py-0.5 rounded-sm text-xs cursor-pointer ${
+              scope === option.value
+                ? "bg-sidebar-accent text-sidebar-foreground"
+                : "text-sidebar-foreground/50 hover:text-sidebar-foreground"
+            }`}
+          >
+            {option.label}
+          </button>
+        ))}
+        <button
+          onClick={() => onRequest(scope)}
+          title="Refresh"
+          className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer"
+        >
+          <RefreshCw className="w-3 h-3" />
+        </button>
+      </div>
+
+      {!diff || diff.scope !== scope ? (
+        <p className="px-3 py-2 text-xs text-sidebar-foreground/50">Loading...</p>
+      ) : diff.files.length === 0 ? (
+        <p className="px-3 py-2 text-xs text-sidebar-foreground/50">No changes</p>
+      ) : (
+        diff.files.map((file) => <FileSection key={`${file.status}:${file.path}`} file={file} />)
+      )}
+    </div>
+  );
+}
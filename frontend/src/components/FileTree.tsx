@@ -0,0 +1,125 @@
+"use client";
+
+import { useEffect, useState } from "react";
+import { ChevronDown, ChevronRight, File, Folder, Link2, RefreshCw } from "lucide-react";
+import type { DirEntry, SessionFiles } from "@/lib/sessions";
+
+interface FileTreeProps {
+  sessionId: string;
+  files?: SessionFiles;
+  onListDir: (path: string) => void;
+  onOpenFile: (path: string) => void;
+}
+
+interface DirectoryProps {
+  path: string;
+  depth: number;
+  files?: SessionFiles;
+  onListDir: (path: string) => void;
+  onOpenFile: (path: string) => void;
+}
+
+function joinPath(dir: string, name: string): string {
+  return dir ? `${dir}/${name}` : name;
+}
+
+function Entry({ entry, ...props }: DirectoryProps & { entry: DirEntry }) {
+  const [isOpen, setIsOpen] = useState(false);
+  const path = joinPath(props.path, entry.name);
+  const isDir = entry.kind === "dir";
+  const Icon = isDir ? Folder : entry.kind === "symlink" ? Link2 : File;
+
+  const handleClick = () => {
+    if (!isDir) {
+      props.onOpenFile(path);
+      return;
+    }
+    if (!isOpen && !props.files?.listings[path]) {
+      props.onListDir(path);
+    }
+    setIsOpen(!isOpen);
+  };
+
+  return (
+    <>
+      <button
+        onClick={handleClick}
+        className="w-full flex items-center gap-1.5 py-0.5 pr-3 text-left text-xs hover:bg-sidebar-accent cursor-pointer"
+        style={{ paddingLeft: `${12 + props.depth * 12}px` }}
+      >
+        {isDir ? (
+          isOpen ? (
+            <ChevronDown className="w-3 h-3 flex-shrink-0 text-sidebar-foreground/50" />
+          ) : (
+            <ChevronRight className="w-3 h-3 flex-shrink-0 text-sidebar-foreground/50" />
+          )
+        ) : (
+          <span className="w-3 flex-shrink-0" />
+        )}
+        <Icon className="w-3 h-3 flex-shrink-0 text-sidebar-foreground/50" />
+        <span className="flex-1 truncate font-mono text-sidebar-foreground">{entry.name}</span>
+      </button>
+      {isOpen && <Directory {...props} path={path} depth={props.depth + 1} />}
+    </>
+  );
+}
+
+function Directory(props: DirectoryProps) {
+  const listing = props.files?.listings[props.path];
+  const error = props.files?.error?.path === props.path ? props.files.error.message : null;
+  const indent = { paddingLeft: `${24 + props.depth * 12}px` };
+
+  if (error) {
+    return <p className="py-0.5 text-xs text-red-700" style={indent}>{error}</p>;
+  }
+  if (!listing) {
+    return <p className="py-0.5 text-xs text-sidebar-foreground/50" style={indent}>Loading...</p>;
+  }
+
+  return (
+    <>
+      {listing.entries.map((entry) => (
+        <Entry key={entry.name} entry={entry} {...props} />
+      ))}
+      {listing.entries.length === 0 && (
+        <p className="py-0.5 text-xs text-sidebar-foreground/50" style={indent}>Empty</p>
+      )}
+      {listing.truncated && (
+        <p className="py-0.5 text-xs text-sidebar-foreground/50" style={indent}>Too many entries to show all</p>
+      )}
+    </>
+  );
+}
+
+/**
+ * Browse the session directory; .gitignore'd files are left out
+ */
+export default function FileTree({ sessionId, files, onListDir, onOpenFile }: FileTreeProps) {
+  useEffect(() => {
+    onListDir("");
+  }, [sessionId]);
+
+  // Reload every directory that has been opened
+  const refresh = () => {
+    const paths = new Set(["", ...Object.keys(files?.listings ?? {})]);
+    paths.forEach(onListDir);
+  };
+
+  return (
+    <div className="flex flex-col">
+      <div className="flex items-center gap-1 px-3 py-2 border-b border-border">
+        <h3 className="text-sm font-medium text-sidebar-foreground mr-auto">Files</h3>
+        <button
+          onClick={refresh}
+          title="Refresh"
+          className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer"
+        >
+          <RefreshCw className="w-3 h-3" />
+        </button>
+      </div>
+      <div className="py-1">
+        <Directory path="" depth={0} files={files} onListDir={onListDir} onOpenFile={onOpenFile} />
+      </div>
+    </div>
+  );
+}
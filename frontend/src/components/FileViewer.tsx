@@ -0,0 +1,158 @@
+"use client";
+
+import { useEffect, useState } from "react";
+import { Pencil, X } from "lucide-react";
+import { Button } from "@/components/ui/button";
+import type { OpenFile } from "@/lib/sessions";
+
+interface FileViewerProps {
+  file: OpenFile;
+  onLoadMore: () => void;
+  onClose: () => void;
+  // Editing is offered when given
+  onSave?: (content: string, overwrite: boolean) => void;
+  onReload?: () => void;
+}
+
+const IMAGE_TYPES: Record<string, string> = {
+  png: "image/png",
+  jpg: "image/jpeg",
+  jpeg: "image/jpeg",
+  gif: "image/gif",
+  webp: "image/webp",
+  ico: "image/x-icon",
+};
+
+function formatSize(bytes: number): string {
+  if (bytes < 1024) return `${bytes} B`;
+  if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KB`;
+  return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+}
+
+/**
+ * View of a file in the session directory; whole text files can be edited
+ */
+export default function FileViewer({ file, onLoadMore, onClose, onSave, onReload }: FileViewerProps) {
+  const [draft, setDraft] = useState<string | null>(null);
+  const content = file.content;
+  const extension = file.path.split(".").pop()?.toLowerCase() ?? "";
+  const imageType = content?.binary && !content.truncated ? IMAGE_TYPES[extension] : undefined;
+  const canEdit = onSave && content && !content.binary && !content.truncated && !file.error;
+  const isSaving = file.saving !== null;
+  const writeError = file.writeError;
+
+  // Leave editing once a save went through
+  useEffect(() => {
+    if (!isSaving && !writeError) setDraft(null);
+  }, [isSaving, writeError]);
+
+  // A different file starts out read-only
+  useEffect(() => {
+    setDraft(null);
+  }, [file.path]);
+
+  // Follow changes made elsewhere, unless they would throw away an edit
+  useEffect(() => {
+    if (file.changedOnDisk && draft === null) onReload?.();
+  }, [file.changedOnDisk, draft]);
+
+  return (
+    <div className="h-full flex flex-col bg-background">
+      <div className="flex items-center justify-between gap-2 px-4 py-3 border-b">
+        <div className="min-w-0">
+          <h3 className="font-semibold text-sm font-mono truncate" title={file.path}>
+            {file.path}
+          </h3>
+          {file.stat && (
+            <p className="text-xs text-muted-foreground mt-0.5">
+              {formatSize(file.stat.size)}
+              {file.stat.modified && ` · modified ${new Date(file.stat.modified).toLocaleString()}`}
+            </p>
+          )}
+        </div>
+        <div className="flex items-center gap-1 flex-shrink-0">
+          {canEdit && draft === null && (
+            <button
+              onClick={() => setDraft(content.content)}
+              title="Edit"
+              className="p-1 hover:bg-muted rounded-md transition-colors"
+            >
+              <Pencil className="w-4 h-4" />
+            </button>
+          )}
+          <button onClick={onClose} className="p-1 hover:bg-muted rounded-md transition-colors">
+            <X className="w-4 h-4" />
+          </button>
+        </div>
+      </div>
+
+      {file.changedOnDisk && draft !== null && !writeError && (
+        <div className="mx-4 mt-3 rounded border border-orange-200 bg-orange-50 px-3 py-2 text-xs text-orange-900">
+          The file changed on disk since you started editing
+        </div>
+      )}
+
+      {writeError && draft !== null && (
+        <div className="mx-4 mt-3 rounded border border-orange-200 bg-orange-50 px-3 py-2 text-xs text-orange-900 space-y-2">
+          <p>{writeError.message}</p>
+          {writeError.conflict && (
+            <div className="flex gap-2">
+              <Button size="sm" variant="outline" onClick={() => onSave?.(draft, true)}>
+                {writeError.conflict.reason === "streaming" ? "Save anyway" : "Overwrite"}
+              </Button>
+              {writeError.conflict.reason === "changed" && (
+                <Button size="sm" variant="outline" onClick={() => onReload?.()}>
+                  Discard and reload
+                </Button>
+              )}
+            </div>
+          )}
+        </div>
+      )}
+
+      <div className="flex-1 overflow-auto">
+        {draft !== null ? (
+          <div className="h-full flex flex-col p-4 gap-2">
+            <textarea
+              value={draft}
+              onChange={(e) => setDraft(e.target.value)}
+              spellCheck={false}
+              className="flex-1 min-h-64 w-full resize-none rounded border border-border bg-background p-2 text-xs font-mono"
+            />
+            <div className="flex justify-end gap-2">
+              <Button size="sm" variant="outline" disabled={isSaving} onClick={() => setDraft(null)}>
+                Cancel
+              </Button>
+              <Button size="sm" disabled={isSaving} onClick={() => onSave?.(draft, false)}>
+                {isSaving ? "Saving..." : "Save"}
+              </Button>
+            </div>
+          </div>
+        ) : file.error ? (
+          <p className="px-4 py-3 text-xs text-red-700">{file.error}</p>
+        ) : !content ? (
+          <p className="px-4 py-3 text-xs text-muted-foreground">Loading...</p>
+        ) : imageType ? (
+          <div className="p-4">
+            {/* eslint-disable-next-line @next/next/no-img-element */}
+            <img src={`data:${imageType};base64,${content.content}`} alt={file.path} className="max-w-full" />
+          </div>
+        ) : content.binary ? (
+          <p className="px-4 py-3 text-xs text-muted-foreground">Binary file, {formatSize(content.size)}</p>
+        ) : (
+          <>
+            <pre className="px-4 py-3 text-xs font-mono whitespace-pre">{content.content}</pre>
+            {content.truncated && (
+              <button
+                onClick={onLoadMore}
+                className="mx-4 mb-3 text-xs text-blue-600 hover:underline cursor-pointer"
+              >
+                Load more ({formatSize(file.loaded)} of {formatSize(content.size)} shown)
+              </button>
+            )}
+          </>
+        )}
+      </div>
+    </div>
+  );
+}
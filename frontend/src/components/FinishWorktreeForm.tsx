@@ -0,0 +1,107 @@
+"use client";
+
+import { useState } from "react";
+import { GitMerge } from "lucide-react";
+import { Button } from "@/components/ui/button";
+import { Input } from "@/components/ui/input";
+import {
+  Select,
+  SelectContent,
+  SelectItem,
+  SelectTrigger,
+  SelectValue,
+} from "@/components/ui/select";
+import type { FinishStage, FinishState, FinishStatus, FinishStrategy } from "@/lib/sessions";
+
+interface FinishWorktreeFormProps {
+  baseRef?: string | null;
+  finish?: FinishState;
+  onFinish: (strategy: FinishStrategy, autoCommit: boolean, commitMessage: string) => void;
+}
+
+const STRATEGIES: { value: FinishStrategy; label: string }[] = [
+  { value: "squash", label: "Squash into one commit" },
+  { value: "merge", label: "Merge commit" },
+  { value: "rebase", label: "Rebase and fast-forward" },
+];
+
+const STAGE_LABELS: Record<FinishStage, string> = {
+  committing: "Committing outstanding changes...",
+  rebasing: "Rebasing...",
+  merging: "Merging...",
+  updating_base: "Updating base branch...",
+};
+
+const STATUS_STYLES: Record<FinishStatus, string> = {
+  finished: "border-green-200 bg-green-50 text-green-900",
+  up_to_date: "border-border bg-muted text-muted-foreground",
+  conflict: "border-orange-200 bg-orange-50 text-orange-900",
+  uncommitted: "border-orange-200 bg-orange-50 text-orange-900",
+  failed: "border-red-200 bg-red-50 text-red-900",
+};
+
+/**
+ * Lands a worktree session's branch on the branch it started from
+ */
+export default function FinishWorktreeForm({ baseRef, finish, onFinish }: FinishWorktreeFormProps) {
+  const [strategy, setStrategy] = useState<FinishStrategy>("squash");
+  const [autoCommit, setAutoCommit] = useState(true);
+  const [commitMessage, setCommitMessage] = useState("");
+  const isRunning = finish !== undefined && finish.result === null;
+  const result = finish?.result;
+
+  return (
+    <div className="space-y-2 text-xs">
+      <div className="flex items-center gap-2 font-medium text-foreground">
+        <GitMerge className="w-3.5 h-3.5" />
+        Finish into {baseRef ? <code className="font-mono">{baseRef}</code> : "the base branch"}
+      </div>
+      <div className="grid grid-cols-2 gap-3">
+        <Select value={strategy} onValueChange={(value) => setStrategy(value as FinishStrategy)}>
+          <SelectTrigger className="w-full">
+            <SelectValue />
+          </SelectTrigger>
+          <SelectContent>
+            {STRATEGIES.map((option) => (
+              <SelectItem key={option.value} value={option.value} className="text-xs">
+                {option.label}
+              </SelectItem>
+            ))}
+          </SelectContent>
+        </Select>
+        <Input
+          value={commitMessage}
+          onChange={(e) => setCommitMessage(e.target.value)}
+          placeholder="Commit message (session title)"
+        />
+      </div>
+      <div className="flex items-center justify-between">
+        <label className="flex items-center gap-2 text-muted-foreground">
+          <input type="checkbox" checked={autoCommit} onChange={(e) => setAutoCommit(e.target.checked)} />
+          Commit outstanding changes first
+        </label>
+        <Button size="sm" disabled={isRunning} onClick={() => onFinish(strategy, autoCommit, commitMessage)}>
+          {isRunning ? (finish.stage ? STAGE_LABELS[finish.stage] : "Starting...") : "Finish"}
+        </Button>
+      </div>
+      {result && (
+        <div className={`rounded border px-3 py-2 ${STATUS_STYLES[result.status]}`}>
+          {result.status === "finished" && (
+            <p>
+              {result.base_branch} is now at <code className="font-mono">{result.commit?.slice(0, 8)}</code>
+            </p>
+          )}
+          {result.status === "up_to_date" && <p>{result.base_branch} already has everything from this session</p>}
+          {result.message && <p>{result.message}</p>}
+          {result.files.length > 0 && (
+            <ul className="mt-1 max-h-24 overflow-y-auto font-mono text-[11px]">
+              {result.files.map((file) => (
+                <li key={file}>{file}</li>
+              ))}
+            </ul>
+          )}
+        </div>
+      )}
+    </div>
+  );
+}
@@ -0,0 +1,60 @@
+"use client";
+
+import { ShieldQuestion } from "lucide-react";
+import { Button } from "@/components/ui/button";
+import type { PermissionDecision, PermissionRequest, PermissionScope } from "@/lib/sessions";
+
+interface PermissionPromptProps {
+  requests: PermissionRequest[];
+  onAnswer: (request: PermissionRequest, decision: PermissionDecision, scope?: PermissionScope) => void;
+}
+
+/**
+ * Short description of what the tool is about to do
+ */
+function describeInput(input: Record<string, unknown>): string {
+  for (const key of ["command", "file_path", "path", "url", "pattern"]) {
+    if (typeof input[key] === "string") return input[key] as string;
+  }
+  return JSON.stringify(input, null, 2);
+}
+
+/**
+ * Tool uses of the current session waiting for approval, oldest first
+ */
+export default function PermissionPrompt({ requests, onAnswer }: PermissionPromptProps) {
+  if (requests.length === 0) return null;
+
+  return (
+    <div className="mb-2 flex flex-col gap-2">
+      {requests.map((request) => (
+        <div
+          key={request.request_id}
+          className="rounded-lg border border-yellow-200 bg-yellow-50 px-3 py-2 text-xs text-yellow-900 shadow-sm"
+        >
+          <div className="flex items-center gap-2 font-medium">
+            <ShieldQuestion className="w-3.5 h-3.5 flex-shrink-0" />
+            <span>Claude wants to use {request.tool_name}</span>
+          </div>
+          <pre className="mt-1.5 max-h-32 overflow-auto whitespace-pre-wrap break-all rounded bg-white/60 px-2 py-1 font-mono text-[11px]">
+            {describeInput(request.input)}
+          </pre>
+          <div className="mt-2 flex flex-wrap gap-1.5">
+            <Button size="sm" onClick={() => onAnswer(request, "allow")}>
+              Allow
+            </Button>
+            <Button size="sm" variant="outline" onClick={() => onAnswer(request, "deny")}>
+              Deny
+            </Button>
+            <Button size="sm" variant="ghost" onClick={() => onAnswer(request, "always_allow", "session")}>
+              Always allow in this session
+            </Button>
+            <Button size="sm" variant="ghost" onClick={() => onAnswer(request, "always_allow", "repo")}>
+              Always allow in this repo
+            </Button>
+          </div>
+        </div>
+      ))}
+    </div>
+  );
+}
@@ -0,0 +1,107 @@
+"use client";
+
+import { useEffect, useState } from "react";
+import { ExternalLink, RotateCw, X } from "lucide-react";
+import type { SessionPreview } from "@/lib/sessions";
+
+interface PreviewPanelProps {
+  sessionId: string;
+  preview?: SessionPreview;
+  // Null until the relay has named the client
+  url: string | null;
+  onExpose: (port: number | null) => void;
+}
+
+/**
+ * A dev server on the client's machine, served through the relay
+ */
+export default function PreviewPanel({ sessionId, preview, url, onExpose }: PreviewPanelProps) {
+  const [portInput, setPortInput] = useState("");
+  // Bumped to reload the frame
+  const [reloads, setReloads] = useState(0);
+  const port = preview?.port ?? null;
+
+  useEffect(() => {
+    setPortInput("");
+  }, [sessionId]);
+
+  const handleSubmit = (e: React.FormEvent) => {
+    e.preventDefault();
+    const value = Number(portInput.trim());
+    if (!Number.isInteger(value) || value < 1 || value > 65535) return;
+    onExpose(value);
+  };
+
+  return (
+    <div className="flex flex-col">
+      <div className="flex items-center gap-1 px-3 py-2 border-b border-border">
+        {port !== null && url ? (
+          <>
+            <a
+              href={url}
+              target="_blank"
+              rel="noreferrer"
+              className="flex-1 min-w-0 truncate text-xs font-mono text-blue-600 hover:underline"
+              title={url}
+            >
+              localhost:{port}
+            </a>
+            <button
+              onClick={() => setReloads((n) => n + 1)}
+              title="Reload"
+              className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer"
+            >
+              <RotateCw className="w-3 h-3" />
+            </button>
+            <a
+              href={url}
+              target="_blank"
+              rel="noreferrer"
+              title="Open in a new tab"
+              className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground"
+            >
+              <ExternalLink className="w-3 h-3" />
+            </a>
+            <button
+              onClick={() => onExpose(null)}
+              disabled={preview?.pending}
+              title="Stop exposing"
+              className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer disabled:opacity-50"
+            >
+              <X className="w-3 h-3" />
+            </button>
+          </>
+        ) : (
+          <form onSubmit={handleSubmit} className="flex flex-1 gap-1">
+            <input
+              value={portInput}
+              onChange={(e) => setPortInput(e.target.value)}
+              disabled={preview?.pending}
+              inputMode="numeric"
+              placeholder="Port, e.g. 5173"
+              className="flex-1 min-w-0 rounded border border-border bg-background px-2 py-1 text-xs font-mono"
+            />
+            <button
+              type="submit"
+              disabled={preview?.pending || !portInput.trim()}
+              className="px-2 py-1 rounded-sm text-xs bg-sidebar-accent text-sidebar-foreground cursor-pointer disabled:opacity-50"
+            >
+              Expose
+            </button>
+          </form>
+        )}
+      </div>
+
+      {preview?.error && <p className="px-3 py-2 text-xs text-red-700">{preview.error}</p>}
+
+      {port !== null && url ? (
+        <iframe key={reloads} src={url} title={`Preview of port ${port}`} className="h-[28rem] w-full bg-white" />
+      ) : (
+        <p className="px-3 py-2 text-xs text-sidebar-foreground/50">
+          Expose a port a dev server listens on in this session to view it here. Apps that link to absolute paths need
+          their base path set to the preview URL.
+        </p>
+      )}
+    </div>
+  );
+}
@@ -0,0 +1,53 @@
+"use client";
+
+import { Megaphone, Wrench, X } from "lucide-react";
+import type { Announcement } from "@/lib/sessions";
+
+interface RelayBannerProps {
+  announcement: Announcement | null;
+  maintenance: string | null;
+  onDismissAnnouncement: () => void;
+}
+
+/**
+ * Messages from the relay operator: maintenance mode and one-off announcements
+ */
+export default function RelayBanner({
+  announcement,
+  maintenance,
+  onDismissAnnouncement,
+}: RelayBannerProps) {
+  if (!announcement && !maintenance) return null;
+
+  const isWarning = announcement && (announcement.level === "warning" || announcement.level === "error");
+
+  return (
+    <div className="flex-shrink-0">
+      {maintenance && (
+        <div className="flex items-center gap-2 px-4 py-2 text-xs border-b border-yellow-200 bg-yellow-50 text-yellow-800">
+          <Wrench className="w-3.5 h-3.5 flex-shrink-0" />
+          <span className="flex-1">Relay maintenance: {maintenance}</span>
+        </div>
+      )}
+      {announcement && (
+        <div
+          className={`flex items-center gap-2 px-4 py-2 text-xs border-b ${
+            isWarning
+              ? "border-yellow-200 bg-yellow-50 text-yellow-800"
+              : "border-border bg-muted text-foreground"
+          }`}
+        >
+          <Megaphone className="w-3.5 h-3.5 flex-shrink-0" />
+          <span className="flex-1 whitespace-pre-wrap">{announcement.message}</span>
+          <button
+            onClick={onDismissAnnouncement}
+            className="p-0.5 rounded hover:bg-black/5"
+            aria-label="Dismiss announcement"
+          >
+            <X className="w-3.5 h-3.5" />
+          </button>
+        </div>
+      )}
+    </div>
+  );
+}
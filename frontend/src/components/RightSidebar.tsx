@@ -1,8 +1,14 @@
 "use client";
 
 import { useRef, useEffect, useState } from "react";
-import { ChatMessage, ClaudeToolUse } from "@/lib/sessions";
+import { ChatMessage, ClaudeToolUse, DiffScope, SessionDiff, SessionCommands, SessionFiles, SessionPreview, SessionTerminals } from "@/lib/sessions";
 import ToolDetailPanel from "./ToolDetailPanel";
+import DiffView from "./DiffView";
+import FileTree from "./FileTree";
+import FileViewer from "./FileViewer";
+import TerminalPanel from "./TerminalPanel";
+import CommandsPanel from "./CommandsPanel";
+import PreviewPanel from "./PreviewPanel";
 
 interface RightSidebarProps {
   isOpen: boolean;
@@ -16,6 +22,31 @@ interface RightSidebarProps {
     precedingContext?: string | null;
   } | null;
   onToolCallClose?: () => void;
+  // The review pane shows while no tool call is selected
+  sessionId?: string | null;
+  sessionLastActive?: string;
+  filesVersion?: number;
+  diff?: SessionDiff;
+  onRequestDiff?: (scope: DiffScope) => void;
+  // An open file shows over everything else until closed
+  files?: SessionFiles;
+  onListDir?: (path: string) => void;
+  onOpenFile?: (path: string) => void;
+  onLoadMoreOfFile?: () => void;
+  onWriteFile?: (content: string, overwrite: boolean) => void;
+  onCloseFile?: () => void;
+  terminals?: SessionTerminals;
+  onOpenTerminal?: (cols: number, rows: number) => void;
+  onTerminalInput?: (terminalId: string, data: string) => void;
+  onResizeTerminal?: (terminalId: string, cols: number, rows: number) => void;
+  onCloseTerminal?: (terminalId: string) => void;
+  commands?: SessionCommands;
+  onRunCommand?: (command: { name: string } | { argv: string[] }) => void;
+  onCancelCommand?: (commandId: string) => void;
+  onDismissCommandRun?: (commandId: string) => void;
+  preview?: SessionPreview;
+  previewUrl?: string | null;
+  onExposePort?: (port: number | null) => void;
 }
 
 export default function RightSidebar({
@@ -26,8 +57,32 @@ export default function RightSidebar({
   onResizingChange,
   selectedToolCall,
   onToolCallClose,
+  sessionId,
+  sessionLastActive,
+  filesVersion,
+  diff,
+  onRequestDiff,
+  files,
+  onListDir,
+  onOpenFile,
+  onLoadMoreOfFile,
+  onWriteFile,
+  onCloseFile,
+  terminals,
+  onOpenTerminal,
+  onTerminalInput,
+  onResizeTerminal,
+  onCloseTerminal,
+  commands,
+  onRunCommand,
+  onCancelCommand,
+  onDismissCommandRun,
+  preview,
+  previewUrl,
+  onExposePort,
 }: RightSidebarProps) {
   const [isResizing, setIsResizing] = useState(false);
+  const [reviewTab, setReviewTab] = useState<"changes" | "files" | "terminal" | "run" | "preview">("changes");
   const sidebarRef = useRef<HTMLElement>(null);
   const onWidthChangeRef = useRef(onWidthChange);
   const onResizingChangeRef = useRef(onResizingChange);
@@ -94,13 +149,73 @@ export default function RightSidebar({
 
       {/* Content */}
       <div className={`flex-1 overflow-y-auto min-h-0 ${isOpen ? 'opacity-100' : 'opacity-0'} transition-opacity duration-150`}>
-        {selectedToolCall ? (
+        {files?.open ? (
+          <FileViewer
+            file={files.open}
+            onLoadMore={() => onLoadMoreOfFile?.()}
+            onClose={() => onCloseFile?.()}
+            onSave={onWriteFile}
+            onReload={() => files.open && onOpenFile?.(files.open.path)}
+          />
+        ) : selectedToolCall ? (
           <ToolDetailPanel
             toolCall={selectedToolCall.tool}
             contextMessage={selectedToolCall.contextMessage}
             precedingContext={selectedToolCall.precedingContext}
             onClose={() => onToolCallClose?.()}
+            onOpenFile={onOpenFile}
           />
+        ) : isOpen && sessionId && onRequestDiff && onListDir && onOpenFile ? (
+          <div className="flex flex-col">
+            <div className="flex gap-1 px-3 pt-2">
+              {(["changes", "files", "terminal", "run", "preview"] as const).map((tab) => (
+                <button
+                  key={tab}
+                  onClick={() => setReviewTab(tab)}
+                  className={`px-1.5 py-0.5 rounded-sm text-xs capitalize cursor-pointer ${
+                    reviewTab === tab
+                      ? "bg-sidebar-accent text-sidebar-foreground"
+                      : "text-sidebar-foreground/50 hover:text-sidebar-foreground"
+                  }`}
+                >
+                  {tab}
+                </button>
+              ))}
+            </div>
+            {reviewTab === "changes" ? (
+              <DiffView
+                sessionId={sessionId}
+                lastActive={sessionLastActive}
+                filesVersion={filesVersion}
+                diff={diff}
+                onRequest={onRequestDiff}
+              />
+            ) : reviewTab === "files" ? (
+              <FileTree sessionId={sessionId} files={files} onListDir={onListDir} onOpenFile={onOpenFile} />
+            ) : reviewTab === "preview" ? (
+              onExposePort ? (
+                <PreviewPanel sessionId={sessionId} preview={preview} url={previewUrl ?? null} onExpose={onExposePort} />
+              ) : null
+            ) : reviewTab === "run" ? (
+              onRunCommand && onCancelCommand && onDismissCommandRun ? (
+                <CommandsPanel
+                  commands={commands}
+                  onRun={onRunCommand}
+                  onCancel={onCancelCommand}
+                  onDismiss={onDismissCommandRun}
+                />
+              ) : null
+            ) : onOpenTerminal && onTerminalInput && onResizeTerminal && onCloseTerminal ? (
+              <TerminalPanel
+                sessionId={sessionId}
+                terminals={terminals}
+                onOpen={onOpenTerminal}
+                onInput={onTerminalInput}
+                onResize={onResizeTerminal}
+                onClose={onCloseTerminal}
+              />
+            ) : null}
+          </div>
         ) : (
           <div className="p-4">
             <h3 className="text-sm font-medium text-sidebar-foreground mb-2">Tool Call Details</h3>
@@ -2,14 +2,24 @@
 
 import { useRef } from "react";
 import { ChevronUp } from "lucide-react";
+import SessionSettingsForm from "./SessionSettingsForm";
+import FinishWorktreeForm from "./FinishWorktreeForm";
+import type { FinishState, FinishStrategy, SessionSettings } from "@/lib/sessions";
 
 interface SessionInfoPanelProps {
   isOpen: boolean;
   onClose: () => void;
   repoName: string;
   sessionId: string;
+  branch?: string | null;
   branchOrigin?: string;
+  // Branch the session started from, if it was one
+  baseRef?: string | null;
   isWorktree?: boolean;
+  settings?: SessionSettings;
+  onSaveSettings: (settings: SessionSettings) => void;
+  finish?: FinishState;
+  onFinish: (strategy: FinishStrategy, autoCommit: boolean, commitMessage: string) => void;
 }
 
 export default function SessionInfoPanel({
@@ -17,8 +27,14 @@ export default function SessionInfoPanel({
   onClose,
   repoName,
   sessionId,
-  branchOrigin = "origin/main",
+  branch,
+  branchOrigin = "HEAD",
+  baseRef,
   isWorktree = false,
+  settings,
+  onSaveSettings,
+  finish,
+  onFinish,
 }: SessionInfoPanelProps) {
   const panelRef = useRef<HTMLDivElement>(null);
 
@@ -28,13 +44,13 @@ export default function SessionInfoPanel({
   return (
     <div
       ref={panelRef}
-      className="session-info-panel bg-background border-b border-border animate-panel-fade-in"
+      className="session-info-panel bg-background border-b border-border animate-panel-fade-in overflow-y-auto"
       style={{
-        height: '33vh',
+        height: '50vh',
         minHeight: '280px',
       }}
     >
-      <div className="h-full relative flex items-center px-8 py-12">
+      <div className="min-h-full relative flex items-center px-8 py-12">
         {/* Subtle background pattern */}
         <div className="absolute inset-0 opacity-[0.02] pointer-events-none">
           <svg width="100%" height="100%" xmlns="http://www.w3.org/2000/svg">
@@ -65,7 +81,7 @@ export default function SessionInfoPanel({
               <>
                 <p className="flex items-center gap-2">
                   <span className="w-1 h-1 rounded-full bg-primary/40" />
-                  Branched <code className="px-1.5 py-0.5 bg-muted rounded text-xs font-mono">{sessionId}</code> from{' '}
+                  Branched <code className="px-1.5 py-0.5 bg-muted rounded text-xs font-mono">{branch ?? sessionId}</code> from{' '}
                   <code className="px-1.5 py-0.5 bg-muted rounded text-xs font-mono">{branchOrigin}</code>
                 </p>
                 <p className="flex items-center gap-2">
@@ -92,6 +108,13 @@ export default function SessionInfoPanel({
             <div className="w-2 h-2 rounded-full bg-primary/60 animate-pulse" />
             <span className="text-sm font-medium text-foreground/80">{repoName}</span>
           </div>
+
+          {isWorktree && (
+            <FinishWorktreeForm key={sessionId} baseRef={baseRef} finish={finish} onFinish={onFinish} />
+          )}
+
+          {/* Agent settings, reset when switching sessions */}
+          <SessionSettingsForm key={sessionId} settings={settings} onSave={onSaveSettings} />
         </div>
 
         {/* Close button */}
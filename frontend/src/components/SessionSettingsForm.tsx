@@ -0,0 +1,110 @@
+"use client";
+
+import { useState } from "react";
+import { Button } from "@/components/ui/button";
+import { Input } from "@/components/ui/input";
+import { Textarea } from "@/components/ui/textarea";
+import {
+  Select,
+  SelectContent,
+  SelectItem,
+  SelectTrigger,
+  SelectValue,
+} from "@/components/ui/select";
+import type { ClaudePermissionMode, SessionSettings } from "@/lib/sessions";
+
+interface SessionSettingsFormProps {
+  settings?: SessionSettings;
+  onSave: (settings: SessionSettings) => void;
+}
+
+const PERMISSION_MODES: { value: ClaudePermissionMode | "none"; label: string }[] = [
+  { value: "none", label: "Client default" },
+  { value: "default", label: "Ask (default)" },
+  { value: "acceptEdits", label: "Accept edits" },
+  { value: "plan", label: "Plan only" },
+  { value: "bypassPermissions", label: "Bypass permissions" },
+];
+
+/**
+ * Split a comma separated list, dropping empty items
+ * Tool rules like `Bash(git log:*)` may contain spaces
+ */
+function splitList(value: string): string[] {
+  return value.split(",").map((item) => item.trim()).filter(Boolean);
+}
+
+/**
+ * Agent settings of a session; changes apply from the next message
+ */
+export default function SessionSettingsForm({ settings, onSave }: SessionSettingsFormProps) {
+  const [model, setModel] = useState(settings?.model ?? "");
+  const [permissionMode, setPermissionMode] = useState<string>(settings?.permission_mode ?? "none");
+  const [allowedTools, setAllowedTools] = useState(settings?.allowed_tools.join(", ") ?? "");
+  const [disallowedTools, setDisallowedTools] = useState(settings?.disallowed_tools.join(", ") ?? "");
+  const [systemPrompt, setSystemPrompt] = useState(settings?.append_system_prompt ?? "");
+  const [maxTurns, setMaxTurns] = useState(settings?.max_turns?.toString() ?? "");
+  const [extraArgs, setExtraArgs] = useState(settings?.extra_args.join(" ") ?? "");
+
+  const handleSave = () => {
+    const turns = parseInt(maxTurns, 10);
+    onSave({
+      model: model.trim() || null,
+      permission_mode: permissionMode === "none" ? null : (permissionMode as ClaudePermissionMode),
+      allowed_tools: splitList(allowedTools),
+      disallowed_tools: splitList(disallowedTools),
+      append_system_prompt: systemPrompt.trim() || null,
+      max_turns: turns > 0 ? turns : null,
+      extra_args: extraArgs.split(/\s+/).filter(Boolean),
+    });
+  };
+
+  return (
+    <div className="grid grid-cols-2 gap-3 text-xs">
+      <label className="space-y-1">
+        <span className="text-muted-foreground">Default model</span>
+        <Input value={model} onChange={(e) => setModel(e.target.value)} placeholder="Composer choice" />
+      </label>
+      <label className="space-y-1">
+        <span className="text-muted-foreground">Permission mode</span>
+        <Select value={permissionMode} onValueChange={setPermissionMode}>
+          <SelectTrigger className="w-full">
+            <SelectValue />
+          </SelectTrigger>
+          <SelectContent>
+            {PERMISSION_MODES.map((mode) => (
+              <SelectItem key={mode.value} value={mode.value} className="text-xs">
+                {mode.label}
+              </SelectItem>
+            ))}
+          </SelectContent>
+        </Select>
+      </label>
+      <label className="space-y-1">
+        <span className="text-muted-foreground">Allowed tools</span>
+        <Input value={allowedTools} onChange={(e) => setAllowedTools(e.target.value)} placeholder="Read, Bash(git:*)" />
+      </label>
+      <label className="space-y-1">
+        <span className="text-muted-foreground">Disallowed tools</span>
+        <Input value={disallowedTools} onChange={(e) => setDisallowedTools(e.target.value)} placeholder="WebFetch" />
+      </label>
+      <label className="col-span-2 space-y-1">
+        <span className="text-muted-foreground">Appended system prompt</span>
+        <Textarea value={systemPrompt} onChange={(e) => setSystemPrompt(e.target.value)} className="min-h-12" />
+      </label>
+      <label className="space-y-1">
+        <span className="text-muted-foreground">Max turns</span>
+        <Input type="number" min={1} value={maxTurns} onChange={(e) => setMaxTurns(e.target.value)} placeholder="Unlimited" />
+      </label>
+      <label className="space-y-1">
+        <span className="text-muted-foreground">Extra CLI args</span>
+        <Input value={extraArgs} onChange={(e) => setExtraArgs(e.target.value)} placeholder="--verbose" />
+      </label>
+      <div className="col-span-2 flex justify-end">
+        <Button size="sm" onClick={handleSave}>
+          Save settings
+        </Button>
+      </div>
+    </div>
+  );
+}
@@ -1,8 +1,8 @@
 "use client";
 
-import { useState, useEffect } from "react";
-import { ChevronDown, ChevronRight, Plus, GitBranch, GitCommitVertical, FolderOpen, FolderClosed } from "lucide-react";
-import type { RepoInfo } from "@/lib/sessions";
+import { useState, useEffect, useRef } from "react";
+import { ChevronDown, ChevronRight, Plus, GitBranch, GitCommitVertical, FolderOpen, FolderClosed, Lock, Archive, ArchiveRestore, Trash2, Pencil } from "lucide-react";
+import type { DeleteBlocked, RepoInfo, SessionInfo, WorktreeOptions } from "@/lib/sessions";
 
 interface SidebarProps {
   repos: RepoInfo[];
@@ -10,7 +10,14 @@ interface SidebarProps {
   currentSessionId: string | null;
   onSelectSession: (repoPath: string, sessionId: string) => void;
   onNewSession: (repoPath: string) => void;
-  onNewWorktreeSession: (repoPath: string) => void;
+  onNewWorktreeSession: (repoPath: string, options?: WorktreeOptions) => void;
+  onPair: (repoPath: string, pairingCode: string) => void;
+  onRenameSession: (repoPath: string, sessionId: string, title: string) => void;
+  onArchiveSession: (repoPath: string, sessionId: string, archived: boolean) => void;
+  onDeleteSession: (repoPath: string, sessionId: string, force?: boolean) => void;
+  onLoadArchived: (repoPath: string) => void;
+  deleteBlocked: DeleteBlocked | null;
+  onDismissDeleteBlocked: () => void;
   isCollapsed: boolean;
   onToggleSidebar: () => void;
   creatingSessionForRepo: string | null;
@@ -34,6 +41,188 @@ function formatRelativeTime(dateString: string): string {
   return date.toLocaleDateString("en-US", { month: "short", day: "numeric" });
 }
 
+/**
+ * Asks for the pairing code printed by `lychee up --e2e`
+ */
+function PairingForm({ repo, onPair }: { repo: RepoInfo; onPair: (repoPath: string, pairingCode: string) => void }) {
+  const [code, setCode] = useState("");
+  const isPairing = repo.pairing === "pairing";
+
+  return (
+    <form
+      onSubmit={(e) => {
+        e.preventDefault();
+        if (code.trim()) onPair(repo.path, code.trim());
+      }}
+      className="px-2 py-1.5 space-y-1.5"
+    >
+      <p className="text-xs text-sidebar-foreground/50">
+        End-to-end encrypted. Enter the pairing code shown by lychee.
+      </p>
+      <div className="flex gap-1">
+        <input
+          value={code}
+          onChange={(e) => setCode(e.target.value)}
+          placeholder="XXXXX-XXXXX-XXXXX-XXXXX"
+          disabled={isPairing}
+          className="flex-1 min-w-0 bg-sidebar-accent rounded-sm px-2 py-1 text-xs font-mono text-sidebar-foreground outline-none"
+        />
+        <button
+          type="submit"
+          disabled={isPairing || !code.trim()}
+          className="px-2 py-1 rounded-sm text-xs text-sidebar-foreground/70 hover:bg-sidebar-accent hover:text-sidebar-foreground disabled:opacity-40 cursor-pointer"
+        >
+          {isPairing ? "Pairing..." : "Pair"}
+        </button>
+      </div>
+    </form>
+  );
+}
+
+/**
+ * Where a new worktree session starts and which branch it gets; both optional
+ */
+function WorktreeForm({ onCreate, onCancel }: { onCreate: (options: WorktreeOptions) => void; onCancel: () => void }) {
+  const [baseRef, setBaseRef] = useState("");
+  const [branch, setBranch] = useState("");
+  const inputClass =
+    "w-full bg-sidebar-accent rounded-sm px-2 py-1 text-xs font-mono text-sidebar-foreground outline-none";
+
+  return (
+    <form
+      onSubmit={(e) => {
+        e.preventDefault();
+        onCreate({ baseRef, branch });
+      }}
+      className="px-2 py-1.5 space-y-1.5"
+    >
+      <input
+        autoFocus
+        value={baseRef}
+        onChange={(e) => setBaseRef(e.target.value)}
+        placeholder="Base: branch, tag or commit (HEAD)"
+        className={inputClass}
+      />
+      <input
+        value={branch}
+        onChange={(e) => setBranch(e.target.value)}
+        placeholder="Branch (lychee/<session>)"
+        className={inputClass}
+      />
+      <div className="flex justify-end gap-1">
+        <button
+          type="button"
+          onClick={onCancel}
+          className="px-2 py-1 rounded-sm text-xs text-sidebar-foreground/70 hover:bg-sidebar-accent hover:text-sidebar-foreground cursor-pointer"
+        >
+          Cancel
+        </button>
+        <button
+          type="submit"
+          className="px-2 py-1 rounded-sm text-xs text-sidebar-foreground/70 hover:bg-sidebar-accent hover:text-sidebar-foreground cursor-pointer"
+        >
+          Create
+        </button>
+      </div>
+    </form>
+  );
+}
+
+/**
+ * Ask before deleting; worktree sessions take their checkout and branch with them
+ */
+function confirmDelete(session: SessionInfo): boolean {
+  const what = session.is_worktree
+    ? "its worktree and branch"
+    : "it from the sessions list";
+  return window.confirm(`Delete session ${session.title || session.lychee_id}? This removes ${what}.`);
+}
+
+/**
+ * Inline title editor; Enter or leaving the field saves, Escape cancels
+ */
+function RenameInput({
+  session,
+  onSave,
+  onCancel,
+}: {
+  session: SessionInfo;
+  onSave: (title: string) => void;
+  onCancel: () => void;
+}) {
+  const [title, setTitle] = useState(session.title ?? "");
+  // Enter and Escape unmount the input, which may blur it once more
+  const done = useRef(false);
+
+  const finish = (save: boolean) => {
+    if (done.current) return;
+    done.current = true;
+    if (save) {
+      onSave(title);
+    } else {
+      onCancel();
+    }
+  };
+
+  return (
+    <input
+      autoFocus
+      value={title}
+      onChange={(e) => setTitle(e.target.value)}
+      onFocus={(e) => e.target.select()}
+      onBlur={() => finish(true)}
+      onKeyDown={(e) => {
+        if (e.key === "Enter") finish(true);
+        if (e.key === "Escape") finish(false);
+      }}
+      placeholder="Untitled"
+      className="w-full bg-sidebar-accent rounded-sm px-2 py-1.5 text-xs text-sidebar-foreground outline-none"
+    />
+  );
+}
+
+/**
+ * Shown under a session the client refused to delete
+ */
+function DeleteBlockedNotice({
+  blocked,
+  onForce,
+  onCancel,
+}: {
+  blocked: DeleteBlocked;
+  onForce: () => void;
+  onCancel: () => void;
+}) {
+  return (
+    <div className="mx-2 mb-1 rounded-sm border border-orange-200 bg-orange-50 px-2 py-1.5 text-xs text-orange-900 space-y-1">
+      <p>{blocked.reason}</p>
+      {blocked.uncommitted_files.length > 0 && (
+        <ul className="max-h-24 overflow-y-auto font-mono text-[11px]">
+          {blocked.uncommitted_files.map((file) => (
+            <li key={file} className="truncate" title={file}>
+              {file}
+            </li>
+          ))}
+        </ul>
+      )}
+      <div className="flex gap-1">
+        <button
+          onClick={onForce}
+          className="px-2 py-0.5 rounded-sm bg-orange-600 text-white hover:bg-orange-700 cursor-pointer"
+        >
+          Delete anyway
+        </button>
+        <button
+          onClick={onCancel}
+          className="px-2 py-0.5 rounded-sm hover:bg-orange-100 cursor-pointer"
+        >
+          Cancel
+        </button>
+      </div>
+    </div>
+  );
+}
+
 export default function Sidebar({
   repos,
   activeRepoPath,
@@ -41,6 +230,13 @@ export default function Sidebar({
   onSelectSession,
   onNewSession,
   onNewWorktreeSession,
+  onPair,
+  onRenameSession,
+  onArchiveSession,
+  onDeleteSession,
+  onLoadArchived,
+  deleteBlocked,
+  onDismissDeleteBlocked,
   isCollapsed,
   onToggleSidebar,
   creatingSessionForRepo,
@@ -63,6 +259,37 @@ export default function Sidebar({
     });
   }, [repos]);
 
+  const [showArchived, setShowArchived] = useState<Set<string>>(new Set());
+  // `${repoPath}:${lycheeId}` of the session whose title is being edited
+  const [renaming, setRenaming] = useState<string | null>(null);
+  const [worktreeFormFor, setWorktreeFormFor] = useState<string | null>(null);
+
+  const toggleArchived = (repoPath: string) => {
+    if (!showArchived.has(repoPath)) {
+      onLoadArchived(repoPath);
+    }
+    setShowArchived((prev) => {
+      const next = new Set(prev);
+      if (next.has(repoPath)) {
+        next.delete(repoPath);
+      } else {
+        next.add(repoPath);
+      }
+      return next;
+    });
+  };
+
+  const renderDeleteBlocked = (repoPath: string, sessionId: string) =>
+    deleteBlocked &&
+    deleteBlocked.repo_path === repoPath &&
+    deleteBlocked.lychee_id === sessionId && (
+      <DeleteBlockedNotice
+        blocked={deleteBlocked}
+        onForce={() => onDeleteSession(repoPath, sessionId, true)}
+        onCancel={onDismissDeleteBlocked}
+      />
+    );
+
   const toggleRepo = (repoPath: string) => {
     setExpandedRepos((prev) => {
       const next = new Set(prev);
@@ -118,10 +345,23 @@ export default function Sidebar({
                     <span className="text-xs font-medium text-sidebar-foreground truncate flex-1">
                       {repo.name}
                     </span>
+                    {repo.pairing && (
+                      <Lock
+                        className={`w-3 h-3 flex-shrink-0 ${
+                          repo.pairing === "paired" ? "text-sidebar-foreground/50" : "text-orange-500"
+                        }`}
+                      />
+                    )}
                   </button>
 
+                  {isExpanded && repo.pairing && repo.pairing !== "paired" && (
+                    <div className="ml-5 mt-0.5">
+                      <PairingForm repo={repo} onPair={onPair} />
+                    </div>
+                  )}
+
                   {/* Sessions */}
-                  {isExpanded && (
+                  {isExpanded && (!repo.pairing || repo.pairing === "paired") && (
                     <div className="ml-5 mt-0.5 space-y-0.5">
                       {/* New Session Button (regular) */}
                       <button
@@ -139,7 +379,7 @@ export default function Sidebar({
 
                       {/* New Worktree Button */}
                       <button
-                        onClick={() => onNewWorktreeSession(repo.path)}
+                        onClick={() => setWorktreeFormFor(worktreeFormFor === repo.path ? null : repo.path)}
                         disabled={isCreating}
                         className={`w-full group flex items-center gap-2 px-2 py-1.5 rounded-sm cursor-pointer transition-colors text-left ${
                           isCreating
@@ -150,6 +390,15 @@ export default function Sidebar({
                         <Plus className={`w-3 h-3 flex-shrink-0 ${isCreating ? "animate-spin" : ""}`} strokeWidth={2} />
                         <span className="text-xs">{isCreating ? "Creating..." : "New Worktree"}</span>
                       </button>
+                      {worktreeFormFor === repo.path && !isCreating && (
+                        <WorktreeForm
+                          onCreate={(options) => {
+                            setWorktreeFormFor(null);
+                            onNewWorktreeSession(repo.path, options);
+                          }}
+                          onCancel={() => setWorktreeFormFor(null)}
+                        />
+                      )}
 
                       {/* Session List */}
                       {repo.sessions.length === 0 ? (
@@ -162,54 +411,147 @@ export default function Sidebar({
                           // Check the session's own isStreaming flag, not the global one
                           const isSessionStreaming = session.isStreaming || false;
 
+                          const renameKey = `${repo.path}:${session.lychee_id}`;
+
+                          if (renaming === renameKey) {
+                            return (
+                              <RenameInput
+                                key={session.lychee_id}
+                                session={session}
+                                onSave={(title) => {
+                                  setRenaming(null);
+                                  if (title.trim() !== (session.title ?? "")) {
+                                    onRenameSession(repo.path, session.lychee_id, title);
+                                  }
+                                }}
+                                onCancel={() => setRenaming(null)}
+                              />
+                            );
+                          }
+
                           return (
-                            <button
-                              key={session.lychee_id}
-                              onClick={() => onSelectSession(repo.path, session.lychee_id)}
-                              className={`w-full group flex items-start gap-2 px-2 py-1.5 rounded-sm cursor-pointer transition-colors text-left ${
-                                isActiveSession
-                                  ? "bg-sidebar-accent text-sidebar-foreground"
-                                  : "hover:bg-sidebar-accent text-sidebar-foreground/70 hover:text-sidebar-foreground"
-                              }`}
-                            >
-                              {session.is_worktree ? (
-                                <GitBranch
-                                  className={`w-3 h-3 mt-0.5 flex-shrink-0 transition-colors ${
-                                    isSessionStreaming
-                                      ? "text-orange-500"
-                                      : isActiveSession
-                                        ? "text-sidebar-foreground"
-                                        : "text-sidebar-foreground/40"
-                                  }`}
-                                  strokeWidth={2}
-                                />
-                              ) : (
-                                <GitCommitVertical
-                                  className={`w-3 h-3 mt-0.5 flex-shrink-0 transition-colors ${
-                                    isSessionStreaming
-                                      ? "text-orange-500"
-                                      : isActiveSession
-                                        ? "text-sidebar-foreground"
-                                        : "text-sidebar-foreground/40"
+                            <div key={session.lychee_id}>
+                              <div className="relative group/session">
+                                <button
+                                  onClick={() => onSelectSession(repo.path, session.lychee_id)}
+                                  className={`w-full group flex items-start gap-2 px-2 py-1.5 rounded-sm cursor-pointer transition-colors text-left ${
+                                    isActiveSession
+                                      ? "bg-sidebar-accent text-sidebar-foreground"
+                                      : "hover:bg-sidebar-accent text-sidebar-foreground/70 hover:text-sidebar-foreground"
                                   }`}
-                                  strokeWidth={2}
-                                />
-                              )}
-                              <div className="flex-1 min-w-0">
-                                <div
-                                  className="text-xs truncate leading-tight"
-                                  title={session.lychee_id}
                                 >
-                                  {session.lychee_id}
-                                </div>
-                                <div className="text-xs text-sidebar-foreground/50 mt-0.5 leading-tight opacity-70">
-                                  {formatRelativeTime(session.last_active || session.created_at)}
+                                  {session.is_worktree ? (
+                                    <GitBranch
+                                      className={`w-3 h-3 mt-0.5 flex-shrink-0 transition-colors ${
+                                        isSessionStreaming
+                                          ? "text-orange-500"
+                                          : isActiveSession
+                                            ? "text-sidebar-foreground"
+                                            : "text-sidebar-foreground/40"
+                                      }`}
+                                      strokeWidth={2}
+                                    />
+                                  ) : (
+                                    <GitCommitVertical
+                                      className={`w-3 h-3 mt-0.5 flex-shrink-0 transition-colors ${
+                                        isSessionStreaming
+                                          ? "text-orange-500"
+                                          : isActiveSession
+                                            ? "text-sidebar-foreground"
+                                            : "text-sidebar-foreground/40"
+                                      }`}
+                                      strokeWidth={2}
+                                    />
+                                  )}
+                                  <div className="flex-1 min-w-0">
+                                    <div
+                                      className="text-xs truncate leading-tight"
+                                      title={session.lychee_id}
+                                    >
+                                      {session.title || session.lychee_id}
+                                    </div>
+                                    <div className="text-xs text-sidebar-foreground/50 mt-0.5 leading-tight opacity-70 truncate">
+                                      {formatRelativeTime(session.last_active || session.created_at)}
+                                      {session.branch && ` · ${session.branch}`}
+                                    </div>
+                                  </div>
+                                </button>
+
+                                {/* Session Actions */}
+                                <div className="absolute right-1 top-1 hidden group-hover/session:flex gap-0.5">
+                                  <button
+                                    onClick={() => setRenaming(renameKey)}
+                                    title="Rename"
+                                    className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground hover:bg-sidebar cursor-pointer"
+                                  >
+                                    <Pencil className="w-3 h-3" />
+                                  </button>
+                                  <button
+                                    onClick={() => onArchiveSession(repo.path, session.lychee_id, true)}
+                                    title="Archive"
+                                    className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground hover:bg-sidebar cursor-pointer"
+                                  >
+                                    <Archive className="w-3 h-3" />
+                                  </button>
+                                  <button
+                                    onClick={() => confirmDelete(session) && onDeleteSession(repo.path, session.lychee_id)}
+                                    title="Delete"
+                                    className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-red-600 hover:bg-sidebar cursor-pointer"
+                                  >
+                                    <Trash2 className="w-3 h-3" />
+                                  </button>
                                 </div>
                               </div>
-                            </button>
+                              {renderDeleteBlocked(repo.path, session.lychee_id)}
+                            </div>
                           );
                         })
                       )}
+
+                      {/* Archived Sessions */}
+                      <button
+                        onClick={() => toggleArchived(repo.path)}
+                        className="w-full flex items-center gap-2 px-2 py-1 rounded-sm cursor-pointer text-left text-sidebar-foreground/50 hover:text-sidebar-foreground hover:bg-sidebar-accent"
+                      >
+                        {showArchived.has(repo.path) ? (
+                          <ChevronDown className="w-3 h-3 flex-shrink-0" />
+                        ) : (
+                          <ChevronRight className="w-3 h-3 flex-shrink-0" />
+                        )}
+                        <span className="text-xs">Archived</span>
+                      </button>
+                      {showArchived.has(repo.path) && (
+                        repo.archivedSessions === undefined ? (
+                          <div className="px-2 py-1 text-xs text-sidebar-foreground/40">Loading...</div>
+                        ) : repo.archivedSessions.length === 0 ? (
+                          <div className="px-2 py-1 text-xs text-sidebar-foreground/40">Nothing archived</div>
+                        ) : (
+                          repo.archivedSessions.map((session) => (
+                            <div key={session.lychee_id}>
+                              <div className="flex items-center gap-2 px-2 py-1 text-sidebar-foreground/50">
+                                <span className="text-xs truncate flex-1" title={session.lychee_id}>
+                                  {session.title || session.lychee_id}
+                                </span>
+                                <button
+                                  onClick={() => onArchiveSession(repo.path, session.lychee_id, false)}
+                                  title="Restore"
+                                  className="p-1 rounded-sm hover:text-sidebar-foreground hover:bg-sidebar-accent cursor-pointer"
+                                >
+                                  <ArchiveRestore className="w-3 h-3" />
+                                </button>
+                                <button
+                                  onClick={() => confirmDelete(session) && onDeleteSession(repo.path, session.lychee_id)}
+                                  title="Delete"
+                                  className="p-1 rounded-sm hover:text-red-600 hover:bg-sidebar-accent cursor-pointer"
+                                >
+                                  <Trash2 className="w-3 h-3" />
+                                </button>
+                              </div>
+                              {renderDeleteBlocked(repo.path, session.lychee_id)}
+                            </div>
+                          ))
+                        )
+                      )}
                     </div>
                   )}
                 </div>
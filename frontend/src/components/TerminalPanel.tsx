@@ -0,0 +1,202 @@
+"use client";
+
+import { useEffect, useRef, useState } from "react";
+import { Plus, X } from "lucide-react";
+import type { SessionTerminals, TerminalView } from "@/lib/sessions";
+
+interface TerminalPanelProps {
+  sessionId: string;
+  terminals?: SessionTerminals;
+  onOpen: (cols: number, rows: number) => void;
+  onInput: (terminalId: string, data: string) => void;
+  onResize: (terminalId: string, cols: number, rows: number) => void;
+  onClose: (terminalId: string) => void;
+}
+
+// What keys without a printable character send
+const KEYS: Record<string, string> = {
+  Enter: "\r",
+  Backspace: "\x7f",
+  Tab: "\t",
+  Escape: "\x1b",
+  ArrowUp: "\x1b[A",
+  ArrowDown: "\x1b[B",
+  ArrowRight: "\x1b[C",
+  ArrowLeft: "\x1b[D",
+  Home: "\x1b[H",
+  End: "\x1b[F",
+  Delete: "\x1b[3~",
+  PageUp: "\x1b[5~",
+  PageDown: "\x1b[6~",
+};
+
+function keyToInput(e: React.KeyboardEvent): string | null {
+  // Cmd shortcuts (copy, reload) stay with the browser
+  if (e.metaKey) return null;
+  if (e.ctrlKey && !e.altKey && e.key.length === 1) {
+    const code = e.key.toUpperCase().charCodeAt(0);
+    return code >= 64 && code <= 95 ? String.fromCharCode(code - 64) : null;
+  }
+  if (KEYS[e.key]) return KEYS[e.key];
+  if (e.key.length === 1) return e.altKey ? `\x1b${e.key}` : e.key;
+  return null;
+}
+
+function exitLabel(terminal: TerminalView): string | null {
+  if (!terminal.exit) return null;
+  return terminal.exit.signal ? `exited (${terminal.exit.signal})` : `exited with code ${terminal.exit.exit_code}`;
+}
+
+/**
+ * Shells in the session directory, on the client's machine
+ */
+export default function TerminalPanel({ sessionId, terminals, onOpen, onInput, onResize, onClose }: TerminalPanelProps) {
+  const [activeId, setActiveId] = useState<string | null>(null);
+  const [isFocused, setIsFocused] = useState(false);
+  const bodyRef = useRef<HTMLDivElement>(null);
+  const measureRef = useRef<HTMLSpanElement>(null);
+  const sizeRef = useRef({ cols: 80, rows: 24 });
+  // Last size each terminal was told about
+  const sentSizes = useRef<Map<string, string>>(new Map());
+
+  const open = terminals?.open ?? [];
+  const active = open.find((t) => t.terminal_id === activeId) ?? open[open.length - 1] ?? null;
+
+  useEffect(() => {
+    setActiveId(null);
+  }, [sessionId]);
+
+  // Follow the newest terminal once it opens
+  useEffect(() => {
+    if (open.length > 0) setActiveId(open[open.length - 1].terminal_id);
+  }, [open.length]);
+
+  const syncSize = () => {
+    const body = bodyRef.current;
+    const measure = measureRef.current;
+    if (!body || !measure) return;
+    const charWidth = measure.getBoundingClientRect().width / 10;
+    const lineHeight = measure.getBoundingClientRect().height;
+    if (!charWidth || !lineHeight) return;
+    // Leave room for the padding
+    const cols = Math.max(20, Math.floor((body.clientWidth - 16) / charWidth));
+    const rows = Math.max(5, Math.floor((body.clientHeight - 16) / lineHeight));
+    sizeRef.current = { cols, rows };
+
+    if (active && !active.exit && sentSizes.current.get(active.terminal_id) !== `${cols}x${rows}`) {
+      sentSizes.current.set(active.terminal_id, `${cols}x${rows}`);
+      onResize(active.terminal_id, cols, rows);
+    }
+  };
+
+  useEffect(() => {
+    const body = bodyRef.current;
+    if (!body) return;
+    const observer = new ResizeObserver(() => syncSize());
+    observer.observe(body);
+    return () => observer.disconnect();
+  }, [active?.terminal_id, active?.exit]);
+
+  // Keep the prompt in view
+  useEffect(() => {
+    const body = bodyRef.current;
+    if (body) body.scrollTop = body.scrollHeight;
+  }, [active?.screen]);
+
+  const handleKeyDown = (e: React.KeyboardEvent) => {
+    if (!active || active.exit) return;
+    const data = keyToInput(e);
+    if (data === null) return;
+    e.preventDefault();
+    onInput(active.terminal_id, data);
+  };
+
+  const handlePaste = (e: React.ClipboardEvent) => {
+    if (!active || active.exit) return;
+    e.preventDefault();
+    const text = e.clipboardData.getData("text").replace(/\r?\n/g, "\r");
+    if (text) onInput(active.terminal_id, text);
+  };
+
+  const lines = active?.screen.lines ?? [];
+  const last = lines[lines.length - 1] ?? "";
+  const col = active?.screen.col ?? 0;
+
+  return (
+    <div className="flex flex-col">
+      <div className="flex items-center gap-1 px-3 py-2 border-b border-border">
+        <div className="flex flex-1 gap-1 min-w-0 overflow-x-auto">
+          {open.map((terminal, index) => (
+            <button
+              key={terminal.terminal_id}
+              onClick={() => setActiveId(terminal.terminal_id)}
+              title={terminal.shell}
+              className={`flex items-center gap-1 px-1.5 py-0.5 rounded-sm text-xs font-mono cursor-pointer ${
+                terminal.terminal_id === active?.terminal_id
+                  ? "bg-sidebar-accent text-sidebar-foreground"
+                  : "text-sidebar-foreground/50 hover:text-sidebar-foreground"
+              } ${terminal.exit ? "line-through" : ""}`}
+            >
+              {terminal.shell.split("/").pop() || "shell"} {index + 1}
+            </button>
+          ))}
+        </div>
+        <button
+          onClick={() => onOpen(sizeRef.current.cols, sizeRef.current.rows)}
+          disabled={terminals?.opening}
+          title="New terminal"
+          className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer disabled:opacity-50"
+        >
+          <Plus className="w-3 h-3" />
+        </button>
+        {active && (
+          <button
+            onClick={() => onClose(active.terminal_id)}
+            title={active.exit ? "Remove" : "Close"}
+            className="p-1 rounded-sm text-sidebar-foreground/50 hover:text-sidebar-foreground cursor-pointer"
+          >
+            <X className="w-3 h-3" />
+          </button>
+        )}
+      </div>
+
+      {terminals?.error && <p className="px-3 py-2 text-xs text-red-700">{terminals.error}</p>}
+
+      {active ? (
+        <div
+          ref={bodyRef}
+          tabIndex={0}
+          onKeyDown={handleKeyDown}
+          onPaste={handlePaste}
+          onFocus={() => setIsFocused(true)}
+          onBlur={() => setIsFocused(false)}
+          className="h-[28rem] overflow-auto bg-black text-gray-100 p-2 text-xs font-mono leading-4 outline-none focus:ring-1 focus:ring-primary/50"
+        >
+          <span ref={measureRef} aria-hidden className="absolute invisible whitespace-pre">
+            MMMMMMMMMM
+          </span>
+          <pre className="whitespace-pre">
+            {lines.slice(0, -1).join("\n")}
+            {lines.length > 1 && "\n"}
+            {last.slice(0, col)}
+            {active.exit ? (
+              last.slice(col)
+            ) : (
+              <>
+                <span className={isFocused ? "bg-gray-100 text-black" : "outline outline-1 outline-gray-400"}>
+                  {last[col] ?? " "}
+                </span>
+                {last.slice(col + 1)}
+              </>
+            )}
+          </pre>
+          {exitLabel(active) && <p className="mt-1 text-gray-400">[{exitLabel(active)}]</p>}
+        </div>
+      ) : (
+        <p className="px-3 py-2 text-xs text-sidebar-foreground/50">
+          {terminals?.opening ? "Opening..." : "No terminals open. Start one to run commands in the session directory."}
+        </p>
+      )}
+    </div>
+  );
+}
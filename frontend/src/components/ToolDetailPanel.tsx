@@ -8,6 +8,8 @@ interface ToolDetailPanelProps {
   contextMessage?: ChatMessage;
   precedingContext?: string | null;
   onClose: () => void;
+  // Paths the tool touched open in the file viewer
+  onOpenFile?: (path: string) => void;
 }
 
 /**
@@ -31,7 +33,8 @@ export default function ToolDetailPanel({
   toolCall,
   contextMessage,
   precedingContext,
-  onClose
+  onClose,
+  onOpenFile
 }: ToolDetailPanelProps) {
   // Determine which context to show (precedingContext from worklog takes priority)
   const contextText = precedingContext || (contextMessage ? getContextText(contextMessage) : null);
@@ -92,13 +95,28 @@ export default function ToolDetailPanel({
         </div>
 
         {/* Tool-specific rendering */}
-        {renderToolSpecificContent(toolCall)}
+        {renderToolSpecificContent(toolCall, onOpenFile)}
       </div>
     </div>
   );
 }
 
-function renderToolSpecificContent(toolCall: ClaudeToolUse) {
+function renderFilePath(path: string, onOpenFile?: (path: string) => void) {
+  if (!onOpenFile) {
+    return <code className="text-sm">{path}</code>;
+  }
+  return (
+    <button
+      onClick={() => onOpenFile(path)}
+      title="Open file"
+      className="text-left text-blue-600 hover:underline cursor-pointer break-all"
+    >
+      <code className="text-sm">{path}</code>
+    </button>
+  );
+}
+
+function renderToolSpecificContent(toolCall: ClaudeToolUse, onOpenFile?: (path: string) => void) {
   const { name, input } = toolCall;
 
   switch (name) {
@@ -121,7 +139,7 @@ function renderToolSpecificContent(toolCall: ClaudeToolUse) {
             File Path
           </h4>
           <div className="bg-muted rounded-lg p-3">
-            <code className="text-sm">{input.file_path as string}</code>
+            {renderFilePath(input.file_path as string, onOpenFile)}
             {input.offset ? (
               <div className="mt-2 text-xs text-muted-foreground">
                 Starting at line {input.offset as number}
@@ -140,7 +158,7 @@ function renderToolSpecificContent(toolCall: ClaudeToolUse) {
             File Path
           </h4>
           <div className="bg-muted rounded-lg p-3">
-            <code className="text-sm">{input.file_path as string}</code>
+            {renderFilePath(input.file_path as string, onOpenFile)}
           </div>
           {name === "Edit" && input.old_string ? (
             <>
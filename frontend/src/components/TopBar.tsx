@@ -29,6 +29,7 @@ export default function TopBar({
   isPanelOpen,
   onTogglePanel,
 }: TopBarProps) {
+  const currentSession = activeRepo?.sessions.find((s) => s.lychee_id === currentSessionId);
 
   return (
     <div className="flex-shrink-0 flex h-12">
@@ -110,7 +111,9 @@ export default function TopBar({
                 <div className={`flex items-center gap-3 ${isPanelOpen ? 'opacity-0' : 'opacity-100'}`}>
                   <div className="font-medium text-foreground">{activeRepo.name}</div>
                   <div className="text-muted-foreground">/</div>
-                  <div className="text-muted-foreground">{currentSessionId}</div>
+                  <div className="text-muted-foreground truncate max-w-md" title={currentSessionId}>
+                    {currentSession?.title || currentSessionId}
+                  </div>
                   <button
                     data-dropdown-trigger
                     onClick={onTogglePanel}
@@ -0,0 +1,145 @@
+"use client";
+
+// Browser half of `lychee up --e2e` (see client/src/e2e.rs for the protocol)
+
+const KDF_INFO = new TextEncoder().encode("lychee-e2e-v1");
+
+function toBase64(bytes: Uint8Array): string {
+  let binary = "";
+  bytes.forEach((b) => (binary += String.fromCharCode(b)));
+  return btoa(binary);
+}
+
+function fromBase64(value: string): Uint8Array<ArrayBuffer> {
+  const binary = atob(value);
+  const bytes = new Uint8Array(binary.length);
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes;
+}
+
+/**
+ * Pairing codes are compared without dashes or case
+ */
+function normalizePairingCode(code: string): string {
+  return code.replace(/[-\s]/g, "").toUpperCase();
+}
+
+async function sealWith(
+  key: CryptoKey,
+  nonce: Uint8Array<ArrayBuffer>,
+  plaintext: Uint8Array<ArrayBuffer>
+): Promise<string> {
+  const ciphertext = new Uint8Array(
+    await crypto.subtle.encrypt({ name: "AES-GCM", iv: nonce }, key, plaintext)
+  );
+  const out = new Uint8Array(nonce.length + ciphertext.length);
+  out.set(nonce);
+  out.set(ciphertext, nonce.length);
+  return toBase64(out);
+}
+
+async function openWith(key: CryptoKey, payload: string): Promise<Uint8Array<ArrayBuffer>> {
+  const data = fromBase64(payload);
+  const plaintext = await crypto.subtle.decrypt(
+    { name: "AES-GCM", iv: data.slice(0, 12) },
+    key,
+    data.slice(12)
+  );
+  return new Uint8Array(plaintext);
+}
+
+/**
+ * A key exchange in progress: created when the user enters a pairing code,
+ * finished by the client's e2e_welcome
+ */
+export class E2ePairing {
+  private constructor(
+    private readonly keyPair: CryptoKeyPair,
+    readonly browserKey: string,
+    private readonly pairingCode: string
+  ) {}
+
+  static async start(pairingCode: string): Promise<E2ePairing> {
+    const keyPair = (await crypto.subtle.generateKey({ name: "X25519" }, false, [
+      "deriveBits",
+    ])) as CryptoKeyPair;
+    const publicKey = new Uint8Array(await crypto.subtle.exportKey("raw", keyPair.publicKey));
+    return new E2ePairing(keyPair, toBase64(publicKey), normalizePairingCode(pairingCode));
+  }
+
+  /**
+   * Unwrap the group key; fails if the pairing code was wrong or a key was swapped
+   */
+  async finish(clientKey: string, wrappedKey: string): Promise<E2eChannel> {
+    const clientPublic = fromBase64(clientKey);
+    const browserPublic = fromBase64(this.browserKey);
+
+    const peer = await crypto.subtle.importKey("raw", clientPublic, { name: "X25519" }, false, []);
+    const shared = await crypto.subtle.deriveBits(
+      { name: "X25519", public: peer },
+      this.keyPair.privateKey,
+      256
+    );
+
+    const info = new Uint8Array(KDF_INFO.length + clientPublic.length + browserPublic.length);
+    info.set(KDF_INFO);
+    info.set(clientPublic, KDF_INFO.length);
+    info.set(browserPublic, KDF_INFO.length + clientPublic.length);
+
+    const hkdfKey = await crypto.subtle.importKey("raw", shared, "HKDF", false, ["deriveKey"]);
+    const wrapKey = await crypto.subtle.deriveKey(
+      {
+        name: "HKDF",
+        hash: "SHA-256",
+        salt: new TextEncoder().encode(this.pairingCode),
+        info,
+      },
+      hkdfKey,
+      { name: "AES-GCM", length: 256 },
+      false,
+      ["decrypt"]
+    );
+
+    const groupKey = await openWith(wrapKey, wrappedKey);
+    return E2eChannel.fromRawKey(groupKey);
+  }
+}
+
+/**
+ * Encrypts and decrypts session payloads with a client's group key
+ *
+ * Nonces are a random sender id followed by a message counter, which is how
+ * the client tells replayed messages apart without remembering every nonce.
+ */
+export class E2eChannel {
+  private readonly sender = crypto.getRandomValues(new Uint8Array(4));
+  private counter = BigInt(0);
+
+  private constructor(private readonly key: CryptoKey, readonly rawKey: string) {}
+
+  static async fromRawKey(rawKey: Uint8Array<ArrayBuffer>): Promise<E2eChannel> {
+    const key = await crypto.subtle.importKey("raw", rawKey, "AES-GCM", false, [
+      "encrypt",
+      "decrypt",
+    ]);
+    return new E2eChannel(key, toBase64(rawKey));
+  }
+
+  static async fromStored(rawKey: string): Promise<E2eChannel> {
+    return E2eChannel.fromRawKey(fromBase64(rawKey));
+  }
+
+  seal(plaintext: string): Promise<string> {
+    const nonce = new Uint8Array(12);
+    nonce.set(this.sender);
+    new DataView(nonce.buffer).setBigUint64(this.sender.length, this.counter);
+    this.counter += BigInt(1);
+    return sealWith(this.key, nonce, new TextEncoder().encode(plaintext));
+  }
+
+  async open(payload: string): Promise<string> {
+    return new TextDecoder().decode(await openWith(this.key, payload));
+  }
+}
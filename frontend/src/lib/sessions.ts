@@ -1,6 +1,8 @@
 "use client";
 
 import { useMemo, useSyncExternalStore } from "react";
+import { E2eChannel, E2ePairing } from "./e2e";
+import { EMPTY_SCREEN, decodeOutput, writeToScreen, type Screen } from "./terminal";
 
 export type ChatRole = "user" | "assistant" | "system";
 
@@ -19,6 +21,8 @@ export interface ClaudeToolUse {
 
 export type ChatMessage = {
   role: ChatRole;
+  // Anthropic message id; Claude writes one entry per content block, all sharing it
+  id?: string;
   content: string | ClaudeTextBlock[] | (ClaudeTextBlock | ClaudeToolUse)[];
   // Additional fields from Claude Code
   isSidechain?: boolean;
@@ -27,6 +31,20 @@ export type ChatMessage = {
   timestamp?: string;
 };
 
+// Claude's own permission modes
+export type ClaudePermissionMode = "default" | "acceptEdits" | "plan" | "bypassPermissions";
+
+// Agent settings of one session, applied when the client starts Claude
+export interface SessionSettings {
+  model: string | null;
+  permission_mode: ClaudePermissionMode | null;
+  allowed_tools: string[];
+  disallowed_tools: string[];
+  append_system_prompt: string | null;
+  max_turns: number | null;
+  extra_args: string[];
+}
+
 export interface SessionInfo {
   lychee_id: string;
   claude_session_id: string | null;
@@ -34,36 +52,372 @@ export interface SessionInfo {
   last_active: string;
   isStreaming?: boolean;
   is_worktree: boolean;
+  settings?: SessionSettings;
+  title?: string | null;
+  // Worktree sessions only
+  branch?: string | null;
+  base_ref?: string | null;
+  base_commit?: string | null;
+}
+
+export interface WorktreeOptions {
+  // Branch, tag or commit; the client's configured base or HEAD when empty
+  baseRef?: string;
+  // Defaults to lychee/<session id>
+  branch?: string;
+}
+
+// A content block still being generated, from claude_stream deltas
+export interface PartialBlock {
+  message_id: string;
+  index: number;
+  block_type: string;
+  text: string;
+}
+
+// A tool use waiting for approval, from `lychee up --permission-mode prompt`
+export interface PermissionRequest {
+  repo_path: string;
+  lychee_id: string;
+  request_id: string;
+  tool_name: string;
+  input: Record<string, unknown>;
+}
+
+export type PermissionDecision = "allow" | "deny" | "always_allow";
+
+// Where an always_allow answer is remembered
+export type PermissionScope = "session" | "repo";
+
+export interface Announcement {
+  message: string;
+  level: string;
 }
 
+// Only set for clients started with `lychee up --e2e`
+export type PairingStatus = "unpaired" | "pairing" | "paired";
+
 export interface RepoInfo {
   name: string;
   path: string;
   sessions: SessionInfo[];
+  // Only loaded once the archive is opened
+  archivedSessions?: SessionInfo[];
+  pairing?: PairingStatus;
+  // Names the client in preview URLs
+  clientId?: string;
+}
+
+export type DiffScope = "all" | "committed" | "staged" | "unstaged";
+export type FileStatus = "added" | "modified" | "deleted" | "renamed" | "copied" | "type_changed" | "untracked";
+
+export interface DiffLine {
+  kind: "context" | "add" | "remove";
+  content: string;
+}
+
+export interface DiffHunk {
+  header: string;
+  old_start: number;
+  old_lines: number;
+  new_start: number;
+  new_lines: number;
+  lines: DiffLine[];
+}
+
+export interface FileDiff {
+  path: string;
+  old_path: string | null;
+  status: FileStatus;
+  binary: boolean;
+  additions: number;
+  deletions: number;
+  hunks: DiffHunk[];
+  // Hunks left out to keep the response small
+  truncated: boolean;
+}
+
+export interface SessionDiff {
+  scope: DiffScope;
+  // Commit the "all" and "committed" scopes compare against
+  base: string;
+  files: FileDiff[];
+}
+
+export type EntryKind = "file" | "dir" | "symlink";
+
+export interface DirEntry {
+  name: string;
+  kind: EntryKind;
+  // Files only
+  size: number | null;
+}
+
+export interface DirListing {
+  entries: DirEntry[];
+  truncated: boolean;
+}
+
+export interface FileContent {
+  // Size of the whole file, in bytes
+  size: number;
+  offset: number;
+  binary: boolean;
+  // Text as utf8, binary files as base64
+  encoding: "utf8" | "base64";
+  content: string;
+  // The file goes on past this range
+  truncated: boolean;
+}
+
+export interface FileStat {
+  kind: EntryKind;
+  size: number;
+  modified: string | null;
+  binary: boolean | null;
+  // What write_file expects back to detect changes underneath
+  sha256: string | null;
+}
+
+// Why a write_file was refused: the file changed, or an agent turn is running
+export type ConflictReason = "changed" | "streaming";
+
+export interface WriteError {
+  message: string;
+  // Set when the write can be retried over the current file
+  conflict: { reason: ConflictReason; current: FileStat | null } | null;
+}
+
+// A file shown in the viewer; path is relative to the session directory once loaded
+export interface OpenFile {
+  path: string;
+  content: FileContent | null;
+  // Bytes read so far, where "Load more" continues
+  loaded: number;
+  stat: FileStat | null;
+  error: string | null;
+  // Content being written, until write_file answers
+  saving: string | null;
+  writeError: WriteError | null;
+  // Something other than this viewer changed the file since it was read
+  changedOnDisk: boolean;
+}
+
+export interface SessionFiles {
+  // Keyed by path relative to the session directory, "" for its root
+  listings: Record<string, DirListing>;
+  open: OpenFile | null;
+  // Last failed listing
+  error: { path: string; message: string } | null;
+}
+
+export type ChangeKind = "created" | "modified" | "removed";
+
+export interface FileChange {
+  // Relative to the session directory
+  path: string;
+  kind: ChangeKind;
+}
+
+const EMPTY_FILES: SessionFiles = { listings: {}, open: null, error: null };
+
+export interface TerminalExit {
+  exit_code: number;
+  // Set when a signal ended the shell, e.g. on close
+  signal: string | null;
+}
+
+export interface TerminalView {
+  terminal_id: string;
+  shell: string;
+  screen: Screen;
+  // Set once the shell is gone
+  exit: TerminalExit | null;
+}
+
+export interface SessionTerminals {
+  // In the order they were opened
+  open: TerminalView[];
+  opening: boolean;
+  error: string | null;
+}
+
+const EMPTY_TERMINALS: SessionTerminals = { open: [], opening: false, error: null };
+
+export interface SessionPreview {
+  // Local port served at the session's preview URL, null while nothing is exposed
+  port: number | null;
+  // Names the exposure in its preview URL, null while nothing is exposed
+  previewId: string | null;
+  pending: boolean;
+  error: string | null;
+}
+
+const EMPTY_PREVIEW: SessionPreview = { port: null, previewId: null, pending: false, error: null };
+
+export type OutputStream = "stdout" | "stderr";
+
+export interface CommandFinished {
+  // null when a signal ended it
+  exit_code: number | null;
+  signal: number | null;
+  duration_ms: number;
+  cancelled: boolean;
+  // Output past the client's limit was dropped
+  truncated: boolean;
+}
+
+export interface CommandRun {
+  command_id: string;
+  // Set for configured targets
+  name: string | null;
+  argv: string[];
+  // Consecutive output of one stream is merged
+  output: { stream: OutputStream; data: string }[];
+  finished: CommandFinished | null;
+  error: string | null;
+}
+
+export interface SessionCommands {
+  // What the client lets browsers run
+  targets: Record<string, string[]>;
+  allowed: string[];
+  // Oldest first
+  runs: CommandRun[];
+  starting: boolean;
+  error: string | null;
+}
+
+const EMPTY_COMMANDS: SessionCommands = { targets: {}, allowed: [], runs: [], starting: false, error: null };
+
+// Output kept per run; the start is dropped past it
+const MAX_COMMAND_OUTPUT = 200_000;
+
+function appendOutput(output: CommandRun["output"], stream: OutputStream, data: string): CommandRun["output"] {
+  const last = output[output.length - 1];
+  const merged =
+    last?.stream === stream ? [...output.slice(0, -1), { stream, data: last.data + data }] : [...output, { stream, data }];
+
+  let excess = merged.reduce((total, chunk) => total + chunk.data.length, 0) - MAX_COMMAND_OUTPUT;
+  while (excess > 0 && merged.length > 0) {
+    const first = merged[0];
+    if (first.data.length <= excess) {
+      merged.shift();
+      excess -= first.data.length;
+    } else {
+      merged[0] = { ...first, data: first.data.slice(excess) };
+      excess = 0;
+    }
+  }
+  return merged;
+}
+
+export type FinishStrategy = "squash" | "merge" | "rebase";
+export type FinishStage = "committing" | "rebasing" | "merging" | "updating_base";
+export type FinishStatus = "finished" | "up_to_date" | "conflict" | "uncommitted" | "failed";
+
+export interface FinishResult {
+  strategy: FinishStrategy;
+  status: FinishStatus;
+  base_branch: string | null;
+  // New tip of the base branch
+  commit: string | null;
+  // Conflicting or uncommitted files, depending on status
+  files: string[];
+  message: string | null;
+}
+
+// Progress of finishing a worktree session; result is set once it is over
+export interface FinishState {
+  stage: FinishStage | null;
+  result: FinishResult | null;
+}
+
+// A delete the client refused, kept until the user confirms or cancels
+export interface DeleteBlocked {
+  repo_path: string;
+  lychee_id: string;
+  reason: string;
+  uncommitted_files: string[];
 }
 
 type ConnectionStatus = "idle" | "connecting" | "open" | "closed" | "error";
 
 type RelayInboundMessage =
-  | { type: "client_connected"; repo_path: string; repo_name: string }
+  | { type: "client_connected"; repo_path: string; repo_name: string; e2e?: boolean; client_id?: string }
   | { type: "client_disconnected"; repo_path: string }
-  | { type: "sessions_list"; repo_path: string; sessions?: SessionInfo[]; active_session_ids?: string[] }
+  | { type: "sessions_list"; repo_path: string; sessions?: SessionInfo[]; active_session_ids?: string[]; archived_sessions?: SessionInfo[] }
+  | { type: "session_deleted"; repo_path: string; lychee_id: string; warning?: string | null }
+  | ({ type: "session_delete_blocked" } & DeleteBlocked)
+  | ({ type: "session_diff"; repo_path: string; lychee_id: string } & SessionDiff)
+  | ({ type: "dir_listing"; repo_path: string; lychee_id: string; path: string } & DirListing)
+  | ({ type: "file_content"; repo_path: string; lychee_id: string; path: string } & FileContent)
+  | ({ type: "file_stat"; repo_path: string; lychee_id: string; path: string } & FileStat)
+  | { type: "file_error"; repo_path: string; lychee_id: string; path: string; message: string }
+  | { type: "files_changed"; repo_path: string; lychee_id: string; changes: FileChange[]; truncated: boolean }
+  | ({ type: "file_written"; repo_path: string; lychee_id: string; path: string; warning?: string | null } & FileStat)
+  | { type: "file_conflict"; repo_path: string; lychee_id: string; path: string; reason: ConflictReason; message: string; current: FileStat | null }
+  | { type: "terminal_opened"; repo_path: string; lychee_id: string; terminal_id: string; shell: string }
+  | { type: "terminals_list"; repo_path: string; lychee_id: string; terminals: { terminal_id: string; shell: string; opened_at: string }[] }
+  | { type: "terminal_output"; repo_path: string; lychee_id: string; terminal_id: string; data: string }
+  | ({ type: "terminal_exited"; repo_path: string; lychee_id: string; terminal_id: string } & TerminalExit)
+  | { type: "terminal_error"; repo_path: string; lychee_id: string; terminal_id: string | null; message: string }
+  | { type: "preview_updated"; repo_path: string; lychee_id: string; port: number | null; preview_id: string | null }
+  | { type: "preview_error"; repo_path: string; lychee_id: string; message: string }
+  | { type: "commands_list"; repo_path: string; lychee_id: string; targets: Record<string, string[]>; allowed: string[]; running: { command_id: string; name: string | null; argv: string[]; started_at: string }[] }
+  | { type: "command_started"; repo_path: string; lychee_id: string; command_id: string; name: string | null; argv: string[] }
+  | { type: "command_output"; repo_path: string; lychee_id: string; command_id: string; stream: OutputStream; data: string }
+  | ({ type: "command_finished"; repo_path: string; lychee_id: string; command_id: string } & CommandFinished)
+  | { type: "command_error"; repo_path: string; lychee_id: string; command_id: string | null; message: string }
+  | { type: "finish_progress"; repo_path: string; lychee_id: string; stage: FinishStage }
+  | ({ type: "finish_result"; repo_path: string; lychee_id: string } & FinishResult)
   | { type: "session_created"; repo_path: string; lychee_id: string }
   | { type: "session_history"; repo_path: string; lychee_id: string; messages?: ChatMessage[] }
   | { type: "session_update"; repo_path: string; lychee_id: string; new_entries?: ChatMessage[] }
   | { type: "stream_start"; repo_path: string; lychee_id: string }
   | { type: "stream_end"; repo_path: string; lychee_id: string }
-  | { type: "claude_stream"; repo_path: string; lychee_id: string; data: unknown }
+  | { type: "claude_stream"; repo_path: string; lychee_id: string; data: PartialBlock }
   | { type: "client_count"; count: number }
-  | { type: "error"; repo_path?: string | null; message: string };
+  | ({ type: "permission_request" } & PermissionRequest)
+  | { type: "permission_resolved"; repo_path: string; lychee_id: string; request_id: string; decision: PermissionDecision }
+  | { type: "error"; repo_path?: string | null; message: string }
+  | { type: "announcement"; message: string; level: string }
+  | { type: "maintenance"; enabled: boolean; message?: string | null }
+  | { type: "e2e_welcome"; repo_path: string; browser_key: string; client_key: string; wrapped_key: string }
+  | { type: "encrypted"; repo_path: string; lychee_id?: string | null; message_type: string; payload: string };
 
 type RelayOutboundMessage =
   | { type: "register_browser" }
-  | { type: "list_sessions"; repo_path: string }
+  | { type: "list_sessions"; repo_path: string; include_archived?: boolean }
+  | { type: "rename_session"; repo_path: string; lychee_id: string; title: string }
+  | { type: "archive_session"; repo_path: string; lychee_id: string; archived: boolean }
+  | { type: "delete_session"; repo_path: string; lychee_id: string; force: boolean }
+  | { type: "get_diff"; repo_path: string; lychee_id: string; scope: DiffScope }
+  | { type: "list_dir"; repo_path: string; lychee_id: string; path: string }
+  | { type: "read_file"; repo_path: string; lychee_id: string; path: string; offset?: number; length?: number }
+  | { type: "stat"; repo_path: string; lychee_id: string; path: string }
+  | { type: "watch_files"; repo_path: string; lychee_id: string }
+  | { type: "unwatch_files"; repo_path: string; lychee_id: string }
+  | { type: "write_file"; repo_path: string; lychee_id: string; path: string; content: string; expected_sha256?: string; expected_modified?: string; force?: boolean }
+  | { type: "open_terminal"; repo_path: string; lychee_id: string; cols: number; rows: number }
+  | { type: "terminal_input"; repo_path: string; lychee_id: string; terminal_id: string; data: string }
+  | { type: "terminal_resize"; repo_path: string; lychee_id: string; terminal_id: string; cols: number; rows: number }
+  | { type: "close_terminal"; repo_path: string; lychee_id: string; terminal_id: string }
+  | { type: "list_terminals"; repo_path: string; lychee_id: string }
+  | { type: "expose_port"; repo_path: string; lychee_id: string; port: number | null }
+  | { type: "get_preview"; repo_path: string; lychee_id: string }
+  | { type: "run_command"; repo_path: string; lychee_id: string; name?: string; argv?: string[] }
+  | { type: "cancel_command"; repo_path: string; lychee_id: string; command_id: string }
+  | { type: "list_commands"; repo_path: string; lychee_id: string }
+  | { type: "finish_worktree_session"; repo_path: string; lychee_id: string; strategy: FinishStrategy; auto_commit: boolean; commit_message?: string }
   | { type: "create_session"; repo_path: string }
-  | { type: "create_worktree_session"; repo_path: string }
+  | { type: "create_worktree_session"; repo_path: string; base_ref?: string; branch?: string }
   | { type: "load_session"; repo_path: string; lychee_id: string }
-  | { type: "send_message"; repo_path: string; lychee_id: string | null; content: string; model: string };
+  | { type: "send_message"; repo_path: string; lychee_id: string | null; content: string; model: string }
+  | { type: "update_session_settings"; repo_path: string; lychee_id: string; settings: SessionSettings }
+  | { type: "permission_response"; repo_path: string; lychee_id: string; request_id: string; decision: PermissionDecision; scope: PermissionScope }
+  | { type: "e2e_hello"; repo_path: string; browser_key: string }
+  | { type: "encrypted"; repo_path: string; lychee_id: string | null; message_type: string; payload: string };
 
 interface SessionsState {
   repos: RepoInfo[];
@@ -72,11 +426,42 @@ interface SessionsState {
   creatingSessionForRepo: string | null;
   isCreatingSession: boolean;
   messages: ChatMessage[];
+  // Not yet persisted blocks of the current session, in stream order
+  partialBlocks: PartialBlock[];
   activeStreams: Set<string>;
+  // Tool approvals still waiting for an answer, across all sessions
+  permissionRequests: PermissionRequest[];
+  deleteBlocked: DeleteBlocked | null;
+  // Keyed by session id
+  finishes: Record<string, FinishState>;
+  // Latest diff of each session, keyed by session id
+  diffs: Record<string, SessionDiff>;
+  // File browser and viewer of each session, keyed by session id
+  files: Record<string, SessionFiles>;
+  // Bumped on every files_changed, keyed by session id
+  filesVersion: Record<string, number>;
+  // Terminals of each session, keyed by session id
+  terminals: Record<string, SessionTerminals>;
+  // Commands run in each session, keyed by session id
+  commands: Record<string, SessionCommands>;
+  // Exposed ports, keyed by session id
+  previews: Record<string, SessionPreview>;
   connectionStatus: ConnectionStatus;
   selectedModel: string;
+  announcement: Announcement | null;
+  // Set while the relay refuses new messages
+  maintenance: string | null;
 }
 
+// Messages about an encrypted repo that come from the relay, or precede the key exchange
+const PLAINTEXT_MESSAGES: ReadonlySet<string> = new Set([
+  "client_connected",
+  "client_disconnected",
+  "e2e_welcome",
+  "encrypted",
+  "error",
+]);
+
 const INITIAL_STATE: SessionsState = {
   repos: [],
   activeRepoPath: null,
@@ -84,9 +469,21 @@ const INITIAL_STATE: SessionsState = {
   creatingSessionForRepo: null,
   isCreatingSession: false,
   messages: [],
+  partialBlocks: [],
   activeStreams: new Set(),
+  permissionRequests: [],
+  deleteBlocked: null,
+  finishes: {},
+  diffs: {},
+  files: {},
+  filesVersion: {},
+  terminals: {},
+  commands: {},
+  previews: {},
   connectionStatus: "idle",
   selectedModel: "claude-sonnet-4-5-20250929",
+  announcement: null,
+  maintenance: null,
 };
 
 type Listener = () => void;
@@ -97,6 +494,16 @@ class SessionsService {
   private ws: WebSocket | null = null;
   private reconnectTimeout: number | null = null;
   private readonly wsUrl: string;
+  // E2E state per repo_path
+  private channels: Map<string, E2eChannel> = new Map();
+  private pairings: Map<string, E2ePairing> = new Map();
+  // Crypto is async; queues keep messages in order
+  private inboundQueue: Promise<void> = Promise.resolve();
+  private outboundQueue: Promise<void> = Promise.resolve();
+  // Session whose file changes the relay sends us
+  private watchedSession: { repoPath: string; lycheeId: string } | null = null;
+  // Terminal output may split UTF-8 sequences, so each terminal decodes as a stream
+  private terminalDecoders: Map<string, TextDecoder> = new Map();
 
   constructor() {
     this.wsUrl = (typeof process !== "undefined" && process.env.NEXT_PUBLIC_WS_URL) || "ws://localhost:3001/ws";
@@ -121,9 +528,12 @@ class SessionsService {
   selectSession = (repoPath: string, lycheeId: string) => {
     this.updateState((prev) => ({
       ...prev,
+      // The session's default model wins over the last one picked elsewhere
+      selectedModel: this.sessionModel(prev.repos, repoPath, lycheeId) ?? prev.selectedModel,
       activeRepoPath: repoPath,
       currentSessionId: lycheeId,
       messages: [],
+      partialBlocks: [],
       isCreatingSession: false,
       creatingSessionForRepo: null,
     }));
@@ -133,8 +543,27 @@ class SessionsService {
       repo_path: repoPath,
       lychee_id: lycheeId,
     });
+    this.watchSession(repoPath, lycheeId);
+    // Terminals outlive page loads on the client
+    this.sendMessage({ type: "list_terminals", repo_path: repoPath, lychee_id: lycheeId });
+    this.sendMessage({ type: "list_commands", repo_path: repoPath, lychee_id: lycheeId });
+    this.sendMessage({ type: "get_preview", repo_path: repoPath, lychee_id: lycheeId });
   };
 
+  /**
+   * Get files_changed for one session, the one on screen
+   * Subscriptions are kept by the relay itself, so they are never sealed
+   */
+  private watchSession(repoPath: string, lycheeId: string) {
+    const previous = this.watchedSession;
+    if (previous?.repoPath === repoPath && previous.lycheeId === lycheeId) return;
+    if (previous) {
+      this.sendRaw({ type: "unwatch_files", repo_path: previous.repoPath, lychee_id: previous.lycheeId });
+    }
+    this.watchedSession = { repoPath, lycheeId };
+    this.sendRaw({ type: "watch_files", repo_path: repoPath, lychee_id: lycheeId });
+  }
+
   createSession = (repoPath: string) => {
     this.updateState((prev) => ({
       ...prev,
@@ -148,7 +577,7 @@ class SessionsService {
     });
   };
 
-  createWorktreeSession = (repoPath: string) => {
+  createWorktreeSession = (repoPath: string, options: WorktreeOptions = {}) => {
     this.updateState((prev) => ({
       ...prev,
       creatingSessionForRepo: repoPath,
@@ -158,6 +587,8 @@ class SessionsService {
     this.sendMessage({
       type: "create_worktree_session",
       repo_path: repoPath,
+      base_ref: options.baseRef?.trim() || undefined,
+      branch: options.branch?.trim() || undefined,
     });
   };
 
@@ -168,6 +599,277 @@ class SessionsService {
     });
   };
 
+  loadArchivedSessions = (repoPath: string) => {
+    this.sendMessage({
+      type: "list_sessions",
+      repo_path: repoPath,
+      include_archived: true,
+    });
+  };
+
+  renameSession = (repoPath: string, lycheeId: string, title: string) => {
+    // An empty title lets the client derive one again
+    this.sendMessage({
+      type: "rename_session",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      title,
+    });
+  };
+
+  archiveSession = (repoPath: string, lycheeId: string, archived: boolean) => {
+    // The client answers with both lists
+    this.sendMessage({
+      type: "archive_session",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      archived,
+    });
+  };
+
+  deleteSession = (repoPath: string, lycheeId: string, force = false) => {
+    this.updateState((prev) => ({
+      ...prev,
+      deleteBlocked: null,
+    }));
+
+    this.sendMessage({
+      type: "delete_session",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      force,
+    });
+  };
+
+  requestDiff = (repoPath: string, lycheeId: string, scope: DiffScope = "all") => {
+    this.sendMessage({
+      type: "get_diff",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      scope,
+    });
+  };
+
+  listDir = (repoPath: string, lycheeId: string, path: string = "") => {
+    this.sendMessage({
+      type: "list_dir",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      path,
+    });
+  };
+
+  /**
+   * Show a file in the viewer; paths may be relative or, as tool calls give
+   * them, absolute inside the session directory
+   */
+  openFile = (repoPath: string, lycheeId: string, path: string) => {
+    this.updateFiles(lycheeId, (files) => ({
+      ...files,
+      open: {
+        path,
+        content: null,
+        loaded: 0,
+        stat: null,
+        error: null,
+        saving: null,
+        writeError: null,
+        changedOnDisk: false,
+      },
+    }));
+
+    this.sendMessage({ type: "stat", repo_path: repoPath, lychee_id: lycheeId, path });
+    this.sendMessage({ type: "read_file", repo_path: repoPath, lychee_id: lycheeId, path });
+  };
+
+  /**
+   * Read the next range of the open file
+   */
+  loadMoreOfFile = (repoPath: string, lycheeId: string) => {
+    const open = this.state.files[lycheeId]?.open;
+    if (!open?.content?.truncated) return;
+
+    this.sendMessage({
+      type: "read_file",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      path: open.path,
+      offset: open.loaded,
+    });
+  };
+
+  /**
+   * Save the open file. The write is refused if the file changed since it
+   * was read; `overwrite` retries a refused write over the file as it is now.
+   */
+  writeFile = (repoPath: string, lycheeId: string, content: string, overwrite = false) => {
+    const open = this.state.files[lycheeId]?.open;
+    if (!open?.content) return;
+
+    const conflict = overwrite ? open.writeError?.conflict : null;
+    const expected = conflict ? conflict.current : open.stat;
+    this.updateFiles(lycheeId, (files) =>
+      files.open ? { ...files, open: { ...files.open, saving: content, writeError: null } } : files
+    );
+
+    this.sendMessage({
+      type: "write_file",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      path: open.path,
+      content,
+      expected_sha256: expected?.sha256 ?? undefined,
+      expected_modified: expected?.sha256 ? undefined : expected?.modified ?? undefined,
+      force: conflict?.reason === "streaming",
+    });
+  };
+
+  closeFile = (lycheeId: string) => {
+    this.updateFiles(lycheeId, (files) => ({ ...files, open: null }));
+  };
+
+  private updateFiles(lycheeId: string, update: (files: SessionFiles) => SessionFiles) {
+    this.updateState((prev) => ({
+      ...prev,
+      files: { ...prev.files, [lycheeId]: update(prev.files[lycheeId] ?? EMPTY_FILES) },
+    }));
+  }
+
+  openTerminal = (repoPath: string, lycheeId: string, cols: number, rows: number) => {
+    this.updateTerminals(lycheeId, (terminals) => ({ ...terminals, opening: true, error: null }));
+    this.sendMessage({ type: "open_terminal", repo_path: repoPath, lychee_id: lycheeId, cols, rows });
+  };
+
+  /**
+   * Keystrokes or pasted text, as the terminal expects them (Enter is "\r")
+   */
+  sendTerminalInput = (repoPath: string, lycheeId: string, terminalId: string, data: string) => {
+    this.sendMessage({ type: "terminal_input", repo_path: repoPath, lychee_id: lycheeId, terminal_id: terminalId, data });
+  };
+
+  resizeTerminal = (repoPath: string, lycheeId: string, terminalId: string, cols: number, rows: number) => {
+    this.sendMessage({ type: "terminal_resize", repo_path: repoPath, lychee_id: lycheeId, terminal_id: terminalId, cols, rows });
+  };
+
+  /**
+   * Hang up on a running terminal; an exited one is just removed
+   */
+  closeTerminal = (repoPath: string, lycheeId: string, terminalId: string) => {
+    const terminal = this.state.terminals[lycheeId]?.open.find((t) => t.terminal_id === terminalId);
+    if (terminal && !terminal.exit) {
+      this.sendMessage({ type: "close_terminal", repo_path: repoPath, lychee_id: lycheeId, terminal_id: terminalId });
+      return;
+    }
+    this.terminalDecoders.delete(terminalId);
+    this.updateTerminals(lycheeId, (terminals) => ({
+      ...terminals,
+      open: terminals.open.filter((t) => t.terminal_id !== terminalId),
+    }));
+  };
+
+  private updateTerminals(lycheeId: string, update: (terminals: SessionTerminals) => SessionTerminals) {
+    this.updateState((prev) => ({
+      ...prev,
+      terminals: { ...prev.terminals, [lycheeId]: update(prev.terminals[lycheeId] ?? EMPTY_TERMINALS) },
+    }));
+  }
+
+  private updateTerminal(lycheeId: string, terminalId: string, update: (terminal: TerminalView) => TerminalView) {
+    this.updateTerminals(lycheeId, (terminals) => ({
+      ...terminals,
+      open: terminals.open.map((t) => (t.terminal_id === terminalId ? update(t) : t)),
+    }));
+  }
+
+  /**
+   * Run a configured target by name, or an allowed program with arguments
+   */
+  runCommand = (repoPath: string, lycheeId: string, command: { name: string } | { argv: string[] }) => {
+    this.updateCommands(lycheeId, (commands) => ({ ...commands, starting: true, error: null }));
+    this.sendMessage({ type: "run_command", repo_path: repoPath, lychee_id: lycheeId, ...command });
+  };
+
+  cancelCommand = (repoPath: string, lycheeId: string, commandId: string) => {
+    this.sendMessage({ type: "cancel_command", repo_path: repoPath, lychee_id: lycheeId, command_id: commandId });
+  };
+
+  dismissCommandRun = (lycheeId: string, commandId: string) => {
+    this.updateCommands(lycheeId, (commands) => ({
+      ...commands,
+      runs: commands.runs.filter((run) => run.command_id !== commandId),
+    }));
+  };
+
+  /**
+   * Serve a local port at the session's preview URL, or stop with null
+   */
+  exposePort = (repoPath: string, lycheeId: string, port: number | null) => {
+    this.updatePreview(lycheeId, (preview) => ({ ...preview, pending: true, error: null }));
+    this.sendMessage({ type: "expose_port", repo_path: repoPath, lychee_id: lycheeId, port });
+  };
+
+  /**
+   * Where the relay serves a session's exposed port
+   */
+  previewUrl = (repoPath: string, lycheeId: string): string | null => {
+    const clientId = this.state.repos.find((repo) => repo.path === repoPath)?.clientId;
+    const previewId = this.state.previews[lycheeId]?.previewId;
+    if (!clientId || !previewId) return null;
+    const relay = new URL(this.wsUrl);
+    relay.protocol = relay.protocol === "wss:" ? "https:" : "http:";
+    return `${relay.origin}/preview/${clientId}/${previewId}/`;
+  };
+
+  private updatePreview(lycheeId: string, update: (preview: SessionPreview) => SessionPreview) {
+    this.updateState((prev) => ({
+      ...prev,
+      previews: { ...prev.previews, [lycheeId]: update(prev.previews[lycheeId] ?? EMPTY_PREVIEW) },
+    }));
+  }
+
+  private updateCommands(lycheeId: string, update: (commands: SessionCommands) => SessionCommands) {
+    this.updateState((prev) => ({
+      ...prev,
+      commands: { ...prev.commands, [lycheeId]: update(prev.commands[lycheeId] ?? EMPTY_COMMANDS) },
+    }));
+  }
+
+  private updateCommandRun(lycheeId: string, commandId: string, update: (run: CommandRun) => CommandRun) {
+    this.updateCommands(lycheeId, (commands) => ({
+      ...commands,
+      runs: commands.runs.map((run) => (run.command_id === commandId ? update(run) : run)),
+    }));
+  }
+
+  finishWorktreeSession = (
+    repoPath: string,
+    lycheeId: string,
+    strategy: FinishStrategy,
+    autoCommit: boolean,
+    commitMessage?: string
+  ) => {
+    this.updateState((prev) => ({
+      ...prev,
+      finishes: { ...prev.finishes, [lycheeId]: { stage: null, result: null } },
+    }));
+
+    this.sendMessage({
+      type: "finish_worktree_session",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      strategy,
+      auto_commit: autoCommit,
+      commit_message: commitMessage?.trim() || undefined,
+    });
+  };
+
+  dismissDeleteBlocked = () => {
+    this.updateState((prev) => ({
+      ...prev,
+      deleteBlocked: null,
+    }));
+  };
+
   setModel = (model: string) => {
     this.updateState((prev) => ({
       ...prev,
@@ -175,12 +877,69 @@ class SessionsService {
     }));
   };
 
+  updateSessionSettings = (repoPath: string, lycheeId: string, settings: SessionSettings) => {
+    if (settings.model) {
+      this.setModel(settings.model);
+    }
+
+    // The client answers with a sessions_list carrying the new settings
+    this.sendMessage({
+      type: "update_session_settings",
+      repo_path: repoPath,
+      lychee_id: lycheeId,
+      settings,
+    });
+  };
+
+  dismissAnnouncement = () => {
+    this.updateState((prev) => ({
+      ...prev,
+      announcement: null,
+    }));
+  };
+
+  pairRepo = (repoPath: string, pairingCode: string) => {
+    this.setPairing(repoPath, "pairing");
+
+    E2ePairing.start(pairingCode)
+      .then((pairing) => {
+        this.pairings.set(repoPath, pairing);
+        this.sendMessage({
+          type: "e2e_hello",
+          repo_path: repoPath,
+          browser_key: pairing.browserKey,
+        });
+      })
+      .catch((error) => {
+        console.error("Failed to start pairing", error);
+        this.pairingFailed(repoPath, "This browser doesn't support X25519 key exchange");
+      });
+  };
+
+  answerPermission = (request: PermissionRequest, decision: PermissionDecision, scope: PermissionScope = "session") => {
+    // Hide it right away; other browsers drop it on permission_resolved
+    this.updateState((prev) => ({
+      ...prev,
+      permissionRequests: prev.permissionRequests.filter((r) => r.request_id !== request.request_id),
+    }));
+
+    this.sendMessage({
+      type: "permission_response",
+      repo_path: request.repo_path,
+      lychee_id: request.lychee_id,
+      request_id: request.request_id,
+      decision,
+      scope,
+    });
+  };
+
   sendChatMessage = (content: string) => {
     const trimmed = content.trim();
     if (!trimmed) return;
 
-    const { activeRepoPath, currentSessionId, activeStreams, selectedModel } = this.state;
-    if (!activeRepoPath || !currentSessionId || activeStreams.has(currentSessionId)) {
+    // Messages sent while Claude is working are queued into the running turn
+    const { activeRepoPath, currentSessionId, selectedModel } = this.state;
+    if (!activeRepoPath || !currentSessionId) {
       return;
     }
 
@@ -236,6 +995,11 @@ class SessionsService {
         connectionStatus: "open",
       }));
       this.sendMessage({ type: "register_browser" });
+      // A restarted relay has forgotten what we watched
+      if (this.watchedSession) {
+        const { repoPath, lycheeId } = this.watchedSession;
+        this.sendRaw({ type: "watch_files", repo_path: repoPath, lychee_id: lycheeId });
+      }
     };
 
     this.ws.onmessage = (event) => {
@@ -264,7 +1028,19 @@ class SessionsService {
     };
   }
 
-  private handleInboundMessage(message: RelayInboundMessage) {
+  private handleInboundMessage(message: RelayInboundMessage, decrypted = false) {
+    if (
+      !decrypted &&
+      "repo_path" in message &&
+      message.repo_path &&
+      this.isEncryptedRepo(message.repo_path) &&
+      !PLAINTEXT_MESSAGES.has(message.type)
+    ) {
+      // The client seals everything about its sessions, so this didn't come from it
+      console.warn("Dropping unencrypted message for an end-to-end encrypted repo", message.type);
+      return;
+    }
+
     switch (message.type) {
       case "client_connected": {
         this.updateState((prev) => {
@@ -280,11 +1056,19 @@ class SessionsService {
                 name: message.repo_name,
                 path: message.repo_path,
                 sessions: [],
+                pairing: message.e2e ? ("unpaired" as const) : undefined,
+                clientId: message.client_id,
               },
             ].sort((a, b) => a.name.localeCompare(b.name)),
           };
         });
 
+        if (message.e2e) {
+          // Sessions can only be listed once we hold the client's key
+          this.restoreChannel(message.repo_path);
+          break;
+        }
+
         this.sendMessage({
           type: "list_sessions",
           repo_path: message.repo_path,
@@ -293,6 +1077,9 @@ class SessionsService {
       }
 
       case "client_disconnected": {
+        // A restarted client has a new key, so pairing starts over
+        this.forgetChannel(message.repo_path);
+
         this.updateState((prev) => {
           const repos = prev.repos.filter((repo) => repo.path !== message.repo_path);
           const wasActive = prev.activeRepoPath === message.repo_path;
@@ -303,6 +1090,7 @@ class SessionsService {
             activeRepoPath: wasActive ? null : prev.activeRepoPath,
             currentSessionId: wasActive ? null : prev.currentSessionId,
             messages: wasActive ? [] : prev.messages,
+            permissionRequests: prev.permissionRequests.filter((r) => r.repo_path !== message.repo_path),
           };
         });
         break;
@@ -311,6 +1099,7 @@ class SessionsService {
       case "sessions_list": {
         const sessions = message.sessions ?? [];
         const activeSessionIds = message.active_session_ids ?? [];
+        const archivedSessions = message.archived_sessions;
 
         this.updateState((prev) => {
           // Merge active session IDs from message with existing activeStreams
@@ -335,6 +1124,7 @@ class SessionsService {
                         ...session,
                         isStreaming: activeStreams.has(session.lychee_id),
                       })),
+                    archivedSessions: archivedSessions ?? repo.archivedSessions,
                   }
                 : repo
             ),
@@ -343,6 +1133,366 @@ class SessionsService {
         break;
       }
 
+      case "session_deleted": {
+        const isCurrent = this.state.currentSessionId === message.lychee_id;
+        const warning: ChatMessage[] = message.warning
+          ? [{ role: "system", content: message.warning }]
+          : [];
+
+        this.updateState((prev) => ({
+          ...prev,
+          currentSessionId: isCurrent ? null : prev.currentSessionId,
+          messages: [...(isCurrent ? [] : prev.messages), ...warning],
+          partialBlocks: isCurrent ? [] : prev.partialBlocks,
+          repos: prev.repos.map((repo) =>
+            repo.path === message.repo_path
+              ? {
+                  ...repo,
+                  sessions: repo.sessions.filter((s) => s.lychee_id !== message.lychee_id),
+                  archivedSessions: repo.archivedSessions?.filter((s) => s.lychee_id !== message.lychee_id),
+                }
+              : repo
+          ),
+        }));
+        break;
+      }
+
+      case "session_delete_blocked": {
+        this.updateState((prev) => ({
+          ...prev,
+          deleteBlocked: {
+            repo_path: message.repo_path,
+            lychee_id: message.lychee_id,
+            reason: message.reason,
+            uncommitted_files: message.uncommitted_files,
+          },
+        }));
+        break;
+      }
+
+      case "session_diff": {
+        this.updateState((prev) => ({
+          ...prev,
+          diffs: {
+            ...prev.diffs,
+            [message.lychee_id]: { scope: message.scope, base: message.base, files: message.files },
+          },
+        }));
+        break;
+      }
+
+      case "dir_listing": {
+        this.updateFiles(message.lychee_id, (files) => ({
+          ...files,
+          listings: {
+            ...files.listings,
+            [message.path]: { entries: message.entries, truncated: message.truncated },
+          },
+          error: files.error?.path === message.path ? null : files.error,
+        }));
+        break;
+      }
+
+      case "file_content": {
+        const path = message.path;
+        const content: FileContent = {
+          size: message.size,
+          offset: message.offset,
+          binary: message.binary,
+          encoding: message.encoding,
+          content: message.content,
+          truncated: message.truncated,
+        };
+        // Text ranges are counted in bytes, the content in UTF-16 units
+        const length = content.encoding === "utf8" ? new TextEncoder().encode(content.content).length : 0;
+        this.updateFiles(message.lychee_id, (files) => {
+          const open = files.open;
+          if (!open) return files;
+          if (content.offset > 0) {
+            if (open.path !== path || !open.content || content.offset !== open.loaded) return files;
+            return {
+              ...files,
+              open: {
+                ...open,
+                content: { ...content, offset: 0, content: open.content.content + content.content },
+                loaded: content.offset + length,
+              },
+            };
+          }
+          return { ...files, open: { ...open, path, content, loaded: length, error: null } };
+        });
+        break;
+      }
+
+      case "file_stat": {
+        const stat: FileStat = {
+          kind: message.kind,
+          size: message.size,
+          modified: message.modified,
+          binary: message.binary,
+          sha256: message.sha256,
+        };
+        this.updateFiles(message.lychee_id, (files) => {
+          const open = files.open;
+          // Before the content arrives the open path may still be the absolute one asked for
+          if (!open || (open.content && open.path !== message.path)) return files;
+          // A re-stat after files_changed keeps the stat the content was read with,
+          // so a save still detects the change
+          if (open.content && open.stat?.sha256) {
+            return open.stat.sha256 === stat.sha256 ? files : { ...files, open: { ...open, changedOnDisk: true } };
+          }
+          return { ...files, open: { ...open, stat } };
+        });
+        break;
+      }
+
+      case "file_error": {
+        this.updateFiles(message.lychee_id, (files) => {
+          if (files.open?.path !== message.path) {
+            return { ...files, error: { path: message.path, message: message.message } };
+          }
+          // A failed save leaves the file on screen
+          return files.open.saving !== null
+            ? { ...files, open: { ...files.open, saving: null, writeError: { message: message.message, conflict: null } } }
+            : { ...files, open: { ...files.open, error: message.message } };
+        });
+        break;
+      }
+
+      case "files_changed": {
+        const changed = new Set(message.changes.map((change) => change.path));
+        const parents = new Set(message.changes.map((change) => change.path.split("/").slice(0, -1).join("/")));
+        const files = this.state.files[message.lychee_id];
+
+        // Refresh what is on screen that may be stale
+        for (const dir of Object.keys(files?.listings ?? {})) {
+          if (message.truncated || parents.has(dir) || changed.has(dir)) {
+            this.listDir(message.repo_path, message.lychee_id, dir);
+          }
+        }
+        const open = files?.open;
+        if (open?.content && (message.truncated || changed.has(open.path))) {
+          this.sendMessage({ type: "stat", repo_path: message.repo_path, lychee_id: message.lychee_id, path: open.path });
+        }
+
+        this.updateState((prev) => ({
+          ...prev,
+          filesVersion: {
+            ...prev.filesVersion,
+            [message.lychee_id]: (prev.filesVersion[message.lychee_id] ?? 0) + 1,
+          },
+        }));
+        break;
+      }
+
+      case "file_written": {
+        const stat: FileStat = {
+          kind: message.kind,
+          size: message.size,
+          modified: message.modified,
+          binary: message.binary,
+          sha256: message.sha256,
+        };
+        this.updateFiles(message.lychee_id, (files) => {
+          const open = files.open;
+          if (open?.path !== message.path || open.saving === null) return files;
+          const content: FileContent = {
+            size: stat.size,
+            offset: 0,
+            binary: false,
+            encoding: "utf8",
+            content: open.saving,
+            truncated: false,
+          };
+          return { ...files, open: { ...open, content, loaded: stat.size, stat, saving: null } };
+        });
+        if (message.warning && this.state.currentSessionId === message.lychee_id) {
+          const warning: ChatMessage = { role: "system", content: message.warning };
+          this.updateState((prev) => ({ ...prev, messages: [...prev.messages, warning] }));
+        }
+        break;
+      }
+
+      case "file_conflict": {
+        this.updateFiles(message.lychee_id, (files) => {
+          if (files.open?.path !== message.path) return files;
+          const writeError: WriteError = {
+            message: message.message,
+            conflict: { reason: message.reason, current: message.current },
+          };
+          return { ...files, open: { ...files.open, saving: null, writeError } };
+        });
+        break;
+      }
+
+      case "terminal_opened": {
+        this.updateTerminals(message.lychee_id, (terminals) => ({
+          open: terminals.open.some((t) => t.terminal_id === message.terminal_id)
+            ? terminals.open
+            : [...terminals.open, { terminal_id: message.terminal_id, shell: message.shell, screen: EMPTY_SCREEN, exit: null }],
+          opening: false,
+          error: null,
+        }));
+        break;
+      }
+
+      case "terminals_list": {
+        // Earlier output is gone; only what comes from now on shows
+        this.updateTerminals(message.lychee_id, (terminals) => ({
+          ...terminals,
+          open: [
+            ...terminals.open,
+            ...message.terminals
+              .filter((listed) => !terminals.open.some((t) => t.terminal_id === listed.terminal_id))
+              .map((listed) => ({ terminal_id: listed.terminal_id, shell: listed.shell, screen: EMPTY_SCREEN, exit: null })),
+          ],
+        }));
+        break;
+      }
+
+      case "terminal_output": {
+        let decoder = this.terminalDecoders.get(message.terminal_id);
+        if (!decoder) {
+          decoder = new TextDecoder();
+          this.terminalDecoders.set(message.terminal_id, decoder);
+        }
+        const text = decoder.decode(decodeOutput(message.data), { stream: true });
+        this.updateTerminal(message.lychee_id, message.terminal_id, (terminal) => ({
+          ...terminal,
+          screen: writeToScreen(terminal.screen, text),
+        }));
+        break;
+      }
+
+      case "terminal_exited": {
+        this.terminalDecoders.delete(message.terminal_id);
+        this.updateTerminal(message.lychee_id, message.terminal_id, (terminal) => ({
+          ...terminal,
+          exit: { exit_code: message.exit_code, signal: message.signal },
+        }));
+        break;
+      }
+
+      case "terminal_error": {
+        this.updateTerminals(message.lychee_id, (terminals) => ({ ...terminals, opening: false, error: message.message }));
+        break;
+      }
+
+      case "preview_updated": {
+        this.updatePreview(message.lychee_id, () => ({
+          port: message.port,
+          previewId: message.preview_id ?? null,
+          pending: false,
+          error: null,
+        }));
+        break;
+      }
+
+      case "preview_error": {
+        this.updatePreview(message.lychee_id, (preview) => ({ ...preview, pending: false, error: message.message }));
+        break;
+      }
+
+      case "commands_list": {
+        // Output of runs started before this page loaded is gone
+        this.updateCommands(message.lychee_id, (commands) => ({
+          ...commands,
+          targets: message.targets,
+          allowed: message.allowed,
+          runs: [
+            ...commands.runs,
+            ...message.running
+              .filter((running) => !commands.runs.some((run) => run.command_id === running.command_id))
+              .map((running) => ({
+                command_id: running.command_id,
+                name: running.name,
+                argv: running.argv,
+                output: [],
+                finished: null,
+                error: null,
+              })),
+          ],
+        }));
+        break;
+      }
+
+      case "command_started": {
+        this.updateCommands(message.lychee_id, (commands) => ({
+          ...commands,
+          runs: commands.runs.some((run) => run.command_id === message.command_id)
+            ? commands.runs
+            : [
+                ...commands.runs,
+                { command_id: message.command_id, name: message.name, argv: message.argv, output: [], finished: null, error: null },
+              ],
+          starting: false,
+          error: null,
+        }));
+        break;
+      }
+
+      case "command_output": {
+        this.updateCommandRun(message.lychee_id, message.command_id, (run) => ({
+          ...run,
+          output: appendOutput(run.output, message.stream, message.data),
+        }));
+        break;
+      }
+
+      case "command_finished": {
+        const finished: CommandFinished = {
+          exit_code: message.exit_code,
+          signal: message.signal,
+          duration_ms: message.duration_ms,
+          cancelled: message.cancelled,
+          truncated: message.truncated,
+        };
+        this.updateCommandRun(message.lychee_id, message.command_id, (run) => ({ ...run, finished }));
+        break;
+      }
+
+      case "command_error": {
+        const commandId = message.command_id;
+        if (commandId) {
+          this.updateCommandRun(message.lychee_id, commandId, (run) => ({ ...run, error: message.message }));
+        } else {
+          this.updateCommands(message.lychee_id, (commands) => ({ ...commands, starting: false, error: message.message }));
+        }
+        break;
+      }
+
+      case "finish_progress": {
+        this.updateState((prev) => ({
+          ...prev,
+          finishes: {
+            ...prev.finishes,
+            [message.lychee_id]: { stage: message.stage, result: null },
+          },
+        }));
+        break;
+      }
+
+      case "finish_result": {
+        this.updateState((prev) => ({
+          ...prev,
+          finishes: {
+            ...prev.finishes,
+            [message.lychee_id]: {
+              stage: null,
+              result: {
+                strategy: message.strategy,
+                status: message.status,
+                base_branch: message.base_branch,
+                commit: message.commit,
+                files: message.files,
+                message: message.message,
+              },
+            },
+          },
+        }));
+        break;
+      }
+
       case "session_created": {
         this.updateState((prev) => ({
           ...prev,
@@ -402,6 +1552,7 @@ class SessionsService {
           return {
             ...prev,
             messages: finalMessages,
+            partialBlocks: reconcilePartials(prev.partialBlocks, finalMessages),
           };
         });
         break;
@@ -451,14 +1602,65 @@ class SessionsService {
             return !existingContents.has(entryContent);
           });
 
+          const messages = [...withoutTempDupes, ...finalEntries];
+
           return {
             ...prev,
-            messages: [...withoutTempDupes, ...finalEntries],
+            messages,
+            partialBlocks: reconcilePartials(prev.partialBlocks, messages),
           };
         });
         break;
       }
 
+      case "claude_stream": {
+        this.updateState((prev) => {
+          if (prev.currentSessionId !== message.lychee_id) {
+            return prev;
+          }
+
+          const delta = message.data;
+          const existing = prev.partialBlocks.find(
+            (b) => b.message_id === delta.message_id && b.index === delta.index
+          );
+
+          return {
+            ...prev,
+            partialBlocks: existing
+              ? prev.partialBlocks.map((b) => (b === existing ? { ...b, text: b.text + delta.text } : b))
+              : [...prev.partialBlocks, { ...delta }],
+          };
+        });
+        break;
+      }
+
+      case "permission_request": {
+        const request: PermissionRequest = {
+          repo_path: message.repo_path,
+          lychee_id: message.lychee_id,
+          request_id: message.request_id,
+          tool_name: message.tool_name,
+          input: message.input,
+        };
+        this.updateState((prev) => ({
+          ...prev,
+          // Re-sent on load_session, so keep one entry per request
+          permissionRequests: [
+            ...prev.permissionRequests.filter((r) => r.request_id !== request.request_id),
+            request,
+          ],
+        }));
+        break;
+      }
+
+      case "permission_resolved": {
+        this.updateState((prev) => ({
+          ...prev,
+          permissionRequests: prev.permissionRequests.filter((r) => r.request_id !== message.request_id),
+        }));
+        break;
+      }
+
       case "stream_start": {
         this.updateState((prev) => {
           const activeStreams = new Set(prev.activeStreams).add(message.lychee_id);
@@ -478,6 +1680,9 @@ class SessionsService {
           activeStreams.delete(message.lychee_id);
           return {
             ...prev,
+            // Anything still partial was cancelled or never persisted
+            partialBlocks: prev.currentSessionId === message.lychee_id ? [] : prev.partialBlocks,
+            permissionRequests: prev.permissionRequests.filter((r) => r.lychee_id !== message.lychee_id),
             activeStreams,
             repos: this.updateStreamingFlags(prev.repos, activeStreams),
           };
@@ -500,12 +1705,138 @@ class SessionsService {
         break;
       }
 
+      case "announcement": {
+        this.updateState((prev) => ({
+          ...prev,
+          announcement: { message: message.message, level: message.level },
+        }));
+        break;
+      }
+
+      case "maintenance": {
+        this.updateState((prev) => ({
+          ...prev,
+          maintenance: message.enabled
+            ? message.message || "New messages are temporarily disabled"
+            : null,
+        }));
+        break;
+      }
+
+      case "e2e_welcome": {
+        const pairing = this.pairings.get(message.repo_path);
+        if (!pairing || pairing.browserKey !== message.browser_key) {
+          // Another browser's key exchange
+          break;
+        }
+        this.pairings.delete(message.repo_path);
+
+        pairing
+          .finish(message.client_key, message.wrapped_key)
+          .then((channel) => {
+            this.channels.set(message.repo_path, channel);
+            if (typeof sessionStorage !== "undefined") {
+              sessionStorage.setItem(`e2e-key-${message.repo_path}`, channel.rawKey);
+            }
+            this.setPairing(message.repo_path, "paired");
+            this.sendMessage({ type: "list_sessions", repo_path: message.repo_path });
+          })
+          .catch(() => this.pairingFailed(message.repo_path, "Pairing failed, check the code shown by lychee"));
+        break;
+      }
+
+      case "encrypted": {
+        const channel = this.channels.get(message.repo_path);
+        if (!channel) {
+          break;
+        }
+
+        this.inboundQueue = this.inboundQueue.then(async () => {
+          try {
+            const inner = JSON.parse(await channel.open(message.payload)) as RelayInboundMessage;
+            // The envelope is visible to the relay, so it must not relabel what's inside
+            if (
+              inner.type === "encrypted" ||
+              inner.type !== message.message_type ||
+              !("repo_path" in inner) ||
+              inner.repo_path !== message.repo_path
+            ) {
+              console.error("Encrypted message doesn't match its envelope", message.message_type);
+              return;
+            }
+            this.handleInboundMessage(inner, true);
+          } catch (error) {
+            console.error("Failed to decrypt message", message.message_type, error);
+          }
+        });
+        break;
+      }
+
       case "client_count":
       default:
         break;
     }
   }
 
+  private sessionModel(repos: RepoInfo[], repoPath: string, lycheeId: string): string | null {
+    const repo = repos.find((r) => r.path === repoPath);
+    return repo?.sessions.find((s) => s.lychee_id === lycheeId)?.settings?.model ?? null;
+  }
+
+  /**
+   * Whether the repo's client was started with `lychee up --e2e`
+   */
+  private isEncryptedRepo(repoPath: string) {
+    return (
+      this.channels.has(repoPath) ||
+      this.state.repos.some((repo) => repo.path === repoPath && repo.pairing !== undefined)
+    );
+  }
+
+  private setPairing(repoPath: string, pairing: PairingStatus) {
+    this.updateState((prev) => ({
+      ...prev,
+      repos: prev.repos.map((repo) => (repo.path === repoPath ? { ...repo, pairing } : repo)),
+    }));
+  }
+
+  private pairingFailed(repoPath: string, reason: string) {
+    this.pairings.delete(repoPath);
+    this.setPairing(repoPath, "unpaired");
+    this.updateState((prev) => ({
+      ...prev,
+      messages: [...prev.messages, { role: "system", content: reason }],
+    }));
+  }
+
+  /**
+   * Reuse a key from earlier in this tab, e.g. across a page reload
+   */
+  private restoreChannel(repoPath: string) {
+    const stored = typeof sessionStorage !== "undefined"
+      ? sessionStorage.getItem(`e2e-key-${repoPath}`)
+      : null;
+    if (!stored) {
+      return;
+    }
+
+    E2eChannel.fromStored(stored)
+      .then((channel) => {
+        this.channels.set(repoPath, channel);
+        this.setPairing(repoPath, "paired");
+        this.sendMessage({ type: "list_sessions", repo_path: repoPath });
+      })
+      .catch(() => this.forgetChannel(repoPath));
+  }
+
+  private forgetChannel(repoPath: string) {
+    this.channels.delete(repoPath);
+    this.pairings.delete(repoPath);
+    if (typeof sessionStorage !== "undefined") {
+      sessionStorage.removeItem(`e2e-key-${repoPath}`);
+    }
+  }
+
   private updateStreamingFlags(repos: RepoInfo[], activeStreams: Set<string>) {
     return repos.map((repo) => ({
       ...repo,
@@ -517,6 +1848,30 @@ class SessionsService {
   }
 
   private sendMessage(message: RelayOutboundMessage) {
+    const channel = "repo_path" in message && message.type !== "e2e_hello"
+      ? this.channels.get(message.repo_path)
+      : undefined;
+
+    if (channel && "repo_path" in message) {
+      // Only the type, repo_path and lychee_id stay visible to the relay
+      const lycheeId = "lychee_id" in message ? message.lychee_id : null;
+      this.outboundQueue = this.outboundQueue.then(async () => {
+        const payload = await channel.seal(JSON.stringify(message));
+        this.sendRaw({
+          type: "encrypted",
+          repo_path: message.repo_path,
+          lychee_id: lycheeId,
+          message_type: message.type,
+          payload,
+        });
+      });
+      return;
+    }
+
+    this.sendRaw(message);
+  }
+
+  private sendRaw(message: RelayOutboundMessage) {
     if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
       console.warn("WebSocket not ready, attempting reconnect");
       this.connect();
@@ -574,6 +1929,28 @@ class SessionsService {
   }
 }
 
+/**
+ * Drop partial blocks whose JSONL entry has arrived
+ * Claude persists the blocks of a message in order, one entry each, so the
+ * first n blocks of a message are done once n entries carry its id
+ */
+function reconcilePartials(partials: PartialBlock[], messages: ChatMessage[]): PartialBlock[] {
+  if (partials.length === 0) return partials;
+
+  const persisted = new Map<string, number>();
+  messages.forEach((m) => {
+    if (m.role === "assistant" && m.id) {
+      persisted.set(m.id, (persisted.get(m.id) ?? 0) + 1);
+    }
+  });
+
+  return partials.filter((block) => {
+    const done = persisted.get(block.message_id) ?? 0;
+    const rank = partials.filter((b) => b.message_id === block.message_id && b.index < block.index).length;
+    return rank >= done;
+  });
+}
+
 let singletonService: SessionsService | null = null;
 
 function getSessionsService() {
@@ -598,8 +1975,33 @@ export function useSessions() {
       createSession: service.createSession,
       createWorktreeSession: service.createWorktreeSession,
       refreshSessions: service.refreshSessions,
+      loadArchivedSessions: service.loadArchivedSessions,
+      renameSession: service.renameSession,
+      archiveSession: service.archiveSession,
+      deleteSession: service.deleteSession,
+      dismissDeleteBlocked: service.dismissDeleteBlocked,
+      finishWorktreeSession: service.finishWorktreeSession,
+      requestDiff: service.requestDiff,
+      listDir: service.listDir,
+      openFile: service.openFile,
+      loadMoreOfFile: service.loadMoreOfFile,
+      writeFile: service.writeFile,
+      closeFile: service.closeFile,
+      openTerminal: service.openTerminal,
+      sendTerminalInput: service.sendTerminalInput,
+      resizeTerminal: service.resizeTerminal,
+      closeTerminal: service.closeTerminal,
+      runCommand: service.runCommand,
+      cancelCommand: service.cancelCommand,
+      dismissCommandRun: service.dismissCommandRun,
+      exposePort: service.exposePort,
+      previewUrl: service.previewUrl,
       sendChatMessage: service.sendChatMessage,
       setModel: service.setModel,
+      dismissAnnouncement: service.dismissAnnouncement,
+      updateSessionSettings: service.updateSessionSettings,
+      pairRepo: service.pairRepo,
+      answerPermission: service.answerPermission,
     }),
     [state, service]
   );
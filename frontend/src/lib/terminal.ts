@@ -0,0 +1,116 @@
+// Lines kept per terminal; older ones scroll away
+const MAX_LINES = 2000;
+
+// Tab stops every 8 columns, like a real terminal
+const TAB_WIDTH = 8;
+
+// Control sequence (colors, cursor movement), operating system command (window title), or a short escape
+const ESCAPE = /^\x1b(?:\[([0-9;?]*)[ -/]*([@-~])|\][^\x07\x1b]*(?:\x07|\x1b\\)|[()][0-9A-Za-z]|[=>78cDEHMNOZ])/;
+
+// What the start of one of those looks like before the rest has arrived
+const PARTIAL_ESCAPE = /^\x1b(?:\[[0-9;?]*[ -/]*|\][^\x07]*(?:\x1b)?|[()])?$/;
+
+export interface Screen {
+  lines: string[];
+  // Cursor column on the last line
+  col: number;
+  // An escape sequence cut off at the end of the last output
+  pending: string;
+}
+
+export const EMPTY_SCREEN: Screen = { lines: [""], col: 0, pending: "" };
+
+function writeAt(line: string, col: number, text: string): string {
+  return line.slice(0, col).padEnd(col) + text + line.slice(col + text.length);
+}
+
+/**
+ * Apply terminal output to a screen, line by line
+ *
+ * Enough for shells and build output: newlines, carriage returns, backspaces,
+ * tabs and erasing the line are followed; colors and other escape sequences
+ * are dropped. Full-screen programs such as vim or top don't render.
+ */
+export function writeToScreen(screen: Screen, text: string): Screen {
+  const input = screen.pending + text;
+  const lines = screen.lines.slice(0, -1);
+  let line = screen.lines[screen.lines.length - 1] ?? "";
+  let col = screen.col;
+  let pending = "";
+
+  let i = 0;
+  while (i < input.length) {
+    const ch = input[i];
+    if (ch === "\x1b") {
+      const rest = input.slice(i);
+      const match = ESCAPE.exec(rest);
+      if (!match) {
+        if (PARTIAL_ESCAPE.test(rest)) {
+          pending = rest;
+          break;
+        }
+        i += 1;
+        continue;
+      }
+      const [sequence, params, command] = match;
+      const count = Math.max(1, parseInt(params || "1", 10) || 1);
+      if (command === "K") {
+        // Erase to the end of the line; 1 and 2 erase before the cursor too, which blanks it here
+        line = params === "1" || params === "2" ? " ".repeat(col) : line.slice(0, col);
+      } else if (command === "D") {
+        col = Math.max(0, col - count);
+      } else if (command === "C") {
+        col += count;
+      } else if (command === "G") {
+        col = count - 1;
+      } else if (command === "J" && (params === "2" || params === "3")) {
+        lines.length = 0;
+        line = "";
+        col = 0;
+      }
+      i += sequence.length;
+      continue;
+    }
+
+    if (ch === "\n") {
+      lines.push(line);
+      line = "";
+      col = 0;
+    } else if (ch === "\r") {
+      col = 0;
+    } else if (ch === "\b") {
+      col = Math.max(0, col - 1);
+    } else if (ch === "\t") {
+      col = (Math.floor(col / TAB_WIDTH) + 1) * TAB_WIDTH;
+    } else if (ch >= " " && ch !== "\x7f") {
+      // Printable runs are written at once
+      let end = i + 1;
+      while (end < input.length && input[end] >= " " && input[end] !== "\x7f") end++;
+      const run = input.slice(i, end);
+      line = writeAt(line, col, run);
+      col += run.length;
+      i = end;
+      continue;
+    }
+    i += 1;
+  }
+
+  lines.push(line);
+  return {
+    lines: lines.length > MAX_LINES ? lines.slice(lines.length - MAX_LINES) : lines,
+    col,
+    pending,
+  };
+}
+
+/**
+ * Raw terminal output from a terminal_output frame
+ */
+export function decodeOutput(data: string): Uint8Array {
+  const binary = atob(data);
+  const bytes = new Uint8Array(binary.length);
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes;
+}
@@ -0,0 +1,226 @@
+use crate::audit::AuditQuery;
+use crate::{broadcast_to_browsers, has_token, AppState, Message};
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+struct DisconnectClientRequest {
+    repo_path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DisconnectBrowserRequest {
+    browser_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnnounceRequest {
+    message: String,
+    #[serde(default = "default_level")]
+    level: String,
+}
+
+fn default_level() -> String {
+    "info".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+    message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ClientSummary {
+    repo_path: String,
+    /// Names the client in preview URLs
+    client_id: String,
+    repo_name: String,
+    addr: String,
+    connected_at: String,
+}
+
+#[derive(Debug, Serialize)]
+struct BrowserSummary {
+    browser_id: String,
+    user: Option<String>,
+    addr: String,
+    connected_at: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ConnectionsResponse {
+    clients: Vec<ClientSummary>,
+    browsers: Vec<BrowserSummary>,
+    maintenance: Option<String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/audit", get(audit))
+        .route("/admin/connections", get(connections))
+        .route("/admin/clients/disconnect", post(disconnect_client))
+        .route("/admin/browsers/disconnect", post(disconnect_browser))
+        .route("/admin/announce", post(announce))
+        .route("/admin/maintenance", post(maintenance))
+}
+
+/**
+ * Check the `Authorization: Bearer <token>` header against RELAY_ADMIN_TOKEN
+ * Admin endpoints are disabled entirely when no token is configured
+ */
+fn is_admin(headers: &HeaderMap, state: &AppState) -> bool {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return false;
+    };
+
+    has_token(headers, expected)
+}
+
+fn unauthorized() -> Response {
+    (StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
+}
+
+async fn audit(
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if !is_admin(&headers, &state) {
+        return unauthorized();
+    }
+
+    let Some(audit) = state.audit.clone() else {
+        return (StatusCode::NOT_FOUND, "Audit log is not enabled").into_response();
+    };
+
+    // Reading the log can touch many files, keep it off the async workers
+    match tokio::task::spawn_blocking(move || audit.query(&query)).await {
+        Ok(records) => Json(records).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn connections(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    if !is_admin(&headers, &state) {
+        return unauthorized();
+    }
+
+    let clients = state
+        .clients
+        .read()
+        .await
+        .iter()
+        .map(|(repo_path, client)| ClientSummary {
+            repo_path: repo_path.clone(),
+            client_id: crate::tunnel::client_id(repo_path),
+            repo_name: client.repo_name.clone(),
+            addr: client.addr.to_string(),
+            connected_at: client.connected_at.clone(),
+        })
+        .collect();
+
+    let browsers = state
+        .browsers
+        .read()
+        .await
+        .values()
+        .map(|browser| BrowserSummary {
+            browser_id: browser.identity.id.clone(),
+            user: browser.identity.user.clone(),
+            addr: browser.identity.addr.to_string(),
+            connected_at: browser.connected_at.clone(),
+        })
+        .collect();
+
+    let maintenance = state.maintenance.read().await.clone();
+
+    Json(ConnectionsResponse { clients, browsers, maintenance }).into_response()
+}
+
+async fn disconnect_client(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(req): Json<DisconnectClientRequest>,
+) -> Response {
+    if !is_admin(&headers, &state) {
+        return unauthorized();
+    }
+
+    // Dropping the handle closes the channel, which makes the send task close the socket
+    let removed = state.clients.write().await.remove(&req.repo_path);
+    match removed {
+        Some(client) => {
+            tracing::info!(repo = %req.repo_path, conn_id = %client.conn_id, "admin disconnected client");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "No client connected for this path").into_response(),
+    }
+}
+
+async fn disconnect_browser(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(req): Json<DisconnectBrowserRequest>,
+) -> Response {
+    if !is_admin(&headers, &state) {
+        return unauthorized();
+    }
+
+    let removed = state.browsers.write().await.remove(&req.browser_id);
+    match removed {
+        Some(browser) => {
+            tracing::info!(browser_id = %browser.identity.id, "admin disconnected browser");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "No browser with this id").into_response(),
+    }
+}
+
+async fn announce(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(req): Json<AnnounceRequest>,
+) -> Response {
+    if !is_admin(&headers, &state) {
+        return unauthorized();
+    }
+
+    tracing::info!(severity = %req.level, message = %req.message, "announcement");
+    broadcast_to_browsers(&state, Message::Announcement {
+        message: req.message,
+        level: req.level,
+    }).await;
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn maintenance(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(req): Json<MaintenanceRequest>,
+) -> Response {
+    if !is_admin(&headers, &state) {
+        return unauthorized();
+    }
+
+    let message = req
+        .enabled
+        .then(|| req.message.unwrap_or_else(|| "new messages are temporarily disabled".to_string()));
+
+    *state.maintenance.write().await = message.clone();
+    tracing::info!(enabled = req.enabled, "maintenance mode changed");
+
+    broadcast_to_browsers(&state, Message::Maintenance {
+        enabled: req.enabled,
+        message,
+    }).await;
+
+    StatusCode::NO_CONTENT.into_response()
+}
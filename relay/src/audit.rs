@@ -0,0 +1,356 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// How much of a prompt ends up in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// Only a SHA-256 of the prompt is stored
+    Hash,
+    /// The full prompt text is stored
+    Full,
+    /// Neither the prompt nor its hash is stored
+    None,
+}
+
+impl Redaction {
+    fn from_env(value: &str) -> Redaction {
+        match value.to_ascii_lowercase().as_str() {
+            "full" => Redaction::Full,
+            "none" => Redaction::None,
+            _ => Redaction::Hash,
+        }
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub browser_id: String,
+    /// The name the browser gave in register_browser; nothing verifies it
+    #[serde(alias = "browser_user")]
+    pub claimed_user: Option<String>,
+    pub browser_ip: String,
+    /// Name the target client registered with
+    pub client: String,
+    /// Connection of the target client that was sent the message
+    #[serde(default)]
+    pub client_conn_id: String,
+    #[serde(default)]
+    pub client_ip: String,
+    pub repo_path: String,
+    pub message_type: String,
+    pub lychee_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+}
+
+/// Filters accepted by the admin query endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub client: Option<String>,
+    pub repo_path: Option<String>,
+    pub browser_id: Option<String>,
+    pub lychee_id: Option<String>,
+    pub message_type: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<usize>,
+}
+
+struct CurrentFile {
+    date: String,
+    index: u32,
+    size: u64,
+    file: File,
+}
+
+/// Append-only JSONL audit log, rotated daily and whenever a file reaches `max_bytes`
+pub struct AuditLog {
+    dir: PathBuf,
+    redaction: Redaction,
+    max_bytes: u64,
+    current: Mutex<Option<CurrentFile>>,
+}
+
+impl AuditLog {
+    /**
+     * Build the audit log from RELAY_AUDIT_* environment variables
+     * Returns None when RELAY_AUDIT_DIR is not set (auditing disabled)
+     */
+    pub fn from_env() -> Option<AuditLog> {
+        let dir = PathBuf::from(std::env::var("RELAY_AUDIT_DIR").ok()?);
+        let redaction = std::env::var("RELAY_AUDIT_REDACTION")
+            .map(|v| Redaction::from_env(&v))
+            .unwrap_or(Redaction::Hash);
+        let max_bytes = std::env::var("RELAY_AUDIT_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(50 * 1024 * 1024);
+
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            tracing::error!(dir = %dir.display(), error = %e, "failed to create audit directory");
+            return None;
+        }
+
+        Some(AuditLog {
+            dir,
+            redaction,
+            max_bytes,
+            current: Mutex::new(None),
+        })
+    }
+
+    /**
+     * Fill in the prompt fields of a record according to the configured redaction
+     */
+    pub fn redact(&self, record: &mut AuditRecord, prompt: Option<&str>) {
+        let Some(prompt) = prompt else { return };
+        match self.redaction {
+            Redaction::Hash => {
+                record.prompt_hash = Some(format!("{:x}", Sha256::digest(prompt.as_bytes())));
+            }
+            Redaction::Full => {
+                record.prompt_hash = Some(format!("{:x}", Sha256::digest(prompt.as_bytes())));
+                record.prompt = Some(prompt.to_string());
+            }
+            Redaction::None => {}
+        }
+    }
+
+    pub fn append(&self, record: &AuditRecord) {
+        let mut line = serde_json::to_string(record).unwrap();
+        line.push('\n');
+
+        let mut current = self.current.lock().unwrap();
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+
+        // Rotate on date change or when the current file is full
+        let needs_rotation = match current.as_ref() {
+            Some(c) => c.date != today || c.size + line.len() as u64 > self.max_bytes,
+            None => true,
+        };
+
+        if needs_rotation {
+            let mut index = match current.as_ref() {
+                Some(c) if c.date == today => c.index + 1,
+                _ => 0,
+            };
+
+            // Skip over files that are already full (e.g. after a restart)
+            loop {
+                let path = self.file_path(&today, index);
+                let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                if size + line.len() as u64 <= self.max_bytes || size == 0 {
+                    match OpenOptions::new().create(true).append(true).open(&path) {
+                        Ok(file) => {
+                            *current = Some(CurrentFile { date: today.clone(), index, size, file });
+                        }
+                        Err(e) => {
+                            tracing::error!(path = %path.display(), error = %e, "failed to open audit file");
+                            *current = None;
+                        }
+                    }
+                    break;
+                }
+                index += 1;
+            }
+        }
+
+        if let Some(c) = current.as_mut() {
+            if let Err(e) = c.file.write_all(line.as_bytes()) {
+                tracing::error!(error = %e, "failed to write audit record");
+                return;
+            }
+            c.size += line.len() as u64;
+        }
+    }
+
+    /**
+     * Read back records matching the query, oldest first, keeping the most recent `limit`
+     */
+    pub fn query(&self, query: &AuditQuery) -> Vec<AuditRecord> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|e| e.path())
+                    .filter(|p| {
+                        p.file_name()
+                            .and_then(|n| n.to_str())
+                            .is_some_and(|n| n.starts_with("audit-") && n.ends_with(".jsonl"))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // File names sort by date; indexes within a day need numeric ordering
+        files.sort_by_key(|p| {
+            let name = p.file_stem().and_then(|n| n.to_str()).unwrap_or("").to_string();
+            let mut parts = name.trim_start_matches("audit-").splitn(2, '.');
+            let date = parts.next().unwrap_or("").to_string();
+            let index = parts.next().and_then(|i| i.parse::<u32>().ok()).unwrap_or(0);
+            (date, index)
+        });
+
+        let limit = query.limit.unwrap_or(100);
+        let mut records = Vec::new();
+
+        for path in files {
+            let Ok(file) = File::open(&path) else { continue };
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                let Ok(record) = serde_json::from_str::<AuditRecord>(&line) else { continue };
+                if matches(&record, query) {
+                    records.push(record);
+                }
+            }
+        }
+
+        if records.len() > limit {
+            records.drain(..records.len() - limit);
+        }
+        records
+    }
+
+    fn file_path(&self, date: &str, index: u32) -> PathBuf {
+        if index == 0 {
+            self.dir.join(format!("audit-{}.jsonl", date))
+        } else {
+            self.dir.join(format!("audit-{}.{}.jsonl", date, index))
+        }
+    }
+}
+
+fn matches(record: &AuditRecord, query: &AuditQuery) -> bool {
+    let eq = |filter: &Option<String>, value: &str| filter.as_ref().is_none_or(|f| f == value);
+
+    eq(&query.client, &record.client)
+        && eq(&query.repo_path, &record.repo_path)
+        && eq(&query.browser_id, &record.browser_id)
+        && eq(&query.message_type, &record.message_type)
+        && query
+            .lychee_id
+            .as_ref()
+            .is_none_or(|id| record.lychee_id.as_deref() == Some(id.as_str()))
+        && query.since.as_ref().is_none_or(|since| record.timestamp.as_str() >= since.as_str())
+        && query.until.as_ref().is_none_or(|until| record.timestamp.as_str() <= until.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(dir: &tempfile::TempDir, redaction: Redaction, max_bytes: u64) -> AuditLog {
+        AuditLog {
+            dir: dir.path().to_path_buf(),
+            redaction,
+            max_bytes,
+            current: Mutex::new(None),
+        }
+    }
+
+    fn record(message_type: &str, lychee_id: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            browser_id: "browser".to_string(),
+            claimed_user: None,
+            browser_ip: "127.0.0.1".to_string(),
+            client: "repo".to_string(),
+            client_conn_id: "conn".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            repo_path: "/repo".to_string(),
+            message_type: message_type.to_string(),
+            lychee_id: lychee_id.map(str::to_string),
+            prompt_hash: None,
+            prompt: None,
+        }
+    }
+
+    fn files(dir: &tempfile::TempDir) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn append_rotates_full_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let line = serde_json::to_string(&record("list_sessions", None)).unwrap().len() as u64 + 1;
+        let audit = log(&dir, Redaction::Hash, line * 2);
+        for _ in 0..5 {
+            audit.append(&record("list_sessions", None));
+        }
+
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        assert_eq!(files(&dir), vec![
+            format!("audit-{}.1.jsonl", today),
+            format!("audit-{}.2.jsonl", today),
+            format!("audit-{}.jsonl", today),
+        ]);
+        assert_eq!(audit.query(&AuditQuery::default()).len(), 5);
+
+        // A restarted relay skips the full files instead of growing them
+        let restarted = log(&dir, Redaction::Hash, line * 2);
+        restarted.append(&record("list_sessions", None));
+        assert_eq!(files(&dir).len(), 3);
+        let last = std::fs::read_to_string(dir.path().join(format!("audit-{}.2.jsonl", today))).unwrap();
+        assert_eq!(last.lines().count(), 2);
+    }
+
+    #[test]
+    fn query_filters_and_keeps_the_latest() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit = log(&dir, Redaction::Hash, 1024 * 1024);
+        audit.append(&record("create_session", None));
+        audit.append(&record("send_message", Some("a")));
+        audit.append(&record("send_message", Some("b")));
+        audit.append(&record("load_session", Some("a")));
+
+        let query = |query: AuditQuery| -> Vec<String> {
+            audit.query(&query).into_iter().map(|r| r.message_type).collect()
+        };
+        assert_eq!(query(AuditQuery { lychee_id: Some("a".to_string()), ..Default::default() }), vec!["send_message", "load_session"]);
+        assert_eq!(query(AuditQuery { message_type: Some("send_message".to_string()), ..Default::default() }).len(), 2);
+        assert_eq!(query(AuditQuery { limit: Some(1), ..Default::default() }), vec!["load_session"]);
+        assert!(query(AuditQuery { repo_path: Some("/other".to_string()), ..Default::default() }).is_empty());
+        assert!(query(AuditQuery { since: Some("9999".to_string()), ..Default::default() }).is_empty());
+    }
+
+    #[test]
+    fn redact_by_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = format!("{:x}", Sha256::digest(b"secret plan"));
+
+        let mut hashed = record("send_message", None);
+        log(&dir, Redaction::Hash, 1024).redact(&mut hashed, Some("secret plan"));
+        assert_eq!(hashed.prompt_hash.as_deref(), Some(hash.as_str()));
+        assert_eq!(hashed.prompt, None);
+
+        let mut full = record("send_message", None);
+        log(&dir, Redaction::Full, 1024).redact(&mut full, Some("secret plan"));
+        assert_eq!(full.prompt.as_deref(), Some("secret plan"));
+
+        let mut none = record("send_message", None);
+        log(&dir, Redaction::None, 1024).redact(&mut none, Some("secret plan"));
+        assert_eq!((none.prompt_hash, none.prompt), (None, None));
+
+        assert_eq!(Redaction::from_env("FULL"), Redaction::Full);
+        assert_eq!(Redaction::from_env("anything"), Redaction::Hash);
+    }
+
+    #[test]
+    fn records_written_with_browser_user_still_parse() {
+        let line = r#"{"timestamp":"t","browser_id":"b","browser_user":"ana","browser_ip":"::1","client":"c","repo_path":"/r","message_type":"list_sessions","lychee_id":null}"#;
+        let record: AuditRecord = serde_json::from_str(line).unwrap();
+        assert_eq!(record.claimed_user.as_deref(), Some("ana"));
+    }
+}
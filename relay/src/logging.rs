@@ -0,0 +1,44 @@
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+/**
+ * Set up tracing from RELAY_LOG_* environment variables
+ *
+ * RELAY_LOG         filter directives, e.g. "info" or "relay=debug" (default "info")
+ * RELAY_LOG_FORMAT  "pretty" (default) or "json"
+ * RELAY_LOG_DIR     also write logs to daily-rotated files in this directory
+ *
+ * The returned guard must be kept alive for file logs to be flushed
+ */
+pub fn init() -> Option<WorkerGuard> {
+    let filter = std::env::var("RELAY_LOG").unwrap_or_else(|_| "info".to_string());
+    let json = std::env::var("RELAY_LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json"));
+
+    let stderr_layer = if json {
+        fmt::layer().json().with_writer(std::io::stderr).boxed()
+    } else {
+        fmt::layer().with_writer(std::io::stderr).boxed()
+    };
+
+    let (file_layer, guard) = match std::env::var("RELAY_LOG_DIR") {
+        Ok(dir) => {
+            let appender = tracing_appender::rolling::daily(dir, "relay.log");
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = if json {
+                fmt::layer().json().with_writer(writer).boxed()
+            } else {
+                fmt::layer().with_ansi(false).with_writer(writer).boxed()
+            };
+            (Some(layer), Some(guard))
+        }
+        Err(_) => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_new(&filter).unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(stderr_layer)
+        .with(file_layer)
+        .init();
+
+    guard
+}
@@ -1,52 +1,427 @@
+mod admin;
+mod audit;
+mod logging;
+mod middleware;
+mod recorder;
+mod tunnel;
+
+use audit::{AuditLog, AuditRecord};
 use axum::{
-    extract::{ws::WebSocket, State, WebSocketUpgrade},
+    extract::{
+        ws::{close_code, CloseFrame, WebSocket},
+        ConnectInfo, State, WebSocketUpgrade,
+    },
+    http::HeaderMap,
     response::IntoResponse,
     routing::get,
     Router,
 };
 use futures_util::{SinkExt, StreamExt};
+use middleware::{Direction, MessageContext, Outcome, Peer, Pipeline};
+use recorder::{FrameDirection, Recorder, Recording};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
 use tokio::sync::{mpsc, RwLock};
+use tracing::Instrument;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
 enum Message {
     // Registration
     #[serde(rename = "register_client")]
-    RegisterClient { repo_path: String, repo_name: String },
+    RegisterClient {
+        repo_path: String,
+        repo_name: String,
+        #[serde(default)]
+        e2e: bool,
+    },
     #[serde(rename = "register_browser")]
-    RegisterBrowser,
+    RegisterBrowser {
+        #[serde(default)]
+        user: Option<String>,
+    },
 
     // Client status
     #[serde(rename = "client_connected")]
-    ClientConnected { repo_path: String, repo_name: String },
+    ClientConnected {
+        repo_path: String,
+        repo_name: String,
+        #[serde(default)]
+        e2e: bool,
+        /// Names the client in preview URLs
+        #[serde(default)]
+        client_id: String,
+    },
     #[serde(rename = "client_disconnected")]
     ClientDisconnected { repo_path: String },
 
+    // End-to-end encryption: the relay only routes these, it can't read the payloads
+    #[serde(rename = "e2e_hello")]
+    E2eHello { repo_path: String, browser_key: String },
+    #[serde(rename = "e2e_welcome")]
+    E2eWelcome {
+        repo_path: String,
+        browser_key: String,
+        client_key: String,
+        wrapped_key: String,
+    },
+    #[serde(rename = "encrypted")]
+    Encrypted {
+        repo_path: String,
+        lychee_id: Option<String>,
+        message_type: String,
+        payload: String,
+    },
+
     // Browser -> Client (via relay)
     #[serde(rename = "list_sessions")]
-    ListSessions { repo_path: String },
+    ListSessions {
+        repo_path: String,
+        #[serde(default)]
+        include_archived: bool,
+    },
     #[serde(rename = "create_session")]
     CreateSession { repo_path: String },
     #[serde(rename = "create_worktree_session")]
-    CreateWorktreeSession { repo_path: String },
+    CreateWorktreeSession {
+        repo_path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        base_ref: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        branch: Option<String>,
+    },
     #[serde(rename = "load_session")]
     LoadSession { repo_path: String, lychee_id: String },
     #[serde(rename = "send_message")]
-    SendMessage { repo_path: String, lychee_id: String, content: String, model: String },
+    SendMessage {
+        repo_path: String,
+        lychee_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+    },
+    #[serde(rename = "update_session_settings")]
+    UpdateSessionSettings { repo_path: String, lychee_id: String, settings: serde_json::Value },
+    #[serde(rename = "rename_session")]
+    RenameSession { repo_path: String, lychee_id: String, title: String },
+    #[serde(rename = "archive_session")]
+    ArchiveSession {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        archived: Option<bool>,
+    },
+    #[serde(rename = "delete_session")]
+    DeleteSession {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        force: Option<bool>,
+    },
+    #[serde(rename = "finish_worktree_session")]
+    FinishWorktreeSession {
+        repo_path: String,
+        lychee_id: String,
+        strategy: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        auto_commit: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        commit_message: Option<String>,
+    },
+    #[serde(rename = "get_diff")]
+    GetDiff {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scope: Option<String>,
+    },
+    #[serde(rename = "permission_response")]
+    PermissionResponse {
+        repo_path: String,
+        lychee_id: String,
+        request_id: String,
+        decision: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scope: Option<String>,
+    },
 
     // Client -> Browser (via relay)
     #[serde(rename = "sessions_list")]
     SessionsList {
         repo_path: String,
         sessions: Vec<SessionInfo>,
-        active_session_ids: Option<Vec<String>>
+        active_session_ids: Option<Vec<String>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        archived_sessions: Option<Vec<SessionInfo>>
+    },
+    #[serde(rename = "session_deleted")]
+    SessionDeleted {
+        repo_path: String,
+        lychee_id: String,
+        warning: Option<String>
+    },
+    #[serde(rename = "session_diff")]
+    SessionDiff {
+        repo_path: String,
+        lychee_id: String,
+        scope: String,
+        base: String,
+        files: serde_json::Value,
+    },
+    #[serde(rename = "list_dir")]
+    ListDir {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default)]
+        path: String,
+    },
+    #[serde(rename = "read_file")]
+    ReadFile {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        length: Option<u64>,
+    },
+    #[serde(rename = "stat")]
+    Stat { repo_path: String, lychee_id: String, path: String },
+    #[serde(rename = "dir_listing")]
+    DirListing {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        listing: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "file_content")]
+    FileContent {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        content: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "file_stat")]
+    FileStat {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        stat: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "file_error")]
+    FileError { repo_path: String, lychee_id: String, path: String, message: String },
+    /// Handled by the relay: start or stop getting files_changed for a session
+    #[serde(rename = "watch_files")]
+    WatchFiles { repo_path: String, lychee_id: String },
+    #[serde(rename = "unwatch_files")]
+    UnwatchFiles { repo_path: String, lychee_id: String },
+    #[serde(rename = "files_changed")]
+    FilesChanged {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(flatten)]
+        changes: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "write_file")]
+    WriteFile {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_sha256: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_modified: Option<String>,
+        #[serde(default)]
+        force: bool,
+    },
+    #[serde(rename = "file_written")]
+    FileWritten {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        stat: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "file_conflict")]
+    FileConflict {
+        repo_path: String,
+        lychee_id: String,
+        path: String,
+        #[serde(flatten)]
+        conflict: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "open_terminal")]
+    OpenTerminal { repo_path: String, lychee_id: String, cols: u16, rows: u16 },
+    #[serde(rename = "terminal_opened")]
+    TerminalOpened { repo_path: String, lychee_id: String, terminal_id: String, shell: String },
+    #[serde(rename = "terminal_input")]
+    TerminalInput {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+        data: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<String>,
+    },
+    #[serde(rename = "terminal_resize")]
+    TerminalResize { repo_path: String, lychee_id: String, terminal_id: String, cols: u16, rows: u16 },
+    #[serde(rename = "close_terminal")]
+    CloseTerminal { repo_path: String, lychee_id: String, terminal_id: String },
+    #[serde(rename = "list_terminals")]
+    ListTerminals { repo_path: String, lychee_id: String },
+    #[serde(rename = "terminals_list")]
+    TerminalsList { repo_path: String, lychee_id: String, terminals: serde_json::Value },
+    #[serde(rename = "terminal_output")]
+    TerminalOutput { repo_path: String, lychee_id: String, terminal_id: String, data: String },
+    #[serde(rename = "terminal_exited")]
+    TerminalExited {
+        repo_path: String,
+        lychee_id: String,
+        terminal_id: String,
+        #[serde(flatten)]
+        exit: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "terminal_error")]
+    TerminalError { repo_path: String, lychee_id: String, terminal_id: Option<String>, message: String },
+    #[serde(rename = "run_command")]
+    RunCommand {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        argv: Option<Vec<String>>,
+    },
+    #[serde(rename = "cancel_command")]
+    CancelCommand { repo_path: String, lychee_id: String, command_id: String },
+    #[serde(rename = "list_commands")]
+    ListCommands { repo_path: String, lychee_id: String },
+    #[serde(rename = "commands_list")]
+    CommandsList {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(flatten)]
+        commands: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "command_started")]
+    CommandStarted {
+        repo_path: String,
+        lychee_id: String,
+        command_id: String,
+        name: Option<String>,
+        argv: Vec<String>,
+    },
+    #[serde(rename = "command_output")]
+    CommandOutput { repo_path: String, lychee_id: String, command_id: String, stream: String, data: String },
+    #[serde(rename = "command_finished")]
+    CommandFinished {
+        repo_path: String,
+        lychee_id: String,
+        command_id: String,
+        #[serde(flatten)]
+        finished: serde_json::Map<String, serde_json::Value>,
+    },
+    #[serde(rename = "command_error")]
+    CommandError { repo_path: String, lychee_id: String, command_id: Option<String>, message: String },
+    #[serde(rename = "expose_port")]
+    ExposePort {
+        repo_path: String,
+        lychee_id: String,
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    #[serde(rename = "get_preview")]
+    GetPreview { repo_path: String, lychee_id: String },
+    #[serde(rename = "preview_updated")]
+    PreviewUpdated {
+        repo_path: String,
+        lychee_id: String,
+        port: Option<u16>,
+        /// Names this exposure in preview URLs
+        #[serde(default)]
+        preview_id: Option<String>,
+    },
+    #[serde(rename = "preview_error")]
+    PreviewError { repo_path: String, lychee_id: String, message: String },
+    // Relay <-> Client: preview traffic (see tunnel.rs), never seen by browsers
+    #[serde(rename = "tunnel_request")]
+    TunnelRequest {
+        request_id: String,
+        preview_id: String,
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: String,
+        websocket: bool,
+    },
+    #[serde(rename = "tunnel_response")]
+    TunnelResponse { request_id: String, status: u16, headers: Vec<(String, String)> },
+    #[serde(rename = "tunnel_data")]
+    TunnelData { request_id: String, data: String },
+    #[serde(rename = "tunnel_frame")]
+    TunnelFrame {
+        request_id: String,
+        data: String,
+        #[serde(default)]
+        binary: bool,
+    },
+    #[serde(rename = "tunnel_close")]
+    TunnelClose {
+        request_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    #[serde(rename = "finish_progress")]
+    FinishProgress {
+        repo_path: String,
+        lychee_id: String,
+        stage: String,
+    },
+    #[serde(rename = "finish_result")]
+    FinishResult {
+        repo_path: String,
+        lychee_id: String,
+        strategy: String,
+        status: String,
+        base_branch: Option<String>,
+        commit: Option<String>,
+        #[serde(default)]
+        files: Vec<String>,
+        message: Option<String>,
+    },
+    #[serde(rename = "session_delete_blocked")]
+    SessionDeleteBlocked {
+        repo_path: String,
+        lychee_id: String,
+        reason: String,
+        uncommitted_files: Vec<String>
     },
     #[serde(rename = "client_count")]
     ClientCount {
         count: usize,
     },
+    /// Sessions open in browsers, sent to the client whenever it changes; they get files_changed and hold tool approvals open
+    #[serde(rename = "watched_sessions")]
+    WatchedSessions {
+        lychee_ids: Vec<String>,
+    },
     #[serde(rename = "session_created")]
     SessionCreated {
         repo_path: String,
@@ -80,11 +455,38 @@ enum Message {
         lychee_id: String,
         data: serde_json::Value
     },
+    #[serde(rename = "permission_request")]
+    PermissionRequest {
+        repo_path: String,
+        lychee_id: String,
+        request_id: String,
+        tool_name: String,
+        input: serde_json::Value
+    },
+    #[serde(rename = "permission_resolved")]
+    PermissionResolved {
+        repo_path: String,
+        lychee_id: String,
+        request_id: String,
+        decision: String
+    },
     #[serde(rename = "error")]
     Error {
         repo_path: Option<String>,
         message: String
     },
+
+    // Relay -> Browser (admin)
+    #[serde(rename = "announcement")]
+    Announcement {
+        message: String,
+        level: String,
+    },
+    #[serde(rename = "maintenance")]
+    Maintenance {
+        enabled: bool,
+        message: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,79 +496,369 @@ struct SessionInfo {
     created_at: String,
     last_active: String,
     is_worktree: bool,
+    #[serde(default)]
+    settings: serde_json::Value,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    branch: Option<String>,
+    #[serde(default)]
+    base_ref: Option<String>,
+    #[serde(default)]
+    base_commit: Option<String>,
+}
+
+impl Message {
+    /**
+     * Session a routed message refers to, if any
+     */
+    fn lychee_id(&self) -> Option<&str> {
+        match self {
+            Message::LoadSession { lychee_id, .. } |
+            Message::SendMessage { lychee_id, .. } |
+            Message::UpdateSessionSettings { lychee_id, .. } |
+            Message::RenameSession { lychee_id, .. } |
+            Message::ArchiveSession { lychee_id, .. } |
+            Message::DeleteSession { lychee_id, .. } |
+            Message::SessionDeleted { lychee_id, .. } |
+            Message::GetDiff { lychee_id, .. } |
+            Message::ListDir { lychee_id, .. } |
+            Message::ReadFile { lychee_id, .. } |
+            Message::Stat { lychee_id, .. } |
+            Message::DirListing { lychee_id, .. } |
+            Message::FileContent { lychee_id, .. } |
+            Message::FileStat { lychee_id, .. } |
+            Message::FileError { lychee_id, .. } |
+            Message::WriteFile { lychee_id, .. } |
+            Message::FileWritten { lychee_id, .. } |
+            Message::FileConflict { lychee_id, .. } |
+            Message::FilesChanged { lychee_id, .. } |
+            Message::OpenTerminal { lychee_id, .. } |
+            Message::TerminalOpened { lychee_id, .. } |
+            Message::TerminalInput { lychee_id, .. } |
+            Message::TerminalResize { lychee_id, .. } |
+            Message::CloseTerminal { lychee_id, .. } |
+            Message::ListTerminals { lychee_id, .. } |
+            Message::TerminalsList { lychee_id, .. } |
+            Message::TerminalOutput { lychee_id, .. } |
+            Message::TerminalExited { lychee_id, .. } |
+            Message::TerminalError { lychee_id, .. } |
+            Message::RunCommand { lychee_id, .. } |
+            Message::CancelCommand { lychee_id, .. } |
+            Message::ListCommands { lychee_id, .. } |
+            Message::CommandsList { lychee_id, .. } |
+            Message::CommandStarted { lychee_id, .. } |
+            Message::CommandOutput { lychee_id, .. } |
+            Message::CommandFinished { lychee_id, .. } |
+            Message::CommandError { lychee_id, .. } |
+            Message::ExposePort { lychee_id, .. } |
+            Message::GetPreview { lychee_id, .. } |
+            Message::PreviewUpdated { lychee_id, .. } |
+            Message::PreviewError { lychee_id, .. } |
+            Message::SessionDiff { lychee_id, .. } |
+            Message::FinishWorktreeSession { lychee_id, .. } |
+            Message::FinishProgress { lychee_id, .. } |
+            Message::FinishResult { lychee_id, .. } |
+            Message::SessionDeleteBlocked { lychee_id, .. } |
+            Message::PermissionResponse { lychee_id, .. } |
+            Message::PermissionRequest { lychee_id, .. } |
+            Message::PermissionResolved { lychee_id, .. } => Some(lychee_id),
+            Message::Encrypted { lychee_id, .. } => lychee_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /**
+     * Session whose watchers alone get this client message
+     */
+    fn watched_session(&self) -> Option<&str> {
+        match self {
+            Message::FilesChanged { lychee_id, .. } => Some(lychee_id),
+            Message::Encrypted { message_type, lychee_id: Some(lychee_id), .. } if message_type == "files_changed" => {
+                Some(lychee_id)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Who sent a browser -> client message, as recorded in the audit log
+#[derive(Debug, Clone)]
+struct BrowserIdentity {
+    id: String,
+    /// Self-reported in register_browser, so only a label
+    user: Option<String>,
+    addr: SocketAddr,
+}
+
+/// A connected client, keyed by repo_path in `AppState::clients`
+/// Dropping the handle closes the client's socket
+struct ClientHandle {
+    conn_id: String,
+    repo_name: String,
+    e2e: bool,
+    addr: SocketAddr,
+    connected_at: String,
+    tx: mpsc::UnboundedSender<String>,
+}
+
+/// A connected browser, keyed by browser id in `AppState::browsers`
+/// Dropping the handle closes the browser's socket
+struct BrowserHandle {
+    identity: BrowserIdentity,
+    connected_at: String,
+    tx: mpsc::UnboundedSender<String>,
+    /// (repo_path, lychee_id) of sessions it gets files_changed for
+    watching: HashSet<(String, String)>,
 }
 
 #[derive(Clone)]
 struct AppState {
-    clients: Arc<RwLock<HashMap<String, mpsc::UnboundedSender<String>>>>,
-    browsers: Arc<RwLock<Vec<mpsc::UnboundedSender<String>>>>,
+    clients: Arc<RwLock<HashMap<String, ClientHandle>>>,
+    browsers: Arc<RwLock<HashMap<String, BrowserHandle>>>,
+    audit: Option<Arc<AuditLog>>,
+    admin_token: Option<String>,
+    // Clients must send it as a bearer token when set
+    client_token: Option<String>,
+    // Some(message) while new send_message requests are being rejected
+    maintenance: Arc<RwLock<Option<String>>>,
+    shutting_down: Arc<AtomicBool>,
+    // Open WebSocket connections, used to wait for sockets to drain on shutdown
+    connections: Arc<AtomicUsize>,
+    middleware: Arc<Pipeline>,
+    recorder: Option<Arc<Recorder>>,
+    tunnels: Arc<tunnel::Tunnels>,
 }
 
 #[tokio::main]
 async fn main() {
+    // Keep the guard alive so buffered file logs are flushed on exit
+    let _log_guard = logging::init();
+
+    let audit = AuditLog::from_env().map(Arc::new);
+    if audit.is_some() {
+        tracing::info!("audit log enabled");
+    }
+
+    let recorder = Recorder::from_env().map(Arc::new);
+    if recorder.is_some() {
+        tracing::warn!("recording all connections, including prompts and file contents (RELAY_RECORD_DIR)");
+    }
+
     let state = AppState {
         clients: Arc::new(RwLock::new(HashMap::new())),
-        browsers: Arc::new(RwLock::new(Vec::new())),
+        browsers: Arc::new(RwLock::new(HashMap::new())),
+        audit,
+        admin_token: std::env::var("RELAY_ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+        client_token: std::env::var("RELAY_CLIENT_TOKEN").ok().filter(|t| !t.is_empty()),
+        maintenance: Arc::new(RwLock::new(None)),
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        connections: Arc::new(AtomicUsize::new(0)),
+        middleware: Arc::new(Pipeline::from_env()),
+        recorder,
+        tunnels: Arc::new(tunnel::Tunnels::default()),
     };
 
+    let shutdown_timeout = std::env::var("RELAY_SHUTDOWN_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(10));
+
     let app = Router::new()
         .route("/ws", get(ws_handler))
-        .with_state(state);
+        .merge(admin::routes())
+        .merge(tunnel::routes())
+        .with_state(state.clone());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
-    println!("🚀 Relay server listening on {}", addr);
+    tracing::info!(%addr, "relay server listening");
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal(state.clone()))
+        .await
+        .unwrap();
+
+    // WebSocket connections outlive axum's graceful shutdown, wait for them separately
+    let drained = tokio::time::timeout(shutdown_timeout, async {
+        while state.connections.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+
+    if drained.is_err() {
+        tracing::warn!(
+            open = state.connections.load(Ordering::SeqCst),
+            timeout_secs = shutdown_timeout.as_secs(),
+            "connections still open after shutdown timeout, exiting anyway"
+        );
+    }
+    tracing::info!("relay stopped");
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_connection(socket, state))
+/**
+ * Resolve on SIGINT or SIGTERM, after telling every peer the relay is going away
+ * Dropping each handle makes its send task flush pending messages and send a close frame
+ */
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("shutting down, closing connections");
+    state.shutting_down.store(true, Ordering::SeqCst);
+
+    state.clients.write().await.clear();
+    state.browsers.write().await.clear();
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // Browsers can't set headers on a WebSocket, so only client registrations are checked
+    let client_authorized = state
+        .client_token
+        .as_deref()
+        .is_none_or(|expected| has_token(&headers, expected));
+    ws.on_upgrade(move |socket| handle_connection(socket, state, addr, client_authorized))
+}
+
+/**
+ * The token of an `Authorization: Bearer <token>` header
+ */
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/**
+ * Whether the request carries `expected` as its bearer token
+ * Compared in constant time, so the token can't be guessed byte by byte
+ */
+fn has_token(headers: &HeaderMap, expected: &str) -> bool {
+    bearer_token(headers).is_some_and(|token| bool::from(token.as_bytes().ct_eq(expected.as_bytes())))
+}
+
+/**
+ * Start recording a connection, if the relay records
+ * Recordings start with the registration frame so a replay knows what it impersonates
+ */
+async fn start_recording(state: &AppState, kind: &str, id: &str, text: &str) -> Option<Arc<Recording>> {
+    let recording = state.recorder.as_ref()?.start(kind, id).await?;
+    recording.record(FrameDirection::Recv, text);
+    Some(Arc::new(recording))
+}
+
+async fn handle_connection(mut socket: WebSocket, state: AppState, addr: SocketAddr, client_authorized: bool) {
+    // Upgrades that raced with shutdown are turned away immediately
+    if state.shutting_down.load(Ordering::SeqCst) {
+        let _ = socket.send(close_frame(true)).await;
+        return;
+    }
+
+    state.connections.fetch_add(1, Ordering::SeqCst);
+    handle_registration(socket, state.clone(), addr, client_authorized).await;
+    state.connections.fetch_sub(1, Ordering::SeqCst);
 }
 
-async fn handle_connection(socket: WebSocket, state: AppState) {
-    let (sender, mut receiver) = socket.split();
+async fn handle_registration(socket: WebSocket, state: AppState, addr: SocketAddr, client_authorized: bool) {
+    let (mut sender, mut receiver) = socket.split();
 
     // Wait for registration message
     let registration = match receiver.next().await {
         Some(Ok(axum::extract::ws::Message::Text(text))) => {
-            serde_json::from_str::<Message>(&text).ok()
+            serde_json::from_str::<Message>(&text).ok().map(|msg| (msg, text))
         }
         _ => None,
     };
 
     match registration {
-        Some(Message::RegisterClient { repo_path, repo_name }) => {
-            handle_client(sender, receiver, state, repo_path, repo_name).await;
+        Some((Message::RegisterClient { repo_path, .. }, _)) if !client_authorized => {
+            tracing::warn!(%addr, repo = %repo_path, "client registration with a missing or wrong token");
+            let frame = CloseFrame {
+                code: close_code::POLICY,
+                reason: "Invalid client token".into(),
+            };
+            let _ = sender.send(axum::extract::ws::Message::Close(Some(frame))).await;
         }
-        Some(Message::RegisterBrowser) => {
-            handle_browser(sender, receiver, state).await;
+        Some((Message::RegisterClient { repo_path, repo_name, e2e }, text)) => {
+            let conn_id = Uuid::new_v4().to_string();
+            let recording = start_recording(&state, "client", &conn_id, &text).await;
+            let span = tracing::info_span!("client", %conn_id, repo = %repo_path, %addr, e2e);
+            handle_client(sender, receiver, state, conn_id, repo_path, repo_name, e2e, addr, recording)
+                .instrument(span)
+                .await;
+        }
+        Some((Message::RegisterBrowser { user }, text)) => {
+            let identity = BrowserIdentity {
+                id: Uuid::new_v4().to_string(),
+                user,
+                addr,
+            };
+            let recording = start_recording(&state, "browser", &identity.id, &text).await;
+            let span = tracing::info_span!("browser", browser_id = %identity.id, %addr);
+            handle_browser(sender, receiver, state, identity, recording)
+                .instrument(span)
+                .await;
         }
         _ => {
-            println!("❌ Invalid registration message");
+            tracing::warn!(%addr, "invalid registration message");
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_client(
     mut sender: futures_util::stream::SplitSink<WebSocket, axum::extract::ws::Message>,
     mut receiver: futures_util::stream::SplitStream<WebSocket>,
     state: AppState,
+    conn_id: String,
     repo_path: String,
     repo_name: String,
+    e2e: bool,
+    addr: SocketAddr,
+    recording: Option<Arc<Recording>>,
 ) {
-    println!("✅ Client connected: {} ({})", repo_name, repo_path);
+    tracing::info!(%repo_name, "client connected");
 
     // Check if already connected
     {
         let clients = state.clients.read().await;
         if clients.contains_key(&repo_path) {
-            let _ = sender.send(axum::extract::ws::Message::Text(
-                serde_json::to_string(&Message::Error {
-                    repo_path: Some(repo_path.clone()),
-                    message: "Client already connected for this directory".to_string(),
-                }).unwrap()
-            )).await;
+            tracing::warn!("client already connected for this directory, rejecting");
+            let text = serde_json::to_string(&Message::Error {
+                repo_path: Some(repo_path.clone()),
+                message: "Client already connected for this directory".to_string(),
+            }).unwrap();
+            if let Some(ref recording) = recording {
+                recording.record(FrameDirection::Send, &text);
+            }
+            let _ = sender.send(axum::extract::ws::Message::Text(text)).await;
             return;
         }
     }
@@ -177,13 +869,22 @@ async fn handle_client(
     // Register client
     {
         let mut clients = state.clients.write().await;
-        clients.insert(repo_path.clone(), tx);
+        clients.insert(repo_path.clone(), ClientHandle {
+            conn_id: conn_id.clone(),
+            repo_name: repo_name.clone(),
+            e2e,
+            addr,
+            connected_at: chrono::Utc::now().to_rfc3339(),
+            tx,
+        });
     }
 
     // Notify all browsers
     broadcast_to_browsers(&state, Message::ClientConnected {
         repo_path: repo_path.clone(),
         repo_name: repo_name.clone(),
+        e2e,
+        client_id: tunnel::client_id(&repo_path),
     }).await;
 
     // Send client count to ALL clients (including this one)
@@ -191,36 +892,92 @@ async fn handle_client(
         let clients = state.clients.read().await;
         let count = clients.len();
         let count_msg = serde_json::to_string(&Message::ClientCount { count }).unwrap();
-        for client_tx in clients.values() {
-            let _ = client_tx.send(count_msg.clone());
+        for client in clients.values() {
+            let _ = client.tx.send(count_msg.clone());
         }
     }
+    // Also decides which tool approvals can time out
+    send_watched_sessions(&state, &repo_path).await;
 
     // Task 1: Forward messages from browsers to this client
+    // The channel only closes when an admin or shutdown drops this client's handle
+    let shutting_down = state.shutting_down.clone();
+    let send_recording = recording.clone();
     let mut send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
+            if let Some(ref recording) = send_recording {
+                recording.record(FrameDirection::Send, &msg);
+            }
             if sender.send(axum::extract::ws::Message::Text(msg)).await.is_err() {
-                break;
+                return;
             }
         }
-    });
+        let _ = sender.send(close_frame(shutting_down.load(Ordering::SeqCst))).await;
+    }.in_current_span());
 
     // Task 2: Forward messages from this client to browsers
     let state_clone = state.clone();
     let repo_path_clone = repo_path.clone();
+    let conn_id_clone = conn_id.clone();
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(axum::extract::ws::Message::Text(text))) = receiver.next().await {
+            if let Some(ref recording) = recording {
+                recording.record(FrameDirection::Recv, &text);
+            }
             // Parse and add repo_path if needed
             if let Ok(mut msg) = serde_json::from_str::<Message>(&text) {
+                // Answers to preview requests stay with the relay
+                if let Message::TunnelResponse { .. }
+                | Message::TunnelData { .. }
+                | Message::TunnelFrame { .. }
+                | Message::TunnelClose { .. } = msg
+                {
+                    if let Some(close) = state_clone.tunnels.deliver(&conn_id_clone, msg)
+                        && let Some(client) = state_clone.clients.read().await.get(&repo_path_clone)
+                        && client.conn_id == conn_id_clone
+                    {
+                        let _ = client.tx.send(serde_json::to_string(&close).unwrap());
+                    }
+                    continue;
+                }
+
                 // Ensure repo_path is set for client->browser messages
                 match &mut msg {
                     Message::SessionsList { repo_path: rp, .. } |
                     Message::SessionCreated { repo_path: rp, .. } |
+                    Message::SessionDeleted { repo_path: rp, .. } |
+                    Message::SessionDiff { repo_path: rp, .. } |
+                    Message::DirListing { repo_path: rp, .. } |
+                    Message::FileContent { repo_path: rp, .. } |
+                    Message::FileStat { repo_path: rp, .. } |
+                    Message::FileError { repo_path: rp, .. } |
+                    Message::FileWritten { repo_path: rp, .. } |
+                    Message::FileConflict { repo_path: rp, .. } |
+                    Message::FilesChanged { repo_path: rp, .. } |
+                    Message::TerminalOpened { repo_path: rp, .. } |
+                    Message::TerminalsList { repo_path: rp, .. } |
+                    Message::TerminalOutput { repo_path: rp, .. } |
+                    Message::TerminalExited { repo_path: rp, .. } |
+                    Message::TerminalError { repo_path: rp, .. } |
+                    Message::CommandsList { repo_path: rp, .. } |
+                    Message::CommandStarted { repo_path: rp, .. } |
+                    Message::CommandOutput { repo_path: rp, .. } |
+                    Message::CommandFinished { repo_path: rp, .. } |
+                    Message::CommandError { repo_path: rp, .. } |
+                    Message::PreviewUpdated { repo_path: rp, .. } |
+                    Message::PreviewError { repo_path: rp, .. } |
+                    Message::FinishProgress { repo_path: rp, .. } |
+                    Message::FinishResult { repo_path: rp, .. } |
+                    Message::SessionDeleteBlocked { repo_path: rp, .. } |
                     Message::SessionHistory { repo_path: rp, .. } |
                     Message::SessionUpdate { repo_path: rp, .. } |
                     Message::StreamStart { repo_path: rp, .. } |
                     Message::StreamEnd { repo_path: rp, .. } |
-                    Message::ClaudeStream { repo_path: rp, .. } => {
+                    Message::ClaudeStream { repo_path: rp, .. } |
+                    Message::PermissionRequest { repo_path: rp, .. } |
+                    Message::PermissionResolved { repo_path: rp, .. } |
+                    Message::E2eWelcome { repo_path: rp, .. } |
+                    Message::Encrypted { repo_path: rp, .. } => {
                         *rp = repo_path_clone.clone();
                     }
                     Message::Error { repo_path: rp, .. } => {
@@ -229,10 +986,26 @@ async fn handle_client(
                     _ => {}
                 }
 
-                broadcast_to_browsers(&state_clone, msg).await;
+                let ctx = MessageContext {
+                    direction: Direction::ClientToBrowser,
+                    repo_path: &repo_path_clone,
+                    peer: Peer::Client { conn_id: &conn_id_clone, addr },
+                    size: text.len(),
+                };
+                match state_clone.middleware.run(&ctx, msg) {
+                    Outcome::Unchanged(msg) => forward_to_browsers(&state_clone, &repo_path_clone, msg).await,
+                    Outcome::Replaced(msgs) => {
+                        for msg in msgs {
+                            forward_to_browsers(&state_clone, &repo_path_clone, msg).await;
+                        }
+                    }
+                    Outcome::Rejected { middleware, reason } => {
+                        tracing::warn!(%middleware, %reason, "client message rejected by middleware");
+                    }
+                }
             }
         }
-    });
+    }.in_current_span());
 
     // Wait for disconnect
     tokio::select! {
@@ -240,11 +1013,14 @@ async fn handle_client(
         _ = &mut recv_task => send_task.abort(),
     }
 
-    // Cleanup
+    // Cleanup (unless an admin already removed us and the path was re-registered)
     {
         let mut clients = state.clients.write().await;
-        clients.remove(&repo_path);
+        if clients.get(&repo_path).is_some_and(|c| c.conn_id == conn_id) {
+            clients.remove(&repo_path);
+        }
     }
+    state.tunnels.close_client(&conn_id);
 
     // Notify browsers
     broadcast_to_browsers(&state, Message::ClientDisconnected {
@@ -256,77 +1032,204 @@ async fn handle_client(
         let clients = state.clients.read().await;
         let count = clients.len();
         let count_msg = serde_json::to_string(&Message::ClientCount { count }).unwrap();
-        for client_tx in clients.values() {
-            let _ = client_tx.send(count_msg.clone());
+        for client in clients.values() {
+            let _ = client.tx.send(count_msg.clone());
         }
     }
 
-    println!("❌ Client disconnected: {}", repo_name);
+    tracing::info!(%repo_name, "client disconnected");
 }
 
 async fn handle_browser(
     mut sender: futures_util::stream::SplitSink<WebSocket, axum::extract::ws::Message>,
     mut receiver: futures_util::stream::SplitStream<WebSocket>,
     state: AppState,
+    identity: BrowserIdentity,
+    recording: Option<Arc<Recording>>,
 ) {
-    println!("✅ Browser connected");
+    tracing::info!(user = identity.user.as_deref(), "browser connected");
 
     let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let browser_id = identity.id.clone();
 
     // Register browser
     {
         let mut browsers = state.browsers.write().await;
-        browsers.push(tx);
+        browsers.insert(browser_id.clone(), BrowserHandle {
+            identity: identity.clone(),
+            connected_at: chrono::Utc::now().to_rfc3339(),
+            tx,
+            watching: HashSet::new(),
+        });
     }
 
     // Send current connected clients
     {
         let clients = state.clients.read().await;
-        for repo_path in clients.keys() {
-            let repo_name = repo_path.split('/').last().unwrap_or("unknown");
+        for (repo_path, client) in clients.iter() {
             let msg = Message::ClientConnected {
                 repo_path: repo_path.clone(),
-                repo_name: repo_name.to_string(),
+                repo_name: client.repo_name.clone(),
+                e2e: client.e2e,
+                client_id: tunnel::client_id(repo_path),
             };
-            let _ = sender.send(axum::extract::ws::Message::Text(
-                serde_json::to_string(&msg).unwrap()
-            )).await;
+            let text = serde_json::to_string(&msg).unwrap();
+            if let Some(ref recording) = recording {
+                recording.record(FrameDirection::Send, &text);
+            }
+            let _ = sender.send(axum::extract::ws::Message::Text(text)).await;
         }
     }
 
+    // Let the browser know if the relay is in maintenance mode
+    if let Some(message) = state.maintenance.read().await.clone() {
+        let msg = Message::Maintenance { enabled: true, message: Some(message) };
+        let text = serde_json::to_string(&msg).unwrap();
+        if let Some(ref recording) = recording {
+            recording.record(FrameDirection::Send, &text);
+        }
+        let _ = sender.send(axum::extract::ws::Message::Text(text)).await;
+    }
+
     // Task 1: Forward broadcasts to this browser
+    // The channel only closes when an admin or shutdown drops this browser's handle
+    let shutting_down = state.shutting_down.clone();
+    let send_recording = recording.clone();
     let mut send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
+            if let Some(ref recording) = send_recording {
+                recording.record(FrameDirection::Send, &msg);
+            }
             if sender.send(axum::extract::ws::Message::Text(msg)).await.is_err() {
-                break;
+                return;
             }
         }
-    });
+        let _ = sender.send(close_frame(shutting_down.load(Ordering::SeqCst))).await;
+    }.in_current_span());
 
     // Task 2: Forward browser requests to appropriate clients
-    let clients = state.clients.clone();
+    let recv_state = state.clone();
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(axum::extract::ws::Message::Text(text))) = receiver.next().await {
+            if let Some(ref recording) = recording {
+                recording.record(FrameDirection::Recv, &text);
+            }
             if let Ok(msg) = serde_json::from_str::<Message>(&text) {
+                if let Message::WatchFiles { repo_path, lychee_id } | Message::UnwatchFiles { repo_path, lychee_id } = &msg {
+                    let key = (repo_path.clone(), lychee_id.clone());
+                    if let Some(browser) = recv_state.browsers.write().await.get_mut(&identity.id) {
+                        if matches!(msg, Message::WatchFiles { .. }) {
+                            browser.watching.insert(key);
+                        } else {
+                            browser.watching.remove(&key);
+                        }
+                    }
+                    send_watched_sessions(&recv_state, repo_path).await;
+                    continue;
+                }
+
                 // Route to appropriate client based on repo_path
                 let repo_path = match &msg {
-                    Message::ListSessions { repo_path } |
+                    Message::ListSessions { repo_path, .. } |
                     Message::CreateSession { repo_path } |
-                    Message::CreateWorktreeSession { repo_path } |
+                    Message::CreateWorktreeSession { repo_path, .. } |
                     Message::LoadSession { repo_path, .. } |
-                    Message::SendMessage { repo_path, .. } => Some(repo_path.clone()),
+                    Message::SendMessage { repo_path, .. } |
+                    Message::UpdateSessionSettings { repo_path, .. } |
+                    Message::RenameSession { repo_path, .. } |
+                    Message::ArchiveSession { repo_path, .. } |
+                    Message::DeleteSession { repo_path, .. } |
+                    Message::GetDiff { repo_path, .. } |
+                    Message::ListDir { repo_path, .. } |
+                    Message::ReadFile { repo_path, .. } |
+                    Message::Stat { repo_path, .. } |
+                    Message::WriteFile { repo_path, .. } |
+                    Message::OpenTerminal { repo_path, .. } |
+                    Message::TerminalInput { repo_path, .. } |
+                    Message::TerminalResize { repo_path, .. } |
+                    Message::CloseTerminal { repo_path, .. } |
+                    Message::ListTerminals { repo_path, .. } |
+                    Message::RunCommand { repo_path, .. } |
+                    Message::CancelCommand { repo_path, .. } |
+                    Message::ListCommands { repo_path, .. } |
+                    Message::ExposePort { repo_path, .. } |
+                    Message::GetPreview { repo_path, .. } |
+                    Message::FinishWorktreeSession { repo_path, .. } |
+                    Message::PermissionResponse { repo_path, .. } |
+                    Message::E2eHello { repo_path, .. } |
+                    Message::Encrypted { repo_path, .. } => Some(repo_path.clone()),
+                    _ => None,
+                };
+
+                // New turns are refused during maintenance; streams in flight keep going
+                let new_turn = match &msg {
+                    Message::SendMessage { repo_path, .. } => Some(repo_path),
+                    Message::Encrypted { repo_path, message_type, .. } if message_type == "send_message" => Some(repo_path),
                     _ => None,
                 };
+                if let Some(repo_path) = new_turn
+                    && let Some(reason) = recv_state.maintenance.read().await.clone()
+                {
+                    let error = Message::Error {
+                        repo_path: Some(repo_path.clone()),
+                        message: format!("Relay is in maintenance mode: {}", reason),
+                    };
+                    send_to_browser(&recv_state, &identity.id, error).await;
+                    continue;
+                }
 
                 if let Some(rp) = repo_path {
-                    let clients_guard = clients.read().await;
-                    if let Some(client_tx) = clients_guard.get(&rp) {
-                        let _ = client_tx.send(text);
+                    let ctx = MessageContext {
+                        direction: Direction::BrowserToClient,
+                        repo_path: &rp,
+                        peer: Peer::Browser(&identity),
+                        size: text.len(),
+                    };
+                    let routed = match recv_state.middleware.run(&ctx, msg) {
+                        Outcome::Unchanged(msg) => vec![(msg, text)],
+                        Outcome::Replaced(msgs) => msgs
+                            .into_iter()
+                            .map(|msg| {
+                                let text = serde_json::to_string(&msg).unwrap();
+                                (msg, text)
+                            })
+                            .collect(),
+                        Outcome::Rejected { middleware, reason } => {
+                            tracing::info!(repo = %rp, %middleware, %reason, "browser message rejected by middleware");
+                            let error = Message::Error {
+                                repo_path: Some(rp.clone()),
+                                message: format!("Rejected by relay policy: {}", reason),
+                            };
+                            send_to_browser(&recv_state, &identity.id, error).await;
+                            continue;
+                        }
+                    };
+
+                    let mut records = Vec::new();
+                    if let Some(client) = recv_state.clients.read().await.get(&rp) {
+                        for (msg, text) in routed {
+                            tracing::debug!(repo = %rp, lychee_id = msg.lychee_id(), "routing browser message to client");
+                            if let Some(ref audit) = recv_state.audit {
+                                records.extend(audit_record(audit, &identity, client, &rp, &msg));
+                            }
+                            let _ = client.tx.send(text);
+                        }
+                    }
+                    // File writes stay off the async workers and outside the clients lock
+                    if let Some(audit) = recv_state.audit.clone()
+                        && !records.is_empty()
+                    {
+                        let _ = tokio::task::spawn_blocking(move || {
+                            for record in &records {
+                                audit.append(record);
+                            }
+                        })
+                        .await;
                     }
                 }
             }
         }
-    });
+    }.in_current_span());
 
     // Wait for disconnect
     tokio::select! {
@@ -335,20 +1238,130 @@ async fn handle_browser(
     }
 
     // Cleanup - remove this browser from the list
-    // Note: This is inefficient but browsers list should be small
-    {
+    let watched_repos: HashSet<String> = {
         let mut browsers = state.browsers.write().await;
-        browsers.retain(|b| !b.is_closed());
+        browsers
+            .remove(&browser_id)
+            .map(|browser| browser.watching.into_iter().map(|(repo_path, _)| repo_path).collect())
+            .unwrap_or_default()
+    };
+    for repo_path in watched_repos {
+        send_watched_sessions(&state, &repo_path).await;
+    }
+
+    tracing::info!("browser disconnected");
+}
+
+/**
+ * The audit record for a browser -> client message that is being routed to `client`
+ * None for messages that aren't recorded
+ */
+fn audit_record(audit: &AuditLog, identity: &BrowserIdentity, client: &ClientHandle, repo_path: &str, msg: &Message) -> Option<AuditRecord> {
+    // Encrypted envelopes carry the inner type in the clear; the prompt stays sealed
+    let message_type = match msg {
+        Message::Encrypted { message_type, .. } => message_type.clone(),
+        _ => serde_json::to_value(msg)
+            .ok()
+            .and_then(|v| v.get("type").and_then(|t| t.as_str()).map(str::to_string))
+            .unwrap_or_default(),
+    };
+    // Opening and closing a terminal is recorded, not every keystroke
+    if message_type == "terminal_input" || message_type == "terminal_resize" {
+        return None;
     }
 
-    println!("❌ Browser disconnected");
+    let mut record = AuditRecord {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        browser_id: identity.id.clone(),
+        claimed_user: identity.user.clone(),
+        browser_ip: identity.addr.ip().to_string(),
+        client: client.repo_name.clone(),
+        client_conn_id: client.conn_id.clone(),
+        client_ip: client.addr.ip().to_string(),
+        repo_path: repo_path.to_string(),
+        message_type,
+        lychee_id: msg.lychee_id().map(str::to_string),
+        prompt_hash: None,
+        prompt: None,
+    };
+
+    let prompt = match msg {
+        Message::SendMessage { content, .. } => Some(content.as_str()),
+        _ => None,
+    };
+    audit.redact(&mut record, prompt);
+    Some(record)
 }
 
 async fn broadcast_to_browsers(state: &AppState, msg: Message) {
     let browsers = state.browsers.read().await;
     let msg_text = serde_json::to_string(&msg).unwrap();
 
-    for browser_tx in browsers.iter() {
-        let _ = browser_tx.send(msg_text.clone());
+    for browser in browsers.values() {
+        let _ = browser.tx.send(msg_text.clone());
+    }
+}
+
+/**
+ * Send a client's message on to browsers; files_changed only goes to those watching its session
+ */
+async fn forward_to_browsers(state: &AppState, repo_path: &str, msg: Message) {
+    let Some(lychee_id) = msg.watched_session() else {
+        return broadcast_to_browsers(state, msg).await;
+    };
+    let key = (repo_path.to_string(), lychee_id.to_string());
+    let msg_text = serde_json::to_string(&msg).unwrap();
+
+    let browsers = state.browsers.read().await;
+    for browser in browsers.values().filter(|browser| browser.watching.contains(&key)) {
+        let _ = browser.tx.send(msg_text.clone());
+    }
+}
+
+/**
+ * Tell a repo's client which of its sessions browsers are watching
+ */
+async fn send_watched_sessions(state: &AppState, repo_path: &str) {
+    let mut lychee_ids: Vec<String> = {
+        let browsers = state.browsers.read().await;
+        browsers
+            .values()
+            .flat_map(|browser| browser.watching.iter())
+            .filter(|(rp, _)| rp == repo_path)
+            .map(|(_, lychee_id)| lychee_id.clone())
+            .collect()
+    };
+    lychee_ids.sort();
+    lychee_ids.dedup();
+
+    let clients = state.clients.read().await;
+    if let Some(client) = clients.get(repo_path) {
+        let _ = client.tx.send(serde_json::to_string(&Message::WatchedSessions { lychee_ids }).unwrap());
+    }
+}
+
+async fn send_to_browser(state: &AppState, browser_id: &str, msg: Message) {
+    let browsers = state.browsers.read().await;
+    if let Some(browser) = browsers.get(browser_id) {
+        let _ = browser.tx.send(serde_json::to_string(&msg).unwrap());
     }
+}
+
+/**
+ * Close frame sent when the relay drops a peer's handle
+ * "Going away" during shutdown, otherwise an admin disconnect
+ */
+fn close_frame(shutting_down: bool) -> axum::extract::ws::Message {
+    let frame = if shutting_down {
+        CloseFrame {
+            code: close_code::AWAY,
+            reason: "Relay is shutting down".into(),
+        }
+    } else {
+        CloseFrame {
+            code: close_code::POLICY,
+            reason: "Disconnected by relay admin".into(),
+        }
+    };
+    axum::extract::ws::Message::Close(Some(frame))
 }
\ No newline at end of file
@@ -0,0 +1,313 @@
+use crate::{BrowserIdentity, Message};
+use regex::Regex;
+use std::net::SocketAddr;
+
+/// Which way a routed message is travelling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    BrowserToClient,
+    ClientToBrowser,
+}
+
+/// The peer a routed message came from
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)] // Read by org-specific middlewares, not the built-ins
+pub enum Peer<'a> {
+    Browser(&'a BrowserIdentity),
+    Client { conn_id: &'a str, addr: SocketAddr },
+}
+
+/// Everything a middleware knows about a message besides the message itself
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)] // Read by org-specific middlewares, not the built-ins
+pub struct MessageContext<'a> {
+    pub direction: Direction,
+    /// The client the message is going to or coming from
+    pub repo_path: &'a str,
+    pub peer: Peer<'a>,
+    /// Size of the WebSocket frame as received, in bytes
+    pub size: usize,
+}
+
+/// What a middleware decided to do with a message
+#[derive(Debug)]
+#[allow(dead_code)] // The built-ins only pass or reject
+pub enum Verdict {
+    /// Forward the message unchanged
+    Pass,
+    /// Forward this message instead
+    Modify(Message),
+    /// Drop the message; browsers are told the reason
+    Reject(String),
+    /// Forward all of these in place of the original, in order
+    Duplicate(Vec<Message>),
+}
+
+/**
+ * A policy hook that sees every routed message
+ *
+ * Middlewares run in registration order. Each message produced by one
+ * middleware (including duplicates) goes through the rest of the chain.
+ * Messages from E2E clients are `encrypted` envelopes, so only the routing
+ * fields are visible to a middleware.
+ */
+pub trait Middleware: Send + Sync {
+    fn name(&self) -> &str;
+    fn process(&self, ctx: &MessageContext, msg: &Message) -> Verdict;
+}
+
+/// Result of running a message through the pipeline
+pub enum Outcome {
+    /// Nothing touched the message, forward the original frame as-is
+    Unchanged(Message),
+    /// Forward these messages instead of the original frame
+    Replaced(Vec<Message>),
+    Rejected { middleware: String, reason: String },
+}
+
+/// The ordered list of middlewares the relay runs on routed messages
+#[derive(Default)]
+pub struct Pipeline {
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl Pipeline {
+    /**
+     * Build the pipeline from the built-in middlewares enabled in the environment
+     * RELAY_MAX_MESSAGE_BYTES enables SizeLimit, RELAY_PROMPT_DENYLIST_FILE enables PromptDenylist
+     */
+    pub fn from_env() -> Pipeline {
+        let mut pipeline = Pipeline::default();
+
+        if let Some(max_bytes) = std::env::var("RELAY_MAX_MESSAGE_BYTES").ok().and_then(|v| v.parse().ok()) {
+            pipeline = pipeline.with(SizeLimit { max_bytes });
+        }
+
+        if let Ok(path) = std::env::var("RELAY_PROMPT_DENYLIST_FILE") {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => pipeline = pipeline.with(PromptDenylist::parse(&contents)),
+                Err(e) => tracing::error!(%path, error = %e, "failed to read prompt denylist"),
+            }
+        }
+
+        pipeline
+    }
+
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Pipeline {
+        tracing::info!(middleware = middleware.name(), "middleware registered");
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    pub fn run(&self, ctx: &MessageContext, msg: Message) -> Outcome {
+        if self.is_empty() {
+            return Outcome::Unchanged(msg);
+        }
+
+        let mut changed = false;
+        let mut messages = vec![msg];
+
+        for middleware in &self.middlewares {
+            let mut next = Vec::with_capacity(messages.len());
+            for msg in messages {
+                match middleware.process(ctx, &msg) {
+                    Verdict::Pass => next.push(msg),
+                    Verdict::Modify(replacement) => {
+                        changed = true;
+                        next.push(replacement);
+                    }
+                    Verdict::Reject(reason) => {
+                        return Outcome::Rejected {
+                            middleware: middleware.name().to_string(),
+                            reason,
+                        };
+                    }
+                    Verdict::Duplicate(copies) => {
+                        changed = true;
+                        next.extend(copies);
+                    }
+                }
+            }
+            messages = next;
+        }
+
+        match (changed, messages.len()) {
+            (false, 1) => Outcome::Unchanged(messages.pop().unwrap()),
+            _ => Outcome::Replaced(messages),
+        }
+    }
+}
+
+/// Rejects browser requests larger than `max_bytes`
+pub struct SizeLimit {
+    pub max_bytes: usize,
+}
+
+impl Middleware for SizeLimit {
+    fn name(&self) -> &str {
+        "size_limit"
+    }
+
+    fn process(&self, ctx: &MessageContext, _msg: &Message) -> Verdict {
+        // Client output (history, tool results) is legitimately large, only limit what browsers send
+        if ctx.direction == Direction::BrowserToClient && ctx.size > self.max_bytes {
+            return Verdict::Reject(format!(
+                "Message is {} bytes, the relay accepts at most {}",
+                ctx.size, self.max_bytes
+            ));
+        }
+        Verdict::Pass
+    }
+}
+
+/// Rejects prompts matching any of a list of regular expressions
+pub struct PromptDenylist {
+    patterns: Vec<Regex>,
+}
+
+impl PromptDenylist {
+    pub fn new(patterns: Vec<Regex>) -> PromptDenylist {
+        PromptDenylist { patterns }
+    }
+
+    /**
+     * One regex per line; blank lines and lines starting with # are ignored
+     * Invalid patterns are logged and skipped
+     */
+    pub fn parse(contents: &str) -> PromptDenylist {
+        let patterns = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| match Regex::new(line) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    tracing::error!(pattern = line, error = %e, "invalid prompt denylist pattern");
+                    None
+                }
+            })
+            .collect();
+        PromptDenylist::new(patterns)
+    }
+}
+
+impl Middleware for PromptDenylist {
+    fn name(&self) -> &str {
+        "prompt_denylist"
+    }
+
+    fn process(&self, _ctx: &MessageContext, msg: &Message) -> Verdict {
+        let Message::SendMessage { content, .. } = msg else {
+            return Verdict::Pass;
+        };
+
+        match self.patterns.iter().find(|re| re.is_match(content)) {
+            Some(re) => Verdict::Reject(format!("Prompt matches a blocked pattern ({})", re.as_str())),
+            None => Verdict::Pass,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a closure as a middleware
+    struct Hook<F>(&'static str, F);
+
+    impl<F: Fn(&Message) -> Verdict + Send + Sync> Middleware for Hook<F> {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn process(&self, _ctx: &MessageContext, msg: &Message) -> Verdict {
+            (self.1)(msg)
+        }
+    }
+
+    fn ctx(direction: Direction, size: usize) -> MessageContext<'static> {
+        MessageContext {
+            direction,
+            repo_path: "/repo",
+            peer: Peer::Client { conn_id: "conn", addr: "127.0.0.1:1".parse().unwrap() },
+            size,
+        }
+    }
+
+    fn prompt(content: &str) -> Message {
+        Message::SendMessage {
+            repo_path: "/repo".to_string(),
+            lychee_id: "s1".to_string(),
+            content: content.to_string(),
+            model: None,
+        }
+    }
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|msg| match msg {
+                Message::SendMessage { content, .. } => content.as_str(),
+                _ => panic!("not a send_message"),
+            })
+            .collect()
+    }
+
+    fn shout(msg: &Message) -> Verdict {
+        match msg {
+            Message::SendMessage { content, .. } => Verdict::Modify(prompt(&content.to_uppercase())),
+            _ => Verdict::Pass,
+        }
+    }
+
+    #[test]
+    fn passing_messages_stay_unchanged() {
+        let pipeline = Pipeline::default().with(Hook("pass", |_: &Message| Verdict::Pass));
+        let outcome = pipeline.run(&ctx(Direction::BrowserToClient, 10), prompt("hi"));
+        assert!(matches!(outcome, Outcome::Unchanged(Message::SendMessage { content, .. }) if content == "hi"));
+    }
+
+    #[test]
+    fn duplicates_go_through_the_rest_of_the_chain() {
+        let pipeline = Pipeline::default()
+            .with(Hook("split", |_: &Message| Verdict::Duplicate(vec![prompt("a"), prompt("b")])))
+            .with(Hook("shout", shout));
+        let Outcome::Replaced(messages) = pipeline.run(&ctx(Direction::BrowserToClient, 10), prompt("hi")) else {
+            panic!("expected the message to be replaced");
+        };
+        assert_eq!(contents(&messages), ["A", "B"]);
+    }
+
+    #[test]
+    fn a_rejection_stops_the_chain() {
+        let pipeline = Pipeline::default()
+            .with(Hook("deny", |_: &Message| Verdict::Reject("no".to_string())))
+            .with(Hook("never", |_: &Message| panic!("ran after a rejection")));
+        let outcome = pipeline.run(&ctx(Direction::BrowserToClient, 10), prompt("hi"));
+        assert!(matches!(outcome, Outcome::Rejected { middleware, reason } if middleware == "deny" && reason == "no"));
+    }
+
+    #[test]
+    fn size_limit_only_applies_to_browsers() {
+        let pipeline = Pipeline::default().with(SizeLimit { max_bytes: 100 });
+        assert!(matches!(pipeline.run(&ctx(Direction::BrowserToClient, 100), prompt("hi")), Outcome::Unchanged(_)));
+        assert!(matches!(pipeline.run(&ctx(Direction::BrowserToClient, 101), prompt("hi")), Outcome::Rejected { .. }));
+        assert!(matches!(pipeline.run(&ctx(Direction::ClientToBrowser, 101), prompt("hi")), Outcome::Unchanged(_)));
+    }
+
+    #[test]
+    fn denylist_skips_comments_and_invalid_patterns() {
+        let denylist = PromptDenylist::parse("# secrets\n\n(?i)password\n[unclosed\n  rm -rf  \n");
+        assert_eq!(denylist.patterns.len(), 2);
+
+        let pipeline = Pipeline::default().with(denylist);
+        let context = ctx(Direction::BrowserToClient, 10);
+        assert!(matches!(pipeline.run(&context, prompt("print the PASSWORD")), Outcome::Rejected { .. }));
+        assert!(matches!(pipeline.run(&context, prompt("please rm -rf /tmp/x")), Outcome::Rejected { .. }));
+        assert!(matches!(pipeline.run(&context, prompt("fix the tests")), Outcome::Unchanged(_)));
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+/// Message types never written to a recording; keystrokes can hold passwords
+const UNRECORDED_TYPES: &[&str] = &["terminal_input"];
+
+/// Whether the relay received a frame from the peer or sent it to the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameDirection {
+    Recv,
+    Send,
+}
+
+/// One line of a recording file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub at: String,
+    /// Milliseconds since the connection was opened, used for replay timing
+    pub elapsed_ms: u64,
+    pub direction: FrameDirection,
+    pub frame: String,
+}
+
+/// The type of a frame, or of what an encrypted envelope carries
+#[derive(Deserialize)]
+struct FrameType {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    message_type: Option<String>,
+}
+
+/**
+ * Writes every frame of every connection to RELAY_RECORD_DIR, one JSONL file
+ * per connection
+ *
+ * Frames are stored verbatim: prompts, file contents sent with write_file,
+ * command output and everything else unencrypted in the traffic ends up on
+ * disk. Only terminal keystrokes (UNRECORDED_TYPES) are left out. Meant for
+ * frontend development against test repositories, not production relays.
+ */
+pub struct Recorder {
+    dir: PathBuf,
+}
+
+impl Recorder {
+    /**
+     * Returns None when RELAY_RECORD_DIR is not set (recording disabled)
+     * See the type's docs for what a recording contains
+     */
+    pub fn from_env() -> Option<Recorder> {
+        let dir = PathBuf::from(std::env::var("RELAY_RECORD_DIR").ok()?);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            tracing::error!(dir = %dir.display(), error = %e, "failed to create recording directory");
+            return None;
+        }
+        Some(Recorder { dir })
+    }
+
+    /**
+     * Open the recording for a new connection
+     * `kind` is "client" or "browser", `id` the connection or browser id
+     */
+    pub async fn start(&self, kind: &str, id: &str) -> Option<Recording> {
+        let name = format!("{}-{}-{}.jsonl", chrono::Utc::now().format("%Y%m%dT%H%M%S"), kind, id);
+        let path = self.dir.join(name);
+        match File::create(&path).await {
+            Ok(file) => {
+                tracing::debug!(path = %path.display(), "recording connection");
+                let (lines, lines_rx) = mpsc::unbounded_channel();
+                tokio::spawn(write_lines(file, lines_rx));
+                Some(Recording {
+                    started: Instant::now(),
+                    lines,
+                })
+            }
+            Err(e) => {
+                tracing::error!(path = %path.display(), error = %e, "failed to create recording");
+                None
+            }
+        }
+    }
+}
+
+/**
+ * Append lines to a recording until every sender is gone
+ * Runs as its own task so socket tasks never wait on the disk
+ */
+async fn write_lines(mut file: File, mut lines: mpsc::UnboundedReceiver<String>) {
+    while let Some(line) = lines.recv().await {
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            tracing::error!(error = %e, "failed to write recorded frame");
+            return;
+        }
+    }
+    let _ = file.flush().await;
+}
+
+/// The recording of a single connection; dropping it finishes the file
+pub struct Recording {
+    started: Instant,
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl Recording {
+    pub fn record(&self, direction: FrameDirection, frame: &str) {
+        if !is_recorded(frame) {
+            return;
+        }
+        let record = RecordedFrame {
+            at: chrono::Utc::now().to_rfc3339(),
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            direction,
+            frame: frame.to_string(),
+        };
+        let mut line = serde_json::to_string(&record).unwrap();
+        line.push('\n');
+
+        let _ = self.lines.send(line);
+    }
+}
+
+/**
+ * Whether a frame may be written to a recording, see UNRECORDED_TYPES
+ */
+fn is_recorded(frame: &str) -> bool {
+    let Ok(frame) = serde_json::from_str::<FrameType>(frame) else {
+        return true;
+    };
+    [frame.kind, frame.message_type]
+        .iter()
+        .flatten()
+        .all(|kind| !UNRECORDED_TYPES.contains(&kind.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminal_input_is_not_recorded() {
+        assert!(!is_recorded(r#"{"type":"terminal_input","repo_path":"/r","lychee_id":"a","terminal_id":"t","data":"hunter2"}"#));
+        assert!(!is_recorded(r#"{"type":"encrypted","repo_path":"/r","lychee_id":"a","message_type":"terminal_input","payload":"x"}"#));
+        assert!(is_recorded(r#"{"type":"terminal_output","repo_path":"/r","lychee_id":"a","terminal_id":"t","data":"x"}"#));
+        assert!(is_recorded(r#"{"type":"list_sessions","repo_path":"/r"}"#));
+        assert!(is_recorded("not json"));
+    }
+
+    #[tokio::test]
+    async fn recordings_are_written_by_their_own_task() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Recorder { dir: dir.path().to_path_buf() };
+        let recording = recorder.start("browser", "id").await.unwrap();
+        recording.record(FrameDirection::Recv, r#"{"type":"register_browser"}"#);
+        recording.record(FrameDirection::Recv, r#"{"type":"terminal_input","data":"x"}"#);
+        recording.record(FrameDirection::Send, r#"{"type":"client_count","count":1}"#);
+        drop(recording);
+
+        let path = std::fs::read_dir(dir.path()).unwrap().next().unwrap().unwrap().path();
+        assert!(path.to_string_lossy().ends_with("-browser-id.jsonl"));
+        let frames = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let contents = tokio::fs::read_to_string(&path).await.unwrap();
+                if contents.lines().count() == 2 {
+                    break contents;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let frames: Vec<RecordedFrame> = frames.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(frames[0].direction, FrameDirection::Recv);
+        assert_eq!(frames[1].direction, FrameDirection::Send);
+        assert!(frames[1].frame.contains("client_count"));
+    }
+}
@@ -0,0 +1,458 @@
+use crate::{AppState, Message};
+use axum::{
+    body::{Body, Bytes},
+    extract::{ws::WebSocket, Path, Request, State, WebSocketUpgrade},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::any,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::{SinkExt, StreamExt};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Largest request body passed on to a client; bodies are buffered, responses are not
+const MAX_REQUEST_BYTES: usize = 10 * 1024 * 1024;
+
+/// Largest response body passed on to a browser; WebSocket frames don't count
+const MAX_RESPONSE_BYTES: usize = 100 * 1024 * 1024;
+
+/// Events held for a browser that is behind; past that its request is given up
+const EVENT_BUFFER: usize = 256;
+
+/// How long a client gets to start answering a preview request
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers about the connection to the relay rather than the request
+const HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// What a client sent back for a preview request
+enum Event {
+    Response { status: u16, headers: Vec<(String, String)> },
+    Data(Vec<u8>),
+    Frame { data: String, binary: bool },
+    /// Nothing more will come; Some when the request failed
+    Close(Option<String>),
+}
+
+/// A preview request waiting on a client
+struct Waiting {
+    /// The client connection it was sent to
+    conn_id: String,
+    events: mpsc::Sender<Event>,
+    /// Response body bytes so far
+    received: usize,
+}
+
+/// Preview requests in flight, keyed by request_id
+#[derive(Default)]
+pub struct Tunnels {
+    waiting: Mutex<HashMap<String, Waiting>>,
+}
+
+impl Tunnels {
+    fn open(&self, request_id: &str, conn_id: &str) -> mpsc::Receiver<Event> {
+        let (events, rx) = mpsc::channel(EVENT_BUFFER);
+        let waiting = Waiting {
+            conn_id: conn_id.to_string(),
+            events,
+            received: 0,
+        };
+        self.waiting.lock().unwrap().insert(request_id.to_string(), waiting);
+        rx
+    }
+
+    /**
+     * Stop waiting on a request; false if it was already over
+     */
+    fn forget(&self, request_id: &str) -> bool {
+        self.waiting.lock().unwrap().remove(request_id).is_some()
+    }
+
+    /**
+     * Pass a tunnel_* message from a client to the request it answers
+     *
+     * Never waits, as that would hold up the client's other messages: a browser
+     * more than EVENT_BUFFER events behind loses its request. Answers to requests
+     * that went to another client are dropped. Returns the tunnel_close to send
+     * the client when the request was given up: the browser fell behind or the
+     * response grew past MAX_RESPONSE_BYTES.
+     */
+    pub fn deliver(&self, conn_id: &str, msg: Message) -> Option<Message> {
+        let (request_id, event) = match msg {
+            Message::TunnelResponse { request_id, status, headers } => (request_id, Event::Response { status, headers }),
+            Message::TunnelData { request_id, data } => match BASE64.decode(data) {
+                Ok(bytes) => (request_id, Event::Data(bytes)),
+                Err(_) => (request_id, Event::Close(Some("Invalid response body".to_string()))),
+            },
+            Message::TunnelFrame { request_id, data, binary } => (request_id, Event::Frame { data, binary }),
+            Message::TunnelClose { request_id, error } => (request_id, Event::Close(error)),
+            _ => return None,
+        };
+        let give_up = |request_id: String| Message::TunnelClose { request_id, error: None };
+
+        let mut waiting = self.waiting.lock().unwrap();
+        let Some(w) = waiting.get_mut(&request_id).filter(|w| w.conn_id == conn_id) else {
+            tracing::debug!(%request_id, "dropping answer to an unknown preview request");
+            return None;
+        };
+        if let Event::Data(ref bytes) = event {
+            w.received += bytes.len();
+        }
+        if w.received > MAX_RESPONSE_BYTES {
+            tracing::warn!(%request_id, "preview response too large");
+            // The browser sees the body cut off, whether or not this fits
+            let _ = w.events.try_send(Event::Close(Some("The response is too large".to_string())));
+            waiting.remove(&request_id);
+            return Some(give_up(request_id));
+        }
+
+        let last = matches!(event, Event::Close(_));
+        match w.events.try_send(event) {
+            Ok(()) if last => {
+                waiting.remove(&request_id);
+                None
+            }
+            Ok(()) => None,
+            // The browser is gone, which already told the client
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                waiting.remove(&request_id);
+                None
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::warn!(%request_id, "browser fell behind on a preview response");
+                // Dropping the sender cuts the response off after what is buffered
+                waiting.remove(&request_id);
+                (!last).then(|| give_up(request_id))
+            }
+        }
+    }
+
+    /**
+     * Fail the requests in flight to a client that went away
+     */
+    pub fn close_client(&self, conn_id: &str) {
+        self.waiting.lock().unwrap().retain(|_, w| w.conn_id != conn_id);
+    }
+}
+
+/// Forgets the request when dropped, telling the client if it was still going
+struct Pending {
+    tunnels: Arc<Tunnels>,
+    request_id: String,
+    client_tx: mpsc::UnboundedSender<String>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.tunnels.forget(&self.request_id) {
+            let close = Message::TunnelClose {
+                request_id: self.request_id.clone(),
+                error: None,
+            };
+            let _ = self.client_tx.send(serde_json::to_string(&close).unwrap());
+        }
+    }
+}
+
+/**
+ * The id of a client in preview URLs
+ * Derived from its repo path, so it survives reconnects without exposing the path
+ */
+pub fn client_id(repo_path: &str) -> String {
+    format!("{:x}", Sha256::digest(repo_path.as_bytes()))[..16].to_string()
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/preview/:client/:preview", any(add_slash))
+        .route("/preview/:client/:preview/", any(preview))
+        .route("/preview/:client/:preview/*path", any(preview))
+}
+
+/**
+ * Relative links only resolve under the preview root with its trailing slash
+ */
+async fn add_slash(Path((client, preview_id)): Path<(String, String)>) -> Redirect {
+    Redirect::permanent(&format!("/preview/{}/{}/", client, preview_id))
+}
+
+/**
+ * Serve a request for a session's exposed port through its client
+ *
+ * Previews need no login, so the URL names the exposure by the random id the
+ * client picked for it rather than by session: it stops working once the port
+ * is no longer exposed and can't be guessed from the session list.
+ */
+async fn preview(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    ws: Option<WebSocketUpgrade>,
+    request: Request,
+) -> Response {
+    let (Some(client), Some(preview_id)) = (params.get("client"), params.get("preview")) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let prefix = format!("/preview/{}/{}", client, preview_id);
+
+    let found = state
+        .clients
+        .read()
+        .await
+        .iter()
+        .find(|(repo_path, _)| client_id(repo_path) == *client)
+        .map(|(repo_path, handle)| (repo_path.clone(), handle.conn_id.clone(), handle.tx.clone()));
+    let Some((repo_path, conn_id, client_tx)) = found else {
+        return (StatusCode::NOT_FOUND, "No client connected with this id").into_response();
+    };
+
+    // As sent, still percent-encoded
+    let path = request
+        .uri()
+        .path_and_query()
+        .and_then(|pq| pq.as_str().strip_prefix(prefix.as_str()))
+        .unwrap_or("/")
+        .to_string();
+    let method = request.method().to_string();
+    let mut headers: Vec<(String, String)> = request
+        .headers()
+        .iter()
+        .filter(|(name, _)| !HOP_HEADERS.contains(&name.as_str()) && *name != "host" && *name != "content-length")
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    // Lets apps that support it build links under the preview root
+    headers.push(("x-forwarded-prefix".to_string(), prefix.clone()));
+    if let Some(host) = request.headers().get("host").and_then(|h| h.to_str().ok()) {
+        headers.push(("x-forwarded-host".to_string(), host.to_string()));
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), MAX_REQUEST_BYTES).await {
+        Ok(body) => body,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large").into_response(),
+    };
+
+    let request_id = Uuid::new_v4().to_string();
+    let mut events = state.tunnels.open(&request_id, &conn_id);
+    let pending = Pending {
+        tunnels: state.tunnels.clone(),
+        request_id: request_id.clone(),
+        client_tx: client_tx.clone(),
+    };
+    tracing::debug!(%request_id, repo = %repo_path, %method, %path, websocket = ws.is_some(), "preview request");
+    let tunnel_request = Message::TunnelRequest {
+        request_id: request_id.clone(),
+        preview_id: preview_id.clone(),
+        method,
+        path,
+        headers,
+        body: BASE64.encode(&body),
+        websocket: ws.is_some(),
+    };
+    let _ = client_tx.send(serde_json::to_string(&tunnel_request).unwrap());
+
+    let (status, headers) = match tokio::time::timeout(RESPONSE_TIMEOUT, events.recv()).await {
+        Ok(Some(Event::Response { status, headers })) => (status, headers),
+        Ok(Some(Event::Close(Some(error)))) => return (StatusCode::BAD_GATEWAY, error).into_response(),
+        Ok(Some(_)) => return (StatusCode::BAD_GATEWAY, "The client sent no response").into_response(),
+        Ok(None) => return (StatusCode::BAD_GATEWAY, "The client disconnected").into_response(),
+        Err(_) => return (StatusCode::GATEWAY_TIMEOUT, "The client didn't answer in time").into_response(),
+    };
+
+    if status == StatusCode::SWITCHING_PROTOCOLS.as_u16()
+        && let Some(ws) = ws
+    {
+        // Browsers drop the socket unless the subprotocol the server picked is echoed
+        let protocols: Vec<String> = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("sec-websocket-protocol"))
+            .map(|(_, value)| value.clone())
+            .collect();
+        return ws
+            .protocols(protocols)
+            .on_upgrade(move |socket| bridge(socket, events, pending))
+            .into_response();
+    }
+
+    let mut response = Response::new(Body::from_stream(futures_util::stream::unfold(
+        (events, pending),
+        |(mut events, pending)| async move {
+            loop {
+                match events.recv().await {
+                    Some(Event::Data(bytes)) => return Some((Ok(Bytes::from(bytes)), (events, pending))),
+                    Some(Event::Close(Some(error))) => {
+                        return Some((Err(std::io::Error::other(error)), (events, pending)));
+                    }
+                    Some(Event::Close(None)) => return None,
+                    // Cut off rather than ended, so the browser doesn't take it as complete
+                    None => {
+                        let error = std::io::Error::other("The client disconnected");
+                        return Some((Err(error), (events, pending)));
+                    }
+                    Some(_) => {}
+                }
+            }
+        },
+    )));
+    *response.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+    for (name, value) in headers {
+        if HOP_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            continue;
+        }
+        // Redirects to the server's own root stay under the preview root
+        let value = match name.eq_ignore_ascii_case("location") && value.starts_with('/') && !value.starts_with("//") {
+            true => format!("{}{}", prefix, value),
+            false => value,
+        };
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+}
+
+/**
+ * Relay frames between a browser's WebSocket and the one the client opened
+ */
+async fn bridge(socket: WebSocket, mut events: mpsc::Receiver<Event>, pending: Pending) {
+    let (mut sink, mut stream) = socket.split();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(Event::Frame { data, binary }) => {
+                    let message = if binary {
+                        match BASE64.decode(data) {
+                            Ok(bytes) => axum::extract::ws::Message::Binary(bytes),
+                            Err(_) => continue,
+                        }
+                    } else {
+                        axum::extract::ws::Message::Text(data)
+                    };
+                    if sink.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Event::Close(_)) | None => break,
+                Some(_) => {}
+            },
+            message = stream.next() => {
+                let (data, binary) = match message {
+                    Some(Ok(axum::extract::ws::Message::Text(text))) => (text, false),
+                    Some(Ok(axum::extract::ws::Message::Binary(bytes))) => (BASE64.encode(bytes), true),
+                    Some(Ok(axum::extract::ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let frame = Message::TunnelFrame {
+                    request_id: pending.request_id.clone(),
+                    data,
+                    binary,
+                };
+                let _ = pending.client_tx.send(serde_json::to_string(&frame).unwrap());
+            }
+        }
+    }
+    // Finishes the close handshake; dropping `pending` tells the client to close its side
+    let _ = sink.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(request_id: &str, bytes: &[u8]) -> Message {
+        Message::TunnelData {
+            request_id: request_id.to_string(),
+            data: BASE64.encode(bytes),
+        }
+    }
+
+    fn given_up(close: Option<Message>) -> bool {
+        matches!(close, Some(Message::TunnelClose { error: None, .. }))
+    }
+
+    #[test]
+    fn answers_reach_the_request() {
+        let tunnels = Tunnels::default();
+        let mut events = tunnels.open("r1", "conn");
+
+        assert!(tunnels.deliver("conn", data("r1", b"hello")).is_none());
+        assert!(matches!(events.try_recv(), Ok(Event::Data(bytes)) if bytes == b"hello"));
+
+        let close = Message::TunnelClose { request_id: "r1".to_string(), error: None };
+        assert!(tunnels.deliver("conn", close).is_none());
+        assert!(matches!(events.try_recv(), Ok(Event::Close(None))));
+        assert!(!tunnels.forget("r1"));
+    }
+
+    #[test]
+    fn answers_from_another_client_are_dropped() {
+        let tunnels = Tunnels::default();
+        let mut events = tunnels.open("r1", "conn");
+
+        assert!(tunnels.deliver("other", data("r1", b"hello")).is_none());
+        assert!(tunnels.deliver("conn", data("unknown", b"hello")).is_none());
+        assert!(events.try_recv().is_err());
+        assert!(tunnels.forget("r1"));
+    }
+
+    #[test]
+    fn oversized_responses_are_cut_off() {
+        let tunnels = Tunnels::default();
+        let mut events = tunnels.open("r1", "conn");
+
+        let chunk = vec![0u8; MAX_RESPONSE_BYTES / 2];
+        assert!(tunnels.deliver("conn", data("r1", &chunk)).is_none());
+        assert!(tunnels.deliver("conn", data("r1", &chunk)).is_none());
+        assert!(given_up(tunnels.deliver("conn", data("r1", b"x"))));
+
+        assert!(matches!(events.try_recv(), Ok(Event::Data(_))));
+        assert!(matches!(events.try_recv(), Ok(Event::Data(_))));
+        assert!(matches!(events.try_recv(), Ok(Event::Close(Some(_)))));
+        assert!(!tunnels.forget("r1"));
+    }
+
+    #[test]
+    fn a_browser_that_falls_behind_loses_the_request() {
+        let tunnels = Tunnels::default();
+        let mut events = tunnels.open("r1", "conn");
+
+        for _ in 0..EVENT_BUFFER {
+            assert!(tunnels.deliver("conn", data("r1", b"x")).is_none());
+        }
+        assert!(given_up(tunnels.deliver("conn", data("r1", b"x"))));
+        assert!(!tunnels.forget("r1"));
+
+        // What was buffered still arrives, then the response ends cut off
+        for _ in 0..EVENT_BUFFER {
+            assert!(matches!(events.try_recv(), Ok(Event::Data(_))));
+        }
+        assert!(matches!(events.try_recv(), Err(mpsc::error::TryRecvError::Disconnected)));
+    }
+
+    #[test]
+    fn corrupt_bodies_fail_the_request() {
+        let tunnels = Tunnels::default();
+        let mut events = tunnels.open("r1", "conn");
+
+        let corrupt = Message::TunnelData { request_id: "r1".to_string(), data: "not base64!".to_string() };
+        assert!(tunnels.deliver("conn", corrupt).is_none());
+        assert!(matches!(events.try_recv(), Ok(Event::Close(Some(_)))));
+        assert!(!tunnels.forget("r1"));
+    }
+}